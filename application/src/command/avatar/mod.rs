@@ -0,0 +1 @@
+pub mod upload_avatar;
\ No newline at end of file
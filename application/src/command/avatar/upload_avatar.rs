@@ -0,0 +1,157 @@
+use crate::command::{Command, CommandHandler};
+use crate::shared::error::AppStatus;
+use async_trait::async_trait;
+use domain::models::avatar::Rating;
+use domain::repositories::avatar_repository::AvatarRepository;
+use domain::repositories::blob_store::BlobStore;
+use domain::repositories::id_provider::IdProvider;
+use domain::services::avatar_service::{AvatarError, AvatarService};
+use domain::views::avatar_view::AvatarView;
+
+#[derive(Debug, Clone)]
+pub struct UploadAvatarCommand {
+    user_id: i64,
+    content_type: String,
+    data: Vec<u8>,
+    rating: Option<String>,
+}
+
+impl UploadAvatarCommand {
+    pub fn new(user_id: i64, content_type: String, data: Vec<u8>) -> Self {
+        Self { user_id, content_type, data, rating: None }
+    }
+
+    /// Rating chosen by the owner, `g`, `pg`, `r` or `x`. Avatars are rated G by default.
+    pub fn with_rating(mut self, rating: String) -> Self {
+        self.rating = Some(rating);
+        self
+    }
+}
+
+impl Command<AvatarView> for UploadAvatarCommand {}
+
+pub struct UploadAvatarCommandHandler<AR, BS, IP>
+where
+    AR: AvatarRepository + Sync + Send,
+    BS: BlobStore + Sync + Send,
+    IP: IdProvider + Sync + Send,
+{
+    avatar_service: AvatarService<AR, BS, IP>,
+}
+
+impl<AR, BS, IP> UploadAvatarCommandHandler<AR, BS, IP>
+where
+    AR: AvatarRepository + Sync + Send,
+    BS: BlobStore + Sync + Send,
+    IP: IdProvider + Sync + Send,
+{
+    pub fn new(avatar_repository: AR, blob_store: BS, id_provider: IP) -> Self {
+        Self {
+            avatar_service: AvatarService::new(avatar_repository, blob_store, id_provider),
+        }
+    }
+}
+
+#[async_trait]
+impl<AR, BS, IP> CommandHandler<UploadAvatarCommand, AvatarView> for UploadAvatarCommandHandler<AR, BS, IP>
+where
+    AR: AvatarRepository + Sync + Send,
+    BS: BlobStore + Sync + Send,
+    IP: IdProvider + Sync + Send,
+{
+    async fn handle(&self, command: UploadAvatarCommand) -> Result<AvatarView, AppStatus> {
+        let rating = match command.rating.as_deref() {
+            Some(rating) => Rating::parse(rating).ok_or_else(|| AppStatus::BadRequest(format!("Unknown rating: {}", rating)))?,
+            None => Rating::G,
+        };
+
+        match self.avatar_service.upload(command.user_id, &command.content_type, &command.data, rating).await {
+            Ok(avatar) => Ok(avatar),
+            Err(AvatarError::InternalError(err)) => Err(AppStatus::InternalError(format!("Failed to store avatar: {}", err))),
+            Err(err) => Err(AppStatus::BadRequest(err.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use domain::models::avatar::CONTENT_TYPE_PNG;
+    use domain::repositories::avatar_repository::InMemoryAvatarRepository;
+    use domain::repositories::blob_store::InMemoryBlobStore;
+    use domain::repositories::id_provider::SimpleIdProvider;
+    use domain::services::default_avatar;
+
+    fn create_handler() -> UploadAvatarCommandHandler<InMemoryAvatarRepository, InMemoryBlobStore, SimpleIdProvider> {
+        UploadAvatarCommandHandler::new(InMemoryAvatarRepository::new(), InMemoryBlobStore::new(), SimpleIdProvider::new())
+    }
+
+    #[tokio::test]
+    async fn test_handle_valid_image() {
+        // Given
+        let handler = create_handler();
+        let command = UploadAvatarCommand::new(1, CONTENT_TYPE_PNG.to_string(), default_avatar::blank().data);
+
+        // When
+        let result = handler.handle(command).await;
+
+        // Then
+        let avatar = result.unwrap();
+        assert_eq!(avatar.user_id, 1);
+        assert!(avatar.is_primary);
+    }
+
+    #[tokio::test]
+    async fn test_handle_unsupported_content_type() {
+        // Given
+        let handler = create_handler();
+        let command = UploadAvatarCommand::new(1, "text/html".to_string(), b"<html></html>".to_vec());
+
+        // When
+        let result = handler.handle(command).await;
+
+        // Then
+        assert!(matches!(result, Err(AppStatus::BadRequest(_))));
+    }
+
+    #[tokio::test]
+    async fn test_handle_mismatched_content() {
+        // Given
+        let handler = create_handler();
+        let command = UploadAvatarCommand::new(1, CONTENT_TYPE_PNG.to_string(), b"GIF89a".to_vec());
+
+        // When
+        let result = handler.handle(command).await;
+
+        // Then
+        assert!(matches!(result, Err(AppStatus::BadRequest(_))));
+    }
+
+    #[tokio::test]
+    async fn test_handle_undecodable_image() {
+        // Given
+        let handler = create_handler();
+        let command = UploadAvatarCommand::new(1, CONTENT_TYPE_PNG.to_string(), b"\x89PNG\r\n\x1a\n\x00".to_vec());
+
+        // When
+        let result = handler.handle(command).await;
+
+        // Then
+        assert!(matches!(result, Err(AppStatus::BadRequest(_))));
+    }
+
+    #[tokio::test]
+    async fn test_handle_rating() {
+        // Given
+        let handler = create_handler();
+        let png = default_avatar::blank().data;
+
+        // When
+        let rated = handler.handle(UploadAvatarCommand::new(1, CONTENT_TYPE_PNG.to_string(), png.clone()).with_rating("PG".to_owned())).await;
+        let unknown = handler.handle(UploadAvatarCommand::new(1, CONTENT_TYPE_PNG.to_string(), png).with_rating("nc17".to_owned())).await;
+
+        // Then
+        assert_eq!(rated.unwrap().rating, Rating::PG);
+        assert!(matches!(unknown, Err(AppStatus::BadRequest(_))));
+    }
+}
@@ -0,0 +1,166 @@
+use crate::command::email::map_email_error;
+use crate::command::{Command, CommandHandler};
+use crate::shared::error::AppStatus;
+use async_trait::async_trait;
+use domain::repositories::email_repository::EmailRepository;
+use domain::repositories::id_provider::IdProvider;
+use domain::models::outbox::MailContent;
+use domain::repositories::user_repository::UserRepository;
+use domain::services::email_service::EmailService;
+use domain::services::otp_hasher::OtpHasher;
+use domain::services::session_rotator::SessionRotator;
+use domain::views::email_view::{EmailChangeView, EmailVerificationView, EmailView};
+use std::sync::Arc;
+
+/// Adds an unverified address to the user and mails it a verification code through the outbox
+#[derive(Debug, Clone)]
+pub struct AddEmailCommand {
+    user_id: i64,
+    address: String,
+    session: Option<String>,
+}
+
+impl AddEmailCommand {
+    pub fn new(user_id: i64, address: String) -> Self {
+        Self { user_id, address, session: None }
+    }
+
+    /// Session the change is made in, it is rotated afterwards
+    pub fn with_session(mut self, session: String) -> Self {
+        self.session = Some(session);
+        self
+    }
+}
+
+impl Command<EmailChangeView<EmailView>> for AddEmailCommand {}
+
+pub struct AddEmailCommandHandler<ER, UR, IP>
+where
+    ER: EmailRepository + Sync + Send,
+    UR: UserRepository + Sync + Send,
+    IP: IdProvider + Sync + Send,
+{
+    email_service: EmailService<ER, UR, IP>,
+    public_url: String,
+}
+
+impl<ER, UR, IP> AddEmailCommandHandler<ER, UR, IP>
+where
+    ER: EmailRepository + Sync + Send,
+    UR: UserRepository + Sync + Send,
+    IP: IdProvider + Sync + Send,
+{
+    pub fn new(email_repository: ER, user_repository: UR, id_provider: IP) -> Self {
+        Self {
+            email_service: EmailService::new(email_repository, user_repository, id_provider),
+            public_url: String::new(),
+        }
+    }
+
+    /// Must be the same hasher the `VerifyEmailCommandHandler` uses
+    pub fn with_otp_hasher(mut self, otp_hasher: OtpHasher) -> Self {
+        self.email_service = self.email_service.with_otp_hasher(otp_hasher);
+        self
+    }
+
+    /// Rotates the session given with `with_session` once the change went through
+    pub fn with_session_rotator(mut self, session_rotator: Arc<dyn SessionRotator>) -> Self {
+        self.email_service = self.email_service.with_session_rotator(session_rotator);
+        self
+    }
+
+    /// Base URL the verification link in the mail points to
+    pub fn with_public_url(mut self, public_url: String) -> Self {
+        self.public_url = public_url.trim_end_matches('/').to_owned();
+        self
+    }
+
+    fn verification_mail(&self, verification: &EmailVerificationView) -> MailContent {
+        let link = format!(
+            "{}/emails/{}/verify?code={}",
+            self.public_url, verification.email.id, verification.code
+        );
+
+        let html_body = format!(
+            r#"<p>Confirm that this address belongs to you by opening <a href="{link}">{link}</a> or by entering the code <b>{code}</b>.</p><p>The code expires at {expires_at}.</p>"#,
+            link = link,
+            code = verification.code,
+            expires_at = verification.expires_at,
+        );
+
+        let plain_body = format!(
+            "Confirm that this address belongs to you by opening {} or by entering the code {}.\nThe code expires at {}.",
+            link, verification.code, verification.expires_at,
+        );
+
+        MailContent { subject: "Confirm your email address".to_owned(), html_body, plain_body }
+    }
+}
+
+#[async_trait]
+impl<ER, UR, IP> CommandHandler<AddEmailCommand, EmailChangeView<EmailView>> for AddEmailCommandHandler<ER, UR, IP>
+where
+    ER: EmailRepository + Sync + Send,
+    UR: UserRepository + Sync + Send,
+    IP: IdProvider + Sync + Send,
+{
+    async fn handle(&self, command: AddEmailCommand) -> Result<EmailChangeView<EmailView>, AppStatus> {
+        // The mail is written to the outbox together with the address
+        let email = self.email_service
+            .add_and_mail(command.user_id, &command.address, |verification| self.verification_mail(verification))
+            .await
+            .map_err(map_email_error)?;
+
+        let session = self.email_service.rotate_session(command.session.as_deref()).await;
+
+        Ok(EmailChangeView { change: email, session })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use domain::models::user::User;
+    use domain::repositories::email_repository::InMemoryEmailRepository;
+    use domain::repositories::id_provider::SimpleIdProvider;
+    use domain::models::outbox::OutboxPayload;
+    use domain::repositories::outbox_repository::InMemoryOutboxRepository;
+    use domain::repositories::user_repository::InMemoryUserRepository;
+
+    #[tokio::test]
+    async fn test_handle() {
+        // Given
+        let user_repository = InMemoryUserRepository::new();
+        let user = user_repository.save(User::new("alice".to_owned())).await.unwrap();
+        let outbox = InMemoryOutboxRepository::new();
+        let email_repository = InMemoryEmailRepository::new().with_outbox(outbox.clone());
+        let handler = AddEmailCommandHandler::new(email_repository.clone(), user_repository, SimpleIdProvider::new())
+            .with_public_url("https://avatars.example/".to_owned());
+
+        // When
+        let added = handler.handle(AddEmailCommand::new(user.id, "alice@example.com".to_owned())).await;
+        let invalid = handler.handle(AddEmailCommand::new(user.id, "alice".to_owned())).await;
+
+        // Then
+        let added = added.unwrap().change;
+        assert!(!added.is_verified);
+        assert_eq!(email_repository.find_by_id(added.id).await.unwrap().value, "alice@example.com");
+        assert!(matches!(invalid, Err(AppStatus::BadRequest(_))));
+        let messages = outbox.messages().await;
+        assert_eq!(messages.len(), 1);
+        let link = format!("https://avatars.example/emails/{}/verify?code={{code}}", added.id);
+        assert!(matches!(&messages[0].payload, OutboxPayload::SealedMail { to, plain_body, .. } if to == "alice@example.com" && plain_body.contains(&link)));
+    }
+
+    #[tokio::test]
+    async fn test_handle_unknown_user() {
+        // Given
+        let handler = AddEmailCommandHandler::new(InMemoryEmailRepository::new(), InMemoryUserRepository::new(), SimpleIdProvider::new());
+
+        // When
+        let result = handler.handle(AddEmailCommand::new(42, "alice@example.com".to_owned())).await;
+
+        // Then
+        assert!(matches!(result, Err(AppStatus::NotFound(_))));
+    }
+}
@@ -0,0 +1,129 @@
+use crate::command::email::map_email_error;
+use crate::command::{Command, CommandHandler};
+use crate::shared::error::AppStatus;
+use async_trait::async_trait;
+use domain::repositories::avatar_repository::AvatarRepository;
+use domain::repositories::blob_store::BlobStore;
+use domain::repositories::email_repository::EmailRepository;
+use domain::repositories::id_provider::IdProvider;
+use domain::repositories::user_repository::UserRepository;
+use domain::services::avatar_service::AvatarService;
+use domain::services::email_service::{EmailAddressError, EmailService};
+use domain::views::email_view::EmailView;
+
+/// Shows a specific avatar from the user's library for one of their verified addresses.
+/// Without an avatar the address falls back to the user's primary avatar.
+#[derive(Debug, Clone)]
+pub struct AssignAvatarToEmailCommand {
+    user_id: i64,
+    email_id: i64,
+    avatar_id: Option<i64>,
+}
+
+impl AssignAvatarToEmailCommand {
+    pub fn new(user_id: i64, email_id: i64, avatar_id: Option<i64>) -> Self {
+        Self { user_id, email_id, avatar_id }
+    }
+}
+
+impl Command<EmailView> for AssignAvatarToEmailCommand {
+    const IDEMPOTENT: bool = true;
+}
+
+pub struct AssignAvatarToEmailCommandHandler<ER, UR, AR, BS, IP>
+where
+    ER: EmailRepository + Sync + Send,
+    UR: UserRepository + Sync + Send,
+    AR: AvatarRepository + Sync + Send,
+    BS: BlobStore + Sync + Send,
+    IP: IdProvider + Sync + Send,
+{
+    email_service: EmailService<ER, UR, IP>,
+    avatar_service: AvatarService<AR, BS, IP>,
+}
+
+impl<ER, UR, AR, BS, IP> AssignAvatarToEmailCommandHandler<ER, UR, AR, BS, IP>
+where
+    ER: EmailRepository + Sync + Send,
+    UR: UserRepository + Sync + Send,
+    AR: AvatarRepository + Sync + Send,
+    BS: BlobStore + Sync + Send,
+    IP: IdProvider + Clone + Sync + Send,
+{
+    pub fn new(email_repository: ER, user_repository: UR, avatar_repository: AR, blob_store: BS, id_provider: IP) -> Self {
+        Self {
+            email_service: EmailService::new(email_repository, user_repository, id_provider.clone()),
+            avatar_service: AvatarService::new(avatar_repository, blob_store, id_provider),
+        }
+    }
+}
+
+#[async_trait]
+impl<ER, UR, AR, BS, IP> CommandHandler<AssignAvatarToEmailCommand, EmailView> for AssignAvatarToEmailCommandHandler<ER, UR, AR, BS, IP>
+where
+    ER: EmailRepository + Sync + Send,
+    UR: UserRepository + Sync + Send,
+    AR: AvatarRepository + Sync + Send,
+    BS: BlobStore + Sync + Send,
+    IP: IdProvider + Sync + Send,
+{
+    async fn handle(&self, command: AssignAvatarToEmailCommand) -> Result<EmailView, AppStatus> {
+        if let Some(avatar_id) = command.avatar_id {
+            if self.avatar_service.find(command.user_id, avatar_id).await.is_none() {
+                return Err(map_email_error(EmailAddressError::AvatarNotFound));
+            }
+        }
+
+        self.email_service.assign_avatar(command.user_id, command.email_id, command.avatar_id).await
+            .map_err(map_email_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use domain::models::avatar::{Rating, CONTENT_TYPE_PNG};
+    use domain::models::user::User;
+    use domain::repositories::avatar_repository::InMemoryAvatarRepository;
+    use domain::repositories::blob_store::InMemoryBlobStore;
+    use domain::repositories::email_repository::InMemoryEmailRepository;
+    use domain::repositories::id_provider::SimpleIdProvider;
+    use domain::repositories::user_repository::InMemoryUserRepository;
+    use domain::services::default_avatar;
+
+    #[tokio::test]
+    async fn test_handle() {
+        // Given
+        let user_repository = InMemoryUserRepository::new();
+        let alice = user_repository.save(User::new("alice".to_owned())).await.unwrap();
+        let bob = user_repository.save(User::new("bob".to_owned())).await.unwrap();
+        let email_repository = InMemoryEmailRepository::new();
+        let avatar_repository = InMemoryAvatarRepository::new();
+        let blob_store = InMemoryBlobStore::new();
+
+        let email_service = EmailService::new(email_repository.clone(), user_repository.clone(), SimpleIdProvider::new());
+        let pending = email_service.add(alice.id, "alice@example.com").await.unwrap();
+        email_service.verify(alice.id, pending.email.id, &pending.code).await.unwrap();
+
+        let avatar_service = AvatarService::new(avatar_repository.clone(), blob_store.clone(), SimpleIdProvider::new());
+        let png = default_avatar::blank().data;
+        let own = avatar_service.upload(alice.id, CONTENT_TYPE_PNG, &png, Rating::G).await.unwrap();
+        let foreign = avatar_service.upload(bob.id, CONTENT_TYPE_PNG, &png, Rating::G).await.unwrap();
+
+        let handler = AssignAvatarToEmailCommandHandler::new(
+            email_repository,
+            user_repository,
+            avatar_repository,
+            blob_store,
+            SimpleIdProvider::new(),
+        );
+
+        // When
+        let assigned = handler.handle(AssignAvatarToEmailCommand::new(alice.id, pending.email.id, Some(own.id))).await;
+        let stolen = handler.handle(AssignAvatarToEmailCommand::new(alice.id, pending.email.id, Some(foreign.id))).await;
+
+        // Then
+        assert_eq!(assigned.unwrap().avatar_id, Some(own.id));
+        assert!(matches!(stolen, Err(AppStatus::NotFound(_))));
+    }
+}
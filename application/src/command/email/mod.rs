@@ -0,0 +1,16 @@
+use crate::shared::error::AppStatus;
+use domain::services::email_service::EmailAddressError;
+
+pub mod add_email;
+pub mod assign_avatar_to_email;
+pub mod remove_email;
+pub mod set_primary_email;
+pub mod verify_email;
+
+pub(crate) fn map_email_error(err: EmailAddressError) -> AppStatus {
+    match err {
+        EmailAddressError::InternalError(msg) => AppStatus::InternalError(msg),
+        EmailAddressError::NotFound | EmailAddressError::UserNotFound | EmailAddressError::AvatarNotFound => AppStatus::NotFound(err.to_string()),
+        err => AppStatus::BadRequest(err.to_string()),
+    }
+}
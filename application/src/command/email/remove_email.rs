@@ -0,0 +1,108 @@
+use crate::command::email::map_email_error;
+use crate::command::{Command, CommandHandler};
+use crate::shared::error::AppStatus;
+use async_trait::async_trait;
+use domain::repositories::email_repository::EmailRepository;
+use domain::repositories::id_provider::IdProvider;
+use domain::repositories::user_repository::UserRepository;
+use domain::services::email_service::EmailService;
+use domain::services::session_rotator::SessionRotator;
+use domain::views::email_view::EmailChangeView;
+use std::sync::Arc;
+
+#[derive(Debug, Clone)]
+pub struct RemoveEmailCommand {
+    user_id: i64,
+    email_id: i64,
+    session: Option<String>,
+}
+
+impl RemoveEmailCommand {
+    pub fn new(user_id: i64, email_id: i64) -> Self {
+        Self { user_id, email_id, session: None }
+    }
+
+    /// Session the change is made in, it is rotated afterwards
+    pub fn with_session(mut self, session: String) -> Self {
+        self.session = Some(session);
+        self
+    }
+}
+
+impl Command<EmailChangeView<()>> for RemoveEmailCommand {}
+
+pub struct RemoveEmailCommandHandler<ER, UR, IP>
+where
+    ER: EmailRepository + Sync + Send,
+    UR: UserRepository + Sync + Send,
+    IP: IdProvider + Sync + Send,
+{
+    email_service: EmailService<ER, UR, IP>,
+}
+
+impl<ER, UR, IP> RemoveEmailCommandHandler<ER, UR, IP>
+where
+    ER: EmailRepository + Sync + Send,
+    UR: UserRepository + Sync + Send,
+    IP: IdProvider + Sync + Send,
+{
+    pub fn new(email_repository: ER, user_repository: UR, id_provider: IP) -> Self {
+        Self {
+            email_service: EmailService::new(email_repository, user_repository, id_provider),
+        }
+    }
+
+    /// Rotates the session given with `with_session` once the change went through
+    pub fn with_session_rotator(mut self, session_rotator: Arc<dyn SessionRotator>) -> Self {
+        self.email_service = self.email_service.with_session_rotator(session_rotator);
+        self
+    }
+}
+
+#[async_trait]
+impl<ER, UR, IP> CommandHandler<RemoveEmailCommand, EmailChangeView<()>> for RemoveEmailCommandHandler<ER, UR, IP>
+where
+    ER: EmailRepository + Sync + Send,
+    UR: UserRepository + Sync + Send,
+    IP: IdProvider + Sync + Send,
+{
+    async fn handle(&self, command: RemoveEmailCommand) -> Result<EmailChangeView<()>, AppStatus> {
+        self.email_service.remove(command.user_id, command.email_id).await
+            .map_err(map_email_error)?;
+        let session = self.email_service.rotate_session(command.session.as_deref()).await;
+
+        Ok(EmailChangeView { change: (), session })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use domain::models::user::User;
+    use domain::repositories::email_repository::InMemoryEmailRepository;
+    use domain::repositories::id_provider::SimpleIdProvider;
+    use domain::repositories::user_repository::InMemoryUserRepository;
+
+    #[tokio::test]
+    async fn test_handle() {
+        // Given
+        let user_repository = InMemoryUserRepository::new();
+        let alice = user_repository.save(User::new("alice".to_owned())).await.unwrap();
+        let bob = user_repository.save(User::new("bob".to_owned())).await.unwrap();
+        let email_repository = InMemoryEmailRepository::new();
+
+        let email_service = EmailService::new(email_repository.clone(), user_repository.clone(), SimpleIdProvider::new());
+        let pending = email_service.add(alice.id, "alice@example.com").await.unwrap();
+
+        let handler = RemoveEmailCommandHandler::new(email_repository.clone(), user_repository, SimpleIdProvider::new());
+
+        // When
+        let by_other_user = handler.handle(RemoveEmailCommand::new(bob.id, pending.email.id)).await;
+        let by_owner = handler.handle(RemoveEmailCommand::new(alice.id, pending.email.id)).await;
+
+        // Then
+        assert!(matches!(by_other_user, Err(AppStatus::NotFound(_))));
+        assert!(by_owner.is_ok());
+        assert!(email_repository.find_by_id(pending.email.id).await.is_none());
+    }
+}
@@ -0,0 +1,105 @@
+use crate::command::email::map_email_error;
+use crate::command::{Command, CommandHandler};
+use crate::shared::error::AppStatus;
+use async_trait::async_trait;
+use domain::repositories::email_repository::EmailRepository;
+use domain::repositories::id_provider::IdProvider;
+use domain::repositories::user_repository::UserRepository;
+use domain::services::email_service::EmailService;
+use domain::services::session_rotator::SessionRotator;
+use domain::views::email_view::{EmailChangeView, EmailView};
+use std::sync::Arc;
+
+/// Makes a verified address the user's primary one
+#[derive(Debug, Clone)]
+pub struct SetPrimaryEmailCommand {
+    user_id: i64,
+    email_id: i64,
+    session: Option<String>,
+}
+
+impl SetPrimaryEmailCommand {
+    pub fn new(user_id: i64, email_id: i64) -> Self {
+        Self { user_id, email_id, session: None }
+    }
+
+    /// Session the change is made in, it is rotated afterwards
+    pub fn with_session(mut self, session: String) -> Self {
+        self.session = Some(session);
+        self
+    }
+}
+
+impl Command<EmailChangeView<EmailView>> for SetPrimaryEmailCommand {}
+
+pub struct SetPrimaryEmailCommandHandler<ER, UR, IP>
+where
+    ER: EmailRepository + Sync + Send,
+    UR: UserRepository + Sync + Send,
+    IP: IdProvider + Sync + Send,
+{
+    email_service: EmailService<ER, UR, IP>,
+}
+
+impl<ER, UR, IP> SetPrimaryEmailCommandHandler<ER, UR, IP>
+where
+    ER: EmailRepository + Sync + Send,
+    UR: UserRepository + Sync + Send,
+    IP: IdProvider + Sync + Send,
+{
+    pub fn new(email_repository: ER, user_repository: UR, id_provider: IP) -> Self {
+        Self {
+            email_service: EmailService::new(email_repository, user_repository, id_provider),
+        }
+    }
+
+    /// Rotates the session given with `with_session` once the change went through
+    pub fn with_session_rotator(mut self, session_rotator: Arc<dyn SessionRotator>) -> Self {
+        self.email_service = self.email_service.with_session_rotator(session_rotator);
+        self
+    }
+}
+
+#[async_trait]
+impl<ER, UR, IP> CommandHandler<SetPrimaryEmailCommand, EmailChangeView<EmailView>> for SetPrimaryEmailCommandHandler<ER, UR, IP>
+where
+    ER: EmailRepository + Sync + Send,
+    UR: UserRepository + Sync + Send,
+    IP: IdProvider + Sync + Send,
+{
+    async fn handle(&self, command: SetPrimaryEmailCommand) -> Result<EmailChangeView<EmailView>, AppStatus> {
+        let email = self.email_service.set_primary(command.user_id, command.email_id).await
+            .map_err(map_email_error)?;
+        let session = self.email_service.rotate_session(command.session.as_deref()).await;
+
+        Ok(EmailChangeView { change: email, session })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use domain::models::user::User;
+    use domain::repositories::email_repository::InMemoryEmailRepository;
+    use domain::repositories::id_provider::SimpleIdProvider;
+    use domain::repositories::user_repository::InMemoryUserRepository;
+
+    #[tokio::test]
+    async fn test_handle_unverified() {
+        // Given
+        let user_repository = InMemoryUserRepository::new();
+        let user = user_repository.save(User::new("alice".to_owned())).await.unwrap();
+        let email_repository = InMemoryEmailRepository::new();
+
+        let email_service = EmailService::new(email_repository.clone(), user_repository.clone(), SimpleIdProvider::new());
+        let pending = email_service.add(user.id, "alice@example.com").await.unwrap();
+
+        let handler = SetPrimaryEmailCommandHandler::new(email_repository, user_repository, SimpleIdProvider::new());
+
+        // When
+        let result = handler.handle(SetPrimaryEmailCommand::new(user.id, pending.email.id)).await;
+
+        // Then
+        assert!(matches!(result, Err(AppStatus::BadRequest(_))));
+    }
+}
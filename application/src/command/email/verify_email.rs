@@ -0,0 +1,118 @@
+use crate::command::email::map_email_error;
+use crate::command::{Command, CommandHandler};
+use crate::shared::error::AppStatus;
+use async_trait::async_trait;
+use domain::repositories::email_repository::EmailRepository;
+use domain::repositories::id_provider::IdProvider;
+use domain::repositories::user_repository::UserRepository;
+use domain::services::email_service::EmailService;
+use domain::services::otp_hasher::OtpHasher;
+use domain::services::session_rotator::SessionRotator;
+use domain::views::email_view::{EmailChangeView, EmailView};
+use std::sync::Arc;
+
+/// Confirms an address of the user with the code mailed by `AddEmailCommand`
+#[derive(Debug, Clone)]
+pub struct VerifyEmailCommand {
+    user_id: i64,
+    email_id: i64,
+    code: String,
+    session: Option<String>,
+}
+
+impl VerifyEmailCommand {
+    pub fn new(user_id: i64, email_id: i64, code: String) -> Self {
+        Self { user_id, email_id, code, session: None }
+    }
+
+    /// Session the change is made in, it is rotated afterwards
+    pub fn with_session(mut self, session: String) -> Self {
+        self.session = Some(session);
+        self
+    }
+}
+
+impl Command<EmailChangeView<EmailView>> for VerifyEmailCommand {}
+
+pub struct VerifyEmailCommandHandler<ER, UR, IP>
+where
+    ER: EmailRepository + Sync + Send,
+    UR: UserRepository + Sync + Send,
+    IP: IdProvider + Sync + Send,
+{
+    email_service: EmailService<ER, UR, IP>,
+}
+
+impl<ER, UR, IP> VerifyEmailCommandHandler<ER, UR, IP>
+where
+    ER: EmailRepository + Sync + Send,
+    UR: UserRepository + Sync + Send,
+    IP: IdProvider + Sync + Send,
+{
+    pub fn new(email_repository: ER, user_repository: UR, id_provider: IP) -> Self {
+        Self {
+            email_service: EmailService::new(email_repository, user_repository, id_provider),
+        }
+    }
+
+    /// Must be the same hasher the `AddEmailCommandHandler` uses
+    pub fn with_otp_hasher(mut self, otp_hasher: OtpHasher) -> Self {
+        self.email_service = self.email_service.with_otp_hasher(otp_hasher);
+        self
+    }
+
+    /// Rotates the session given with `with_session` once the change went through
+    pub fn with_session_rotator(mut self, session_rotator: Arc<dyn SessionRotator>) -> Self {
+        self.email_service = self.email_service.with_session_rotator(session_rotator);
+        self
+    }
+}
+
+#[async_trait]
+impl<ER, UR, IP> CommandHandler<VerifyEmailCommand, EmailChangeView<EmailView>> for VerifyEmailCommandHandler<ER, UR, IP>
+where
+    ER: EmailRepository + Sync + Send,
+    UR: UserRepository + Sync + Send,
+    IP: IdProvider + Sync + Send,
+{
+    async fn handle(&self, command: VerifyEmailCommand) -> Result<EmailChangeView<EmailView>, AppStatus> {
+        let email = self.email_service.verify(command.user_id, command.email_id, &command.code).await
+            .map_err(map_email_error)?;
+        let session = self.email_service.rotate_session(command.session.as_deref()).await;
+
+        Ok(EmailChangeView { change: email, session })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use domain::models::user::User;
+    use domain::repositories::email_repository::InMemoryEmailRepository;
+    use domain::repositories::id_provider::SimpleIdProvider;
+    use domain::repositories::user_repository::InMemoryUserRepository;
+
+    #[tokio::test]
+    async fn test_handle() {
+        // Given
+        let user_repository = InMemoryUserRepository::new();
+        let user = user_repository.save(User::new("alice".to_owned())).await.unwrap();
+        let email_repository = InMemoryEmailRepository::new();
+        let otp_hasher = OtpHasher::new(b"secret");
+
+        let email_service = EmailService::new(email_repository.clone(), user_repository.clone(), SimpleIdProvider::new())
+            .with_otp_hasher(otp_hasher.clone());
+        let pending = email_service.add(user.id, "alice@example.com").await.unwrap();
+
+        let handler = VerifyEmailCommandHandler::new(email_repository, user_repository, SimpleIdProvider::new())
+            .with_otp_hasher(otp_hasher);
+
+        // When
+        let wrong = handler.handle(VerifyEmailCommand::new(user.id, pending.email.id, "wrong".to_owned())).await;
+        let verified = handler.handle(VerifyEmailCommand::new(user.id, pending.email.id, pending.code)).await;
+
+        // Then
+        assert!(matches!(wrong, Err(AppStatus::BadRequest(_))));
+        assert!(verified.unwrap().change.is_primary);
+    }
+}
@@ -1,14 +1,25 @@
 use crate::shared::error::AppStatus;
 use async_trait::async_trait;
 
+pub mod avatar;
+pub mod email;
 pub mod user;
 
-pub trait Command<REQUEST> {}
+pub trait Command<REQUEST> {
+    /// Whether running the command twice leaves the same state as running it once. Only such
+    /// commands are run again by `RetryBehavior`, and only they are copied for every run.
+    const IDEMPOTENT: bool = false;
+
+    /// Checks the input before the command reaches its handler, see `ValidationBehavior`
+    fn validate(&self) -> Result<(), AppStatus> {
+        Ok(())
+    }
+}
 
 #[async_trait]
 pub trait CommandHandler<REQUEST, RESPONSE>
 where
     REQUEST: Command<RESPONSE>,
 {
-    async fn handle(&mut self, command: REQUEST) -> Result<RESPONSE, AppStatus>;
+    async fn handle(&self, command: REQUEST) -> Result<RESPONSE, AppStatus>;
 }
\ No newline at end of file
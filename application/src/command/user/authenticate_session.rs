@@ -0,0 +1,68 @@
+use crate::command::{Command, CommandHandler};
+use crate::shared::error::AppStatus;
+use async_trait::async_trait;
+use domain::repositories::id_provider::IdProvider;
+use domain::repositories::otp_repository::OtpRepository;
+use domain::repositories::session_repository::SessionRepository;
+use domain::repositories::user_repository::UserRepository;
+use domain::services::user_service::{SessionPolicy, UserService};
+use domain::views::session_view::SessionView;
+
+/// Resolves the value of a session cookie to the session and its user, extending the session
+#[derive(Debug, Clone)]
+pub struct AuthenticateSessionCommand {
+    value: String,
+}
+
+impl AuthenticateSessionCommand {
+    pub fn new(value: String) -> Self {
+        Self { value }
+    }
+}
+
+impl Command<SessionView> for AuthenticateSessionCommand {
+    const IDEMPOTENT: bool = true;
+}
+
+pub struct AuthenticateSessionCommandHandler<UR, SR, OR, IP>
+where
+    UR: UserRepository + Sync + Send,
+    SR: SessionRepository + Sync + Send,
+    OR: OtpRepository + Sync + Send,
+    IP: IdProvider + Sync + Send,
+{
+    user_service: UserService<UR, SR, OR, IP>,
+}
+
+impl<UR, SR, OR, IP> AuthenticateSessionCommandHandler<UR, SR, OR, IP>
+where
+    UR: UserRepository + Sync + Send,
+    SR: SessionRepository + Sync + Send,
+    OR: OtpRepository + Sync + Send,
+    IP: IdProvider + Sync + Send,
+{
+    pub fn new(user_repository: UR, session_repository: SR, otp_repository: OR, id_provider: IP) -> Self {
+        Self {
+            user_service: UserService::new(user_repository, session_repository, otp_repository, id_provider),
+        }
+    }
+
+    pub fn with_session_policy(mut self, session_policy: SessionPolicy) -> Self {
+        self.user_service = self.user_service.with_session_policy(session_policy);
+        self
+    }
+}
+
+#[async_trait]
+impl<UR, SR, OR, IP> CommandHandler<AuthenticateSessionCommand, SessionView> for AuthenticateSessionCommandHandler<UR, SR, OR, IP>
+where
+    UR: UserRepository + Sync + Send,
+    SR: SessionRepository + Sync + Send,
+    OR: OtpRepository + Sync + Send,
+    IP: IdProvider + Sync + Send,
+{
+    async fn handle(&self, command: AuthenticateSessionCommand) -> Result<SessionView, AppStatus> {
+        self.user_service.authenticate(&command.value).await
+            .ok_or_else(|| AppStatus::AuthError("Session is invalid or expired".to_owned()))
+    }
+}
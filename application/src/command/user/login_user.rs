@@ -2,136 +2,220 @@ use crate::command::{Command, CommandHandler};
 use crate::shared::error::AppStatus;
 use crate::shared::error::AppStatus::{AuthError, BadRequest};
 use async_trait::async_trait;
-use domain::models::session::Session;
+use chrono::{DateTime, Utc};
+use domain::models::outbox::MailContent;
+use domain::repositories::email_repository::EmailRepository;
 use domain::repositories::id_provider::IdProvider;
 use domain::repositories::otp_repository::OtpRepository;
 use domain::repositories::session_repository::SessionRepository;
+use domain::repositories::unit_of_work::{Transaction, UnitOfWork};
 use domain::repositories::user_repository::UserRepository;
-use domain::services::mail_service::MailService;
-use domain::services::user_service::UserService;
+use domain::services::otp_hasher::OtpHasher;
+use domain::services::event_publisher::EventPublisher;
+use domain::services::user_service::{LoginPolicy, OtpValidationError, SessionPolicy, UserService};
 use domain::views::session_view::SessionView;
 use domain::views::user_view::UserView;
-use log::info;
+use std::sync::Arc;
+
+/// Greets new users once they signed in for the first time. The login is the address they
+/// signed up with.
+fn welcome_mail() -> MailContent {
+    MailContent {
+        subject: "Welcome to Avatars".to_owned(),
+        html_body: "<p>Welcome! Upload an avatar and it will show up wherever your address is used.</p>".to_owned(),
+        plain_body: "Welcome! Upload an avatar and it will show up wherever your address is used.".to_owned(),
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct LoginUserCommand {
     login: String,
     otp: Option<String>,
+    user_agent: Option<String>,
+    ip_address: Option<String>,
 }
 
 impl LoginUserCommand {
     pub fn new(username: String, otp: Option<String>) -> Self {
-        Self { login: username, otp }
+        Self { login: username, otp, user_agent: None, ip_address: None }
+    }
+
+    /// Client signing in, remembered with the session so the user can recognise their devices
+    pub fn with_client(mut self, user_agent: Option<String>, ip_address: Option<String>) -> Self {
+        self.user_agent = user_agent;
+        self.ip_address = ip_address;
+        self
     }
 }
 
-impl Command<SessionView> for LoginUserCommand {}
+/// Outcome of a sign-in step. Signing in without an OTP mails one, signing in with it starts
+/// the session.
+#[derive(Debug)]
+pub enum LoginResponse {
+    /// A code was mailed, or one mailed before `resend_after` is still pending
+    OtpSent { expires_at: DateTime<Utc>, resend_after: DateTime<Utc> },
+    LoggedIn(SessionView),
+}
+
+impl Command<LoginResponse> for LoginUserCommand {
+    fn validate(&self) -> Result<(), AppStatus> {
+        if self.login.is_empty() {
+            return Err(BadRequest("Username is required".to_string()));
+        }
+
+        Ok(())
+    }
+}
 
-pub struct LoginUserCommandHandler<UR, SR, OR, IP, MS>
+pub struct LoginUserCommandHandler<UR, SR, OR, ER, IP, UW>
 where
     UR: UserRepository + Sync + Send,
     SR: SessionRepository + Sync + Send,
     OR: OtpRepository + Sync + Send,
-    IP: IdProvider + Sync + Send,
-    MS: MailService + Sync + Send,
+    ER: EmailRepository + Sync + Send,
+    IP: IdProvider + Clone + Sync + Send,
+    UW: UnitOfWork,
 {
     user_service: UserService<UR, SR, OR, IP>,
-    mail_service: MS,
+    email_repository: ER,
+    unit_of_work: UW,
 }
 
-impl<UR, SR, OR, IP, MS> LoginUserCommandHandler<UR, SR, OR, IP, MS>
+impl<UR, SR, OR, ER, IP, UW> LoginUserCommandHandler<UR, SR, OR, ER, IP, UW>
 where
     UR: UserRepository + Sync + Send,
     SR: SessionRepository + Sync + Send,
     OR: OtpRepository + Sync + Send,
-    IP: IdProvider + Sync + Send,
-    MS: MailService + Sync + Send,
+    ER: EmailRepository + Sync + Send,
+    IP: IdProvider + Clone + Sync + Send,
+    UW: UnitOfWork,
 {
-    pub fn new(user_repository: UR, session_repository: SR, otp_repository: OR, otp_id_provider: IP, mail_service: MS) -> Self {
-        let user_service = UserService::new(user_repository, session_repository, otp_repository, otp_id_provider);
+    pub fn new(user_repository: UR, session_repository: SR, otp_repository: OR, email_repository: ER, otp_id_provider: IP, unit_of_work: UW) -> Self {
+        let user_service = UserService::new(user_repository, session_repository, otp_repository, otp_id_provider)
+            .with_welcome_mail(welcome_mail());
 
         Self {
             user_service,
-            mail_service,
+            email_repository,
+            unit_of_work,
         }
     }
 
-    async fn process_user(&mut self, command: LoginUserCommand) -> Result<UserView, AppStatus> {
-        let user_result = self.user_service.find_by_login(&command.login).await;
+    pub fn with_login_policy(mut self, login_policy: LoginPolicy) -> Self {
+        self.user_service = self.user_service.with_login_policy(login_policy);
+        self
+    }
+
+    pub fn with_otp_hasher(mut self, otp_hasher: OtpHasher) -> Self {
+        self.user_service = self.user_service.with_otp_hasher(otp_hasher);
+        self
+    }
 
-        if let Ok(user) = user_result {
-            return Ok(user);
-        }
+    pub fn with_session_policy(mut self, session_policy: SessionPolicy) -> Self {
+        self.user_service = self.user_service.with_session_policy(session_policy);
+        self
+    }
 
-        match self.user_service.create(command.login).await {
-            Ok(user) => Ok(user),
-            Err(err) => {
-                info!("{}", err);
-                Err(AppStatus::InternalError(err))
-            }
+    pub fn with_event_publisher(mut self, event_publisher: Arc<dyn EventPublisher>) -> Self {
+        self.user_service = self.user_service.with_event_publisher(event_publisher);
+        self
+    }
+
+    /// Address the code is mailed to: the primary address once the user verified one, the login
+    /// until then. A login is not a verified address, but its code only ever signs in to the
+    /// account of that login, so mailing it discloses nothing to a stranger owning the address.
+    async fn otp_address(&self, user: &UserView) -> String {
+        let primary = match user.primary_email_id {
+            Some(id) => self.email_repository.find_by_id(id).await,
+            None => None,
+        };
+
+        primary
+            .filter(|email| email.is_verified && email.user_id == user.id)
+            .map_or_else(|| user.username.clone(), |email| email.value)
+    }
+
+    /// Creates the user on their first sign-in and issues them an OTP in one transaction, so
+    /// that a failure never leaves a user behind that was never sent a code
+    async fn request_otp(&self, login: &str) -> Result<LoginResponse, AppStatus> {
+        let transaction = self.unit_of_work.begin().await
+            .map_err(|err| AppStatus::InternalError(format!("Failed to begin transaction: {}", err)))?;
+        let (user_service, events) = self.user_service.in_transaction(&transaction);
+
+        let user_view = user_service.find_or_create(login).await.map_err(AppStatus::InternalError)?;
+
+        if let Some(until) = user_service.locked_until(&user_view.username).await.map_err(AppStatus::InternalError)? {
+            return Err(AppStatus::TooManyAttempts(format!("Account locked until {}", until)));
         }
+
+        // The mail goes out through the outbox, written together with the code
+        let address = self.otp_address(&user_view).await;
+        let sent = match user_service.issue_otp(user_view.id, &address).await {
+            Ok(sent) => sent,
+            Err(err) => return Err(AppStatus::InternalError(format!("Failed to save OTP: {}", err))),
+        };
+
+        transaction.commit().await
+            .map_err(|err| AppStatus::InternalError(format!("Failed to commit transaction: {}", err)))?;
+        events.flush().await;
+
+        Ok(LoginResponse::OtpSent { expires_at: sent.expires_at, resend_after: sent.resend_after })
     }
 }
 
 #[async_trait]
-impl<UR, SR, OR, IP, MS> CommandHandler<LoginUserCommand, SessionView> for LoginUserCommandHandler<UR, SR, OR, IP, MS>
+impl<UR, SR, OR, ER, IP, UW> CommandHandler<LoginUserCommand, LoginResponse> for LoginUserCommandHandler<UR, SR, OR, ER, IP, UW>
 where
     UR: UserRepository + Sync + Send,
     SR: SessionRepository + Sync + Send,
     OR: OtpRepository + Sync + Send,
-    IP: IdProvider + Sync + Send,
-    MS: MailService + Sync + Send,
+    ER: EmailRepository + Sync + Send,
+    IP: IdProvider + Clone + Sync + Send,
+    UW: UnitOfWork,
 {
-    async fn handle(&mut self, command: LoginUserCommand) -> Result<SessionView, AppStatus> {
-        if command.login.is_empty() {
-            return Err(BadRequest("Username is required".to_string()));
-        }
-
-        let user_view = match self.process_user(command.clone()).await {
-            Ok(u) => u,
-            Err(err) => return Err(err),
+    async fn handle(&self, command: LoginUserCommand) -> Result<LoginResponse, AppStatus> {
+        let Some(otp_code) = command.otp else {
+            return self.request_otp(&command.login).await;
         };
 
-        if command.otp.is_none() {
-            let otp_view = match self.user_service.save_otp(user_view.id).await {
-                Ok(otp) => otp,
-                Err(err) => {
-                    return Err(AppStatus::InternalError(format!("Failed to save OTP: {}", err)));
-                }
-            };
-
-            if let Err(err) = self.mail_service.send_otp(&user_view.username, otp_view).await {
-                return Err(AppStatus::InternalError(format!("Failed to send OTP: {}", err)));
-            }
+        let user_view = self.user_service.find_or_create(&command.login).await.map_err(AppStatus::InternalError)?;
 
-            return Err(AppStatus::Ok("OTP sent to email".to_string()));
+        if let Some(until) = self.user_service.locked_until(&user_view.username).await.map_err(AppStatus::InternalError)? {
+            return Err(AppStatus::TooManyAttempts(format!("Account locked until {}", until)));
         }
 
-        let otp_code = command.otp.unwrap();
-
         match self.user_service.validate_otp(&user_view.username, &otp_code).await {
             Ok(u) => u,
-            Err(err) => return Err(AuthError(err)),
+            Err(OtpValidationError::InternalError(err)) => return Err(AppStatus::InternalError(err)),
+            Err(err @ OtpValidationError::TooManyAttempts(_)) => return Err(AppStatus::TooManyAttempts(err.to_string())),
+            Err(err) => return Err(AuthError(err.to_string())),
         };
 
-        let session = match self.user_service.generate_session(user_view.username.as_str()).await {
+        let session = match self.user_service.generate_session(user_view.username.as_str(), command.user_agent, command.ip_address).await {
             Ok(s) => s,
             Err(err) => return Err(AppStatus::InternalError(format!("Failed to generate session: {}", err))),
         };
 
-        Ok(session)
+        Ok(LoginResponse::LoggedIn(session))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::mediator::Mediator;
+    use crate::pipeline::validation::ValidationBehavior;
+    use domain::models::email_address::Email;
+    use domain::models::user::User;
+    use domain::repositories::email_repository::InMemoryEmailRepository;
     use domain::repositories::id_provider::SimpleIdProvider;
     use domain::repositories::otp_repository::InMemoryOtpRepository;
     use domain::repositories::session_repository::InMemorySessionRepository;
+    use domain::repositories::unit_of_work::InMemoryUnitOfWork;
     use domain::repositories::user_repository::InMemoryUserRepository;
     use domain::repositories::OTP_LENGTH;
-    use domain::services::mail_service::InMemoryMailService;
+    use domain::models::outbox::OutboxPayload;
+    use domain::repositories::outbox_repository::InMemoryOutboxRepository;
 
     #[tokio::test]
     async fn test_handle_with_empty_username() {
@@ -140,13 +224,15 @@ mod tests {
         let sr = InMemorySessionRepository::new();
         let or = InMemoryOtpRepository::new();
         let ip = SimpleIdProvider::new();
-        let ms = InMemoryMailService::new();
+        let uw = InMemoryUnitOfWork::new(ur.clone(), sr.clone(), or.clone());
 
-        let mut handler = LoginUserCommandHandler::new(ur, sr, or, ip, ms);
+        let mut mediator = Mediator::new();
+        mediator.register_handler(LoginUserCommandHandler::new(ur, sr, or, InMemoryEmailRepository::new(), ip, uw));
+        mediator.register_behavior(ValidationBehavior);
         let command = LoginUserCommand::new("".to_string(), None);
 
         // When
-        let result = handler.handle(command).await;
+        let result = mediator.send(command).await;
 
         // Then
         assert!(matches!(result, Err(BadRequest(_))));
@@ -157,18 +243,48 @@ mod tests {
         // Given
         let ur = InMemoryUserRepository::new();
         let sr = InMemorySessionRepository::new();
-        let or = InMemoryOtpRepository::new();
+        let outbox = InMemoryOutboxRepository::new();
+        let or = InMemoryOtpRepository::new().with_outbox(outbox.clone());
         let ip = SimpleIdProvider::new();
-        let ms = InMemoryMailService::new();
+        let uw = InMemoryUnitOfWork::new(ur.clone(), sr.clone(), or.clone());
 
-        let mut handler = LoginUserCommandHandler::new(ur, sr, or, ip, ms);
+        let handler = LoginUserCommandHandler::new(ur, sr, or, InMemoryEmailRepository::new(), ip, uw);
         let command = LoginUserCommand::new("test_user".to_string(), None);
 
         // When
         let result = handler.handle(command).await;
 
         // Then
-        assert!(matches!(result, Err(AppStatus::Ok(_))));
+        assert!(matches!(result, Ok(LoginResponse::OtpSent { .. })));
+        assert!(outbox.messages().await.iter()
+            .any(|message| matches!(&message.payload, OutboxPayload::OtpMail { to, .. } if to == "test_user")));
+    }
+
+    #[tokio::test]
+    async fn test_handle_mails_otp_to_primary_address() {
+        // Given
+        let ur = InMemoryUserRepository::new();
+        let sr = InMemorySessionRepository::new();
+        let outbox = InMemoryOutboxRepository::new();
+        let or = InMemoryOtpRepository::new().with_outbox(outbox.clone());
+        let er = InMemoryEmailRepository::new();
+        let uw = InMemoryUnitOfWork::new(ur.clone(), sr.clone(), or.clone());
+
+        let mut user = ur.save(User::new("test_user".to_string())).await.unwrap();
+        let mut email = Email::new(user.id, "test@example.com".to_string());
+        email.is_verified = true;
+        user.primary_email_id = Some(er.save(email).await.unwrap().id);
+        ur.save(user).await.unwrap();
+
+        let handler = LoginUserCommandHandler::new(ur, sr, or, er, SimpleIdProvider::new(), uw);
+
+        // When
+        let result = handler.handle(LoginUserCommand::new("test_user".to_string(), None)).await;
+
+        // Then
+        assert!(matches!(result, Ok(LoginResponse::OtpSent { .. })));
+        assert!(outbox.messages().await.iter()
+            .any(|message| matches!(&message.payload, OutboxPayload::OtpMail { to, .. } if to == "test@example.com")));
     }
 
     #[derive(Debug, Clone)]
@@ -202,9 +318,9 @@ mod tests {
         let sr = InMemorySessionRepository::new();
         let or = InMemoryOtpRepository::new();
         let ip = TestIdProvider::new();
-        let ms = InMemoryMailService::new();
+        let uw = InMemoryUnitOfWork::new(ur.clone(), sr.clone(), or.clone());
 
-        let mut handler = LoginUserCommandHandler::new(ur, sr, or, ip.clone(), ms);
+        let handler = LoginUserCommandHandler::new(ur, sr, or, InMemoryEmailRepository::new(), ip.clone(), uw);
         let start_command = LoginUserCommand::new("test_user".to_string(), None);
         let command = LoginUserCommand::new("test_user".to_string(), Some(ip.get_numeric_id(OTP_LENGTH)));
 
@@ -213,6 +329,28 @@ mod tests {
         let result = handler.handle(command).await;
 
         // Then
-        assert!(matches!(result, Ok(_)));
+        assert!(matches!(result, Ok(LoginResponse::LoggedIn(_))));
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_handle_too_many_attempts() {
+        // Given
+        let ur = InMemoryUserRepository::new();
+        let sr = InMemorySessionRepository::new();
+        let or = InMemoryOtpRepository::new();
+        let ip = TestIdProvider::new();
+        let uw = InMemoryUnitOfWork::new(ur.clone(), sr.clone(), or.clone());
+        let policy = LoginPolicy { max_attempts: 1, ..LoginPolicy::default() };
+
+        let handler = LoginUserCommandHandler::new(ur, sr, or, InMemoryEmailRepository::new(), ip, uw).with_login_policy(policy);
+        let _ = handler.handle(LoginUserCommand::new("test_user".to_string(), None)).await;
+
+        // When
+        let wrong_otp = handler.handle(LoginUserCommand::new("test_user".to_string(), Some("99999999".to_string()))).await;
+        let new_otp = handler.handle(LoginUserCommand::new("test_user".to_string(), None)).await;
+
+        // Then
+        assert!(matches!(wrong_otp, Err(AppStatus::TooManyAttempts(_))));
+        assert!(matches!(new_otp, Err(AppStatus::TooManyAttempts(_))));
+    }
+}
@@ -0,0 +1,69 @@
+use crate::command::{Command, CommandHandler};
+use crate::shared::error::AppStatus;
+use async_trait::async_trait;
+use domain::repositories::id_provider::IdProvider;
+use domain::repositories::otp_repository::OtpRepository;
+use domain::repositories::session_repository::SessionRepository;
+use domain::repositories::user_repository::UserRepository;
+use domain::services::event_publisher::EventPublisher;
+use domain::services::user_service::UserService;
+use std::sync::Arc;
+
+/// Ends the session with the given value, the one the request was made with
+#[derive(Debug, Clone)]
+pub struct LogoutCommand {
+    value: String,
+}
+
+impl LogoutCommand {
+    pub fn new(value: String) -> Self {
+        Self { value }
+    }
+}
+
+impl Command<()> for LogoutCommand {
+    const IDEMPOTENT: bool = true;
+}
+
+pub struct LogoutCommandHandler<UR, SR, OR, IP>
+where
+    UR: UserRepository + Sync + Send,
+    SR: SessionRepository + Sync + Send,
+    OR: OtpRepository + Sync + Send,
+    IP: IdProvider + Sync + Send,
+{
+    user_service: UserService<UR, SR, OR, IP>,
+}
+
+impl<UR, SR, OR, IP> LogoutCommandHandler<UR, SR, OR, IP>
+where
+    UR: UserRepository + Sync + Send,
+    SR: SessionRepository + Sync + Send,
+    OR: OtpRepository + Sync + Send,
+    IP: IdProvider + Sync + Send,
+{
+    pub fn new(user_repository: UR, session_repository: SR, otp_repository: OR, id_provider: IP) -> Self {
+        Self {
+            user_service: UserService::new(user_repository, session_repository, otp_repository, id_provider),
+        }
+    }
+
+    pub fn with_event_publisher(mut self, event_publisher: Arc<dyn EventPublisher>) -> Self {
+        self.user_service = self.user_service.with_event_publisher(event_publisher);
+        self
+    }
+}
+
+#[async_trait]
+impl<UR, SR, OR, IP> CommandHandler<LogoutCommand, ()> for LogoutCommandHandler<UR, SR, OR, IP>
+where
+    UR: UserRepository + Sync + Send,
+    SR: SessionRepository + Sync + Send,
+    OR: OtpRepository + Sync + Send,
+    IP: IdProvider + Sync + Send,
+{
+    async fn handle(&self, command: LogoutCommand) -> Result<(), AppStatus> {
+        self.user_service.logout(&command.value).await
+            .map_err(|e| AppStatus::InternalError(format!("Failed to end session: {}", e)))
+    }
+}
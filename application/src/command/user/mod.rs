@@ -1 +1,5 @@
-pub mod login_user;
\ No newline at end of file
+pub mod authenticate_session;
+pub mod login_user;
+pub mod logout;
+pub mod revoke_all_sessions;
+pub mod rotate_session;
\ No newline at end of file
@@ -0,0 +1,70 @@
+use crate::command::{Command, CommandHandler};
+use crate::shared::error::AppStatus;
+use async_trait::async_trait;
+use domain::repositories::id_provider::IdProvider;
+use domain::repositories::otp_repository::OtpRepository;
+use domain::repositories::session_repository::SessionRepository;
+use domain::repositories::user_repository::UserRepository;
+use domain::services::event_publisher::EventPublisher;
+use domain::services::user_service::UserService;
+use std::sync::Arc;
+
+/// Signs the user out on every device, including the one making the request. Responds with
+/// the number of ended sessions.
+#[derive(Debug, Clone)]
+pub struct RevokeAllSessionsCommand {
+    user_id: i64,
+}
+
+impl RevokeAllSessionsCommand {
+    pub fn new(user_id: i64) -> Self {
+        Self { user_id }
+    }
+}
+
+impl Command<u64> for RevokeAllSessionsCommand {
+    const IDEMPOTENT: bool = true;
+}
+
+pub struct RevokeAllSessionsCommandHandler<UR, SR, OR, IP>
+where
+    UR: UserRepository + Sync + Send,
+    SR: SessionRepository + Sync + Send,
+    OR: OtpRepository + Sync + Send,
+    IP: IdProvider + Sync + Send,
+{
+    user_service: UserService<UR, SR, OR, IP>,
+}
+
+impl<UR, SR, OR, IP> RevokeAllSessionsCommandHandler<UR, SR, OR, IP>
+where
+    UR: UserRepository + Sync + Send,
+    SR: SessionRepository + Sync + Send,
+    OR: OtpRepository + Sync + Send,
+    IP: IdProvider + Sync + Send,
+{
+    pub fn new(user_repository: UR, session_repository: SR, otp_repository: OR, id_provider: IP) -> Self {
+        Self {
+            user_service: UserService::new(user_repository, session_repository, otp_repository, id_provider),
+        }
+    }
+
+    pub fn with_event_publisher(mut self, event_publisher: Arc<dyn EventPublisher>) -> Self {
+        self.user_service = self.user_service.with_event_publisher(event_publisher);
+        self
+    }
+}
+
+#[async_trait]
+impl<UR, SR, OR, IP> CommandHandler<RevokeAllSessionsCommand, u64> for RevokeAllSessionsCommandHandler<UR, SR, OR, IP>
+where
+    UR: UserRepository + Sync + Send,
+    SR: SessionRepository + Sync + Send,
+    OR: OtpRepository + Sync + Send,
+    IP: IdProvider + Sync + Send,
+{
+    async fn handle(&self, command: RevokeAllSessionsCommand) -> Result<u64, AppStatus> {
+        self.user_service.revoke_sessions(command.user_id).await
+            .map_err(|e| AppStatus::InternalError(format!("Failed to revoke sessions: {}", e)))
+    }
+}
@@ -0,0 +1,62 @@
+use crate::command::{Command, CommandHandler};
+use crate::shared::error::AppStatus;
+use async_trait::async_trait;
+use domain::repositories::id_provider::IdProvider;
+use domain::repositories::otp_repository::OtpRepository;
+use domain::repositories::session_repository::SessionRepository;
+use domain::repositories::user_repository::UserRepository;
+use domain::services::user_service::UserService;
+use domain::views::session_view::SessionView;
+
+/// Replaces the value of the current session after a privilege-sensitive action. Responds with
+/// the session under its new value, the old one stops working.
+#[derive(Debug, Clone)]
+pub struct RotateSessionCommand {
+    value: String,
+}
+
+impl RotateSessionCommand {
+    pub fn new(value: String) -> Self {
+        Self { value }
+    }
+}
+
+impl Command<SessionView> for RotateSessionCommand {}
+
+pub struct RotateSessionCommandHandler<UR, SR, OR, IP>
+where
+    UR: UserRepository + Sync + Send,
+    SR: SessionRepository + Sync + Send,
+    OR: OtpRepository + Sync + Send,
+    IP: IdProvider + Sync + Send,
+{
+    user_service: UserService<UR, SR, OR, IP>,
+}
+
+impl<UR, SR, OR, IP> RotateSessionCommandHandler<UR, SR, OR, IP>
+where
+    UR: UserRepository + Sync + Send,
+    SR: SessionRepository + Sync + Send,
+    OR: OtpRepository + Sync + Send,
+    IP: IdProvider + Sync + Send,
+{
+    pub fn new(user_repository: UR, session_repository: SR, otp_repository: OR, id_provider: IP) -> Self {
+        Self {
+            user_service: UserService::new(user_repository, session_repository, otp_repository, id_provider),
+        }
+    }
+}
+
+#[async_trait]
+impl<UR, SR, OR, IP> CommandHandler<RotateSessionCommand, SessionView> for RotateSessionCommandHandler<UR, SR, OR, IP>
+where
+    UR: UserRepository + Sync + Send,
+    SR: SessionRepository + Sync + Send,
+    OR: OtpRepository + Sync + Send,
+    IP: IdProvider + Sync + Send,
+{
+    async fn handle(&self, command: RotateSessionCommand) -> Result<SessionView, AppStatus> {
+        self.user_service.rotate_session(&command.value).await
+            .map_err(AppStatus::AuthError)
+    }
+}
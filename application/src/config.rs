@@ -0,0 +1,13 @@
+use domain::services::user_service::{LoginPolicy, SessionPolicy};
+
+/// Tunables applied to the handlers built by `build_mediator`
+#[derive(Debug, Clone, Default)]
+pub struct AppConfig {
+    pub login_policy: LoginPolicy,
+    pub session_policy: SessionPolicy,
+    /// Key for hashing OTPs and sealing them in the outbox. A random key is generated on
+    /// startup when not set.
+    pub otp_secret: Option<String>,
+    /// Base URL of the site, used for links in mails
+    pub public_url: Option<String>,
+}
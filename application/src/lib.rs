@@ -1,77 +1,316 @@
 pub mod command;
+pub mod config;
 pub mod shared;
 pub mod mediator;
+pub mod notification;
+pub mod pipeline;
+pub mod query;
 
 use crate::command::Command;
+use crate::config::AppConfig;
 use crate::mediator::Mediator;
+use crate::notification::{Dispatch, EventBus};
+use crate::pipeline::metrics::{CommandStats, MetricsBehavior};
+use crate::query::Query;
 use crate::shared::error::AppStatus;
+use domain::repositories::avatar_repository::AvatarRepository;
+use domain::repositories::blob_store::BlobStore;
+use domain::repositories::email_repository::EmailRepository;
 use domain::repositories::id_provider::IdProvider;
 use domain::repositories::otp_repository::OtpRepository;
 use domain::repositories::session_repository::SessionRepository;
+use domain::repositories::unit_of_work::UnitOfWork;
 use domain::repositories::user_repository::UserRepository;
-use domain::services::mail_service::MailService;
+use domain::services::otp_hasher::OtpHasher;
+use domain::services::session_rotator::SessionRotator;
+use domain::services::user_service::UserService;
+use std::collections::BTreeMap;
+use std::sync::Arc;
 
 pub struct AppContainer {
     mediator: Mediator,
+    otp_hasher: OtpHasher,
+    metrics: MetricsBehavior,
 }
 
 impl AppContainer {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
-        user_repository: impl UserRepository + Sync + Send + 'static,
-        session_repository: impl SessionRepository + Sync + Send + 'static,
-        otp_repository: impl OtpRepository + Sync + Send + 'static,
-        id_provider: impl IdProvider + Sync + Send + 'static,
-        mail_service: impl MailService + Sync + Send + 'static,
+        user_repository: impl UserRepository + Clone + Sync + Send + 'static,
+        session_repository: impl SessionRepository + Clone + 'static,
+        otp_repository: impl OtpRepository + Clone + Sync + Send + 'static,
+        id_provider: impl IdProvider + Clone + Sync + Send + 'static,
+        avatar_repository: impl AvatarRepository + Clone + Sync + Send + 'static,
+        blob_store: impl BlobStore + Clone + Sync + Send + 'static,
+        email_repository: impl EmailRepository + Clone + Sync + Send + 'static,
+        unit_of_work: impl UnitOfWork + 'static,
     ) -> Self {
+        Self::with_config(
+            user_repository,
+            session_repository,
+            otp_repository,
+            id_provider,
+            avatar_repository,
+            blob_store,
+            email_repository,
+            unit_of_work,
+            AppConfig::default(),
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn with_config(
+        user_repository: impl UserRepository + Clone + Sync + Send + 'static,
+        session_repository: impl SessionRepository + Clone + 'static,
+        otp_repository: impl OtpRepository + Clone + Sync + Send + 'static,
+        id_provider: impl IdProvider + Clone + Sync + Send + 'static,
+        avatar_repository: impl AvatarRepository + Clone + Sync + Send + 'static,
+        blob_store: impl BlobStore + Clone + Sync + Send + 'static,
+        email_repository: impl EmailRepository + Clone + Sync + Send + 'static,
+        unit_of_work: impl UnitOfWork + 'static,
+        config: AppConfig,
+    ) -> Self {
+        // Shared so that codes issued by one handler verify in another, and so that the outbox
+        // dispatcher can open the codes sealed into mails
+        let otp_hasher = match &config.otp_secret {
+            Some(secret) => OtpHasher::new(secret.as_bytes()),
+            None => OtpHasher::random(),
+        };
+
+        let metrics = MetricsBehavior::new();
+
         let mediator = build_mediator(
             user_repository,
             session_repository,
             otp_repository,
             id_provider,
-            mail_service,
+            avatar_repository,
+            blob_store,
+            email_repository,
+            unit_of_work,
+            otp_hasher.clone(),
+            metrics.clone(),
+            config,
         );
 
-        Self { mediator }
+        Self { mediator, otp_hasher, metrics }
     }
 
     pub fn new_from_mediator(mediator: Mediator) -> Self {
-        Self { mediator }
+        Self { mediator, otp_hasher: OtpHasher::random(), metrics: MetricsBehavior::new() }
     }
 
     pub async fn send_command<REQUEST, RESPONSE>(&self, command: REQUEST) -> Result<RESPONSE, AppStatus>
     where
-        REQUEST: Command<RESPONSE> + 'static,
-        RESPONSE: 'static,
+        REQUEST: Command<RESPONSE> + Clone + Send + Sync + 'static,
+        RESPONSE: Send + 'static,
     {
         self.mediator.send::<REQUEST, RESPONSE>(command).await
     }
+
+    pub async fn query<REQUEST, RESPONSE>(&self, query: REQUEST) -> Result<RESPONSE, AppStatus>
+    where
+        REQUEST: Query<RESPONSE> + 'static,
+        RESPONSE: 'static,
+    {
+        self.mediator.query::<REQUEST, RESPONSE>(query).await
+    }
+
+    /// Bus the notification handlers are subscribed to, for events delivered from outside the
+    /// mediator such as those in the outbox
+    pub fn event_bus(&self) -> Arc<EventBus> {
+        self.mediator.event_bus()
+    }
+
+    /// Hasher the OTPs and verification codes are issued with, for the outbox dispatcher to
+    /// open the codes in the mails it delivers
+    pub fn otp_hasher(&self) -> OtpHasher {
+        self.otp_hasher.clone()
+    }
+
+    /// Outcomes of the commands sent so far, keyed by command name
+    pub async fn command_metrics(&self) -> BTreeMap<&'static str, CommandStats> {
+        self.metrics.snapshot().await
+    }
 }
 
-pub fn build_mediator<UR, SR, OR, IP, MS>(
+#[allow(clippy::too_many_arguments)]
+pub fn build_mediator<UR, SR, OR, IP, AR, BS, ER, UW>(
     user_repository: UR,
     session_repository: SR,
     otp_repository: OR,
     id_provider: IP,
-    mail_service: MS,
+    avatar_repository: AR,
+    blob_store: BS,
+    email_repository: ER,
+    unit_of_work: UW,
+    otp_hasher: OtpHasher,
+    metrics: MetricsBehavior,
+    config: AppConfig,
 ) -> Mediator
 where
-    UR: UserRepository + Sync + Send + 'static,
-    SR: SessionRepository + Sync + Send + 'static,
-    OR: OtpRepository + Sync + Send + 'static,
-    IP: IdProvider + Sync + Send + 'static,
-    MS: MailService + Sync + Send + 'static,
+    UR: UserRepository + Clone + Sync + Send + 'static,
+    SR: SessionRepository + Clone + 'static,
+    OR: OtpRepository + Clone + Sync + Send + 'static,
+    IP: IdProvider + Clone + Sync + Send + 'static,
+    AR: AvatarRepository + Clone + Sync + Send + 'static,
+    BS: BlobStore + Clone + Sync + Send + 'static,
+    ER: EmailRepository + Clone + Sync + Send + 'static,
+    UW: UnitOfWork + 'static,
 {
+    let mut mediator = Mediator::new();
+
+    // Domain events raised by the services are published through the mediator
+    let event_bus = mediator.event_bus();
+
     let login_ch = command::user::login_user::LoginUserCommandHandler::new(
-        user_repository,
+        user_repository.clone(),
+        session_repository.clone(),
+        otp_repository.clone(),
+        email_repository.clone(),
+        id_provider.clone(),
+        unit_of_work,
+    )
+        .with_login_policy(config.login_policy)
+        .with_session_policy(config.session_policy.clone())
+        .with_otp_hasher(otp_hasher.clone())
+        .with_event_publisher(event_bus.clone());
+
+    let authenticate_session_ch = command::user::authenticate_session::AuthenticateSessionCommandHandler::new(
+        user_repository.clone(),
+        session_repository.clone(),
+        otp_repository.clone(),
+        id_provider.clone(),
+    ).with_session_policy(config.session_policy);
+
+    // Address changes rotate the session they are made in
+    let session_rotator: Arc<dyn SessionRotator> = Arc::new(UserService::new(
+        user_repository.clone(),
+        session_repository.clone(),
+        otp_repository.clone(),
+        id_provider.clone(),
+    ));
+
+    let rotate_session_ch = command::user::rotate_session::RotateSessionCommandHandler::new(
+        user_repository.clone(),
+        session_repository.clone(),
+        otp_repository.clone(),
+        id_provider.clone(),
+    );
+
+    let logout_ch = command::user::logout::LogoutCommandHandler::new(
+        user_repository.clone(),
+        session_repository.clone(),
+        otp_repository.clone(),
+        id_provider.clone(),
+    ).with_event_publisher(event_bus.clone());
+
+    let revoke_all_sessions_ch = command::user::revoke_all_sessions::RevokeAllSessionsCommandHandler::new(
+        user_repository.clone(),
+        session_repository.clone(),
+        otp_repository.clone(),
+        id_provider.clone(),
+    ).with_event_publisher(event_bus);
+
+    let list_sessions_qh = query::user::list_sessions::ListSessionsQueryHandler::new(
+        user_repository.clone(),
+        session_repository.clone(),
+        otp_repository.clone(),
+        id_provider.clone(),
+    );
+
+    let get_profile_qh = query::user::get_profile::GetProfileQueryHandler::new(
+        user_repository.clone(),
         session_repository,
         otp_repository,
+        id_provider.clone(),
+    );
+
+    let list_emails_qh = query::email::list_emails::ListEmailsQueryHandler::new(
+        email_repository.clone(),
+        user_repository.clone(),
+        id_provider.clone(),
+    );
+
+    let add_email_ch = command::email::add_email::AddEmailCommandHandler::new(
+        email_repository.clone(),
+        user_repository.clone(),
+        id_provider.clone(),
+    )
+        .with_otp_hasher(otp_hasher.clone())
+        .with_public_url(config.public_url.unwrap_or_default())
+        .with_session_rotator(session_rotator.clone());
+
+    let verify_email_ch = command::email::verify_email::VerifyEmailCommandHandler::new(
+        email_repository.clone(),
+        user_repository.clone(),
+        id_provider.clone(),
+    ).with_otp_hasher(otp_hasher).with_session_rotator(session_rotator.clone());
+
+    let remove_email_ch = command::email::remove_email::RemoveEmailCommandHandler::new(
+        email_repository.clone(),
+        user_repository.clone(),
+        id_provider.clone(),
+    ).with_session_rotator(session_rotator.clone());
+
+    let set_primary_email_ch = command::email::set_primary_email::SetPrimaryEmailCommandHandler::new(
+        email_repository.clone(),
+        user_repository.clone(),
+        id_provider.clone(),
+    ).with_session_rotator(session_rotator);
+
+    let assign_avatar_to_email_ch = command::email::assign_avatar_to_email::AssignAvatarToEmailCommandHandler::new(
+        email_repository.clone(),
+        user_repository.clone(),
+        avatar_repository.clone(),
+        blob_store.clone(),
+        id_provider.clone(),
+    );
+
+    let list_avatars_qh = query::avatar::list_avatars::ListAvatarsQueryHandler::new(
+        avatar_repository.clone(),
+        blob_store.clone(),
+        id_provider.clone(),
+    );
+
+    let upload_avatar_ch = command::avatar::upload_avatar::UploadAvatarCommandHandler::new(
+        avatar_repository.clone(),
+        blob_store.clone(),
+        id_provider.clone(),
+    );
+
+    let get_avatar_qh = query::avatar::get_avatar::GetAvatarQueryHandler::new(
+        user_repository,
+        email_repository,
+        avatar_repository,
+        blob_store,
         id_provider,
-        mail_service,
     );
 
-    let mut mediator = Mediator::new();
+    mediator.register_behavior(pipeline::logging::LoggingBehavior);
+    mediator.register_behavior(metrics);
+    mediator.register_behavior(pipeline::retry::RetryBehavior::default());
+    mediator.register_behavior(pipeline::validation::ValidationBehavior);
 
     mediator.register_handler(login_ch);
+    mediator.register_handler(authenticate_session_ch);
+    mediator.register_handler(rotate_session_ch);
+    mediator.register_handler(logout_ch);
+    mediator.register_handler(revoke_all_sessions_ch);
+    mediator.register_handler(add_email_ch);
+    mediator.register_handler(verify_email_ch);
+    mediator.register_handler(remove_email_ch);
+    mediator.register_handler(set_primary_email_ch);
+    mediator.register_handler(assign_avatar_to_email_ch);
+    mediator.register_handler(upload_avatar_ch);
+
+    mediator.register_query_handler(list_sessions_qh);
+    mediator.register_query_handler(get_profile_qh);
+    mediator.register_query_handler(list_emails_qh);
+    mediator.register_query_handler(list_avatars_qh);
+    mediator.register_query_handler(get_avatar_qh);
+
+    mediator.register_notification_handler(notification::audit_log::AuditLogHandler, Dispatch::Await);
 
     mediator
 }
@@ -80,26 +319,20 @@ where
 mod tests {
     use super::*;
     use crate::command::user::login_user::LoginUserCommand;
-    use crate::command::CommandHandler;
-    use async_trait::async_trait;
+    use domain::repositories::avatar_repository::InMemoryAvatarRepository;
+    use domain::repositories::blob_store::InMemoryBlobStore;
+    use domain::repositories::email_repository::InMemoryEmailRepository;
     use domain::repositories::id_provider::SimpleIdProvider;
     use domain::repositories::otp_repository::InMemoryOtpRepository;
     use domain::repositories::session_repository::InMemorySessionRepository;
+    use domain::repositories::unit_of_work::InMemoryUnitOfWork;
     use domain::repositories::user_repository::InMemoryUserRepository;
-    use domain::services::mail_service::InMemoryMailService;
 
+    #[derive(Clone)]
     struct TestCommand;
-    struct TestResponse(pub String);
+    struct TestResponse;
     impl Command<TestResponse> for TestCommand {}
 
-    struct TestCommandHandler;
-    #[async_trait]
-    impl CommandHandler<TestCommand, TestResponse> for TestCommandHandler {
-        async fn handle(&mut self, _command: TestCommand) -> Result<TestResponse, AppStatus> {
-            Ok(TestResponse("Command result".to_string()))
-        }
-    }
-
     #[tokio::test]
     async fn test_send_command_without_handler() {
         // Given
@@ -120,14 +353,18 @@ mod tests {
         let session_repository = InMemorySessionRepository::new();
         let otp_repository = InMemoryOtpRepository::new();
         let id_provider = SimpleIdProvider::new();
-        let mail_service = InMemoryMailService::new();
+
+        let unit_of_work = InMemoryUnitOfWork::new(user_repository.clone(), session_repository.clone(), otp_repository.clone());
 
         let app_container = AppContainer::new(
             user_repository,
             session_repository,
             otp_repository,
             id_provider,
-            mail_service,
+            InMemoryAvatarRepository::new(),
+            InMemoryBlobStore::new(),
+            InMemoryEmailRepository::new(),
+            unit_of_work,
         );
 
         let command = LoginUserCommand::new("user".to_string(), Some("password".to_string()));
@@ -136,9 +373,6 @@ mod tests {
         let response = app_container.send_command(command).await;
 
         // Then
-        match response {
-            Err(AppStatus::AuthError(_)) => { assert!(true) }
-            _ => { assert!(false); }
-        }
+        assert!(matches!(response, Err(AppStatus::AuthError(_))));
     }
 }
\ No newline at end of file
@@ -1,18 +1,36 @@
 use crate::command::{Command, CommandHandler};
+use crate::notification::{Dispatch, EventBus, Notification, NotificationHandler};
+use crate::pipeline::{BoxedResponse, CommandRef, Next, PipelineBehavior};
+use crate::query::{Query, QueryHandler};
 use crate::shared::error::AppStatus;
 use std::any::{Any, TypeId};
 use std::collections::HashMap;
-use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, PoisonError};
 
-/// The Mediator struct manages command handlers associated with command types.
+/// The Mediator struct manages command and query handlers associated with their request types.
 pub struct Mediator {
     handlers: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
+    query_handlers: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
+    behaviors: Vec<Arc<dyn PipelineBehavior>>,
+    event_bus: Arc<EventBus>,
+}
+
+impl Default for Mediator {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Mediator {
     pub fn new() -> Self {
-        Self { handlers: HashMap::new() }
+        Self {
+            handlers: HashMap::new(),
+            query_handlers: HashMap::new(),
+            behaviors: Vec::new(),
+            event_bus: Arc::new(EventBus::new()),
+        }
     }
 
     /// Registers a handler for a specific command type
@@ -25,12 +43,23 @@ impl Mediator {
         REQUEST: Command<RESPONSE> + 'static,
         H: CommandHandler<REQUEST, RESPONSE> + Send + Sync + 'static,
     {
-        let handler: Arc<Mutex<dyn CommandHandler<REQUEST, RESPONSE> + Send + Sync>> = Arc::new(Mutex::new(handler));
+        let handler: Arc<dyn CommandHandler<REQUEST, RESPONSE> + Send + Sync> = Arc::new(handler);
 
         self.handlers.insert(TypeId::of::<REQUEST>(), Box::new(handler));
     }
 
-    /// Sends a command to the appropriate handler and awaits the result.
+    /// Adds a behavior wrapped around every command. The first behavior registered is the
+    /// outermost one.
+    ///
+    /// # Arguments
+    ///
+    /// * `behavior` - The behavior to be registered.
+    pub fn register_behavior(&mut self, behavior: impl PipelineBehavior + 'static) {
+        self.behaviors.push(Arc::new(behavior));
+    }
+
+    /// Sends a command through the pipeline behaviors to the appropriate handler and awaits
+    /// the result.
     ///
     /// # Arguments
     ///
@@ -41,26 +70,109 @@ impl Mediator {
     /// The result of the command handler's handle method.
     pub async fn send<REQUEST, RESPONSE>(&self, command: REQUEST) -> Result<RESPONSE, AppStatus>
     where
-        REQUEST: Command<RESPONSE> + 'static,
-        RESPONSE: 'static,
+        REQUEST: Command<RESPONSE> + Clone + Send + Sync + 'static,
+        RESPONSE: Send + 'static,
     {
-        // Retrieve the handler associated with the command's type.
+        // Handlers are shared between all callers, nothing is locked around `handle`
         let handler = self.handlers.get(&TypeId::of::<REQUEST>())
-            .ok_or(AppStatus::InternalError("Handler not found".to_string()))?;
-
-        // Downcast the handler to the specific type we expect (Arc<Mutex<dyn CommandHandler>>).
-        let handler = match handler
-            .downcast_ref::<Arc<Mutex<dyn CommandHandler<REQUEST, RESPONSE> + Send + Sync>>>()
-            .ok_or(AppStatus::InternalError("Handler type mismatch".to_string()))
-        {
-            Ok(handler) => handler,
-            Err(err) => return Err(err),
+            .ok_or(AppStatus::InternalError("Handler not found".to_string()))?
+            .downcast_ref::<Arc<dyn CommandHandler<REQUEST, RESPONSE> + Send + Sync>>()
+            .ok_or(AppStatus::InternalError("Handler type mismatch".to_string()))?;
+
+        if self.behaviors.is_empty() {
+            return handler.handle(command).await;
+        }
+
+        let pending = Mutex::new(Some(command));
+
+        let run = || -> Pin<Box<dyn Future<Output = Result<BoxedResponse, AppStatus>> + Send + '_>> {
+            let command = {
+                let mut pending = pending.lock().unwrap_or_else(PoisonError::into_inner);
+
+                if REQUEST::IDEMPOTENT { pending.clone() } else { pending.take() }
+            };
+
+            Box::pin(async move {
+                let command = command.ok_or(AppStatus::InternalError("Command was already handled".to_string()))?;
+
+                handler.handle(command).await.map(|response| Box::new(response) as BoxedResponse)
+            })
         };
 
-        // Lock the mutex to access the handler and then call its handle method
-        let mut handler = handler.lock().await;
+        let info = CommandRef::<REQUEST, RESPONSE>::new(&pending);
+        let response = Next::new(&self.behaviors, &run, &info).run().await?;
+
+        response.downcast::<RESPONSE>()
+            .map(|response| *response)
+            .map_err(|_| AppStatus::InternalError("Response type mismatch".to_string()))
+    }
+
+    /// Bus behind `publish`, to be handed to the services raising domain events
+    pub fn event_bus(&self) -> Arc<EventBus> {
+        self.event_bus.clone()
+    }
+
+    /// Registers one of possibly many handlers for a notification type
+    ///
+    /// # Arguments
+    ///
+    /// * `handler` - The handler to be registered.
+    /// * `dispatch` - Whether `publish` awaits the handler or spawns it.
+    pub fn register_notification_handler<NOTIFICATION, H>(&mut self, handler: H, dispatch: Dispatch)
+    where
+        NOTIFICATION: Notification,
+        H: NotificationHandler<NOTIFICATION> + 'static,
+    {
+        self.event_bus.subscribe(handler, dispatch);
+    }
+
+    /// Fans a notification out to every handler registered for its type
+    ///
+    /// # Arguments
+    ///
+    /// * `notification` - The notification to be published.
+    pub async fn publish<NOTIFICATION>(&self, notification: NOTIFICATION)
+    where
+        NOTIFICATION: Notification,
+    {
+        self.event_bus.publish(notification).await;
+    }
+
+    /// Registers a handler for a specific query type
+    ///
+    /// # Arguments
+    ///
+    /// * `handler` - The handler to be registered.
+    pub fn register_query_handler<REQUEST, RESPONSE: 'static, H>(&mut self, handler: H)
+    where
+        REQUEST: Query<RESPONSE> + 'static,
+        H: QueryHandler<REQUEST, RESPONSE> + Send + Sync + 'static,
+    {
+        let handler: Arc<dyn QueryHandler<REQUEST, RESPONSE> + Send + Sync> = Arc::new(handler);
 
-        handler.handle(command).await
+        self.query_handlers.insert(TypeId::of::<REQUEST>(), Box::new(handler));
+    }
+
+    /// Sends a query to the appropriate handler and awaits the result.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query to be sent.
+    ///
+    /// # Returns
+    ///
+    /// The result of the query handler's handle method.
+    pub async fn query<REQUEST, RESPONSE>(&self, query: REQUEST) -> Result<RESPONSE, AppStatus>
+    where
+        REQUEST: Query<RESPONSE> + 'static,
+        RESPONSE: 'static,
+    {
+        let handler = self.query_handlers.get(&TypeId::of::<REQUEST>())
+            .ok_or(AppStatus::InternalError("Handler not found".to_string()))?
+            .downcast_ref::<Arc<dyn QueryHandler<REQUEST, RESPONSE> + Send + Sync>>()
+            .ok_or(AppStatus::InternalError("Handler type mismatch".to_string()))?;
+
+        handler.handle(query).await
     }
 }
 
@@ -69,6 +181,7 @@ mod tests {
     use super::*;
     use async_trait::async_trait;
 
+    #[derive(Clone)]
     struct TestCommand;
     struct TestResponse(pub String);
     impl Command<TestResponse> for TestCommand {}
@@ -76,7 +189,7 @@ mod tests {
 
     #[async_trait]
     impl CommandHandler<TestCommand, TestResponse> for TestCommandHandler {
-        async fn handle(&mut self, _command: TestCommand) -> Result<TestResponse, AppStatus> {
+        async fn handle(&self, _command: TestCommand) -> Result<TestResponse, AppStatus> {
             Ok(TestResponse("Test executed".to_string()))
         }
     }
@@ -98,6 +211,65 @@ mod tests {
         assert_eq!(response.unwrap().0, "Test executed");
     }
 
+    struct TestQuery;
+    impl Query<TestResponse> for TestQuery {}
+    struct TestQueryHandler;
+
+    #[async_trait]
+    impl QueryHandler<TestQuery, TestResponse> for TestQueryHandler {
+        async fn handle(&self, _query: TestQuery) -> Result<TestResponse, AppStatus> {
+            Ok(TestResponse("Query executed".to_string()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_register_and_send_query() {
+        // Given
+        let mut mediator = Mediator::new();
+        mediator.register_query_handler(TestQueryHandler);
+
+        // When
+        let (first, second) = tokio::join!(mediator.query(TestQuery), mediator.query(TestQuery));
+
+        // Then
+        assert_eq!(first.unwrap().0, "Query executed");
+        assert_eq!(second.unwrap().0, "Query executed");
+    }
+
+    #[derive(Clone)]
+    struct SlowCommand;
+    impl Command<()> for SlowCommand {}
+
+    /// Only returns once two commands are being handled at the same time
+    struct SlowCommandHandler {
+        barrier: Arc<tokio::sync::Barrier>,
+    }
+
+    #[async_trait]
+    impl CommandHandler<SlowCommand, ()> for SlowCommandHandler {
+        async fn handle(&self, _command: SlowCommand) -> Result<(), AppStatus> {
+            self.barrier.wait().await;
+
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send_commands_in_parallel() {
+        // Given
+        let mut mediator = Mediator::new();
+        mediator.register_handler(SlowCommandHandler { barrier: Arc::new(tokio::sync::Barrier::new(2)) });
+
+        // When
+        let both = async { tokio::join!(mediator.send(SlowCommand), mediator.send(SlowCommand)) };
+        let result = tokio::time::timeout(std::time::Duration::from_secs(5), both).await;
+
+        // Then
+        let (first, second) = result.expect("Commands were handled one after the other");
+        assert!(first.is_ok());
+        assert!(second.is_ok());
+    }
+
     #[tokio::test]
     async fn test_send_command_without_handler() {
         // Given
@@ -0,0 +1,27 @@
+use crate::notification::NotificationHandler;
+use crate::shared::error::AppStatus;
+use async_trait::async_trait;
+use domain::models::event::{DomainEvent, EventEnvelope};
+use log::info;
+
+/// Writes an audit record of every domain event to the log
+pub struct AuditLogHandler;
+
+#[async_trait]
+impl NotificationHandler<EventEnvelope> for AuditLogHandler {
+    async fn handle(&self, notification: &EventEnvelope) -> Result<(), AppStatus> {
+        let event = &notification.event;
+
+        match event {
+            DomainEvent::LoginFailed { reason, .. } => {
+                info!("audit {} user={} at={} reason={}", event.name(), event.user_id(), notification.occurred_at, reason)
+            }
+            DomainEvent::SessionRevoked { count, .. } => {
+                info!("audit {} user={} at={} count={}", event.name(), event.user_id(), notification.occurred_at, count)
+            }
+            _ => info!("audit {} user={} at={}", event.name(), event.user_id(), notification.occurred_at),
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,201 @@
+use crate::shared::error::AppStatus;
+use async_trait::async_trait;
+use domain::models::event::EventEnvelope;
+use domain::services::event_publisher::EventPublisher;
+use log::error;
+use std::any::{type_name, Any, TypeId};
+use std::collections::HashMap;
+use std::fmt::{self, Debug, Formatter};
+use std::sync::{Arc, RwLock};
+
+pub mod audit_log;
+
+/// Something that happened, fanned out to every handler registered for its type
+pub trait Notification: Clone + Send + Sync + 'static {}
+
+impl Notification for EventEnvelope {}
+
+#[async_trait]
+pub trait NotificationHandler<NOTIFICATION>: Send + Sync
+where
+    NOTIFICATION: Notification,
+{
+    async fn handle(&self, notification: &NOTIFICATION) -> Result<(), AppStatus>;
+}
+
+/// How `EventBus::publish` runs a handler
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Dispatch {
+    /// Before `publish` returns, in the order the handlers were registered
+    Await,
+    /// On a task of its own, so slow handlers like mailers don't hold up the publisher
+    Spawn,
+}
+
+struct Subscriber<NOTIFICATION> {
+    handler: Arc<dyn NotificationHandler<NOTIFICATION>>,
+    dispatch: Dispatch,
+}
+
+impl<NOTIFICATION> Clone for Subscriber<NOTIFICATION> {
+    fn clone(&self) -> Self {
+        Self { handler: self.handler.clone(), dispatch: self.dispatch }
+    }
+}
+
+/// Notification handlers by notification type. Shared between the `Mediator` and the services
+/// raising domain events, handlers can be added after both were built.
+#[derive(Default)]
+pub struct EventBus {
+    subscribers: RwLock<HashMap<TypeId, Box<dyn Any + Send + Sync>>>,
+}
+
+impl Debug for EventBus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventBus").finish_non_exhaustive()
+    }
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn subscribe<NOTIFICATION, H>(&self, handler: H, dispatch: Dispatch)
+    where
+        NOTIFICATION: Notification,
+        H: NotificationHandler<NOTIFICATION> + 'static,
+    {
+        let subscriber = Subscriber::<NOTIFICATION> { handler: Arc::new(handler), dispatch };
+        let mut subscribers = self.subscribers.write().unwrap_or_else(|e| e.into_inner());
+
+        subscribers.entry(TypeId::of::<NOTIFICATION>())
+            .or_insert_with(|| Box::new(Vec::<Subscriber<NOTIFICATION>>::new()))
+            .downcast_mut::<Vec<Subscriber<NOTIFICATION>>>()
+            .expect("Subscribers are stored by their notification type")
+            .push(subscriber);
+    }
+
+    /// Hands the notification to every handler registered for its type. Failing handlers are
+    /// logged, they don't affect the others or the publisher.
+    pub async fn publish<NOTIFICATION>(&self, notification: NOTIFICATION)
+    where
+        NOTIFICATION: Notification,
+    {
+        // Copied so that the lock is not held while handlers run
+        let subscribers = self.subscribers.read().unwrap_or_else(|e| e.into_inner())
+            .get(&TypeId::of::<NOTIFICATION>())
+            .and_then(|subscribers| subscribers.downcast_ref::<Vec<Subscriber<NOTIFICATION>>>())
+            .cloned()
+            .unwrap_or_default();
+
+        for subscriber in subscribers {
+            match subscriber.dispatch {
+                Dispatch::Await => {
+                    if let Err(err) = subscriber.handler.handle(&notification).await {
+                        error!("Handling {} failed: {}", type_name::<NOTIFICATION>(), err);
+                    }
+                }
+                Dispatch::Spawn => {
+                    let notification = notification.clone();
+
+                    tokio::spawn(async move {
+                        if let Err(err) = subscriber.handler.handle(&notification).await {
+                            error!("Handling {} failed: {}", type_name::<NOTIFICATION>(), err);
+                        }
+                    });
+                }
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl EventPublisher for EventBus {
+    async fn publish(&self, event: EventEnvelope) {
+        EventBus::publish(self, event).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use domain::models::event::DomainEvent;
+    use tokio::sync::{mpsc, Mutex};
+
+    struct RecordingHandler {
+        name: &'static str,
+        calls: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl NotificationHandler<EventEnvelope> for RecordingHandler {
+        async fn handle(&self, notification: &EventEnvelope) -> Result<(), AppStatus> {
+            self.calls.lock().await.push(format!("{} {}", self.name, notification.event.name()));
+
+            Ok(())
+        }
+    }
+
+    struct FailingHandler;
+
+    #[async_trait]
+    impl NotificationHandler<EventEnvelope> for FailingHandler {
+        async fn handle(&self, _notification: &EventEnvelope) -> Result<(), AppStatus> {
+            Err(AppStatus::InternalError("Failed".to_string()))
+        }
+    }
+
+    struct ChannelHandler {
+        sender: mpsc::UnboundedSender<i64>,
+    }
+
+    #[async_trait]
+    impl NotificationHandler<EventEnvelope> for ChannelHandler {
+        async fn handle(&self, notification: &EventEnvelope) -> Result<(), AppStatus> {
+            self.sender.send(notification.event.user_id()).map_err(|e| AppStatus::InternalError(e.to_string()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_publish_to_every_handler() {
+        // Given
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let bus = EventBus::new();
+        bus.subscribe(RecordingHandler { name: "first", calls: calls.clone() }, Dispatch::Await);
+        bus.subscribe(FailingHandler, Dispatch::Await);
+        bus.subscribe(RecordingHandler { name: "second", calls: calls.clone() }, Dispatch::Await);
+
+        // When
+        bus.publish(EventEnvelope::new(DomainEvent::OtpIssued { user_id: 1 })).await;
+
+        // Then
+        assert_eq!(*calls.lock().await, vec!["first otp_issued", "second otp_issued"]);
+    }
+
+    #[tokio::test]
+    async fn test_publish_spawned() {
+        // Given
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let bus = EventBus::new();
+        bus.subscribe(ChannelHandler { sender }, Dispatch::Spawn);
+
+        // When
+        bus.publish(EventEnvelope::new(DomainEvent::LoginSucceeded { user_id: 7 })).await;
+
+        // Then
+        assert_eq!(receiver.recv().await, Some(7));
+    }
+
+    #[tokio::test]
+    async fn test_publish_without_handlers() {
+        // Given
+        let bus = EventBus::new();
+
+        // When
+        bus.publish(EventEnvelope::new(DomainEvent::OtpIssued { user_id: 1 })).await;
+
+        // Then
+        assert!(bus.subscribers.read().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,26 @@
+use crate::pipeline::{CommandInfo, Next, PipelineBehavior, PipelineResult};
+use crate::shared::error::AppStatus;
+use async_trait::async_trait;
+use log::{error, info};
+use std::time::Instant;
+
+/// Logs every command with the time it took. Internal errors are logged as errors, other
+/// failures are expected outcomes like a wrong OTP.
+pub struct LoggingBehavior;
+
+#[async_trait]
+impl PipelineBehavior for LoggingBehavior {
+    async fn handle(&self, command: &dyn CommandInfo, next: Next<'_>) -> PipelineResult {
+        let started = Instant::now();
+        let result = next.run().await;
+        let elapsed = started.elapsed();
+
+        match &result {
+            Ok(_) => info!("{} succeeded in {:?}", command.name(), elapsed),
+            Err(AppStatus::InternalError(err)) => error!("{} failed in {:?}: {}", command.name(), elapsed, err),
+            Err(err) => info!("{} finished in {:?}: {}", command.name(), elapsed, err),
+        }
+
+        result
+    }
+}
@@ -0,0 +1,59 @@
+use crate::pipeline::{CommandInfo, Next, PipelineBehavior, PipelineResult};
+use crate::shared::error::AppStatus;
+use async_trait::async_trait;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+
+/// Outcomes of one command type since startup
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CommandStats {
+    pub succeeded: u64,
+    /// Expected failures such as a wrong OTP or a missing session
+    pub rejected: u64,
+    /// Internal errors, counted once per command even when it was retried
+    pub failed: u64,
+    /// Time spent in the rest of the pipeline, retries included
+    pub total_time: Duration,
+}
+
+/// Counts the outcomes of every command per command type. Clones share the same counters, so
+/// one clone can be registered while another one is read.
+#[derive(Debug, Clone, Default)]
+pub struct MetricsBehavior {
+    stats: Arc<RwLock<BTreeMap<&'static str, CommandStats>>>,
+}
+
+impl MetricsBehavior {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counters recorded so far, keyed by command name
+    pub async fn snapshot(&self) -> BTreeMap<&'static str, CommandStats> {
+        self.stats.read().await.clone()
+    }
+}
+
+#[async_trait]
+impl PipelineBehavior for MetricsBehavior {
+    async fn handle(&self, command: &dyn CommandInfo, next: Next<'_>) -> PipelineResult {
+        let started = Instant::now();
+        let result = next.run().await;
+        let elapsed = started.elapsed();
+
+        let mut stats = self.stats.write().await;
+        let entry = stats.entry(command.name()).or_default();
+
+        match &result {
+            Ok(_) => entry.succeeded += 1,
+            Err(AppStatus::InternalError(_)) => entry.failed += 1,
+            Err(_) => entry.rejected += 1,
+        }
+
+        entry.total_time += elapsed;
+
+        result
+    }
+}
@@ -0,0 +1,345 @@
+use crate::command::Command;
+use crate::shared::error::AppStatus;
+use async_trait::async_trait;
+use std::any::{type_name, Any};
+use std::future::Future;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, PoisonError};
+
+pub mod logging;
+pub mod metrics;
+pub mod retry;
+pub mod validation;
+
+/// Response of a handler with its type erased, so that one behavior can wrap every command
+pub type BoxedResponse = Box<dyn Any + Send>;
+
+pub type PipelineResult = Result<BoxedResponse, AppStatus>;
+
+type PipelineFuture<'a> = Pin<Box<dyn Future<Output = PipelineResult> + Send + 'a>>;
+
+/// What behaviors get to see of the command going through the pipeline
+pub trait CommandInfo: Send + Sync {
+    /// Type name of the command, for logs and metrics
+    fn name(&self) -> &'static str;
+
+    fn validate(&self) -> Result<(), AppStatus>;
+
+    /// See `Command::IDEMPOTENT`
+    fn idempotent(&self) -> bool;
+}
+
+/// The command waiting to be handed to its handler. Idempotent commands stay here and a copy is
+/// handed over on every run, others are moved out on the only run.
+pub(crate) struct CommandRef<'a, REQUEST, RESPONSE> {
+    command: &'a Mutex<Option<REQUEST>>,
+    response: PhantomData<fn() -> RESPONSE>,
+}
+
+impl<'a, REQUEST, RESPONSE> CommandRef<'a, REQUEST, RESPONSE> {
+    pub(crate) fn new(command: &'a Mutex<Option<REQUEST>>) -> Self {
+        Self { command, response: PhantomData }
+    }
+}
+
+impl<REQUEST, RESPONSE> CommandInfo for CommandRef<'_, REQUEST, RESPONSE>
+where
+    REQUEST: Command<RESPONSE> + Send + Sync,
+{
+    fn name(&self) -> &'static str {
+        type_name::<REQUEST>().rsplit("::").next().unwrap_or_default()
+    }
+
+    fn validate(&self) -> Result<(), AppStatus> {
+        match self.command.lock().unwrap_or_else(PoisonError::into_inner).as_ref() {
+            Some(command) => command.validate(),
+            None => Ok(()),
+        }
+    }
+
+    fn idempotent(&self) -> bool {
+        REQUEST::IDEMPOTENT
+    }
+}
+
+/// Cross-cutting step wrapped around every command sent through the `Mediator`. Behaviors run
+/// in the order they were registered, each deciding whether and how often to call the rest of
+/// the pipeline through `next`.
+#[async_trait]
+pub trait PipelineBehavior: Send + Sync {
+    async fn handle(&self, command: &dyn CommandInfo, next: Next<'_>) -> PipelineResult;
+}
+
+/// Remainder of the pipeline after the current behavior, ending with the handler
+#[derive(Clone, Copy)]
+pub struct Next<'a> {
+    behaviors: &'a [Arc<dyn PipelineBehavior>],
+    handler: &'a (dyn Fn() -> PipelineFuture<'a> + Send + Sync),
+    command: &'a dyn CommandInfo,
+}
+
+impl<'a> Next<'a> {
+    pub(crate) fn new(
+        behaviors: &'a [Arc<dyn PipelineBehavior>],
+        handler: &'a (dyn Fn() -> PipelineFuture<'a> + Send + Sync),
+        command: &'a dyn CommandInfo,
+    ) -> Self {
+        Self { behaviors, handler, command }
+    }
+
+    /// Runs the rest of the pipeline. Only idempotent commands may run it more than once, every
+    /// call then hands the handler a fresh copy of the command.
+    pub async fn run(self) -> PipelineResult {
+        match self.behaviors.split_first() {
+            Some((behavior, behaviors)) => behavior.handle(self.command, Next { behaviors, ..self }).await,
+            None => (self.handler)().await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::CommandHandler;
+    use crate::mediator::Mediator;
+    use tokio::sync::Mutex;
+
+    #[derive(Clone)]
+    struct TestCommand {
+        value: String,
+    }
+
+    impl Command<String> for TestCommand {
+        const IDEMPOTENT: bool = true;
+
+        fn validate(&self) -> Result<(), AppStatus> {
+            if self.value.is_empty() {
+                return Err(AppStatus::BadRequest("Value is required".to_string()));
+            }
+
+            Ok(())
+        }
+    }
+
+    struct TestCommandHandler {
+        calls: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl CommandHandler<TestCommand, String> for TestCommandHandler {
+        async fn handle(&self, command: TestCommand) -> Result<String, AppStatus> {
+            self.calls.lock().await.push("handler".to_string());
+
+            Ok(command.value)
+        }
+    }
+
+    /// Records when it is entered and left
+    struct RecordingBehavior {
+        name: &'static str,
+        calls: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl PipelineBehavior for RecordingBehavior {
+        async fn handle(&self, _command: &dyn CommandInfo, next: Next<'_>) -> PipelineResult {
+            self.calls.lock().await.push(format!("{} before", self.name));
+            let result = next.run().await;
+            self.calls.lock().await.push(format!("{} after", self.name));
+
+            result
+        }
+    }
+
+    /// Answers every command itself
+    struct ShortCircuitBehavior;
+
+    #[async_trait]
+    impl PipelineBehavior for ShortCircuitBehavior {
+        async fn handle(&self, command: &dyn CommandInfo, _next: Next<'_>) -> PipelineResult {
+            Err(AppStatus::BadRequest(format!("{} rejected", command.name())))
+        }
+    }
+
+    fn create_mediator(calls: &Arc<Mutex<Vec<String>>>) -> Mediator {
+        let mut mediator = Mediator::new();
+        mediator.register_handler(TestCommandHandler { calls: calls.clone() });
+        mediator.register_behavior(RecordingBehavior { name: "outer", calls: calls.clone() });
+        mediator.register_behavior(RecordingBehavior { name: "inner", calls: calls.clone() });
+
+        mediator
+    }
+
+    #[tokio::test]
+    async fn test_behaviors_run_in_order() {
+        // Given
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let mediator = create_mediator(&calls);
+
+        // When
+        let response = mediator.send(TestCommand { value: "value".to_string() }).await;
+
+        // Then
+        assert_eq!(response, Ok("value".to_string()));
+        assert_eq!(*calls.lock().await, vec!["outer before", "inner before", "handler", "inner after", "outer after"]);
+    }
+
+    #[tokio::test]
+    async fn test_behavior_short_circuits() {
+        // Given
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let mut mediator = create_mediator(&calls);
+        mediator.register_behavior(ShortCircuitBehavior);
+
+        // When
+        let response = mediator.send(TestCommand { value: "value".to_string() }).await;
+
+        // Then
+        assert_eq!(response, Err(AppStatus::BadRequest("TestCommand rejected".to_string())));
+        assert_eq!(*calls.lock().await, vec!["outer before", "inner before", "inner after", "outer after"]);
+    }
+
+    #[tokio::test]
+    async fn test_validation_short_circuits() {
+        // Given
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let mut mediator = create_mediator(&calls);
+        mediator.register_behavior(validation::ValidationBehavior);
+
+        // When
+        let response = mediator.send(TestCommand { value: String::new() }).await;
+
+        // Then
+        assert!(matches!(response, Err(AppStatus::BadRequest(_))));
+        assert!(!calls.lock().await.contains(&"handler".to_string()));
+    }
+
+    /// Fails with an internal error until it has been called `failures` times
+    struct FlakyCommandHandler {
+        failures: usize,
+        calls: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl CommandHandler<TestCommand, String> for FlakyCommandHandler {
+        async fn handle(&self, command: TestCommand) -> Result<String, AppStatus> {
+            let mut calls = self.calls.lock().await;
+            calls.push("handler".to_string());
+
+            if calls.len() <= self.failures {
+                return Err(AppStatus::InternalError("Connection reset".to_string()));
+            }
+
+            Ok(command.value)
+        }
+    }
+
+    fn create_flaky_mediator(failures: usize, calls: &Arc<Mutex<Vec<String>>>) -> Mediator {
+        let mut mediator = Mediator::new();
+        mediator.register_handler(FlakyCommandHandler { failures, calls: calls.clone() });
+        mediator.register_behavior(retry::RetryBehavior::new(3, std::time::Duration::ZERO));
+
+        mediator
+    }
+
+    #[tokio::test]
+    async fn test_retry_recovers_from_internal_error() {
+        // Given
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let mediator = create_flaky_mediator(2, &calls);
+
+        // When
+        let response = mediator.send(TestCommand { value: "value".to_string() }).await;
+
+        // Then
+        assert_eq!(response, Ok("value".to_string()));
+        assert_eq!(calls.lock().await.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_retry_gives_up_after_max_attempts() {
+        // Given
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let mediator = create_flaky_mediator(5, &calls);
+
+        // When
+        let response = mediator.send(TestCommand { value: "value".to_string() }).await;
+
+        // Then
+        assert!(matches!(response, Err(AppStatus::InternalError(_))));
+        assert_eq!(calls.lock().await.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_retry_returns_other_errors_right_away() {
+        // Given
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let mut mediator = create_mediator(&calls);
+        mediator.register_behavior(retry::RetryBehavior::new(3, std::time::Duration::ZERO));
+        mediator.register_behavior(ShortCircuitBehavior);
+
+        // When
+        let response = mediator.send(TestCommand { value: "value".to_string() }).await;
+
+        // Then
+        assert!(matches!(response, Err(AppStatus::BadRequest(_))));
+        assert_eq!(calls.lock().await.iter().filter(|call| *call == "inner before").count(), 1);
+    }
+
+    /// Not safe to run twice
+    #[derive(Clone)]
+    struct OnceCommand;
+    impl Command<()> for OnceCommand {}
+
+    /// Always fails with an internal error
+    struct FailingCommandHandler {
+        calls: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl CommandHandler<OnceCommand, ()> for FailingCommandHandler {
+        async fn handle(&self, _command: OnceCommand) -> Result<(), AppStatus> {
+            self.calls.lock().await.push("handler".to_string());
+
+            Err(AppStatus::InternalError("Connection reset".to_string()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry_skips_commands_not_idempotent() {
+        // Given
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let mut mediator = Mediator::new();
+        mediator.register_handler(FailingCommandHandler { calls: calls.clone() });
+        mediator.register_behavior(retry::RetryBehavior::new(3, std::time::Duration::ZERO));
+
+        // When
+        let response = mediator.send(OnceCommand).await;
+
+        // Then
+        assert!(matches!(response, Err(AppStatus::InternalError(_))));
+        assert_eq!(calls.lock().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_metrics_count_outcomes() {
+        // Given
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let metrics = metrics::MetricsBehavior::new();
+        let mut mediator = Mediator::new();
+        mediator.register_handler(FlakyCommandHandler { failures: 3, calls: calls.clone() });
+        mediator.register_behavior(metrics.clone());
+        mediator.register_behavior(retry::RetryBehavior::new(3, std::time::Duration::ZERO));
+        mediator.register_behavior(validation::ValidationBehavior);
+
+        // When
+        let _ = mediator.send(TestCommand { value: "value".to_string() }).await;
+        let _ = mediator.send(TestCommand { value: String::new() }).await;
+        let _ = mediator.send(TestCommand { value: "value".to_string() }).await;
+
+        // Then
+        let stats = metrics.snapshot().await["TestCommand"];
+        assert_eq!((stats.succeeded, stats.rejected, stats.failed), (1, 1, 1));
+    }
+}
@@ -0,0 +1,53 @@
+use crate::pipeline::{CommandInfo, Next, PipelineBehavior, PipelineResult};
+use crate::shared::error::AppStatus;
+use async_trait::async_trait;
+use log::warn;
+use std::time::Duration;
+
+/// Runs idempotent commands again when they fail with an internal error, such as a lost
+/// database connection or a serialization failure. The failed run may have written part of its
+/// changes, which only idempotent commands can safely repeat. Other commands and other failures
+/// are returned right away.
+pub struct RetryBehavior {
+    /// How often the command is run at most, including the first attempt
+    max_attempts: u32,
+    /// Wait before the first retry, doubled for every further one
+    backoff: Duration,
+}
+
+impl Default for RetryBehavior {
+    fn default() -> Self {
+        Self::new(3, Duration::from_millis(50))
+    }
+}
+
+impl RetryBehavior {
+    pub fn new(max_attempts: u32, backoff: Duration) -> Self {
+        Self { max_attempts: max_attempts.max(1), backoff }
+    }
+}
+
+#[async_trait]
+impl PipelineBehavior for RetryBehavior {
+    async fn handle(&self, command: &dyn CommandInfo, next: Next<'_>) -> PipelineResult {
+        if !command.idempotent() {
+            return next.run().await;
+        }
+
+        let mut backoff = self.backoff;
+        let mut attempt = 1;
+
+        loop {
+            match next.run().await {
+                Err(AppStatus::InternalError(err)) if attempt < self.max_attempts => {
+                    warn!("{} failed on attempt {}, retrying in {:?}: {}", command.name(), attempt, backoff, err);
+
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
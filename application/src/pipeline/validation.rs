@@ -0,0 +1,14 @@
+use crate::pipeline::{CommandInfo, Next, PipelineBehavior, PipelineResult};
+use async_trait::async_trait;
+
+/// Rejects commands failing `Command::validate` before they reach their handler
+pub struct ValidationBehavior;
+
+#[async_trait]
+impl PipelineBehavior for ValidationBehavior {
+    async fn handle(&self, command: &dyn CommandInfo, next: Next<'_>) -> PipelineResult {
+        command.validate()?;
+
+        next.run().await
+    }
+}
@@ -0,0 +1,372 @@
+use crate::query::{Query, QueryHandler};
+use crate::shared::error::AppStatus;
+use async_trait::async_trait;
+use domain::models::avatar::{snap_render_size, Rating, DEFAULT_RENDER_SIZE};
+use domain::models::user::User;
+use domain::repositories::avatar_repository::AvatarRepository;
+use domain::repositories::blob_store::BlobStore;
+use domain::repositories::email_repository::EmailRepository;
+use domain::repositories::id_provider::IdProvider;
+use domain::repositories::user_repository::UserRepository;
+use domain::services::avatar_service::AvatarService;
+use domain::services::default_avatar::{self, DefaultImage};
+use domain::services::image_pipeline::{self, OutputFormat};
+use domain::views::avatar_view::AvatarImage;
+
+/// Public lookup of the avatar behind an email hash, following the Gravatar request format
+#[derive(Debug, Clone)]
+pub struct GetAvatarQuery {
+    hash: String,
+    size: Option<u32>,
+    default: Option<String>,
+    accept: Option<String>,
+    rating: Option<String>,
+}
+
+impl GetAvatarQuery {
+    /// * `hash` - MD5 or SHA-256 hex of the trimmed, lower-cased email address
+    /// * `size` - requested edge length in pixels, the `s` parameter, rounded up to one of `RENDER_SIZES`
+    /// * `default` - fallback when no avatar is found, the `d` parameter
+    pub fn new(hash: String, size: Option<u32>, default: Option<String>) -> Self {
+        Self { hash, size, default, accept: None, rating: None }
+    }
+
+    /// Negotiates the served encoding from the request's `Accept` header
+    pub fn with_accept(mut self, accept: Option<String>) -> Self {
+        self.accept = accept;
+        self
+    }
+
+    /// Highest rating the caller accepts, the `r` parameter. Missing or unknown values mean G.
+    pub fn with_rating(mut self, rating: Option<String>) -> Self {
+        self.rating = rating;
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AvatarResponse {
+    Image(AvatarImage),
+    /// The caller asked to be sent to its own default image
+    Redirect(String),
+}
+
+impl Query<AvatarResponse> for GetAvatarQuery {}
+
+fn is_email_hash(hash: &str) -> bool {
+    (hash.len() == 32 || hash.len() == 64) && hash.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// User found behind an email hash
+struct Owner {
+    user: User,
+    /// Picture assigned to the matched address, the user's primary one when `None`
+    avatar_id: Option<i64>,
+    /// SHA-256 of the matched address, so that its MD5 and SHA-256 URLs get the same default image
+    seed: String,
+}
+
+pub struct GetAvatarQueryHandler<UR, ER, AR, BS, IP>
+where
+    UR: UserRepository + Sync + Send,
+    ER: EmailRepository + Sync + Send,
+    AR: AvatarRepository + Sync + Send,
+    BS: BlobStore + Sync + Send,
+    IP: IdProvider + Sync + Send,
+{
+    user_repository: UR,
+    email_repository: ER,
+    avatar_service: AvatarService<AR, BS, IP>,
+}
+
+impl<UR, ER, AR, BS, IP> GetAvatarQueryHandler<UR, ER, AR, BS, IP>
+where
+    UR: UserRepository + Sync + Send,
+    ER: EmailRepository + Sync + Send,
+    AR: AvatarRepository + Sync + Send,
+    BS: BlobStore + Sync + Send,
+    IP: IdProvider + Sync + Send,
+{
+    pub fn new(user_repository: UR, email_repository: ER, avatar_repository: AR, blob_store: BS, id_provider: IP) -> Self {
+        Self {
+            user_repository,
+            email_repository,
+            avatar_service: AvatarService::new(avatar_repository, blob_store, id_provider),
+        }
+    }
+
+    /// Resolves the hash through the verified addresses only, an address nobody proved to own
+    /// must not reveal whether an account exists for it
+    async fn find_owner(&self, hash: &str) -> Result<Option<Owner>, AppStatus> {
+        if !is_email_hash(hash) {
+            return Ok(None);
+        }
+
+        let Some(email) = self.email_repository.find_verified_by_hash(hash).await else {
+            return Ok(None);
+        };
+
+        let user = self.user_repository.find_by_id(email.user_id).await
+            .map_err(|e| AppStatus::InternalError(format!("Failed to load user: {}", e)))?;
+
+        Ok(user.map(|user| Owner { user, avatar_id: email.avatar_id, seed: email.sha256_hash }))
+    }
+
+    async fn find_image(&self, owner: Option<&Owner>, rating: Rating, size: u32, format: OutputFormat) -> Result<Option<AvatarImage>, AppStatus> {
+        let Some(owner) = owner else {
+            return Ok(None);
+        };
+
+        self.avatar_service.render(owner.user.id, owner.avatar_id, rating, size, format).await
+            .map_err(|e| AppStatus::InternalError(format!("Failed to load avatar: {}", e)))
+    }
+}
+
+#[async_trait]
+impl<UR, ER, AR, BS, IP> QueryHandler<GetAvatarQuery, AvatarResponse> for GetAvatarQueryHandler<UR, ER, AR, BS, IP>
+where
+    UR: UserRepository + Sync + Send,
+    ER: EmailRepository + Sync + Send,
+    AR: AvatarRepository + Sync + Send,
+    BS: BlobStore + Sync + Send,
+    IP: IdProvider + Sync + Send,
+{
+    async fn handle(&self, query: GetAvatarQuery) -> Result<AvatarResponse, AppStatus> {
+        let hash = query.hash.to_lowercase();
+        let size = snap_render_size(query.size.unwrap_or(DEFAULT_RENDER_SIZE));
+        let format = OutputFormat::negotiate(query.accept.as_deref());
+        let svg = image_pipeline::prefers_svg(query.accept.as_deref());
+        let rating = query.rating.as_deref().and_then(Rating::parse).unwrap_or_default();
+
+        let owner = self.find_owner(&hash).await?;
+
+        if let Some(image) = self.find_image(owner.as_ref(), rating, size, format).await? {
+            return Ok(AvatarResponse::Image(image));
+        }
+
+        let default = query.default.as_deref().map(DefaultImage::parse).unwrap_or_default();
+
+        let seed = owner.as_ref().map_or(hash.as_str(), |owner| owner.seed.as_str());
+
+        let pattern = match default {
+            DefaultImage::NotFound => return Err(AppStatus::NotFound(format!("No avatar for {}", hash))),
+            DefaultImage::MysteryPerson => return Ok(AvatarResponse::Image(default_avatar::mystery_person(size))),
+            DefaultImage::Blank => return Ok(AvatarResponse::Image(default_avatar::blank())),
+            DefaultImage::Url(url) => return Ok(AvatarResponse::Redirect(url)),
+            DefaultImage::Identicon => default_avatar::identicon(seed),
+            DefaultImage::Retro => default_avatar::retro(seed),
+            // Unknown addresses and names without letters get an identicon instead
+            DefaultImage::Initials => owner.as_ref()
+                .and_then(|owner| default_avatar::initials(&owner.user.username, seed))
+                .unwrap_or_else(|| default_avatar::identicon(seed)),
+        };
+
+        pattern.render(size, svg, format)
+            .map(AvatarResponse::Image)
+            .map_err(|e| AppStatus::InternalError(format!("Failed to render avatar: {}", e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use domain::models::avatar::{detect_content_type, CONTENT_TYPE_PNG, CONTENT_TYPE_SVG, CONTENT_TYPE_WEBP};
+    use domain::models::email_address::{md5_hash, sha256_hash, Email};
+    use domain::repositories::avatar_repository::InMemoryAvatarRepository;
+    use domain::repositories::blob_store::InMemoryBlobStore;
+    use domain::repositories::email_repository::InMemoryEmailRepository;
+    use domain::repositories::id_provider::SimpleIdProvider;
+    use domain::repositories::user_repository::InMemoryUserRepository;
+
+    const MD5: &str = "0bc83cb571cd1c50ba6f3e8a78ef1346";
+    const SHA256: &str = "84059b07d4be67b806386c0aad8070a23f18836bbaae342275dc0a83414c32ee";
+
+    type Handler = GetAvatarQueryHandler<InMemoryUserRepository, InMemoryEmailRepository, InMemoryAvatarRepository, InMemoryBlobStore, SimpleIdProvider>;
+
+    async fn create_handler() -> Handler {
+        let user_repository = InMemoryUserRepository::new();
+        let email_repository = InMemoryEmailRepository::new();
+        let avatar_repository = InMemoryAvatarRepository::new();
+        let blob_store = InMemoryBlobStore::new();
+
+        let user = user_repository.save(User::new("MyEmailAddress@example.com".to_owned())).await.unwrap();
+        save_verified(&email_repository, user.id, "MyEmailAddress@example.com").await;
+
+        let avatar_service = AvatarService::new(avatar_repository.clone(), blob_store.clone(), SimpleIdProvider::new());
+        avatar_service.upload(user.id, CONTENT_TYPE_PNG, &default_avatar::blank().data, Rating::G).await.unwrap();
+
+        GetAvatarQueryHandler::new(user_repository, email_repository, avatar_repository, blob_store, SimpleIdProvider::new())
+    }
+
+    async fn save_verified(email_repository: &InMemoryEmailRepository, user_id: i64, address: &str) -> Email {
+        let mut email = Email::new(user_id, address.to_owned());
+        email.is_verified = true;
+        email_repository.save(email).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_handle_known_hash() {
+        // Given
+        let handler = create_handler().await;
+
+        // When
+        let by_md5 = handler.handle(GetAvatarQuery::new(MD5.to_uppercase(), None, None)).await;
+        let by_sha256 = handler.handle(GetAvatarQuery::new(SHA256.to_owned(), Some(16), Some("404".to_owned()))).await;
+
+        // Then
+        assert!(matches!(by_md5, Ok(AvatarResponse::Image(image)) if image.content_type == CONTENT_TYPE_PNG));
+        assert!(matches!(by_sha256, Ok(AvatarResponse::Image(image)) if image.content_type == CONTENT_TYPE_PNG));
+    }
+
+    #[tokio::test]
+    async fn test_handle_negotiates_format() {
+        // Given
+        let handler = create_handler().await;
+        let query = GetAvatarQuery::new(MD5.to_owned(), Some(32), None)
+            .with_accept(Some("image/avif,image/webp,*/*;q=0.8".to_owned()));
+
+        // When
+        let response = handler.handle(query).await;
+
+        // Then
+        match response {
+            Ok(AvatarResponse::Image(image)) => {
+                assert_eq!(image.content_type, CONTENT_TYPE_WEBP);
+                assert_eq!(detect_content_type(&image.data), Some(CONTENT_TYPE_WEBP));
+            }
+            other => panic!("Unexpected response {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handle_unknown_hash_defaults() {
+        // Given
+        let handler = create_handler().await;
+        let unknown = "00000000000000000000000000000000".to_owned();
+
+        // When
+        let not_found = handler.handle(GetAvatarQuery::new(unknown.clone(), None, Some("404".to_owned()))).await;
+        let redirect = handler.handle(GetAvatarQuery::new(unknown.clone(), None, Some("https://example.com/a.png".to_owned()))).await;
+        let identicon = handler.handle(GetAvatarQuery::new(unknown.clone(), Some(40), Some("identicon".to_owned()))).await;
+        let mystery = handler.handle(GetAvatarQuery::new("not-a-hash".to_owned(), None, None)).await;
+
+        // Then
+        assert!(matches!(not_found, Err(AppStatus::NotFound(_))));
+        assert_eq!(redirect, Ok(AvatarResponse::Redirect("https://example.com/a.png".to_owned())));
+        assert!(matches!(identicon, Ok(AvatarResponse::Image(image)) if image.content_type == CONTENT_TYPE_PNG));
+        assert!(matches!(mystery, Ok(AvatarResponse::Image(image)) if image.content_type == CONTENT_TYPE_SVG));
+    }
+
+    #[tokio::test]
+    async fn test_handle_generated_defaults() {
+        // Given
+        let handler = create_handler().await;
+        let user = handler.user_repository.save(User::new("john.doe@example.com".to_owned())).await.unwrap();
+        save_verified(&handler.email_repository, user.id, "john.doe@example.com").await;
+        let hash = md5_hash("john.doe@example.com");
+        let unknown = "00000000000000000000000000000000".to_owned();
+        let svg = Some("image/svg+xml".to_owned());
+
+        // When
+        let initials = handler.handle(GetAvatarQuery::new(hash.clone(), None, Some("initials".to_owned())).with_accept(svg.clone())).await;
+        let fallback = handler.handle(GetAvatarQuery::new(unknown.clone(), None, Some("initials".to_owned())).with_accept(svg.clone())).await;
+        let identicon = handler.handle(GetAvatarQuery::new(unknown.clone(), None, Some("identicon".to_owned())).with_accept(svg.clone())).await;
+        let retro = handler.handle(GetAvatarQuery::new(hash.clone(), Some(24), Some("retro".to_owned()))).await;
+
+        // Then
+        let expected = default_avatar::initials("john.doe@example.com", &sha256_hash("john.doe@example.com")).unwrap().to_svg(DEFAULT_RENDER_SIZE);
+        assert_eq!(initials, Ok(AvatarResponse::Image(expected)));
+        assert_eq!(fallback, identicon);
+        assert!(matches!(retro, Ok(AvatarResponse::Image(image)) if image.content_type == CONTENT_TYPE_PNG));
+    }
+
+    #[tokio::test]
+    async fn test_handle_generated_defaults_ignore_hash_kind() {
+        // Given
+        let handler = create_handler().await;
+        let user = handler.user_repository.save(User::new("john.doe@example.com".to_owned())).await.unwrap();
+        save_verified(&handler.email_repository, user.id, "john.doe@example.com").await;
+        save_verified(&handler.email_repository, user.id, "work@example.com").await;
+
+        for address in ["john.doe@example.com", "work@example.com"] {
+            for default in ["identicon", "retro", "initials"] {
+                // When
+                let by_md5 = handler.handle(GetAvatarQuery::new(md5_hash(address), None, Some(default.to_owned()))).await;
+                let by_sha256 = handler.handle(GetAvatarQuery::new(sha256_hash(address), None, Some(default.to_owned()))).await;
+
+                // Then
+                assert_eq!(by_md5, by_sha256, "{} for {}", default, address);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handle_verified_email() {
+        // Given
+        let handler = create_handler().await;
+        let user = handler.user_repository.find_by_login("MyEmailAddress@example.com").await.unwrap().unwrap();
+        save_verified(&handler.email_repository, user.id, "work@example.com").await;
+        handler.email_repository.save(Email::new(user.id, "pending@example.com".to_owned())).await.unwrap();
+
+        // When
+        let by_verified = handler.handle(GetAvatarQuery::new(md5_hash("work@example.com"), None, Some("404".to_owned()))).await;
+        let by_pending = handler.handle(GetAvatarQuery::new(md5_hash("pending@example.com"), None, Some("404".to_owned()))).await;
+
+        // Then
+        assert!(matches!(by_verified, Ok(AvatarResponse::Image(_))));
+        assert!(matches!(by_pending, Err(AppStatus::NotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn test_handle_assigned_avatar() {
+        // Given
+        let handler = create_handler().await;
+        let user = handler.user_repository.find_by_login("MyEmailAddress@example.com").await.unwrap().unwrap();
+        let blank = handler.avatar_service.list(user.id).await.unwrap().remove(0);
+        let identicon = default_avatar::identicon(MD5).render(80, false, OutputFormat::Png).unwrap();
+        handler.avatar_service.upload(user.id, CONTENT_TYPE_PNG, &identicon.data, Rating::G).await.unwrap();
+
+        let mut work = Email::new(user.id, "work@example.com".to_owned());
+        work.is_verified = true;
+        work.avatar_id = Some(blank.id);
+        handler.email_repository.save(work).await.unwrap();
+
+        // When
+        let by_work = handler.handle(GetAvatarQuery::new(md5_hash("work@example.com"), None, None)).await.unwrap();
+        let by_primary = handler.handle(GetAvatarQuery::new(MD5.to_owned(), None, None)).await.unwrap();
+
+        // Then
+        assert_ne!(by_work, by_primary);
+    }
+
+    #[tokio::test]
+    async fn test_handle_ignores_login_without_verified_email() {
+        // Given
+        let handler = create_handler().await;
+        handler.user_repository.save(User::new("never.verified@example.com".to_owned())).await.unwrap();
+
+        // When
+        let response = handler.handle(GetAvatarQuery::new(md5_hash("never.verified@example.com"), None, Some("404".to_owned()))).await;
+
+        // Then
+        assert!(matches!(response, Err(AppStatus::NotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn test_handle_rating() {
+        // Given
+        let handler = create_handler().await;
+        let user = handler.user_repository.find_by_login("MyEmailAddress@example.com").await.unwrap().unwrap();
+        handler.avatar_service.upload(user.id, CONTENT_TYPE_PNG, &default_avatar::blank().data, Rating::R).await.unwrap();
+
+        // When
+        let unrated = handler.handle(GetAvatarQuery::new(MD5.to_owned(), None, Some("404".to_owned()))).await;
+        let pg = handler.handle(GetAvatarQuery::new(MD5.to_owned(), None, Some("404".to_owned())).with_rating(Some("pg".to_owned()))).await;
+        let x = handler.handle(GetAvatarQuery::new(MD5.to_owned(), None, Some("404".to_owned())).with_rating(Some("X".to_owned()))).await;
+
+        // Then
+        assert!(matches!(unrated, Err(AppStatus::NotFound(_))));
+        assert!(matches!(pg, Err(AppStatus::NotFound(_))));
+        assert!(matches!(x, Ok(AvatarResponse::Image(_))));
+    }
+}
@@ -0,0 +1,57 @@
+use crate::query::{Query, QueryHandler};
+use crate::shared::error::AppStatus;
+use async_trait::async_trait;
+use domain::repositories::avatar_repository::AvatarRepository;
+use domain::repositories::blob_store::BlobStore;
+use domain::repositories::id_provider::IdProvider;
+use domain::services::avatar_service::AvatarService;
+use domain::views::avatar_view::AvatarView;
+
+/// Lists the avatars in the user's library, newest first
+#[derive(Debug, Clone)]
+pub struct ListAvatarsQuery {
+    user_id: i64,
+}
+
+impl ListAvatarsQuery {
+    pub fn new(user_id: i64) -> Self {
+        Self { user_id }
+    }
+}
+
+impl Query<Vec<AvatarView>> for ListAvatarsQuery {}
+
+pub struct ListAvatarsQueryHandler<AR, BS, IP>
+where
+    AR: AvatarRepository + Sync + Send,
+    BS: BlobStore + Sync + Send,
+    IP: IdProvider + Sync + Send,
+{
+    avatar_service: AvatarService<AR, BS, IP>,
+}
+
+impl<AR, BS, IP> ListAvatarsQueryHandler<AR, BS, IP>
+where
+    AR: AvatarRepository + Sync + Send,
+    BS: BlobStore + Sync + Send,
+    IP: IdProvider + Sync + Send,
+{
+    pub fn new(avatar_repository: AR, blob_store: BS, id_provider: IP) -> Self {
+        Self {
+            avatar_service: AvatarService::new(avatar_repository, blob_store, id_provider),
+        }
+    }
+}
+
+#[async_trait]
+impl<AR, BS, IP> QueryHandler<ListAvatarsQuery, Vec<AvatarView>> for ListAvatarsQueryHandler<AR, BS, IP>
+where
+    AR: AvatarRepository + Sync + Send,
+    BS: BlobStore + Sync + Send,
+    IP: IdProvider + Sync + Send,
+{
+    async fn handle(&self, query: ListAvatarsQuery) -> Result<Vec<AvatarView>, AppStatus> {
+        self.avatar_service.list(query.user_id).await
+            .map_err(|e| AppStatus::InternalError(format!("Failed to list avatars: {}", e)))
+    }
+}
@@ -0,0 +1,2 @@
+pub mod get_avatar;
+pub mod list_avatars;
\ No newline at end of file
@@ -0,0 +1,58 @@
+use crate::command::email::map_email_error;
+use crate::query::{Query, QueryHandler};
+use crate::shared::error::AppStatus;
+use async_trait::async_trait;
+use domain::repositories::email_repository::EmailRepository;
+use domain::repositories::id_provider::IdProvider;
+use domain::repositories::user_repository::UserRepository;
+use domain::services::email_service::EmailService;
+use domain::views::email_view::EmailView;
+
+/// Lists the verified and pending addresses of the user
+#[derive(Debug, Clone)]
+pub struct ListEmailsQuery {
+    user_id: i64,
+}
+
+impl ListEmailsQuery {
+    pub fn new(user_id: i64) -> Self {
+        Self { user_id }
+    }
+}
+
+impl Query<Vec<EmailView>> for ListEmailsQuery {}
+
+pub struct ListEmailsQueryHandler<ER, UR, IP>
+where
+    ER: EmailRepository + Sync + Send,
+    UR: UserRepository + Sync + Send,
+    IP: IdProvider + Sync + Send,
+{
+    email_service: EmailService<ER, UR, IP>,
+}
+
+impl<ER, UR, IP> ListEmailsQueryHandler<ER, UR, IP>
+where
+    ER: EmailRepository + Sync + Send,
+    UR: UserRepository + Sync + Send,
+    IP: IdProvider + Sync + Send,
+{
+    pub fn new(email_repository: ER, user_repository: UR, id_provider: IP) -> Self {
+        Self {
+            email_service: EmailService::new(email_repository, user_repository, id_provider),
+        }
+    }
+}
+
+#[async_trait]
+impl<ER, UR, IP> QueryHandler<ListEmailsQuery, Vec<EmailView>> for ListEmailsQueryHandler<ER, UR, IP>
+where
+    ER: EmailRepository + Sync + Send,
+    UR: UserRepository + Sync + Send,
+    IP: IdProvider + Sync + Send,
+{
+    async fn handle(&self, query: ListEmailsQuery) -> Result<Vec<EmailView>, AppStatus> {
+        self.email_service.list(query.user_id).await
+            .map_err(map_email_error)
+    }
+}
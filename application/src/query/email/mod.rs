@@ -0,0 +1 @@
+pub mod list_emails;
\ No newline at end of file
@@ -0,0 +1,17 @@
+use crate::shared::error::AppStatus;
+use async_trait::async_trait;
+
+pub mod avatar;
+pub mod email;
+pub mod user;
+
+/// Read-only request, kept apart from commands so that reads never go through write paths
+pub trait Query<REQUEST> {}
+
+#[async_trait]
+pub trait QueryHandler<REQUEST, RESPONSE>
+where
+    REQUEST: Query<RESPONSE>,
+{
+    async fn handle(&self, query: REQUEST) -> Result<RESPONSE, AppStatus>;
+}
@@ -0,0 +1,61 @@
+use crate::query::{Query, QueryHandler};
+use crate::shared::error::AppStatus;
+use async_trait::async_trait;
+use domain::repositories::id_provider::IdProvider;
+use domain::repositories::otp_repository::OtpRepository;
+use domain::repositories::session_repository::SessionRepository;
+use domain::repositories::user_repository::UserRepository;
+use domain::services::user_service::UserService;
+use domain::views::user_view::UserView;
+
+/// Loads the account of the signed in user
+#[derive(Debug, Clone)]
+pub struct GetProfileQuery {
+    user_id: i64,
+}
+
+impl GetProfileQuery {
+    pub fn new(user_id: i64) -> Self {
+        Self { user_id }
+    }
+}
+
+impl Query<UserView> for GetProfileQuery {}
+
+pub struct GetProfileQueryHandler<UR, SR, OR, IP>
+where
+    UR: UserRepository + Sync + Send,
+    SR: SessionRepository + Sync + Send,
+    OR: OtpRepository + Sync + Send,
+    IP: IdProvider + Sync + Send,
+{
+    user_service: UserService<UR, SR, OR, IP>,
+}
+
+impl<UR, SR, OR, IP> GetProfileQueryHandler<UR, SR, OR, IP>
+where
+    UR: UserRepository + Sync + Send,
+    SR: SessionRepository + Sync + Send,
+    OR: OtpRepository + Sync + Send,
+    IP: IdProvider + Sync + Send,
+{
+    pub fn new(user_repository: UR, session_repository: SR, otp_repository: OR, id_provider: IP) -> Self {
+        Self {
+            user_service: UserService::new(user_repository, session_repository, otp_repository, id_provider),
+        }
+    }
+}
+
+#[async_trait]
+impl<UR, SR, OR, IP> QueryHandler<GetProfileQuery, UserView> for GetProfileQueryHandler<UR, SR, OR, IP>
+where
+    UR: UserRepository + Sync + Send,
+    SR: SessionRepository + Sync + Send,
+    OR: OtpRepository + Sync + Send,
+    IP: IdProvider + Sync + Send,
+{
+    async fn handle(&self, query: GetProfileQuery) -> Result<UserView, AppStatus> {
+        self.user_service.find_by_id(query.user_id).await
+            .map_err(AppStatus::NotFound)
+    }
+}
@@ -0,0 +1,107 @@
+use crate::query::{Query, QueryHandler};
+use crate::shared::error::AppStatus;
+use async_trait::async_trait;
+use domain::repositories::id_provider::IdProvider;
+use domain::repositories::otp_repository::OtpRepository;
+use domain::repositories::session_repository::SessionRepository;
+use domain::repositories::user_repository::UserRepository;
+use domain::services::user_service::UserService;
+use domain::views::session_view::SessionInfoView;
+
+/// Lists the devices the user is signed in on, newest first
+#[derive(Debug, Clone)]
+pub struct ListSessionsQuery {
+    user_id: i64,
+    current: Option<String>,
+}
+
+impl ListSessionsQuery {
+    /// * `current` - value of the session the request was made with, marked in the result
+    pub fn new(user_id: i64, current: Option<String>) -> Self {
+        Self { user_id, current }
+    }
+}
+
+impl Query<Vec<SessionInfoView>> for ListSessionsQuery {}
+
+pub struct ListSessionsQueryHandler<UR, SR, OR, IP>
+where
+    UR: UserRepository + Sync + Send,
+    SR: SessionRepository + Sync + Send,
+    OR: OtpRepository + Sync + Send,
+    IP: IdProvider + Sync + Send,
+{
+    user_service: UserService<UR, SR, OR, IP>,
+}
+
+impl<UR, SR, OR, IP> ListSessionsQueryHandler<UR, SR, OR, IP>
+where
+    UR: UserRepository + Sync + Send,
+    SR: SessionRepository + Sync + Send,
+    OR: OtpRepository + Sync + Send,
+    IP: IdProvider + Sync + Send,
+{
+    pub fn new(user_repository: UR, session_repository: SR, otp_repository: OR, id_provider: IP) -> Self {
+        Self {
+            user_service: UserService::new(user_repository, session_repository, otp_repository, id_provider),
+        }
+    }
+}
+
+#[async_trait]
+impl<UR, SR, OR, IP> QueryHandler<ListSessionsQuery, Vec<SessionInfoView>> for ListSessionsQueryHandler<UR, SR, OR, IP>
+where
+    UR: UserRepository + Sync + Send,
+    SR: SessionRepository + Sync + Send,
+    OR: OtpRepository + Sync + Send,
+    IP: IdProvider + Sync + Send,
+{
+    async fn handle(&self, query: ListSessionsQuery) -> Result<Vec<SessionInfoView>, AppStatus> {
+        self.user_service.list_sessions(query.user_id, query.current.as_deref()).await
+            .map_err(|e| AppStatus::InternalError(format!("Failed to list sessions: {}", e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::CommandHandler;
+    use crate::command::user::logout::{LogoutCommand, LogoutCommandHandler};
+    use crate::command::user::revoke_all_sessions::{RevokeAllSessionsCommand, RevokeAllSessionsCommandHandler};
+    use domain::repositories::id_provider::SimpleIdProvider;
+    use domain::repositories::otp_repository::InMemoryOtpRepository;
+    use domain::repositories::session_repository::InMemorySessionRepository;
+    use domain::repositories::user_repository::InMemoryUserRepository;
+
+    #[tokio::test]
+    async fn test_logout_and_revoke() {
+        // Given
+        let ur = InMemoryUserRepository::new();
+        let sr = InMemorySessionRepository::new();
+        let or = InMemoryOtpRepository::new();
+        let ip = SimpleIdProvider::new();
+
+        let user_service = UserService::new(ur.clone(), sr.clone(), or.clone(), ip.clone());
+        let user = user_service.create("alice".to_owned()).await.unwrap();
+        let laptop = user_service.generate_session("alice", Some("Firefox".to_owned()), None).await.unwrap();
+        let phone = user_service.generate_session("alice", Some("Safari".to_owned()), None).await.unwrap();
+        user_service.generate_session("alice", None, None).await.unwrap();
+
+        let list = ListSessionsQueryHandler::new(ur.clone(), sr.clone(), or.clone(), ip.clone());
+        let logout = LogoutCommandHandler::new(ur.clone(), sr.clone(), or.clone(), ip.clone());
+        let revoke = RevokeAllSessionsCommandHandler::new(ur, sr, or, ip);
+
+        // When
+        logout.handle(LogoutCommand::new(laptop.value.clone())).await.unwrap();
+        let listed = list.handle(ListSessionsQuery::new(user.id, Some(phone.value.clone()))).await.unwrap();
+        let revoked = revoke.handle(RevokeAllSessionsCommand::new(user.id)).await.unwrap();
+        let after = list.handle(ListSessionsQuery::new(user.id, None)).await.unwrap();
+
+        // Then
+        assert_eq!(listed.len(), 2);
+        assert_eq!(listed.iter().filter(|s| s.is_current).count(), 1);
+        assert!(listed.iter().any(|s| s.is_current && s.user_agent.as_deref() == Some("Safari")));
+        assert_eq!(revoked, 2);
+        assert!(after.is_empty());
+    }
+}
@@ -0,0 +1,2 @@
+pub mod get_profile;
+pub mod list_sessions;
\ No newline at end of file
@@ -1,22 +1,38 @@
-use std::fmt::{self, Display, Formatter};
-
+/// Why a command or query failed. Each variant carries a message for humans and has a stable
+/// `code` for clients to act on.
 #[derive(thiserror::Error, Debug, PartialEq)]
 pub enum AppStatus {
-    Ok(String),
+    #[error("Not found: {0}")]
     NotFound(String),
+    #[error("Bad request: {0}")]
     BadRequest(String),
+    #[error("Auth error: {0}")]
     AuthError(String),
+    #[error("Too many attempts: {0}")]
+    TooManyAttempts(String),
+    #[error("Internal error: {0}")]
     InternalError(String),
 }
 
-impl Display for AppStatus {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+impl AppStatus {
+    /// Machine-readable name of the error kind, stable across releases
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppStatus::NotFound(_) => "not_found",
+            AppStatus::BadRequest(_) => "bad_request",
+            AppStatus::AuthError(_) => "auth_failed",
+            AppStatus::TooManyAttempts(_) => "too_many_attempts",
+            AppStatus::InternalError(_) => "internal_error",
+        }
+    }
+
+    pub fn message(&self) -> &str {
         match self {
-            AppStatus::NotFound(msg) => write!(f, "Not found: {}", msg),
-            AppStatus::BadRequest(msg) => write!(f, "Bad request: {}", msg),
-            AppStatus::InternalError(msg) => write!(f, "Internal error: {}", msg),
-            AppStatus::AuthError(msg) => write!(f, "Auth error: {}", msg),
-            AppStatus::Ok(msg) => write!(f, "Ok: {}", msg),
+            AppStatus::NotFound(msg)
+            | AppStatus::BadRequest(msg)
+            | AppStatus::AuthError(msg)
+            | AppStatus::TooManyAttempts(msg)
+            | AppStatus::InternalError(msg) => msg,
         }
     }
 }
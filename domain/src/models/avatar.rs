@@ -0,0 +1,154 @@
+use chrono::{DateTime, Utc};
+use sqlx::FromRow;
+
+/// Largest accepted upload, in bytes
+pub const MAX_AVATAR_SIZE: usize = 2 * 1024 * 1024;
+
+/// Edge length, in pixels, of served avatars when none is requested
+pub const DEFAULT_RENDER_SIZE: u32 = 80;
+/// Largest edge length, in pixels, avatars are served at
+pub const MAX_RENDER_SIZE: u32 = 2048;
+/// Edge lengths avatars are rendered at. Requested sizes are rounded up to one of them, so that
+/// each stored image has only a few renderings to produce and cache.
+pub const RENDER_SIZES: [u32; 11] = [16, 32, 48, 64, 80, 96, 128, 256, 512, 1024, MAX_RENDER_SIZE];
+
+pub const CONTENT_TYPE_PNG: &str = "image/png";
+pub const CONTENT_TYPE_JPEG: &str = "image/jpeg";
+pub const CONTENT_TYPE_GIF: &str = "image/gif";
+pub const CONTENT_TYPE_WEBP: &str = "image/webp";
+pub const CONTENT_TYPE_SVG: &str = "image/svg+xml";
+
+pub const SUPPORTED_CONTENT_TYPES: [&str; 4] = [CONTENT_TYPE_PNG, CONTENT_TYPE_JPEG, CONTENT_TYPE_GIF, CONTENT_TYPE_WEBP];
+
+/// Rounds `size` up to the next of `RENDER_SIZES`, larger sizes get `MAX_RENDER_SIZE`
+pub fn snap_render_size(size: u32) -> u32 {
+    RENDER_SIZES.into_iter().find(|&snapped| snapped >= size).unwrap_or(MAX_RENDER_SIZE)
+}
+
+/// Audience an avatar is suitable for, from the most to the least restrictive. Sites embedding
+/// avatars ask for a maximum rating and are served the default image for anything above it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, sqlx::Type)]
+#[repr(i16)]
+pub enum Rating {
+    /// Suitable for display on all websites with any audience type
+    #[default]
+    G = 0,
+    /// May contain rude gestures, provocatively dressed individuals, mild violence
+    PG = 1,
+    /// May contain harsh profanity, intense violence, nudity or hard drug use
+    R = 2,
+    /// May contain sexual imagery or extremely disturbing violence
+    X = 3,
+}
+
+impl Rating {
+    /// Parses the `r` parameter, `g`, `pg`, `r` or `x` in any case
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "g" => Some(Rating::G),
+            "pg" => Some(Rating::PG),
+            "r" => Some(Rating::R),
+            "x" => Some(Rating::X),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Rating::G => "g",
+            Rating::PG => "pg",
+            Rating::R => "r",
+            Rating::X => "x",
+        }
+    }
+}
+
+/// Picture uploaded by a user. The image itself lives in a `BlobStore` under `storage_key`.
+#[derive(Debug, Clone, FromRow, PartialEq)]
+pub struct Avatar {
+    pub id: i64,
+    pub user_id: i64,
+    pub content_type: String,
+    pub size_bytes: i64,
+    pub storage_key: String,
+    /// Whether this is the picture shown for the user by default
+    pub is_primary: bool,
+    pub rating: Rating,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Avatar {
+    pub fn new(user_id: i64, content_type: String, size_bytes: i64, storage_key: String) -> Self {
+        Self {
+            id: -1,
+            user_id,
+            content_type,
+            size_bytes,
+            storage_key,
+            is_primary: false,
+            rating: Rating::G,
+            created_at: Utc::now(),
+        }
+    }
+}
+
+/// Detects the image format from the leading magic bytes, independent of what the client claims
+pub fn detect_content_type(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(&[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A]) {
+        Some(CONTENT_TYPE_PNG)
+    } else if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some(CONTENT_TYPE_JPEG)
+    } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        Some(CONTENT_TYPE_GIF)
+    } else if data.len() >= 12 && data.starts_with(b"RIFF") && &data[8..12] == b"WEBP" {
+        Some(CONTENT_TYPE_WEBP)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    pub async fn test_avatar_model() {
+        // Given
+        let avatar = Avatar::new(1, CONTENT_TYPE_PNG.to_owned(), 42, "key".to_owned());
+
+        // Then
+        assert_eq!(avatar.id, -1);
+        assert_eq!(avatar.user_id, 1);
+        assert_eq!(avatar.content_type, "image/png");
+        assert_eq!(avatar.size_bytes, 42);
+        assert!(!avatar.is_primary);
+        assert_eq!(avatar.rating, Rating::G);
+    }
+
+    #[tokio::test]
+    pub async fn test_rating() {
+        assert_eq!(Rating::parse("PG"), Some(Rating::PG));
+        assert_eq!(Rating::parse(" x"), Some(Rating::X));
+        assert_eq!(Rating::parse("nc17"), None);
+        assert!(Rating::G < Rating::PG && Rating::PG < Rating::R && Rating::R < Rating::X);
+        assert_eq!(Rating::R.as_str(), "r");
+    }
+
+    #[test]
+    fn test_snap_render_size() {
+        assert_eq!(snap_render_size(0), 16);
+        assert_eq!(snap_render_size(80), 80);
+        assert_eq!(snap_render_size(81), 96);
+        assert_eq!(snap_render_size(8192), MAX_RENDER_SIZE);
+    }
+
+    #[tokio::test]
+    pub async fn test_detect_content_type() {
+        assert_eq!(detect_content_type(b"\x89PNG\r\n\x1a\n...."), Some(CONTENT_TYPE_PNG));
+        assert_eq!(detect_content_type(b"\xff\xd8\xff\xe0...."), Some(CONTENT_TYPE_JPEG));
+        assert_eq!(detect_content_type(b"GIF89a...."), Some(CONTENT_TYPE_GIF));
+        assert_eq!(detect_content_type(b"RIFF\x00\x00\x00\x00WEBPVP8 "), Some(CONTENT_TYPE_WEBP));
+        assert_eq!(detect_content_type(b"<svg></svg>"), None);
+        assert_eq!(detect_content_type(b""), None);
+    }
+}
@@ -1,29 +1,85 @@
 use chrono::{DateTime, Utc};
+use md5::Md5;
+use sqlx::FromRow;
+use sha2::{Digest, Sha256};
 use std::fmt::Display;
 
-#[derive(Debug, Clone)]
+/// Lifetime of the code mailed to confirm an address
+pub const EMAIL_VERIFICATION_LIFETIME_HOURS: i64 = 24;
+
+/// Address owned by a user. The address is stored normalized, together with its MD5 and SHA-256
+/// hashes so avatars can be looked up by hash. Only verified addresses resolve to an avatar.
+#[derive(Debug, Clone, FromRow, PartialEq)]
 pub struct Email {
-    pub id: i32,
-    pub user_id: i32,
+    pub id: i64,
+    pub user_id: i64,
     pub value: String,
+    pub md5_hash: String,
+    pub sha256_hash: String,
     pub is_verified: bool,
+    /// Picture from the user's library shown for this address instead of their primary one
+    pub avatar_id: Option<i64>,
+    /// Keyed hash of the pending verification code, cleared once verified
+    pub verification_hash: Option<String>,
+    pub verification_expires_at: Option<DateTime<Utc>>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
 impl Email {
-    pub fn new(user_id: i32, value: String) -> Self {
+    pub fn new(user_id: i64, value: String) -> Self {
         let now = Utc::now();
+        let value = normalize_email(&value);
 
         Self {
             id: -1,
             user_id,
+            md5_hash: md5_hash(&value),
+            sha256_hash: sha256_hash(&value),
             value,
             is_verified: false,
+            avatar_id: None,
+            verification_hash: None,
+            verification_expires_at: None,
             created_at: now,
             updated_at: now,
         }
     }
+
+    pub fn is_verification_expired(&self) -> bool {
+        self.verification_expires_at.is_none_or(|expires_at| expires_at < Utc::now())
+    }
+}
+
+/// Rough syntax check: a single `@` between a non-empty local part and a dotted domain, no
+/// whitespace. Deliverability is proven by the verification mail.
+pub fn is_valid_email(address: &str) -> bool {
+    let address = address.trim();
+
+    match address.split_once('@') {
+        Some((local, domain)) => !local.is_empty()
+            && !domain.contains('@')
+            && domain.split('.').count() >= 2
+            && domain.split('.').all(|part| !part.is_empty())
+            && !address.chars().any(char::is_whitespace)
+            && address.len() <= 254,
+        None => false,
+    }
+}
+
+/// Trims and lower-cases the address, the form avatar hashes are computed over
+pub fn normalize_email(address: &str) -> String {
+    address.trim().to_lowercase()
+}
+
+/// Hex MD5 of the normalized address, as used by Gravatar-compatible clients
+pub fn md5_hash(address: &str) -> String {
+    hex::encode(Md5::digest(normalize_email(address)))
+}
+
+/// Hex SHA-256 of the normalized address
+pub fn sha256_hash(address: &str) -> String {
+    hex::encode(Sha256::digest(normalize_email(address)))
 }
 
 impl Display for Email {
@@ -34,17 +90,39 @@ impl Display for Email {
 
 #[cfg(test)]
 mod tests {
-    use crate::models::email_address::Email;
+    use crate::models::email_address::{is_valid_email, md5_hash, sha256_hash, Email};
 
     #[tokio::test]
     pub async fn test_email_model_valid() {
         // Given
-        let email = Email::new(1, "example@email.com".to_owned());
+        let email = Email::new(1, " Example@Email.com".to_owned());
 
         // Then
         assert_eq!(email.id, -1);
         assert_eq!(email.user_id, 1);
         assert_eq!(email.value, "example@email.com");
-        assert_eq!(email.is_verified, false);
+        assert_eq!(email.md5_hash, md5_hash("example@email.com"));
+        assert!(!email.is_verified);
+        assert!(email.is_verification_expired());
+    }
+
+    #[tokio::test]
+    pub async fn test_is_valid_email() {
+        assert!(is_valid_email("john.doe@example.com"));
+        assert!(!is_valid_email("john.doe"));
+        assert!(!is_valid_email("@example.com"));
+        assert!(!is_valid_email("john@localhost"));
+        assert!(!is_valid_email("john@@example.com"));
+        assert!(!is_valid_email("john doe@example.com"));
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    pub async fn test_email_hashes() {
+        // Given
+        let address = "  MyEmailAddress@example.com ";
+
+        // Then
+        assert_eq!(md5_hash(address), "0bc83cb571cd1c50ba6f3e8a78ef1346");
+        assert_eq!(sha256_hash(address), "84059b07d4be67b806386c0aad8070a23f18836bbaae342275dc0a83414c32ee");
+    }
+}
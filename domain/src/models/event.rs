@@ -0,0 +1,49 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Something that happened in the domain that other use cases may react to
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum DomainEvent {
+    UserCreated { user_id: i64, login: String },
+    OtpIssued { user_id: i64 },
+    LoginSucceeded { user_id: i64 },
+    LoginFailed { user_id: i64, reason: String },
+    /// `count` sessions were ended, by signing out or revoking all sessions
+    SessionRevoked { user_id: i64, count: u64 },
+}
+
+impl DomainEvent {
+    pub fn user_id(&self) -> i64 {
+        match self {
+            DomainEvent::UserCreated { user_id, .. }
+            | DomainEvent::OtpIssued { user_id }
+            | DomainEvent::LoginSucceeded { user_id }
+            | DomainEvent::LoginFailed { user_id, .. }
+            | DomainEvent::SessionRevoked { user_id, .. } => *user_id,
+        }
+    }
+
+    /// Short name for logs and audit records
+    pub fn name(&self) -> &'static str {
+        match self {
+            DomainEvent::UserCreated { .. } => "user_created",
+            DomainEvent::OtpIssued { .. } => "otp_issued",
+            DomainEvent::LoginSucceeded { .. } => "login_succeeded",
+            DomainEvent::LoginFailed { .. } => "login_failed",
+            DomainEvent::SessionRevoked { .. } => "session_revoked",
+        }
+    }
+}
+
+/// Event with the time it happened
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EventEnvelope {
+    pub event: DomainEvent,
+    pub occurred_at: DateTime<Utc>,
+}
+
+impl EventEnvelope {
+    pub fn new(event: DomainEvent) -> Self {
+        Self { event, occurred_at: Utc::now() }
+    }
+}
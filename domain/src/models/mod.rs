@@ -2,4 +2,6 @@ pub mod user;
 pub mod otp;
 pub mod session;
 pub mod email_address;
-
+pub mod avatar;
+pub mod event;
+pub mod outbox;
\ No newline at end of file
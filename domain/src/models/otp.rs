@@ -1,47 +1,59 @@
 use chrono::{DateTime, Utc};
 use sqlx::FromRow;
 use crate::repositories::OTP_LENGTH;
+use crate::services::otp_hasher::OtpHasher;
 
 pub enum OtpError {
     InvalidLength,
 }
 
+/// A pending one-time code. Only the keyed hash of the code is kept, the plaintext is handed
+/// out once through `OtpView` and never stored.
 #[derive(Debug, Clone, FromRow, PartialEq)]
 pub struct Otp {
-    pub id: String,
+    pub code_hash: String,
     pub user_id: i64,
     pub created_at: DateTime<Utc>,
     pub expires_at: DateTime<Utc>,
 }
 
 impl Otp {
-    pub fn new(id: String, user_id: i64, lifetime_seconds: usize) -> Result<Self, String> {
+    pub fn new(code: &str, user_id: i64, lifetime_seconds: usize, hasher: &OtpHasher) -> Result<Self, String> {
         let created_at = Utc::now();
         let expires_at = created_at + chrono::Duration::seconds(lifetime_seconds as i64);
 
-        if id.len() != OTP_LENGTH {
+        if code.len() != OTP_LENGTH {
             return Err("Invalid OTP length".to_owned());
         }
 
-        Ok(Self { id, user_id, created_at, expires_at })
+        let code_hash = hasher.hash(user_id, code);
+
+        Ok(Self { code_hash, user_id, created_at, expires_at })
     }
 
     pub fn is_expired(&self) -> bool {
         self.expires_at < Utc::now()
     }
+
+    /// Compares `code` with the stored hash in constant time
+    pub fn matches(&self, code: &str, hasher: &OtpHasher) -> bool {
+        hasher.verify(self.user_id, code, &self.code_hash)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::models::otp::Otp;
+    use crate::services::otp_hasher::OtpHasher;
 
     #[tokio::test]
     pub async fn test_otp_model_valid() {
         // Given
-        let otp = Otp::new("12345678".to_owned(), 1, 100).unwrap();
+        let hasher = OtpHasher::new(b"secret");
+        let otp = Otp::new("12345678", 1, 100, &hasher).unwrap();
 
         // Then
-        assert_eq!(otp.id, "12345678");
+        assert_eq!(otp.code_hash, hasher.hash(1, "12345678"));
         assert_eq!(otp.user_id, 1);
         assert_eq!(otp.created_at, otp.expires_at - chrono::Duration::seconds(100));
     }
@@ -49,7 +61,7 @@ mod tests {
     #[tokio::test]
     pub async fn test_otp_model_invalid() {
         // Given
-        let otp = Otp::new("1234567".to_owned(), 1, 100);
+        let otp = Otp::new("1234567", 1, 100, &OtpHasher::new(b"secret"));
 
         // Then
         assert_eq!(otp, Err("Invalid OTP length".to_owned()));
@@ -58,9 +70,21 @@ mod tests {
     #[tokio::test]
     pub async fn test_otp_is_expired() {
         // Given
-        let otp = Otp::new("12345678".to_owned(), 1, 100).unwrap();
+        let otp = Otp::new("12345678", 1, 100, &OtpHasher::new(b"secret")).unwrap();
+
+        // Then
+        assert!(!otp.is_expired());
+    }
+
+    #[tokio::test]
+    pub async fn test_otp_matches() {
+        // Given
+        let hasher = OtpHasher::new(b"secret");
+        let otp = Otp::new("12345678", 1, 100, &hasher).unwrap();
 
         // Then
-        assert_eq!(otp.is_expired(), false);
+        assert!(otp.matches("12345678", &hasher));
+        assert!(!otp.matches("12345679", &hasher));
+        assert!(!otp.matches("12345678", &OtpHasher::new(b"other")));
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,90 @@
+use crate::models::event::EventEnvelope;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Stands in for the code in the bodies of a `SealedMail`
+pub const CODE_PLACEHOLDER: &str = "{code}";
+
+/// What an outbox message delivers
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum OutboxPayload {
+    /// Sent through `MailService::send`
+    Mail { to: String, subject: String, html_body: String, plain_body: String },
+    /// Sent through `MailService::send_otp`. The code is only stored sealed by
+    /// `OtpHasher::seal`, the dispatcher opens it right before sending.
+    OtpMail { to: String, user_id: i64, sealed_code: String, expires_at: DateTime<Utc> },
+    /// Sent through `MailService::send` once `CODE_PLACEHOLDER` in the bodies has been replaced
+    /// with the code, which is only stored sealed like in an `OtpMail`
+    SealedMail { to: String, user_id: i64, sealed_code: String, subject: String, html_body: String, plain_body: String },
+    /// Handed to the `EventPublisher`
+    Event(EventEnvelope),
+}
+
+/// Subject and bodies of a mail, to be addressed once it is written to the outbox
+#[derive(Debug, Clone, PartialEq)]
+pub struct MailContent {
+    pub subject: String,
+    pub html_body: String,
+    pub plain_body: String,
+}
+
+impl MailContent {
+    pub fn to(self, to: String) -> OutboxPayload {
+        OutboxPayload::Mail { to, subject: self.subject, html_body: self.html_body, plain_body: self.plain_body }
+    }
+
+    /// Mail carrying the code sealed by `OtpHasher::seal` in place of `CODE_PLACEHOLDER`
+    pub fn sealed(self, to: String, user_id: i64, sealed_code: String) -> OutboxPayload {
+        OutboxPayload::SealedMail {
+            to,
+            user_id,
+            sealed_code,
+            subject: self.subject,
+            html_body: self.html_body,
+            plain_body: self.plain_body,
+        }
+    }
+}
+
+/// Mail or event stored together with the change that caused it and delivered afterwards by
+/// a background dispatcher, so that it is sent exactly when the change was committed
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutboxMessage {
+    pub id: i64,
+    /// Unique per message. Enqueuing a key that is already pending keeps the first message,
+    /// so retried writes don't deliver twice.
+    pub key: String,
+    pub payload: OutboxPayload,
+    /// Delivery attempts made so far
+    pub attempts: i32,
+    pub next_attempt_at: DateTime<Utc>,
+    /// Messages not delivered by then are dropped, e.g. codes that are no longer valid
+    pub expires_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl OutboxMessage {
+    pub fn new(key: String, payload: OutboxPayload) -> Self {
+        let now = Utc::now();
+
+        Self {
+            id: 0,
+            key,
+            payload,
+            attempts: 0,
+            next_attempt_at: now,
+            expires_at: None,
+            created_at: now,
+        }
+    }
+
+    pub fn with_expiry(mut self, expires_at: DateTime<Utc>) -> Self {
+        self.expires_at = Some(expires_at);
+        self
+    }
+
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
@@ -1,7 +1,10 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
+
+/// Longest `User-Agent` kept with a session, longer ones are cut
+pub const MAX_USER_AGENT_LENGTH: usize = 512;
 
 /// Represents a session with an ID, user ID, value, and creation time.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Session {
     /// Unique identifier for the session
     pub id: i64,
@@ -13,6 +16,14 @@ pub struct Session {
     pub created_at: DateTime<Utc>,
     /// Timestamp when the session was expired
     pub expired_at: DateTime<Utc>,
+    /// Timestamp of the last request that extended the session
+    pub last_seen_at: DateTime<Utc>,
+    /// Timestamp the session cannot be extended beyond, however active it is
+    pub absolute_expired_at: DateTime<Utc>,
+    /// `User-Agent` of the client that signed in
+    pub user_agent: Option<String>,
+    /// Address of the client that signed in
+    pub ip_address: Option<String>,
 }
 
 impl Session {
@@ -29,7 +40,7 @@ impl Session {
     /// A new `Session` instance
     pub fn new(value: String, user_id: String, lifetime_seconds: usize) -> Self {
         let created_at = Utc::now();
-        let expired_at = created_at + chrono::Duration::seconds(lifetime_seconds as i64);
+        let expired_at = created_at + Duration::seconds(lifetime_seconds as i64);
 
         Self {
             id: 0,
@@ -37,8 +48,37 @@ impl Session {
             user_id,
             created_at,
             expired_at,
+            last_seen_at: created_at,
+            absolute_expired_at: expired_at,
+            user_agent: None,
+            ip_address: None,
         }
     }
+
+    /// Records the client the session was created for, shown in the list of signed in devices
+    pub fn with_client(mut self, user_agent: Option<String>, ip_address: Option<String>) -> Self {
+        self.user_agent = user_agent.map(|agent| agent.chars().take(MAX_USER_AGENT_LENGTH).collect());
+        self.ip_address = ip_address;
+        self
+    }
+
+    /// Lets the session end `idle_timeout` after creation unless it is extended before
+    pub fn with_idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.expired_at = (self.created_at + idle_timeout).min(self.absolute_expired_at);
+        self
+    }
+
+    /// Whether the last extension is at least `interval` old
+    pub fn is_extension_due(&self, now: DateTime<Utc>, interval: Duration) -> bool {
+        now - self.last_seen_at >= interval
+    }
+
+    /// Marks the session as used at `now`, pushing its end `idle_timeout` ahead but never past
+    /// `absolute_expired_at`
+    pub fn extend(&mut self, now: DateTime<Utc>, idle_timeout: Duration) {
+        self.last_seen_at = now;
+        self.expired_at = (now + idle_timeout).min(self.absolute_expired_at);
+    }
 }
 
 #[cfg(test)]
@@ -104,4 +144,30 @@ mod tests {
         let now = Utc::now();
         assert!(session.expired_at <= now, "Session should be expired");
     }
+
+    #[tokio::test]
+    async fn test_session_sliding_expiry() {
+        // Given
+        let mut session = Session::new("session_token".to_owned(), "user123".to_owned(), 3600)
+            .with_idle_timeout(Duration::seconds(600));
+        let created_at = session.created_at;
+
+        // Then
+        assert_eq!(session.expired_at, created_at + Duration::seconds(600));
+        assert!(!session.is_extension_due(created_at + Duration::seconds(30), Duration::seconds(60)));
+        assert!(session.is_extension_due(created_at + Duration::seconds(60), Duration::seconds(60)));
+
+        // When
+        session.extend(created_at + Duration::seconds(300), Duration::seconds(600));
+
+        // Then
+        assert_eq!(session.last_seen_at, created_at + Duration::seconds(300));
+        assert_eq!(session.expired_at, created_at + Duration::seconds(900));
+
+        // When
+        session.extend(created_at + Duration::seconds(3500), Duration::seconds(600));
+
+        // Then
+        assert_eq!(session.expired_at, session.absolute_expired_at);
+    }
 }
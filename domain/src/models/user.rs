@@ -1,16 +1,18 @@
 use chrono::{DateTime, Utc};
 use sqlx::FromRow;
 
-#[derive(Debug, Clone, FromRow)]
+#[derive(Debug, Clone, PartialEq, FromRow)]
 pub struct User {
     pub id: i64,
     pub username: String,
+    #[sqlx(try_from = "i16")]
     pub login_attempts: i8,
     pub register_complete: bool,
     pub primary_email_id: Option<i64>,
     pub register_date: DateTime<Utc>,
     pub last_update_date: DateTime<Utc>,
     pub last_login_date: Option<DateTime<Utc>>,
+    pub locked_until: Option<DateTime<Utc>>,
 }
 
 impl User {
@@ -26,8 +28,13 @@ impl User {
             register_date: now,
             last_update_date: now,
             last_login_date: None,
+            locked_until: None,
         }
     }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked_until.is_some_and(|until| until > Utc::now())
+    }
 }
 
 #[cfg(test)]
@@ -41,8 +48,20 @@ mod tests {
         assert_eq!(user.id, -1);
         assert_eq!(user.username, "example");
         assert_eq!(user.register_date, user.last_update_date);
-        assert_eq!(user.register_complete, false);
+        assert!(!user.register_complete);
         assert_eq!(user.login_attempts, 0);
         assert_eq!(user.primary_email_id, None);
+        assert!(!user.is_locked());
+    }
+
+    #[tokio::test]
+    pub async fn test_user_is_locked() {
+        let mut user = User::new(String::from("example"));
+
+        user.locked_until = Some(chrono::Utc::now() + chrono::Duration::seconds(60));
+        assert!(user.is_locked());
+
+        user.locked_until = Some(chrono::Utc::now() - chrono::Duration::seconds(60));
+        assert!(!user.is_locked());
     }
 }
\ No newline at end of file
@@ -0,0 +1,176 @@
+use crate::models::avatar::Avatar;
+use crate::repositories::DbError;
+use async_trait::async_trait;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+#[async_trait]
+pub trait AvatarRepository {
+    /// Inserts the avatar when it has no id yet (`id < 0`), otherwise updates the stored avatar
+    async fn save(&self, avatar: Avatar) -> Result<Avatar, DbError>;
+    async fn find_by_id(&self, id: i64) -> Option<Avatar>;
+    async fn find_by_user(&self, user_id: i64) -> Result<Vec<Avatar>, DbError>;
+    async fn find_primary_by_user(&self, user_id: i64) -> Option<Avatar>;
+    /// Marks `avatar_id` as the primary avatar of `user_id` and clears the flag on all others
+    async fn set_primary(&self, user_id: i64, avatar_id: i64) -> Result<(), DbError>;
+    async fn delete(&self, id: i64) -> Result<(), DbError>;
+}
+
+struct AvatarStore {
+    avatars: Vec<Avatar>,
+    counter: i64,
+}
+
+/// Clones share the same avatars
+#[derive(Clone)]
+pub struct InMemoryAvatarRepository {
+    store: Arc<RwLock<AvatarStore>>,
+}
+
+impl Default for InMemoryAvatarRepository {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InMemoryAvatarRepository {
+    pub fn new() -> Self {
+        Self { store: Arc::new(RwLock::new(AvatarStore { avatars: Vec::new(), counter: 1 })) }
+    }
+}
+
+#[async_trait]
+impl AvatarRepository for InMemoryAvatarRepository {
+    async fn save(&self, avatar: Avatar) -> Result<Avatar, DbError> {
+        let mut avatar = avatar;
+        let mut store = self.store.write().await;
+
+        if avatar.id >= 0 {
+            return match store.avatars.iter_mut().find(|a| a.id == avatar.id) {
+                Some(stored) => {
+                    *stored = avatar.clone();
+                    Ok(avatar)
+                }
+                None => Err(DbError::NotFound(format!("Avatar with id {}", avatar.id))),
+            };
+        }
+
+        avatar.id = store.counter;
+        store.counter += 1;
+        store.avatars.push(avatar.clone());
+
+        Ok(avatar)
+    }
+
+    async fn find_by_id(&self, id: i64) -> Option<Avatar> {
+        self.store.read().await.avatars.iter().find(|a| a.id == id).cloned()
+    }
+
+    async fn find_by_user(&self, user_id: i64) -> Result<Vec<Avatar>, DbError> {
+        Ok(self.store.read().await.avatars.iter().filter(|a| a.user_id == user_id).cloned().collect())
+    }
+
+    async fn find_primary_by_user(&self, user_id: i64) -> Option<Avatar> {
+        self.store.read().await.avatars.iter().find(|a| a.user_id == user_id && a.is_primary).cloned()
+    }
+
+    async fn set_primary(&self, user_id: i64, avatar_id: i64) -> Result<(), DbError> {
+        let mut store = self.store.write().await;
+
+        if !store.avatars.iter().any(|a| a.id == avatar_id && a.user_id == user_id) {
+            return Err(DbError::NotFound(format!("Avatar with id {}", avatar_id)));
+        }
+
+        for avatar in store.avatars.iter_mut().filter(|a| a.user_id == user_id) {
+            avatar.is_primary = avatar.id == avatar_id;
+        }
+
+        Ok(())
+    }
+
+    async fn delete(&self, id: i64) -> Result<(), DbError> {
+        self.store.write().await.avatars.retain(|a| a.id != id);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::avatar::CONTENT_TYPE_PNG;
+
+    fn create_test_avatar(user_id: i64, key: &str) -> Avatar {
+        Avatar::new(user_id, CONTENT_TYPE_PNG.to_owned(), 10, key.to_owned())
+    }
+
+    #[tokio::test]
+    async fn test_save_and_find_by_id() {
+        // Given
+        let repo = InMemoryAvatarRepository::new();
+
+        // When
+        let saved = repo.save(create_test_avatar(1, "a")).await.unwrap();
+
+        // Then
+        assert_eq!(saved.id, 1);
+        assert_eq!(repo.find_by_id(saved.id).await, Some(saved));
+    }
+
+    #[tokio::test]
+    async fn test_find_by_user() {
+        // Given
+        let repo = InMemoryAvatarRepository::new();
+        repo.save(create_test_avatar(1, "a")).await.unwrap();
+        repo.save(create_test_avatar(1, "b")).await.unwrap();
+        repo.save(create_test_avatar(2, "c")).await.unwrap();
+
+        // When
+        let avatars = repo.find_by_user(1).await.unwrap();
+
+        // Then
+        assert_eq!(avatars.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_set_primary() {
+        // Given
+        let repo = InMemoryAvatarRepository::new();
+        let first = repo.save(create_test_avatar(1, "a")).await.unwrap();
+        let second = repo.save(create_test_avatar(1, "b")).await.unwrap();
+
+        // When
+        repo.set_primary(1, first.id).await.unwrap();
+        repo.set_primary(1, second.id).await.unwrap();
+
+        // Then
+        assert_eq!(repo.find_primary_by_user(1).await.unwrap().id, second.id);
+        assert!(!repo.find_by_id(first.id).await.unwrap().is_primary);
+    }
+
+    #[tokio::test]
+    async fn test_set_primary_of_other_user() {
+        // Given
+        let repo = InMemoryAvatarRepository::new();
+        let avatar = repo.save(create_test_avatar(1, "a")).await.unwrap();
+
+        // When
+        let result = repo.set_primary(2, avatar.id).await;
+
+        // Then
+        assert!(matches!(result, Err(DbError::NotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn test_delete() {
+        // Given
+        let repo = InMemoryAvatarRepository::new();
+        let avatar = repo.save(create_test_avatar(1, "a")).await.unwrap();
+
+        // When
+        repo.delete(avatar.id).await.unwrap();
+
+        // Then
+        assert!(repo.find_by_id(avatar.id).await.is_none());
+    }
+}
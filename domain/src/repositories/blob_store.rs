@@ -0,0 +1,81 @@
+use crate::repositories::DbError;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+/// Storage for binary objects such as uploaded images, addressed by an opaque key
+#[async_trait]
+pub trait BlobStore {
+    async fn put(&self, key: &str, data: &[u8]) -> Result<(), DbError>;
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, DbError>;
+    async fn delete(&self, key: &str) -> Result<(), DbError>;
+}
+
+/// Clones share the same blobs
+#[derive(Clone)]
+pub struct InMemoryBlobStore {
+    blobs: Arc<RwLock<HashMap<String, Vec<u8>>>>,
+}
+
+impl Default for InMemoryBlobStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InMemoryBlobStore {
+    pub fn new() -> Self {
+        Self { blobs: Arc::new(RwLock::new(HashMap::new())) }
+    }
+}
+
+#[async_trait]
+impl BlobStore for InMemoryBlobStore {
+    async fn put(&self, key: &str, data: &[u8]) -> Result<(), DbError> {
+        self.blobs.write().await.insert(key.to_owned(), data.to_vec());
+
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, DbError> {
+        Ok(self.blobs.read().await.get(key).cloned())
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), DbError> {
+        self.blobs.write().await.remove(key);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_put_and_get() {
+        // Given
+        let store = InMemoryBlobStore::new();
+
+        // When
+        store.put("key", b"data").await.unwrap();
+
+        // Then
+        assert_eq!(store.get("key").await.unwrap(), Some(b"data".to_vec()));
+        assert_eq!(store.get("missing").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_delete() {
+        // Given
+        let store = InMemoryBlobStore::new();
+        store.put("key", b"data").await.unwrap();
+
+        // When
+        store.delete("key").await.unwrap();
+
+        // Then
+        assert_eq!(store.get("key").await.unwrap(), None);
+    }
+}
@@ -0,0 +1,202 @@
+use crate::models::email_address::{normalize_email, Email};
+use crate::repositories::outbox_repository::{InMemoryOutboxRepository, OutboxBuilder, OutboxRepository};
+use crate::repositories::DbError;
+use async_trait::async_trait;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+#[async_trait]
+pub trait EmailRepository {
+    /// Inserts the email when it has no id yet (`id < 0`), otherwise updates the stored email.
+    /// Fails with `UniqueViolation` when the user already has the address or another user has
+    /// already verified it.
+    async fn save(&self, email: Email) -> Result<Email, DbError>;
+    /// Saves the email like `save` together with the outbox messages built from the saved
+    /// email, all or nothing
+    async fn save_with_outbox<'a>(&'a self, email: Email, messages: OutboxBuilder<'a, Email>) -> Result<Email, DbError>;
+    async fn find_by_id(&self, id: i64) -> Option<Email>;
+    async fn find_by_user(&self, user_id: i64) -> Result<Vec<Email>, DbError>;
+    /// Finds the verified email whose address is `value`, compared after normalization
+    async fn find_verified_by_value(&self, value: &str) -> Option<Email>;
+    /// Finds the verified email whose MD5 or SHA-256 hash is `hash`
+    async fn find_verified_by_hash(&self, hash: &str) -> Option<Email>;
+    async fn delete(&self, id: i64) -> Result<(), DbError>;
+}
+
+struct EmailStore {
+    emails: Vec<Email>,
+    counter: i64,
+}
+
+/// Clones share the same emails
+#[derive(Clone)]
+pub struct InMemoryEmailRepository {
+    store: Arc<RwLock<EmailStore>>,
+    outbox: InMemoryOutboxRepository,
+}
+
+impl Default for InMemoryEmailRepository {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InMemoryEmailRepository {
+    pub fn new() -> Self {
+        Self {
+            store: Arc::new(RwLock::new(EmailStore { emails: Vec::new(), counter: 1 })),
+            outbox: InMemoryOutboxRepository::new(),
+        }
+    }
+
+    /// Writes outbox messages to `outbox`, to share it with the dispatcher
+    pub fn with_outbox(mut self, outbox: InMemoryOutboxRepository) -> Self {
+        self.outbox = outbox;
+        self
+    }
+
+    fn save_in(store: &mut EmailStore, mut email: Email) -> Result<Email, DbError> {
+        let conflict = store.emails.iter().any(|e| {
+            e.id != email.id
+                && e.value == email.value
+                && (e.user_id == email.user_id || (e.is_verified && email.is_verified))
+        });
+
+        if conflict {
+            return Err(DbError::UniqueViolation(format!("Email {} is taken", email.value)));
+        }
+
+        if email.id >= 0 {
+            return match store.emails.iter_mut().find(|e| e.id == email.id) {
+                Some(stored) => {
+                    *stored = email.clone();
+                    Ok(email)
+                }
+                None => Err(DbError::NotFound(format!("Email with id {}", email.id))),
+            };
+        }
+
+        email.id = store.counter;
+        store.counter += 1;
+        store.emails.push(email.clone());
+
+        Ok(email)
+    }
+}
+
+#[async_trait]
+impl EmailRepository for InMemoryEmailRepository {
+    async fn save(&self, email: Email) -> Result<Email, DbError> {
+        Self::save_in(&mut *self.store.write().await, email)
+    }
+
+    async fn save_with_outbox<'a>(&'a self, email: Email, messages: OutboxBuilder<'a, Email>) -> Result<Email, DbError> {
+        // Holding the lock keeps the email invisible until its messages are stored as well
+        let mut store = self.store.write().await;
+        let mut staged = EmailStore { emails: store.emails.clone(), counter: store.counter };
+        let email = Self::save_in(&mut staged, email)?;
+
+        for message in messages(&email) {
+            self.outbox.enqueue(message).await?;
+        }
+
+        *store = staged;
+
+        Ok(email)
+    }
+
+    async fn find_by_id(&self, id: i64) -> Option<Email> {
+        self.store.read().await.emails.iter().find(|e| e.id == id).cloned()
+    }
+
+    async fn find_by_user(&self, user_id: i64) -> Result<Vec<Email>, DbError> {
+        Ok(self.store.read().await.emails.iter().filter(|e| e.user_id == user_id).cloned().collect())
+    }
+
+    async fn find_verified_by_value(&self, value: &str) -> Option<Email> {
+        let value = normalize_email(value);
+
+        self.store.read().await.emails.iter().find(|e| e.is_verified && e.value == value).cloned()
+    }
+
+    async fn find_verified_by_hash(&self, hash: &str) -> Option<Email> {
+        self.store.read().await.emails.iter()
+            .find(|e| e.is_verified && (e.md5_hash == hash || e.sha256_hash == hash))
+            .cloned()
+    }
+
+    async fn delete(&self, id: i64) -> Result<(), DbError> {
+        self.store.write().await.emails.retain(|e| e.id != id);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_verified_email(user_id: i64, value: &str) -> Email {
+        let mut email = Email::new(user_id, value.to_owned());
+        email.is_verified = true;
+        email
+    }
+
+    #[tokio::test]
+    async fn test_save_and_find() {
+        // Given
+        let repo = InMemoryEmailRepository::new();
+
+        // When
+        let saved = repo.save(Email::new(1, "a@example.com".to_owned())).await.unwrap();
+
+        // Then
+        assert_eq!(saved.id, 1);
+        assert_eq!(repo.find_by_id(saved.id).await, Some(saved.clone()));
+        assert_eq!(repo.find_by_user(1).await.unwrap(), vec![saved]);
+    }
+
+    #[tokio::test]
+    async fn test_save_duplicate() {
+        // Given
+        let repo = InMemoryEmailRepository::new();
+        repo.save(create_verified_email(1, "a@example.com")).await.unwrap();
+
+        // When
+        let same_user = repo.save(Email::new(1, "A@example.com".to_owned())).await;
+        let other_user_pending = repo.save(Email::new(2, "a@example.com".to_owned())).await;
+        let other_user_verified = repo.save(create_verified_email(3, "a@example.com")).await;
+
+        // Then
+        assert!(matches!(same_user, Err(DbError::UniqueViolation(_))));
+        assert!(other_user_pending.is_ok());
+        assert!(matches!(other_user_verified, Err(DbError::UniqueViolation(_))));
+    }
+
+    #[tokio::test]
+    async fn test_find_verified() {
+        // Given
+        let repo = InMemoryEmailRepository::new();
+        let verified = repo.save(create_verified_email(1, "a@example.com")).await.unwrap();
+        let pending = repo.save(Email::new(1, "b@example.com".to_owned())).await.unwrap();
+
+        // Then
+        assert_eq!(repo.find_verified_by_value(" A@example.com").await, Some(verified.clone()));
+        assert_eq!(repo.find_verified_by_hash(&verified.md5_hash).await, Some(verified.clone()));
+        assert_eq!(repo.find_verified_by_hash(&verified.sha256_hash).await, Some(verified));
+        assert_eq!(repo.find_verified_by_hash(&pending.md5_hash).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_delete() {
+        // Given
+        let repo = InMemoryEmailRepository::new();
+        let saved = repo.save(Email::new(1, "a@example.com".to_owned())).await.unwrap();
+
+        // When
+        repo.delete(saved.id).await.unwrap();
+
+        // Then
+        assert!(repo.find_by_id(saved.id).await.is_none());
+    }
+}
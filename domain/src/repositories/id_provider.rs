@@ -1,13 +1,30 @@
-use rand::{thread_rng, Rng};
+use rand::rngs::OsRng;
+use rand::Rng;
+
+pub const LOWERCASE: &str = "abcdefghijklmnopqrstuvwxyz";
+pub const UPPERCASE: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+pub const DIGITS: &str = "0123456789";
+/// Symbols allowed in URLs and cookie values without any escaping
+pub const URL_SAFE_SYMBOLS: &str = "-_";
 
 pub trait IdProvider {
+    /// Random identifier that is safe to put into URLs and `Set-Cookie` headers as is
     fn get_id(&self, length: usize) -> String;
     fn get_numeric_id(&self, length: usize) -> String;
+    /// Random string whose characters are drawn uniformly from the union of all groups in `alphabet`
     fn get_from_alphabet(&self, alphabet: Vec<&str>, length: usize) -> String;
 }
 
+/// `IdProvider` backed by the operating system CSPRNG
+#[derive(Debug, Clone)]
 pub struct SimpleIdProvider {}
 
+impl Default for SimpleIdProvider {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl SimpleIdProvider {
     pub fn new() -> Self {
         Self {}
@@ -16,33 +33,24 @@ impl SimpleIdProvider {
 
 impl IdProvider for SimpleIdProvider {
     fn get_id(&self, length: usize) -> String {
-        self.get_from_alphabet(
-            vec![
-                "abcdefghijklmnopqrstuvwxyz",
-                "ABCDEFGHIJKLMNOPQRSTUVWXYZ",
-                "0123456789",
-                "!@#$%^&*()_+-=[]{}|;:,.<>?/`~",
-            ],
-            length,
-        )
+        self.get_from_alphabet(vec![LOWERCASE, UPPERCASE, DIGITS, URL_SAFE_SYMBOLS], length)
     }
 
     fn get_numeric_id(&self, length: usize) -> String {
-        self.get_from_alphabet(vec!["0123456789"], length)
+        self.get_from_alphabet(vec![DIGITS], length)
     }
 
     fn get_from_alphabet(&self, alphabet: Vec<&str>, length: usize) -> String {
-        let mut rng = thread_rng();
-
-        (0..length)
-            .map(|_| {
-                let index = rng.gen_range(0..alphabet.len());
-                let charterers = alphabet[index];
+        let mut characters: Vec<char> = alphabet.iter().flat_map(|group| group.chars()).collect();
+        characters.sort_unstable();
+        characters.dedup();
 
-                let char_id = rng.gen_range(0..charterers.len());
+        if characters.is_empty() {
+            return String::new();
+        }
 
-                charterers.chars().nth(char_id).unwrap()
-            })
+        (0..length)
+            .map(|_| characters[OsRng.gen_range(0..characters.len())])
             .collect()
     }
 }
@@ -50,6 +58,7 @@ impl IdProvider for SimpleIdProvider {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::HashMap;
     use tokio;
 
     #[tokio::test]
@@ -97,7 +106,7 @@ mod tests {
         // Given
         let provider = SimpleIdProvider::new();
         let id = provider.get_id(20);
-        let valid_chars: Vec<char> = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789!@#$%^&*()_+-=[]{}|;:,.<>?/`~".chars().collect();
+        let valid_chars: Vec<char> = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789-_".chars().collect();
 
         // Then
         assert!(id.chars().all(|c| valid_chars.contains(&c)), "ID should only contain valid characters");
@@ -125,4 +134,75 @@ mod tests {
         // Then
         assert!(result.chars().all(|c| valid_chars.contains(&c)), "Result should only contain characters from the provided alphabet");
     }
+
+    #[tokio::test]
+    async fn test_get_id_is_cookie_safe() {
+        // Given
+        let provider = SimpleIdProvider::new();
+        let id = provider.get_id(1000);
+
+        // Then
+        assert!(id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'), "ID should be safe for cookies and URLs");
+    }
+
+    #[tokio::test]
+    async fn test_get_from_alphabet_empty() {
+        // Given
+        let provider = SimpleIdProvider::new();
+
+        // Then
+        assert_eq!(provider.get_from_alphabet(vec![], 10), "");
+    }
+
+    /// Pearson's chi-squared statistic of the character counts against a uniform distribution
+    fn chi_squared(sample: &str, alphabet: &str) -> f64 {
+        let mut counts: HashMap<char, usize> = alphabet.chars().map(|c| (c, 0)).collect();
+
+        for c in sample.chars() {
+            *counts.get_mut(&c).expect("character outside of the alphabet") += 1;
+        }
+
+        let expected = sample.chars().count() as f64 / counts.len() as f64;
+
+        counts.values().map(|&count| (count as f64 - expected).powi(2) / expected).sum()
+    }
+
+    #[tokio::test]
+    async fn test_get_from_alphabet_is_uniform_across_groups() {
+        // Given
+        let provider = SimpleIdProvider::new();
+        let result = provider.get_from_alphabet(vec!["ab", "0123456789"], 120_000);
+
+        // Then
+        // 11 degrees of freedom, the 99.99th percentile is ~35.6
+        let statistic = chi_squared(&result, "ab0123456789");
+        assert!(statistic < 40.0, "Characters should be uniformly distributed, chi-squared = {}", statistic);
+    }
+
+    #[tokio::test]
+    async fn test_get_id_is_uniform() {
+        // Given
+        let provider = SimpleIdProvider::new();
+        let id = provider.get_id(128_000);
+
+        // Then
+        // 63 degrees of freedom, the 99.99th percentile is ~111
+        let statistic = chi_squared(&id, &format!("{}{}{}{}", LOWERCASE, UPPERCASE, DIGITS, URL_SAFE_SYMBOLS));
+        assert!(statistic < 120.0, "Characters should be uniformly distributed, chi-squared = {}", statistic);
+
+        let digits = id.chars().filter(|c| c.is_ascii_digit()).count() as f64 / id.len() as f64;
+        assert!((digits - 10.0 / 64.0).abs() < 0.01, "Digits should not be over-represented, ratio = {}", digits);
+    }
+
+    #[tokio::test]
+    async fn test_get_numeric_id_is_uniform() {
+        // Given
+        let provider = SimpleIdProvider::new();
+        let id = provider.get_numeric_id(100_000);
+
+        // Then
+        // 9 degrees of freedom, the 99.99th percentile is ~33.7
+        let statistic = chi_squared(&id, DIGITS);
+        assert!(statistic < 38.0, "Digits should be uniformly distributed, chi-squared = {}", statistic);
+    }
 }
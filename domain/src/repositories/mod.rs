@@ -5,6 +5,11 @@ pub mod user_repository;
 pub mod id_provider;
 pub mod session_repository;
 pub mod otp_repository;
+pub mod avatar_repository;
+pub mod blob_store;
+pub mod email_repository;
+pub mod outbox_repository;
+pub mod unit_of_work;
 pub const OTP_LENGTH: usize = 8;
 
 
@@ -1,72 +1,168 @@
 use crate::models::otp::Otp;
+use crate::models::outbox::OutboxMessage;
+use crate::repositories::outbox_repository::{InMemoryOutboxRepository, OutboxRepository};
+use crate::repositories::unit_of_work::merge_map_changes;
 use crate::repositories::DbError;
 use async_trait::async_trait;
-use chrono::DateTime;
-use std::collections::HashMap;
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tokio::sync::RwLock;
 
+/// Storage for one-time codes. Codes are always scoped to the user they were issued for,
+/// so the same code issued to two users never collides. Codes are addressed by their hash,
+/// the plaintext never reaches the repository.
 #[async_trait]
 pub trait OtpRepository {
-    async fn save<'a>(&'a mut self, otp: Otp) -> Result<Otp, DbError>;
-    async fn find_by_id<'a>(&'a self, id: &'a str) -> Option<Otp>;
-    async fn delete<'a>(&'a mut self, id: &'a str) -> Result<(), DbError>;
+    /// Stores the OTP, purging the codes of the same user that have already expired
+    async fn save<'a>(&'a self, otp: Otp) -> Result<Otp, DbError>;
+    /// Stores the OTP together with the outbox messages announcing it, all or nothing
+    async fn save_with_outbox<'a>(&'a self, otp: Otp, messages: Vec<OutboxMessage>) -> Result<Otp, DbError>;
+    /// Returns the OTP issued to `user_id` with the given code hash, whether expired or not
+    async fn find_by_id<'a>(&'a self, user_id: i64, code_hash: &'a str) -> Option<Otp>;
+    /// Returns every OTP currently stored for `user_id`, including expired ones
+    async fn find_by_user<'a>(&'a self, user_id: i64) -> Result<Vec<Otp>, DbError>;
+    async fn delete<'a>(&'a self, user_id: i64, code_hash: &'a str) -> Result<(), DbError>;
+    /// Atomically removes and returns the OTP issued to `user_id` with the given code hash, so
+    /// that concurrent callers can never both consume the same code
+    async fn consume<'a>(&'a self, user_id: i64, code_hash: &'a str) -> Result<Option<Otp>, DbError>;
+    /// Removes every expired OTP
+    async fn cleanup<'a>(&'a self) -> Result<(), DbError>;
 }
 
+/// `(user_id, code_hash)` to `(created_at, expires_at)` timestamps
+type OtpStore = BTreeMap<(i64, String), (i64, i64)>;
+
+/// Clones share the same store
+#[derive(Clone)]
 pub struct InMemoryOtpRepository {
-    store: HashMap<String, (i64, i64, i64)>,
+    store: Arc<RwLock<OtpStore>>,
+    outbox: InMemoryOutboxRepository,
+}
+
+impl Default for InMemoryOtpRepository {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl InMemoryOtpRepository {
     pub fn new() -> Self {
         Self {
-            store: HashMap::new(),
+            store: Arc::new(RwLock::new(BTreeMap::new())),
+            outbox: InMemoryOutboxRepository::new(),
+        }
+    }
+
+    /// Repository starting with a copy of the codes and outbox messages, not shared with this one
+    pub(crate) async fn snapshot(&self) -> Self {
+        Self {
+            store: Arc::new(RwLock::new(self.store.read().await.clone())),
+            outbox: self.outbox.snapshot().await,
+        }
+    }
+
+    /// Writes the codes and outbox messages changed from `base` to `working` back, see
+    /// `unit_of_work::merge_changes`
+    pub(crate) async fn merge(&self, base: &Self, working: &Self) {
+        {
+            let base = base.store.read().await;
+            let working = working.store.read().await;
+
+            merge_map_changes(&mut *self.store.write().await, &base, &working);
+        }
+
+        self.outbox.merge(&base.outbox, &working.outbox).await;
+    }
+
+    /// Writes outbox messages to `outbox`, to share it with the dispatcher
+    pub fn with_outbox(mut self, outbox: InMemoryOutboxRepository) -> Self {
+        self.outbox = outbox;
+        self
+    }
+
+    /// Removes the expired codes of `user_id`, codes of other users are left to `cleanup`
+    fn purge_expired(store: &mut OtpStore, user_id: i64) {
+        let now = Utc::now().timestamp();
+
+        store.retain(|(owner, _), (_, expires_at)| *owner != user_id || *expires_at > now);
+    }
+
+    fn to_otp(user_id: i64, code_hash: &str, (created_at, expires_at): (i64, i64)) -> Option<Otp> {
+        let created = DateTime::from_timestamp(created_at, 0)?;
+        let expired = DateTime::from_timestamp(expires_at, 0)?;
+
+        if created > expired {
+            return None;
         }
+
+        Some(Otp {
+            code_hash: code_hash.to_string(),
+            user_id,
+            created_at: created,
+            expires_at: expired,
+        })
     }
 }
 
 #[async_trait]
 impl OtpRepository for InMemoryOtpRepository {
-    async fn save<'a>(&'a mut self, otp: Otp) -> Result<Otp, DbError> {
-        self.store.insert(otp.id.clone(), (otp.user_id.clone(), otp.created_at.timestamp(), otp.expires_at.timestamp()));
+    async fn save<'a>(&'a self, otp: Otp) -> Result<Otp, DbError> {
+        let mut store = self.store.write().await;
+
+        Self::purge_expired(&mut store, otp.user_id);
+        store.insert((otp.user_id, otp.code_hash.clone()), (otp.created_at.timestamp(), otp.expires_at.timestamp()));
 
         Ok(otp)
     }
 
-    async fn find_by_id<'a>(&'a self, id: &'a str) -> Option<Otp> {
-        let otp = self.store.get(id);
-
-        match otp {
-            Some((user_id, created_at, expires_at)) => {
-                let created = match DateTime::from_timestamp(*created_at, 0) {
-                    None => {
-                        return None;
-                    }
-                    Some(created) => created,
-                };
-
-                let expired = match DateTime::from_timestamp(*expires_at, 0) {
-                    None => {
-                        return None;
-                    }
-                    Some(expired) => expired,
-                };
-
-                if created > expired {
-                    return None;
-                }
-
-                Some(Otp {
-                    id: id.to_string(),
-                    user_id: *user_id,
-                    created_at: created,
-                    expires_at: expired,
-                })
-            }
-            None => None,
+    async fn save_with_outbox<'a>(&'a self, otp: Otp, messages: Vec<OutboxMessage>) -> Result<Otp, DbError> {
+        // Holding the lock keeps the code invisible until its messages are stored as well
+        let mut store = self.store.write().await;
+
+        Self::purge_expired(&mut store, otp.user_id);
+
+        for message in messages {
+            self.outbox.enqueue(message).await?;
         }
+
+        store.insert((otp.user_id, otp.code_hash.clone()), (otp.created_at.timestamp(), otp.expires_at.timestamp()));
+
+        Ok(otp)
     }
 
-    async fn delete<'a>(&'a mut self, id: &'a str) -> Result<(), DbError> {
-        self.store.remove(id);
+    async fn find_by_id<'a>(&'a self, user_id: i64, code_hash: &'a str) -> Option<Otp> {
+        let store = self.store.read().await;
+
+        store.get(&(user_id, code_hash.to_string()))
+            .and_then(|times| Self::to_otp(user_id, code_hash, *times))
+    }
+
+    async fn find_by_user<'a>(&'a self, user_id: i64) -> Result<Vec<Otp>, DbError> {
+        let store = self.store.read().await;
+
+        Ok(store.iter()
+            .filter(|((owner, _), _)| *owner == user_id)
+            .filter_map(|((owner, code_hash), times)| Self::to_otp(*owner, code_hash, *times))
+            .collect())
+    }
+
+    async fn delete<'a>(&'a self, user_id: i64, code_hash: &'a str) -> Result<(), DbError> {
+        self.store.write().await.remove(&(user_id, code_hash.to_string()));
+
+        Ok(())
+    }
+
+    async fn consume<'a>(&'a self, user_id: i64, code_hash: &'a str) -> Result<Option<Otp>, DbError> {
+        let removed = self.store.write().await.remove(&(user_id, code_hash.to_string()));
+
+        Ok(removed.and_then(|times| Self::to_otp(user_id, code_hash, times)))
+    }
+
+    async fn cleanup<'a>(&'a self) -> Result<(), DbError> {
+        let now = Utc::now().timestamp();
+
+        self.store.write().await.retain(|_, (_, expires_at)| *expires_at > now);
 
         Ok(())
     }
@@ -75,9 +171,12 @@ impl OtpRepository for InMemoryOtpRepository {
 #[cfg(test)]
 mod tests {
     use super::*;
-    fn create_test_otp(id: &str, user_id: i64, created_at: i64, expires_at: i64) -> Otp {
+    use crate::models::event::{DomainEvent, EventEnvelope};
+    use crate::models::outbox::OutboxPayload;
+
+    fn create_test_otp(code_hash: &str, user_id: i64, created_at: i64, expires_at: i64) -> Otp {
         Otp {
-            id: id.to_string(),
+            code_hash: code_hash.to_string(),
             user_id,
             created_at: DateTime::from_timestamp(created_at, 0).unwrap(),
             expires_at: DateTime::from_timestamp(expires_at, 0).unwrap(),
@@ -87,7 +186,7 @@ mod tests {
     #[tokio::test]
     async fn test_save() {
         // Given
-        let mut repo = InMemoryOtpRepository::new();
+        let repo = InMemoryOtpRepository::new();
         let otp = create_test_otp("1", 123, 1627846261, 1627849861);
 
         // When
@@ -95,23 +194,23 @@ mod tests {
 
         // Then
         assert!(result.is_ok());
-        assert_eq!(repo.store.get("1"), Some(&(123, 1627846261, 1627849861)));
+        assert_eq!(repo.store.read().await.get(&(123, "1".to_string())), Some(&(1627846261, 1627849861)));
     }
 
     #[tokio::test]
     async fn test_find_by_id() {
         // Given
-        let mut repo = InMemoryOtpRepository::new();
+        let repo = InMemoryOtpRepository::new();
         let otp = create_test_otp("1", 123, 1627846261, 1627849861);
         repo.save(otp.clone()).await.unwrap();
 
         // When
-        let found = repo.find_by_id("1").await;
+        let found = repo.find_by_id(123, "1").await;
 
         // Then
         assert!(found.is_some());
         let found_otp = found.unwrap();
-        assert_eq!(found_otp.id, "1");
+        assert_eq!(found_otp.code_hash, "1");
         assert_eq!(found_otp.user_id, 123);
         assert_eq!(found_otp.created_at.timestamp(), 1627846261);
         assert_eq!(found_otp.expires_at.timestamp(), 1627849861);
@@ -123,7 +222,21 @@ mod tests {
         let repo = InMemoryOtpRepository::new();
 
         // When
-        let found = repo.find_by_id("1").await;
+        let found = repo.find_by_id(123, "1").await;
+
+        // Then
+        assert!(found.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_find_by_id_other_user() {
+        // Given
+        let repo = InMemoryOtpRepository::new();
+        let otp = create_test_otp("1", 123, 1627846261, 1627849861);
+        repo.save(otp.clone()).await.unwrap();
+
+        // When
+        let found = repo.find_by_id(456, "1").await;
 
         // Then
         assert!(found.is_none());
@@ -132,34 +245,125 @@ mod tests {
     #[tokio::test]
     async fn test_find_by_id_expired() {
         // Given
-        let mut repo = InMemoryOtpRepository::new();
+        let repo = InMemoryOtpRepository::new();
         let otp = create_test_otp("1", 123, 1627846261, 1627849861);
         repo.save(otp.clone()).await.unwrap();
 
         // When
-        let found = repo.find_by_id("1").await;
+        let found = repo.find_by_id(123, "1").await;
 
         // Then
         assert!(found.is_some());
         let found_otp = found.unwrap();
-        assert_eq!(found_otp.id, "1");
+        assert_eq!(found_otp.code_hash, "1");
         assert_eq!(found_otp.user_id, 123);
         assert_eq!(found_otp.created_at.timestamp(), 1627846261);
         assert_eq!(found_otp.expires_at.timestamp(), 1627849861);
     }
 
+    #[tokio::test]
+    async fn test_save_purges_expired() {
+        // Given
+        let repo = InMemoryOtpRepository::new();
+        let now = Utc::now().timestamp();
+        repo.save(create_test_otp("1", 123, 1627846261, 1627849861)).await.unwrap();
+
+        // When
+        repo.save(create_test_otp("2", 123, now, now + 300)).await.unwrap();
+
+        // Then
+        assert!(repo.find_by_id(123, "1").await.is_none());
+        assert!(repo.find_by_id(123, "2").await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_save_keeps_expired_codes_of_other_users() {
+        // Given
+        let repo = InMemoryOtpRepository::new();
+        let now = Utc::now().timestamp();
+        repo.save(create_test_otp("1", 456, 1627846261, 1627849861)).await.unwrap();
+
+        // When
+        repo.save(create_test_otp("2", 123, now, now + 300)).await.unwrap();
+
+        // Then
+        assert!(repo.find_by_id(456, "1").await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_save_with_outbox() {
+        // Given
+        let outbox = InMemoryOutboxRepository::new();
+        let repo = InMemoryOtpRepository::new().with_outbox(outbox.clone());
+        let now = Utc::now().timestamp();
+        let message = OutboxMessage::new("otp:1".to_string(), OutboxPayload::Event(EventEnvelope::new(DomainEvent::OtpIssued { user_id: 123 })));
+
+        // When
+        repo.save_with_outbox(create_test_otp("1", 123, now, now + 300), vec![message]).await.unwrap();
+
+        // Then
+        assert!(repo.find_by_id(123, "1").await.is_some());
+        assert_eq!(outbox.messages().await[0].key, "otp:1");
+    }
+
     #[tokio::test]
     async fn test_delete() {
         // Given
-        let mut repo = InMemoryOtpRepository::new();
+        let repo = InMemoryOtpRepository::new();
         let otp = create_test_otp("1", 123, 1627846261, 1627849861);
         repo.save(otp.clone()).await.unwrap();
 
         // When
-        let result = repo.delete("1").await;
+        let result = repo.delete(123, "1").await;
+
+        // Then
+        assert!(result.is_ok());
+        assert!(!repo.store.read().await.contains_key(&(123, "1".to_string())));
+    }
+
+    #[tokio::test]
+    async fn test_find_by_user() {
+        // Given
+        let repo = InMemoryOtpRepository::new();
+        let now = Utc::now().timestamp();
+        repo.save(create_test_otp("1", 123, now, now + 300)).await.unwrap();
+        repo.save(create_test_otp("2", 456, now, now + 300)).await.unwrap();
+
+        // When
+        let found = repo.find_by_user(123).await.unwrap();
+
+        // Then
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].code_hash, "1");
+    }
+
+    #[tokio::test]
+    async fn test_consume() {
+        // Given
+        let repo = InMemoryOtpRepository::new();
+        repo.save(create_test_otp("1", 123, 1627846261, 1627849861)).await.unwrap();
+
+        // When
+        let first = repo.consume(123, "1").await.unwrap();
+        let second = repo.consume(123, "1").await.unwrap();
+
+        // Then
+        assert_eq!(first.unwrap().code_hash, "1");
+        assert!(second.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_cleanup() {
+        // Given
+        let repo = InMemoryOtpRepository::new();
+        let otp = create_test_otp("1", 123, 1627846261, 1627849861);
+        repo.store.write().await.insert((otp.user_id, otp.code_hash.clone()), (otp.created_at.timestamp(), otp.expires_at.timestamp()));
+
+        // When
+        let result = repo.cleanup().await;
 
         // Then
         assert!(result.is_ok());
-        assert!(repo.store.get("1").is_none());
+        assert!(repo.store.read().await.is_empty());
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,253 @@
+use crate::models::outbox::OutboxMessage;
+use crate::repositories::unit_of_work::merge_map_changes;
+use crate::repositories::DbError;
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+/// Builds the outbox messages belonging to a record from the record as written, e.g. to use the
+/// id it was given
+pub type OutboxBuilder<'a, T> = Box<dyn FnOnce(&T) -> Vec<OutboxMessage> + Send + 'a>;
+
+/// Pending outbox messages. Messages are written by the repositories that make the change
+/// they belong to and read back by the dispatcher, which deletes them once delivered.
+#[async_trait]
+pub trait OutboxRepository: Send + Sync {
+    /// Stores the message unless one with the same key is already stored
+    async fn enqueue(&self, message: OutboxMessage) -> Result<(), DbError>;
+    /// Returns up to `limit` unexpired messages due at `now`, oldest first, counting an attempt
+    /// for each. They are not due again until `lease` has passed, so concurrent dispatchers
+    /// never claim the same message while it is being delivered.
+    async fn claim_due(&self, now: DateTime<Utc>, limit: usize, lease: Duration) -> Result<Vec<OutboxMessage>, DbError>;
+    /// Removes a delivered message
+    async fn mark_delivered(&self, id: i64) -> Result<(), DbError>;
+    /// Records a failed attempt. The message is due again at `retry_at`, or never when `None`,
+    /// in which case it is kept with `error` for inspection.
+    async fn mark_failed(&self, id: i64, error: &str, retry_at: Option<DateTime<Utc>>) -> Result<(), DbError>;
+    /// Removes expired messages, returning how many were removed
+    async fn cleanup(&self, now: DateTime<Utc>) -> Result<u64, DbError>;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct OutboxEntry {
+    message: OutboxMessage,
+    last_error: Option<String>,
+    /// Set once the dispatcher gave up on the message
+    failed: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+struct OutboxStore {
+    entries: BTreeMap<i64, OutboxEntry>,
+}
+
+/// Clones share the same messages
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryOutboxRepository {
+    store: Arc<RwLock<OutboxStore>>,
+    /// Shared with snapshots, so that messages enqueued in a transaction never take the id of
+    /// one enqueued outside of it
+    last_id: Arc<AtomicI64>,
+}
+
+impl InMemoryOutboxRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Repository starting with a copy of the messages, not shared with this one
+    pub(crate) async fn snapshot(&self) -> Self {
+        Self {
+            store: Arc::new(RwLock::new(self.store.read().await.clone())),
+            last_id: self.last_id.clone(),
+        }
+    }
+
+    /// Writes the messages changed from `base` to `working` back, see
+    /// `unit_of_work::merge_changes`. Added messages whose key has been enqueued in the
+    /// meantime are dropped, like `enqueue` drops them.
+    pub(crate) async fn merge(&self, base: &Self, working: &Self) {
+        let base = base.store.read().await;
+        let mut working = working.store.read().await.entries.clone();
+        let mut store = self.store.write().await;
+
+        working.retain(|id, entry| {
+            base.entries.contains_key(id) || !store.entries.values().any(|stored| stored.message.key == entry.message.key)
+        });
+
+        merge_map_changes(&mut store.entries, &base.entries, &working);
+    }
+
+    /// Every stored message, whether due, failed or not
+    pub async fn messages(&self) -> Vec<OutboxMessage> {
+        self.store.read().await.entries.values().map(|entry| entry.message.clone()).collect()
+    }
+
+    /// Error of the last failed attempt to deliver message `id`
+    pub async fn last_error(&self, id: i64) -> Option<String> {
+        self.store.read().await.entries.get(&id).and_then(|entry| entry.last_error.clone())
+    }
+}
+
+#[async_trait]
+impl OutboxRepository for InMemoryOutboxRepository {
+    async fn enqueue(&self, mut message: OutboxMessage) -> Result<(), DbError> {
+        let mut store = self.store.write().await;
+
+        if store.entries.values().any(|entry| entry.message.key == message.key) {
+            return Ok(());
+        }
+
+        message.id = self.last_id.fetch_add(1, Ordering::SeqCst) + 1;
+        store.entries.insert(message.id, OutboxEntry { message, last_error: None, failed: false });
+
+        Ok(())
+    }
+
+    async fn claim_due(&self, now: DateTime<Utc>, limit: usize, lease: Duration) -> Result<Vec<OutboxMessage>, DbError> {
+        let mut store = self.store.write().await;
+
+        Ok(store.entries.values_mut()
+            .filter(|entry| !entry.failed && entry.message.next_attempt_at <= now && !entry.message.is_expired(now))
+            .take(limit)
+            .map(|entry| {
+                entry.message.attempts += 1;
+                entry.message.next_attempt_at = now + lease;
+                entry.message.clone()
+            })
+            .collect())
+    }
+
+    async fn mark_delivered(&self, id: i64) -> Result<(), DbError> {
+        self.store.write().await.entries.remove(&id);
+
+        Ok(())
+    }
+
+    async fn mark_failed(&self, id: i64, error: &str, retry_at: Option<DateTime<Utc>>) -> Result<(), DbError> {
+        let mut store = self.store.write().await;
+
+        let entry = store.entries.get_mut(&id).ok_or_else(|| DbError::NotFound(format!("Outbox message {}", id)))?;
+
+        entry.last_error = Some(error.to_owned());
+
+        match retry_at {
+            Some(retry_at) => entry.message.next_attempt_at = retry_at,
+            None => entry.failed = true,
+        }
+
+        Ok(())
+    }
+
+    async fn cleanup(&self, now: DateTime<Utc>) -> Result<u64, DbError> {
+        let mut store = self.store.write().await;
+        let before = store.entries.len();
+
+        store.entries.retain(|_, entry| !entry.message.is_expired(now));
+
+        Ok((before - store.entries.len()) as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::outbox::OutboxPayload;
+
+    fn create_test_message(key: &str) -> OutboxMessage {
+        OutboxMessage::new(key.to_string(), OutboxPayload::Mail {
+            to: "alice@example.com".to_string(),
+            subject: "Hello".to_string(),
+            html_body: "<p>Hello</p>".to_string(),
+            plain_body: "Hello".to_string(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_enqueue_is_idempotent() {
+        // Given
+        let repo = InMemoryOutboxRepository::new();
+
+        // When
+        repo.enqueue(create_test_message("a")).await.unwrap();
+        repo.enqueue(create_test_message("a")).await.unwrap();
+        repo.enqueue(create_test_message("b")).await.unwrap();
+
+        // Then
+        let keys: Vec<String> = repo.messages().await.into_iter().map(|m| m.key).collect();
+        assert_eq!(keys, vec!["a", "b"]);
+    }
+
+    #[tokio::test]
+    async fn test_claim_due_leases_messages() {
+        // Given
+        let repo = InMemoryOutboxRepository::new();
+        repo.enqueue(create_test_message("a")).await.unwrap();
+        let now = Utc::now();
+
+        // When
+        let first = repo.claim_due(now, 10, Duration::seconds(30)).await.unwrap();
+        let second = repo.claim_due(now, 10, Duration::seconds(30)).await.unwrap();
+        let after_lease = repo.claim_due(now + Duration::seconds(31), 10, Duration::seconds(30)).await.unwrap();
+
+        // Then
+        assert_eq!(first.len(), 1);
+        assert_eq!(first[0].attempts, 1);
+        assert!(second.is_empty());
+        assert_eq!(after_lease[0].attempts, 2);
+    }
+
+    #[tokio::test]
+    async fn test_claim_due_skips_expired() {
+        // Given
+        let repo = InMemoryOutboxRepository::new();
+        let now = Utc::now();
+        repo.enqueue(create_test_message("a").with_expiry(now - Duration::seconds(1))).await.unwrap();
+
+        // When
+        let claimed = repo.claim_due(now, 10, Duration::seconds(30)).await.unwrap();
+        let removed = repo.cleanup(now).await.unwrap();
+
+        // Then
+        assert!(claimed.is_empty());
+        assert_eq!(removed, 1);
+        assert!(repo.messages().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_mark_failed() {
+        // Given
+        let repo = InMemoryOutboxRepository::new();
+        repo.enqueue(create_test_message("a")).await.unwrap();
+        let now = Utc::now();
+        let id = repo.claim_due(now, 10, Duration::seconds(30)).await.unwrap()[0].id;
+
+        // When
+        repo.mark_failed(id, "timeout", Some(now)).await.unwrap();
+        let retried = repo.claim_due(now, 10, Duration::seconds(30)).await.unwrap();
+        repo.mark_failed(id, "rejected", None).await.unwrap();
+        let given_up = repo.claim_due(now + Duration::days(1), 10, Duration::seconds(30)).await.unwrap();
+
+        // Then
+        assert_eq!(retried.len(), 1);
+        assert!(given_up.is_empty());
+        assert_eq!(repo.last_error(id).await, Some("rejected".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_mark_delivered() {
+        // Given
+        let repo = InMemoryOutboxRepository::new();
+        repo.enqueue(create_test_message("a")).await.unwrap();
+        let id = repo.messages().await[0].id;
+
+        // When
+        repo.mark_delivered(id).await.unwrap();
+
+        // Then
+        assert!(repo.messages().await.is_empty());
+    }
+}
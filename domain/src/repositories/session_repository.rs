@@ -1,5 +1,10 @@
 use crate::models::session::Session;
+use crate::repositories::unit_of_work::merge_changes;
+use crate::repositories::DbError;
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::sync::Arc;
+use tokio::sync::RwLock;
 
 #[async_trait::async_trait]
 pub trait SessionRepository: Send + Sync {
@@ -12,7 +17,8 @@ pub trait SessionRepository: Send + Sync {
     /// ### Returns
     ///
     /// An `Option<Session>` containing the session if found, or `None` if the session does not exist
-    async fn load(&mut self, id: &str) -> Option<Session>;
+    /// or has already expired
+    async fn load(&self, id: &str) -> Option<Session>;
 
     /// Save the session and return the session ID
     ///
@@ -23,7 +29,7 @@ pub trait SessionRepository: Send + Sync {
     /// ### Returns
     ///
     /// The unique identifier of the saved session
-    async fn save(&mut self, session: &Session) -> String;
+    async fn save(&self, session: &Session) -> Result<String, DbError>;
 
     /// Destroy the session by its ID
     ///
@@ -34,57 +40,184 @@ pub trait SessionRepository: Send + Sync {
     /// ### Returns
     ///
     /// A `Result` indicating whether the session was successfully destroyed
-    async fn destroy(&mut self, id: &str) -> Result<bool, String>;
+    async fn destroy(&self, id: &str) -> Result<bool, DbError>;
+
+    /// Record activity on an unexpired session, moving its expiry
+    ///
+    /// ### Arguments
+    ///
+    /// * `id` - The unique identifier for the session to extend
+    /// * `last_seen_at` - Time of the request that extends the session
+    /// * `expired_at` - The new expiry of the session
+    ///
+    /// ### Returns
+    ///
+    /// Whether an unexpired session was found and extended
+    async fn extend(&self, id: &str, last_seen_at: DateTime<Utc>, expired_at: DateTime<Utc>) -> Result<bool, DbError>;
+
+    /// Replace the identifier of an unexpired session, keeping everything else
+    ///
+    /// ### Arguments
+    ///
+    /// * `id` - The current identifier of the session
+    /// * `new_id` - The identifier the session is known by from now on
+    ///
+    /// ### Returns
+    ///
+    /// Whether an unexpired session was found and rotated
+    async fn rotate(&self, id: &str, new_id: &str) -> Result<bool, DbError>;
+
+    /// Load the unexpired sessions of a user, newest first
+    ///
+    /// ### Arguments
+    ///
+    /// * `user_id` - The identifier of the user whose sessions to load
+    async fn find_by_user(&self, user_id: i64) -> Result<Vec<Session>, DbError>;
+
+    /// Destroy every session of a user
+    ///
+    /// ### Arguments
+    ///
+    /// * `user_id` - The identifier of the user whose sessions to destroy
+    ///
+    /// ### Returns
+    ///
+    /// The number of destroyed sessions
+    async fn destroy_by_user(&self, user_id: i64) -> Result<u64, DbError>;
 
     /// Clean up expired sessions
     ///
     /// ### Returns
     ///
     /// A `Result` indicating whether the cleanup operation was successful
-    async fn cleanup(&mut self) -> Result<(), String>;
+    async fn cleanup(&self) -> Result<(), String>;
 }
 
+/// Clones share the same sessions
+#[derive(Clone)]
 pub struct InMemorySessionRepository {
-    sessions: Vec<Session>,
+    sessions: Arc<RwLock<Vec<Session>>>,
+}
+
+impl Default for InMemorySessionRepository {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl InMemorySessionRepository {
     pub fn new() -> Self {
-        InMemorySessionRepository { sessions: Vec::new() }
+        InMemorySessionRepository { sessions: Arc::new(RwLock::new(Vec::new())) }
+    }
+
+    /// Repository starting with a copy of the sessions, not shared with this one
+    pub(crate) async fn snapshot(&self) -> Self {
+        InMemorySessionRepository { sessions: Arc::new(RwLock::new(self.sessions.read().await.clone())) }
+    }
+
+    /// Writes the sessions changed from `base` to `working` back, see
+    /// `unit_of_work::merge_changes`
+    pub(crate) async fn merge(&self, base: &Self, working: &Self) {
+        let base = base.sessions.read().await;
+        let working = working.sessions.read().await;
+
+        merge_changes(&mut *self.sessions.write().await, &base, &working, |session| session.value.clone());
     }
 }
 
 #[async_trait]
 impl SessionRepository for InMemorySessionRepository {
-    async fn load(&mut self, id: &str) -> Option<Session> {
-        let session = self.sessions.iter().find(|s| s.value == id);
+    async fn load(&self, id: &str) -> Option<Session> {
+        let now = Utc::now();
 
-        match session {
-            Some(session) => Some(session.clone()),
-            None => None,
-        }
+        self.sessions.read().await.iter().find(|s| s.value == id && s.expired_at > now).cloned()
     }
 
-    async fn save(&mut self, session: &Session) -> String {
-        let mut session = session.clone();
-        session.value = "1".to_string();
+    async fn save(&self, session: &Session) -> Result<String, DbError> {
+        let mut sessions = self.sessions.write().await;
+
+        if sessions.iter().any(|s| s.value == session.value) {
+            return Err(DbError::UniqueViolation("Session value already exists".to_string()));
+        }
 
-        self.sessions.push(session.clone());
+        sessions.push(session.clone());
 
-        session.value
+        Ok(session.value.clone())
     }
 
-    async fn destroy(&mut self, id: &str) -> Result<bool, String> {
-        if let Some(index) = self.sessions.iter().position(|s| s.value == id) {
-            self.sessions.remove(index);
+    async fn destroy(&self, id: &str) -> Result<bool, DbError> {
+        let mut sessions = self.sessions.write().await;
+
+        if let Some(index) = sessions.iter().position(|s| s.value == id) {
+            sessions.remove(index);
 
             Ok(true)
         } else {
-            Err("Session not found".to_string())
+            Err(DbError::NotFound(format!("Session {}", id)))
+        }
+    }
+
+    async fn extend(&self, id: &str, last_seen_at: DateTime<Utc>, expired_at: DateTime<Utc>) -> Result<bool, DbError> {
+        let now = Utc::now();
+
+        match self.sessions.write().await.iter_mut().find(|s| s.value == id && s.expired_at > now) {
+            Some(session) => {
+                session.last_seen_at = last_seen_at;
+                session.expired_at = expired_at;
+
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn rotate(&self, id: &str, new_id: &str) -> Result<bool, DbError> {
+        let now = Utc::now();
+        let mut sessions = self.sessions.write().await;
+
+        if sessions.iter().any(|s| s.value == new_id) {
+            return Err(DbError::UniqueViolation("Session value already exists".to_string()));
+        }
+
+        match sessions.iter_mut().find(|s| s.value == id && s.expired_at > now) {
+            Some(session) => {
+                session.value = new_id.to_owned();
+
+                Ok(true)
+            }
+            None => Ok(false),
         }
     }
 
-    async fn cleanup(&mut self) -> Result<(), String> {
+    async fn find_by_user(&self, user_id: i64) -> Result<Vec<Session>, DbError> {
+        let now = Utc::now();
+        let user_id = user_id.to_string();
+
+        let mut sessions: Vec<Session> = self.sessions.read().await.iter()
+            .filter(|s| s.user_id == user_id && s.expired_at > now)
+            .cloned()
+            .collect();
+
+        sessions.sort_by_key(|s| std::cmp::Reverse(s.created_at));
+
+        Ok(sessions)
+    }
+
+    async fn destroy_by_user(&self, user_id: i64) -> Result<u64, DbError> {
+        let user_id = user_id.to_string();
+        let mut sessions = self.sessions.write().await;
+        let before = sessions.len();
+
+        sessions.retain(|s| s.user_id != user_id);
+
+        Ok((before - sessions.len()) as u64)
+    }
+
+    async fn cleanup(&self) -> Result<(), String> {
+        let now = Utc::now();
+
+        self.sessions.write().await.retain(|s| s.expired_at > now);
+
         Ok(())
     }
 }
@@ -100,9 +233,9 @@ mod tests {
     #[tokio::test]
     async fn test_load() {
         // Given
-        let mut repo = InMemorySessionRepository::new();
+        let repo = InMemorySessionRepository::new();
         let session = create_test_session("1");
-        repo.save(&session).await;
+        repo.save(&session).await.unwrap();
 
         // When
         let loaded = repo.load("1").await;
@@ -115,11 +248,11 @@ mod tests {
     #[tokio::test]
     async fn test_save() {
         // Given
-        let mut repo = InMemorySessionRepository::new();
+        let repo = InMemorySessionRepository::new();
         let session = create_test_session("1");
 
         // When
-        let id = repo.save(&session).await;
+        let id = repo.save(&session).await.unwrap();
 
         // Then
         assert_eq!(id, "1");
@@ -129,9 +262,9 @@ mod tests {
     #[tokio::test]
     async fn test_destroy() {
         // Given
-        let mut repo = InMemorySessionRepository::new();
+        let repo = InMemorySessionRepository::new();
         let session = create_test_session("1");
-        repo.save(&session).await;
+        repo.save(&session).await.unwrap();
 
         // When
         let result = repo.destroy("1").await;
@@ -141,15 +274,88 @@ mod tests {
         assert!(repo.load("1").await.is_none());
     }
 
+    #[tokio::test]
+    async fn test_extend_and_rotate() {
+        // Given
+        let repo = InMemorySessionRepository::new();
+        repo.save(&create_test_session("1")).await.unwrap();
+        let expired_at = Utc::now() + chrono::Duration::seconds(600);
+
+        // When
+        let extended = repo.extend("1", Utc::now(), expired_at).await.unwrap();
+        let rotated = repo.rotate("1", "2").await.unwrap();
+        let missing = repo.rotate("1", "3").await.unwrap();
+
+        // Then
+        assert!(extended && rotated && !missing);
+        assert!(repo.load("1").await.is_none());
+        assert_eq!(repo.load("2").await.unwrap().expired_at, expired_at);
+    }
+
+    #[tokio::test]
+    async fn test_find_and_destroy_by_user() {
+        // Given
+        let repo = InMemorySessionRepository::new();
+        repo.save(&Session::new("1".to_owned(), "1".to_owned(), 300)).await.unwrap();
+        repo.save(&Session::new("2".to_owned(), "1".to_owned(), 300)).await.unwrap();
+        repo.save(&Session::new("3".to_owned(), "2".to_owned(), 300)).await.unwrap();
+
+        // When
+        let found = repo.find_by_user(1).await.unwrap();
+        let destroyed = repo.destroy_by_user(1).await.unwrap();
+
+        // Then
+        assert_eq!(found.len(), 2);
+        assert_eq!(destroyed, 2);
+        assert!(repo.find_by_user(1).await.unwrap().is_empty());
+        assert!(repo.load("3").await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_save_keeps_session_value() {
+        // Given
+        let repo = InMemorySessionRepository::new();
+        let session = create_test_session("token");
+
+        // When
+        let id = repo.save(&session).await.unwrap();
+
+        // Then
+        assert_eq!(id, "token");
+        assert_eq!(repo.load("token").await.unwrap().value, "token");
+    }
+
+    #[tokio::test]
+    async fn test_load_expired() {
+        // Given
+        let repo = InMemorySessionRepository::new();
+        let mut session = create_test_session("1");
+        session.expired_at = Utc::now() - chrono::Duration::seconds(1);
+        repo.save(&session).await.unwrap();
+
+        // When
+        let loaded = repo.load("1").await;
+
+        // Then
+        assert!(loaded.is_none());
+    }
+
     #[tokio::test]
     async fn test_cleanup() {
         // Given
-        let mut repo = InMemorySessionRepository::new();
+        let repo = InMemorySessionRepository::new();
+        let mut expired = create_test_session("1");
+        expired.expired_at = Utc::now() - chrono::Duration::seconds(1);
+        repo.save(&expired).await.unwrap();
+        repo.save(&create_test_session("2")).await.unwrap();
 
         // When
         let result = repo.cleanup().await;
 
         // Then
         assert!(result.is_ok());
+        let sessions = repo.sessions.read().await;
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].value, "2");
     }
 }
\ No newline at end of file
@@ -0,0 +1,251 @@
+use crate::repositories::otp_repository::{InMemoryOtpRepository, OtpRepository};
+use crate::repositories::session_repository::{InMemorySessionRepository, SessionRepository};
+use crate::repositories::user_repository::{InMemoryUserRepository, UserRepository};
+use crate::repositories::DbError;
+use async_trait::async_trait;
+use std::collections::BTreeMap;
+
+/// Starts transactions spanning several repositories, for changes that have to be made all
+/// together or not at all, like creating a user and issuing their first OTP
+#[async_trait]
+pub trait UnitOfWork: Send + Sync {
+    type Transaction: Transaction;
+
+    async fn begin(&self) -> Result<Self::Transaction, DbError>;
+}
+
+/// Open transaction handing out repositories bound to it. Changes made through them are seen
+/// by the other repositories of the transaction right away and by everyone else once it is
+/// committed. Dropping the transaction without committing rolls it back.
+#[async_trait]
+pub trait Transaction: Send + Sync {
+    type Users: UserRepository + Clone + Sync + Send;
+    type Sessions: SessionRepository + Clone;
+    type Otps: OtpRepository + Clone + Sync + Send;
+
+    fn users(&self) -> Self::Users;
+    fn sessions(&self) -> Self::Sessions;
+    fn otps(&self) -> Self::Otps;
+
+    async fn commit(self) -> Result<(), DbError>;
+    async fn rollback(self) -> Result<(), DbError>;
+}
+
+/// `UnitOfWork` over the in-memory repositories. A transaction works on copies of their
+/// contents. The commit writes back only the rows the transaction changed, see
+/// `merge_changes`, so that changes made outside the transaction in the meantime are kept.
+#[derive(Clone)]
+pub struct InMemoryUnitOfWork {
+    users: InMemoryUserRepository,
+    sessions: InMemorySessionRepository,
+    otps: InMemoryOtpRepository,
+}
+
+impl InMemoryUnitOfWork {
+    pub fn new(users: InMemoryUserRepository, sessions: InMemorySessionRepository, otps: InMemoryOtpRepository) -> Self {
+        Self { users, sessions, otps }
+    }
+}
+
+pub struct InMemoryTransaction {
+    committed: InMemoryUnitOfWork,
+    /// Contents as the transaction began, to tell which rows it changed
+    base: InMemoryUnitOfWork,
+    working: InMemoryUnitOfWork,
+}
+
+/// Applies the changes a transaction made to the rows identified by `key`, from `base` as it
+/// began to `working` as it ends, to `target`: rows it added or changed are written and rows
+/// it removed are removed. Rows it did not touch keep their value in `target`, however they
+/// were changed in the meantime.
+pub(crate) fn merge_changes<T, K>(target: &mut Vec<T>, base: &[T], working: &[T], key: impl Fn(&T) -> K)
+where
+    T: Clone + PartialEq,
+    K: PartialEq,
+{
+    target.retain(|row| !base.iter().any(|b| key(b) == key(row)) || working.iter().any(|w| key(w) == key(row)));
+
+    for row in working.iter().filter(|row| !base.contains(row)) {
+        match target.iter_mut().find(|t| key(t) == key(row)) {
+            Some(stored) => *stored = row.clone(),
+            None => target.push(row.clone()),
+        }
+    }
+}
+
+/// `merge_changes` for rows keyed in a map
+pub(crate) fn merge_map_changes<K, V>(target: &mut BTreeMap<K, V>, base: &BTreeMap<K, V>, working: &BTreeMap<K, V>)
+where
+    K: Ord + Clone,
+    V: Clone + PartialEq,
+{
+    target.retain(|key, _| !base.contains_key(key) || working.contains_key(key));
+
+    for (key, value) in working.iter().filter(|(key, value)| base.get(key) != Some(value)) {
+        target.insert(key.clone(), value.clone());
+    }
+}
+
+#[async_trait]
+impl UnitOfWork for InMemoryUnitOfWork {
+    type Transaction = InMemoryTransaction;
+
+    async fn begin(&self) -> Result<Self::Transaction, DbError> {
+        let base = InMemoryUnitOfWork {
+            users: self.users.snapshot().await,
+            sessions: self.sessions.snapshot().await,
+            otps: self.otps.snapshot().await,
+        };
+        let working = InMemoryUnitOfWork {
+            users: base.users.snapshot().await,
+            sessions: base.sessions.snapshot().await,
+            otps: base.otps.snapshot().await,
+        };
+
+        Ok(InMemoryTransaction { committed: self.clone(), base, working })
+    }
+}
+
+#[async_trait]
+impl Transaction for InMemoryTransaction {
+    type Users = InMemoryUserRepository;
+    type Sessions = InMemorySessionRepository;
+    type Otps = InMemoryOtpRepository;
+
+    fn users(&self) -> Self::Users {
+        self.working.users.clone()
+    }
+
+    fn sessions(&self) -> Self::Sessions {
+        self.working.sessions.clone()
+    }
+
+    fn otps(&self) -> Self::Otps {
+        self.working.otps.clone()
+    }
+
+    async fn commit(self) -> Result<(), DbError> {
+        // Users go first, they are the only rows whose merge can fail
+        self.committed.users.merge(&self.base.users, &self.working.users).await?;
+        self.committed.sessions.merge(&self.base.sessions, &self.working.sessions).await;
+        self.committed.otps.merge(&self.base.otps, &self.working.otps).await;
+
+        Ok(())
+    }
+
+    async fn rollback(self) -> Result<(), DbError> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::otp::Otp;
+    use crate::models::user::User;
+    use crate::services::otp_hasher::OtpHasher;
+    use chrono::Utc;
+
+    fn create_unit_of_work() -> (InMemoryUnitOfWork, InMemoryUserRepository, InMemoryOtpRepository) {
+        let users = InMemoryUserRepository::new();
+        let otps = InMemoryOtpRepository::new();
+        let unit_of_work = InMemoryUnitOfWork::new(users.clone(), InMemorySessionRepository::new(), otps.clone());
+
+        (unit_of_work, users, otps)
+    }
+
+    async fn create_user_with_otp(transaction: &InMemoryTransaction) -> User {
+        let user = transaction.users().save(User::new("alice".to_string())).await.unwrap();
+        let otp = Otp::new("12345678", user.id, 300, &OtpHasher::new(b"secret")).unwrap();
+        transaction.otps().save(otp).await.unwrap();
+
+        user
+    }
+
+    #[tokio::test]
+    async fn test_commit() {
+        // Given
+        let (unit_of_work, users, otps) = create_unit_of_work();
+        let transaction = unit_of_work.begin().await.unwrap();
+        let user = create_user_with_otp(&transaction).await;
+
+        // When
+        let before_commit = users.find_by_login("alice").await.unwrap();
+        transaction.commit().await.unwrap();
+
+        // Then
+        assert!(before_commit.is_none());
+        assert_eq!(users.find_by_login("alice").await.unwrap().unwrap().id, user.id);
+        assert_eq!(otps.find_by_user(user.id).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_commit_keeps_changes_made_meanwhile() {
+        // Given
+        let (unit_of_work, users, otps) = create_unit_of_work();
+        let bob = users.save(User::new("bob".to_string())).await.unwrap();
+        let transaction = unit_of_work.begin().await.unwrap();
+        let alice = create_user_with_otp(&transaction).await;
+
+        // When
+        let carol = users.save(User::new("carol".to_string())).await.unwrap();
+        users.record_failed_login(bob.id, Utc::now(), 5, Utc::now()).await.unwrap();
+        otps.save(Otp::new("87654321", carol.id, 300, &OtpHasher::new(b"secret")).unwrap()).await.unwrap();
+        transaction.commit().await.unwrap();
+
+        // Then
+        assert_ne!(alice.id, carol.id);
+        assert_eq!(users.find_by_id(bob.id).await.unwrap().unwrap().login_attempts, 1);
+        assert!(users.find_by_id(carol.id).await.unwrap().is_some());
+        assert!(users.find_by_id(alice.id).await.unwrap().is_some());
+        assert_eq!(otps.find_by_user(carol.id).await.unwrap().len(), 1);
+        assert_eq!(otps.find_by_user(alice.id).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_commit_fails_on_login_taken_meanwhile() {
+        // Given
+        let (unit_of_work, users, otps) = create_unit_of_work();
+        let transaction = unit_of_work.begin().await.unwrap();
+        let alice = create_user_with_otp(&transaction).await;
+        let taken = users.save(User::new("alice".to_string())).await.unwrap();
+
+        // When
+        let result = transaction.commit().await;
+
+        // Then
+        assert!(matches!(result, Err(DbError::UniqueViolation(_))));
+        assert_eq!(users.find_by_login("alice").await.unwrap().unwrap().id, taken.id);
+        assert!(otps.find_by_user(alice.id).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_rollback() {
+        // Given
+        let (unit_of_work, users, otps) = create_unit_of_work();
+        let transaction = unit_of_work.begin().await.unwrap();
+        let user = create_user_with_otp(&transaction).await;
+
+        // When
+        transaction.rollback().await.unwrap();
+
+        // Then
+        assert!(users.find_by_login("alice").await.unwrap().is_none());
+        assert!(otps.find_by_user(user.id).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_drop_rolls_back() {
+        // Given
+        let (unit_of_work, users, _) = create_unit_of_work();
+
+        // When
+        {
+            let transaction = unit_of_work.begin().await.unwrap();
+            create_user_with_otp(&transaction).await;
+        }
+
+        // Then
+        assert!(users.find_by_login("alice").await.unwrap().is_none());
+    }
+}
@@ -1,75 +1,224 @@
+use crate::models::outbox::OutboxMessage;
 use crate::models::user::User;
+use crate::repositories::outbox_repository::{InMemoryOutboxRepository, OutboxRepository};
 use crate::repositories::DbError;
 use async_trait::async_trait;
+use crate::repositories::unit_of_work::merge_changes;
+use chrono::{DateTime, Utc};
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+use tokio::sync::RwLock;
 
 #[async_trait]
 pub trait UserRepository {
-    async fn find_by_login(&self, login: &str) -> Option<User>;
-    async fn save(&mut self, user: User) -> Result<User, DbError>;
+    async fn find_by_id(&self, id: i64) -> Result<Option<User>, DbError>;
+
+    async fn find_by_login(&self, login: &str) -> Result<Option<User>, DbError>;
+
+    /// Inserts the user when it has no id yet (`id < 0`), otherwise updates the stored user
+    async fn save(&self, user: User) -> Result<User, DbError>;
+
+    /// Counts a failed sign-in of user `id` at `at` in a single atomic update, so that concurrent
+    /// failures are all counted. The attempt reaching `max_attempts` starts the count over and
+    /// locks the user until `lock_until`; attempts while locked are not counted. Returns the
+    /// updated user, `None` when there is no user `id`.
+    async fn record_failed_login(&self, id: i64, at: DateTime<Utc>, max_attempts: i8, lock_until: DateTime<Utc>) -> Result<Option<User>, DbError>;
+
+    /// Records a successful sign-in of user `id` at `at`, clearing failed attempts and lockout in
+    /// a single atomic update that leaves the other fields alone. The `first_login` messages are
+    /// written to the outbox with the update when the user never signed in before. Returns the
+    /// updated user, `None` when there is no user `id`.
+    async fn record_login(&self, id: i64, at: DateTime<Utc>, first_login: Vec<OutboxMessage>) -> Result<Option<User>, DbError>;
+}
+
+#[derive(Clone)]
+struct UserStore {
+    users: Vec<User>,
 }
 
+/// Clones share the same users
+#[derive(Clone)]
 pub struct InMemoryUserRepository {
-    pub users: Vec<User>,
-    pub counter: u64,
+    store: Arc<RwLock<UserStore>>,
+    /// Shared with snapshots, so that users inserted in a transaction never take the id of one
+    /// inserted outside of it
+    next_id: Arc<AtomicI64>,
+    outbox: InMemoryOutboxRepository,
+}
+
+impl Default for InMemoryUserRepository {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl InMemoryUserRepository {
     pub fn new() -> Self {
-        InMemoryUserRepository { users: Vec::new(), counter: 1 }
+        InMemoryUserRepository {
+            store: Arc::new(RwLock::new(UserStore { users: Vec::new() })),
+            next_id: Arc::new(AtomicI64::new(1)),
+            outbox: InMemoryOutboxRepository::new(),
+        }
+    }
+
+    /// Writes outbox messages to `outbox`, to share it with the dispatcher
+    pub fn with_outbox(mut self, outbox: InMemoryOutboxRepository) -> Self {
+        self.outbox = outbox;
+        self
+    }
+
+    /// Repository starting with a copy of the users and outbox messages, not shared with this one
+    pub(crate) async fn snapshot(&self) -> Self {
+        InMemoryUserRepository {
+            store: Arc::new(RwLock::new(self.store.read().await.clone())),
+            next_id: self.next_id.clone(),
+            outbox: self.outbox.snapshot().await,
+        }
+    }
+
+    /// Writes the users and outbox messages changed from `base` to `working` back, see
+    /// `unit_of_work::merge_changes`. Fails without writing anything when a changed login is
+    /// taken by another user by now.
+    pub(crate) async fn merge(&self, base: &Self, working: &Self) -> Result<(), DbError> {
+        {
+            let base = base.store.read().await;
+            let working = working.store.read().await;
+            let mut store = self.store.write().await;
+
+            for user in working.users.iter().filter(|user| !base.users.contains(user)) {
+                if store.users.iter().any(|u| u.id != user.id && u.username == user.username) {
+                    return Err(DbError::UniqueViolation(format!("Username {} is taken", user.username)));
+                }
+            }
+
+            merge_changes(&mut store.users, &base.users, &working.users, |user| user.id);
+        }
+
+        self.outbox.merge(&base.outbox, &working.outbox).await;
+
+        Ok(())
     }
 }
 
 #[async_trait]
 impl UserRepository for InMemoryUserRepository {
-    async fn find_by_login(&self, login: &str) -> Option<User> {
-        self.users.iter().find(|u| u.username == login).cloned()
+    async fn find_by_id(&self, id: i64) -> Result<Option<User>, DbError> {
+        Ok(self.store.read().await.users.iter().find(|u| u.id == id).cloned())
     }
 
-    async fn save(&mut self, user: User) -> Result<User, DbError> {
+    async fn find_by_login(&self, login: &str) -> Result<Option<User>, DbError> {
+        Ok(self.store.read().await.users.iter().find(|u| u.username == login).cloned())
+    }
+
+    async fn save(&self, user: User) -> Result<User, DbError> {
         let mut user = user;
+        let mut store = self.store.write().await;
+
+        if user.id >= 0 {
+            return match store.users.iter_mut().find(|u| u.id == user.id) {
+                Some(stored) => {
+                    *stored = user.clone();
+                    Ok(user)
+                }
+                None => Err(DbError::NotFound(format!("User with id {}", user.id))),
+            };
+        }
 
-        user.id = self.counter as i64;
+        if store.users.iter().any(|u| u.username == user.username) {
+            return Err(DbError::UniqueViolation(format!("Username {} is taken", user.username)));
+        }
 
-        self.counter += 1;
-        self.users.push(user.clone());
+        user.id = self.next_id.fetch_add(1, Ordering::SeqCst);
+
+        store.users.push(user.clone());
 
         Ok(user)
     }
+
+    async fn record_failed_login(&self, id: i64, at: DateTime<Utc>, max_attempts: i8, lock_until: DateTime<Utc>) -> Result<Option<User>, DbError> {
+        let mut store = self.store.write().await;
+
+        let Some(user) = store.users.iter_mut().find(|u| u.id == id) else {
+            return Ok(None);
+        };
+
+        if user.locked_until.is_some_and(|until| until > at) {
+            return Ok(Some(user.clone()));
+        }
+
+        user.login_attempts = user.login_attempts.saturating_add(1);
+        user.last_update_date = at;
+
+        if user.login_attempts >= max_attempts {
+            user.login_attempts = 0;
+            user.locked_until = Some(lock_until);
+        }
+
+        Ok(Some(user.clone()))
+    }
+
+    async fn record_login(&self, id: i64, at: DateTime<Utc>, first_login: Vec<OutboxMessage>) -> Result<Option<User>, DbError> {
+        let mut store = self.store.write().await;
+
+        let Some(user) = store.users.iter_mut().find(|u| u.id == id) else {
+            return Ok(None);
+        };
+
+        if user.last_login_date.is_none() {
+            for message in first_login {
+                self.outbox.enqueue(message).await?;
+            }
+        }
+
+        user.login_attempts = 0;
+        user.locked_until = None;
+        user.last_login_date = Some(at);
+        user.last_update_date = at;
+
+        Ok(Some(user.clone()))
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::models::otp::Otp;
+    use crate::models::outbox::OutboxPayload;
     use crate::models::user::User;
 
     fn create_test_user(username: &str) -> User {
         User::new(username.to_string())
     }
 
-    fn create_test_otp(user_id: i64, otp_id: &str) -> Otp {
-        Otp::new(otp_id.to_string(), user_id, 300).unwrap()
-    }
-
     #[tokio::test]
     async fn test_find_by_login() {
         // Given
-        let mut repo = InMemoryUserRepository::new();
+        let repo = InMemoryUserRepository::new();
         let user = create_test_user("test_user");
         repo.save(user.clone()).await.unwrap();
 
         // When
-        let found_user = repo.find_by_login("test_user").await;
+        let found_user = repo.find_by_login("test_user").await.unwrap();
 
         // Then
         assert!(found_user.is_some());
         assert_eq!(found_user.unwrap().username, "test_user");
     }
 
+    #[tokio::test]
+    async fn test_find_by_id() {
+        // Given
+        let repo = InMemoryUserRepository::new();
+        let user = repo.save(create_test_user("test_user")).await.unwrap();
+
+        // Then
+        assert_eq!(repo.find_by_id(user.id).await.unwrap().unwrap().username, "test_user");
+        assert!(repo.find_by_id(user.id + 1).await.unwrap().is_none());
+    }
+
     #[tokio::test]
     async fn test_save() {
         // Given
-        let mut repo = InMemoryUserRepository::new();
+        let repo = InMemoryUserRepository::new();
         let user = create_test_user("test_user");
 
         // When
@@ -77,6 +226,96 @@ mod tests {
 
         // Then
         assert_eq!(saved_user.username, "test_user");
-        assert!(repo.find_by_login("test_user").await.is_some());
+        assert!(repo.find_by_login("test_user").await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_save_updates_existing_user() {
+        // Given
+        let repo = InMemoryUserRepository::new();
+        let mut user = repo.save(create_test_user("test_user")).await.unwrap();
+        user.register_complete = true;
+
+        // When
+        let updated = repo.save(user.clone()).await.unwrap();
+
+        // Then
+        assert_eq!(updated.id, user.id);
+        assert_eq!(repo.store.read().await.users.len(), 1);
+        assert!(repo.find_by_login("test_user").await.unwrap().unwrap().register_complete);
+    }
+
+    #[tokio::test]
+    async fn test_save_duplicate_username() {
+        // Given
+        let repo = InMemoryUserRepository::new();
+        repo.save(create_test_user("test_user")).await.unwrap();
+
+        // When
+        let result = repo.save(create_test_user("test_user")).await;
+
+        // Then
+        assert!(matches!(result, Err(DbError::UniqueViolation(_))));
+    }
+
+    #[tokio::test]
+    async fn test_record_failed_login() {
+        // Given
+        let repo = InMemoryUserRepository::new();
+        let user = repo.save(create_test_user("test_user")).await.unwrap();
+        let now = Utc::now();
+        let until = now + chrono::Duration::minutes(15);
+
+        // When
+        let first = repo.record_failed_login(user.id, now, 2, until).await.unwrap().unwrap();
+        let second = repo.record_failed_login(user.id, now, 2, until).await.unwrap().unwrap();
+        let while_locked = repo.record_failed_login(user.id, now, 2, now + chrono::Duration::hours(1)).await.unwrap().unwrap();
+        let missing = repo.record_failed_login(user.id + 1, now, 2, until).await.unwrap();
+
+        // Then
+        assert_eq!((first.login_attempts, first.locked_until), (1, None));
+        assert_eq!((second.login_attempts, second.locked_until), (0, Some(until)));
+        assert_eq!((while_locked.login_attempts, while_locked.locked_until), (0, Some(until)));
+        assert!(missing.is_none());
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_record_login() {
+        // Given
+        let repo = InMemoryUserRepository::new();
+        let user = repo.save(create_test_user("test_user")).await.unwrap();
+        let now = Utc::now();
+        repo.record_failed_login(user.id, now, 5, now).await.unwrap();
+
+        // When
+        let result = repo.record_login(user.id, now, Vec::new()).await.unwrap().unwrap();
+
+        // Then
+        assert_eq!(result.login_attempts, 0);
+        assert_eq!(result.last_login_date, Some(now));
+        assert_eq!(repo.find_by_id(user.id).await.unwrap().unwrap().login_attempts, 0);
+    }
+
+    #[tokio::test]
+    async fn test_record_login_writes_first_login_messages_once() {
+        // Given
+        let outbox = InMemoryOutboxRepository::new();
+        let repo = InMemoryUserRepository::new().with_outbox(outbox.clone());
+        let user = repo.save(create_test_user("test_user")).await.unwrap();
+        let welcome = |n: i32| vec![OutboxMessage::new(format!("welcome:{}", n), OutboxPayload::Mail {
+            to: "test_user".to_string(),
+            subject: "Welcome".to_string(),
+            html_body: String::new(),
+            plain_body: String::new(),
+        })];
+
+        // When
+        repo.record_login(user.id, Utc::now(), welcome(1)).await.unwrap();
+        repo.record_login(user.id, Utc::now(), welcome(2)).await.unwrap();
+
+        // Then
+        let messages = outbox.messages().await;
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].key, "welcome:1");
+    }
+}
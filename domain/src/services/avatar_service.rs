@@ -0,0 +1,366 @@
+use crate::models::avatar::{detect_content_type, snap_render_size, Avatar, Rating, MAX_AVATAR_SIZE, SUPPORTED_CONTENT_TYPES};
+use crate::repositories::avatar_repository::AvatarRepository;
+use crate::repositories::blob_store::BlobStore;
+use crate::repositories::id_provider::IdProvider;
+use crate::services::image_pipeline::{self, OutputFormat};
+use crate::services::render_cache::{RenderCache, RenderKey};
+use crate::views::avatar_view::{AvatarImage, AvatarView};
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum AvatarError {
+    #[error("Image is empty")]
+    Empty,
+    #[error("Image is {0} bytes, at most {MAX_AVATAR_SIZE} bytes are allowed")]
+    TooLarge(usize),
+    #[error("Unsupported content type: {0}")]
+    UnsupportedContentType(String),
+    #[error("Image content does not match {0}")]
+    ContentTypeMismatch(String),
+    /// The image could not be decoded, it is truncated, corrupt or too large once decoded
+    #[error("Image could not be decoded: {0}")]
+    InvalidImage(String),
+    #[error("Internal error: {0}")]
+    InternalError(String),
+}
+
+/// Checks that `data` is a supported image of the declared `content_type` within the size limit
+/// that decodes, so that every stored avatar can be rendered. Decoding is CPU bound, call this
+/// from a blocking task.
+pub fn validate_upload(content_type: &str, data: &[u8]) -> Result<(), AvatarError> {
+    if data.is_empty() {
+        return Err(AvatarError::Empty);
+    }
+
+    if data.len() > MAX_AVATAR_SIZE {
+        return Err(AvatarError::TooLarge(data.len()));
+    }
+
+    if !SUPPORTED_CONTENT_TYPES.contains(&content_type) {
+        return Err(AvatarError::UnsupportedContentType(content_type.to_owned()));
+    }
+
+    if detect_content_type(data) != Some(content_type) {
+        return Err(AvatarError::ContentTypeMismatch(content_type.to_owned()));
+    }
+
+    image_pipeline::decode(data)?;
+
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+pub struct AvatarService<AR, BS, IP>
+where
+    AR: AvatarRepository + Sync + Send,
+    BS: BlobStore + Sync + Send,
+    IP: IdProvider + Sync + Send,
+{
+    avatar_repository: AR,
+    blob_store: BS,
+    id_provider: IP,
+    render_cache: RenderCache,
+}
+
+impl<AR, BS, IP> AvatarService<AR, BS, IP>
+where
+    AR: AvatarRepository + Sync + Send,
+    BS: BlobStore + Sync + Send,
+    IP: IdProvider + Sync + Send,
+{
+    pub fn new(avatar_repository: AR, blob_store: BS, id_provider: IP) -> Self {
+        AvatarService { avatar_repository, blob_store, id_provider, render_cache: RenderCache::default() }
+    }
+
+    pub fn with_render_cache(mut self, render_cache: RenderCache) -> Self {
+        self.render_cache = render_cache;
+        self
+    }
+
+    /// Stores a new avatar with the rating chosen by its owner and makes it their primary one
+    pub async fn upload(&self, user_id: i64, content_type: &str, data: &[u8], rating: Rating) -> Result<AvatarView, AvatarError> {
+        let (checked_type, checked_data) = (content_type.to_owned(), data.to_vec());
+
+        tokio::task::spawn_blocking(move || validate_upload(&checked_type, &checked_data))
+            .await
+            .map_err(|e| AvatarError::InternalError(e.to_string()))??;
+
+        let storage_key = format!("avatars/{}/{}", user_id, self.id_provider.get_id(32));
+
+        self.blob_store.put(&storage_key, data).await
+            .map_err(|e| AvatarError::InternalError(e.to_string()))?;
+
+        let mut avatar = Avatar::new(user_id, content_type.to_owned(), data.len() as i64, storage_key.clone());
+        avatar.rating = rating;
+
+        let mut avatar = match self.avatar_repository.save(avatar).await {
+            Ok(avatar) => avatar,
+            Err(err) => {
+                let _ = self.blob_store.delete(&storage_key).await;
+
+                return Err(AvatarError::InternalError(err.to_string()));
+            }
+        };
+
+        self.avatar_repository.set_primary(user_id, avatar.id).await
+            .map_err(|e| AvatarError::InternalError(e.to_string()))?;
+
+        avatar.is_primary = true;
+
+        Ok(AvatarView::new(avatar))
+    }
+
+    /// Lists the user's library, newest first
+    pub async fn list(&self, user_id: i64) -> Result<Vec<AvatarView>, AvatarError> {
+        let avatars = self.avatar_repository.find_by_user(user_id).await
+            .map_err(|e| AvatarError::InternalError(e.to_string()))?;
+
+        Ok(avatars.into_iter().map(AvatarView::new).collect())
+    }
+
+    /// Returns the avatar when it belongs to `user_id`
+    pub async fn find(&self, user_id: i64, avatar_id: i64) -> Option<AvatarView> {
+        self.avatar_repository.find_by_id(avatar_id).await
+            .filter(|avatar| avatar.user_id == user_id)
+            .map(AvatarView::new)
+    }
+
+    /// Returns the primary picture of the user cropped to a square and encoded as `format`, or
+    /// `None` when they have none, its blob is gone or it does not decode. `size` is rounded up
+    /// to one of `RENDER_SIZES`. Renderings are cached.
+    pub async fn render_primary(&self, user_id: i64, size: u32, format: OutputFormat) -> Result<Option<AvatarImage>, AvatarError> {
+        self.render(user_id, None, Rating::X, size, format).await
+    }
+
+    /// Like `render_primary`, but shows `avatar_id` when it is set and still in the user's
+    /// library. Returns `None` as well when the chosen avatar is rated above `max_rating`.
+    pub async fn render(&self, user_id: i64, avatar_id: Option<i64>, max_rating: Rating, size: u32, format: OutputFormat) -> Result<Option<AvatarImage>, AvatarError> {
+        let assigned = match avatar_id {
+            Some(avatar_id) => self.avatar_repository.find_by_id(avatar_id).await.filter(|avatar| avatar.user_id == user_id),
+            None => None,
+        };
+
+        let avatar = match assigned {
+            Some(avatar) => avatar,
+            None => match self.avatar_repository.find_primary_by_user(user_id).await {
+                Some(avatar) => avatar,
+                None => return Ok(None),
+            },
+        };
+
+        if avatar.rating > max_rating {
+            return Ok(None);
+        }
+
+        let size = snap_render_size(size);
+        let key = RenderKey::new(&avatar.storage_key, size, format);
+
+        if let Some(image) = self.render_cache.get(&key).await {
+            return Ok(Some(image));
+        }
+
+        let data = match self.blob_store.get(&avatar.storage_key).await {
+            Ok(Some(data)) => data,
+            Ok(None) => return Ok(None),
+            Err(err) => return Err(AvatarError::InternalError(err.to_string())),
+        };
+
+        let rendered = tokio::task::spawn_blocking(move || image_pipeline::render(&data, size, format))
+            .await
+            .map_err(|e| AvatarError::InternalError(e.to_string()))?;
+
+        // Images stored before uploads were decoded may be broken, serve the default instead
+        let image = match rendered {
+            Ok(image) => image,
+            Err(AvatarError::InvalidImage(_)) => return Ok(None),
+            Err(err) => return Err(err),
+        };
+
+        self.render_cache.put(key, image.clone()).await;
+
+        Ok(Some(image))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::avatar::{CONTENT_TYPE_GIF, CONTENT_TYPE_PNG, CONTENT_TYPE_WEBP};
+    use crate::repositories::avatar_repository::InMemoryAvatarRepository;
+    use crate::repositories::blob_store::InMemoryBlobStore;
+    use crate::repositories::id_provider::SimpleIdProvider;
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR";
+
+    fn create_png(width: u32, height: u32) -> Vec<u8> {
+        let mut buffer = std::io::Cursor::new(Vec::new());
+        image::RgbaImage::new(width, height).write_to(&mut buffer, image::ImageFormat::Png).unwrap();
+
+        buffer.into_inner()
+    }
+
+    fn create_service() -> AvatarService<InMemoryAvatarRepository, InMemoryBlobStore, SimpleIdProvider> {
+        AvatarService::new(InMemoryAvatarRepository::new(), InMemoryBlobStore::new(), SimpleIdProvider::new())
+    }
+
+    #[tokio::test]
+    async fn test_upload() {
+        // Given
+        let service = create_service();
+
+        // When
+        let png = create_png(8, 8);
+        let avatar = service.upload(1, CONTENT_TYPE_PNG, &png, Rating::G).await.unwrap();
+
+        // Then
+        assert_eq!(avatar.user_id, 1);
+        assert_eq!(avatar.size_bytes, png.len() as i64);
+        assert!(avatar.is_primary);
+
+        let stored = service.avatar_repository.find_by_id(avatar.id).await.unwrap();
+        assert_eq!(service.blob_store.get(&stored.storage_key).await.unwrap(), Some(png));
+    }
+
+    #[tokio::test]
+    async fn test_upload_replaces_primary() {
+        // Given
+        let service = create_service();
+        let first = service.upload(1, CONTENT_TYPE_PNG, &create_png(8, 8), Rating::G).await.unwrap();
+
+        // When
+        let second = service.upload(1, CONTENT_TYPE_PNG, &create_png(8, 8), Rating::G).await.unwrap();
+
+        // Then
+        assert_eq!(service.avatar_repository.find_primary_by_user(1).await.unwrap().id, second.id);
+        assert!(!service.avatar_repository.find_by_id(first.id).await.unwrap().is_primary);
+    }
+
+    #[tokio::test]
+    async fn test_render_primary() {
+        // Given
+        let service = create_service();
+        service.upload(1, CONTENT_TYPE_PNG, &create_png(40, 20), Rating::G).await.unwrap();
+
+        // When
+        let image = service.render_primary(1, 16, OutputFormat::WebP).await.unwrap().unwrap();
+        let missing = service.render_primary(2, 16, OutputFormat::WebP).await.unwrap();
+
+        // Then
+        assert_eq!(image.content_type, CONTENT_TYPE_WEBP);
+        assert_eq!(image::load_from_memory(&image.data).unwrap().width(), 16);
+        assert!(missing.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_render_assigned() {
+        // Given
+        let service = create_service();
+        let solid = |rgba: [u8; 4]| {
+            let mut buffer = std::io::Cursor::new(Vec::new());
+            image::RgbaImage::from_pixel(8, 8, image::Rgba(rgba)).write_to(&mut buffer, image::ImageFormat::Png).unwrap();
+            buffer.into_inner()
+        };
+
+        let assigned = service.upload(1, CONTENT_TYPE_PNG, &solid([255, 0, 0, 255]), Rating::G).await.unwrap();
+        service.upload(1, CONTENT_TYPE_PNG, &solid([0, 0, 255, 255]), Rating::G).await.unwrap();
+        let foreign = service.upload(2, CONTENT_TYPE_PNG, &solid([0, 255, 0, 255]), Rating::G).await.unwrap();
+
+        // When
+        let own = service.render(1, Some(assigned.id), Rating::G, 8, OutputFormat::Png).await.unwrap().unwrap();
+        let other = service.render(1, Some(foreign.id), Rating::G, 8, OutputFormat::Png).await.unwrap().unwrap();
+        let primary = service.render_primary(1, 8, OutputFormat::Png).await.unwrap().unwrap();
+
+        // Then
+        assert_ne!(own, primary);
+        assert_eq!(other, primary);
+        assert_eq!(service.list(1).await.unwrap().len(), 2);
+        assert!(service.find(1, assigned.id).await.is_some());
+        assert!(service.find(1, foreign.id).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_render_filters_rating() {
+        // Given
+        let service = create_service();
+        let avatar = service.upload(1, CONTENT_TYPE_PNG, &create_png(8, 8), Rating::R).await.unwrap();
+
+        // When
+        let for_pg = service.render(1, None, Rating::PG, 8, OutputFormat::Png).await.unwrap();
+        let for_r = service.render(1, None, Rating::R, 8, OutputFormat::Png).await.unwrap();
+
+        // Then
+        assert_eq!(avatar.rating, Rating::R);
+        assert!(for_pg.is_none());
+        assert!(for_r.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_render_primary_uses_cache() {
+        // Given
+        let service = create_service();
+        let avatar = service.upload(1, CONTENT_TYPE_PNG, &create_png(8, 8), Rating::G).await.unwrap();
+        let first = service.render_primary(1, 4, OutputFormat::Png).await.unwrap();
+
+        // When
+        let storage_key = service.avatar_repository.find_by_id(avatar.id).await.unwrap().storage_key;
+        service.blob_store.delete(&storage_key).await.unwrap();
+        let second = service.render_primary(1, 4, OutputFormat::Png).await.unwrap();
+
+        // Then
+        assert!(first.is_some());
+        assert_eq!(first, second);
+    }
+
+    #[tokio::test]
+    async fn test_render_snaps_size() {
+        // Given
+        let service = create_service();
+        service.upload(1, CONTENT_TYPE_PNG, &create_png(40, 40), Rating::G).await.unwrap();
+
+        // When
+        let first = service.render_primary(1, 17, OutputFormat::Png).await.unwrap().unwrap();
+        let second = service.render_primary(1, 31, OutputFormat::Png).await.unwrap().unwrap();
+
+        // Then
+        assert_eq!(image::load_from_memory(&first.data).unwrap().width(), 32);
+        assert_eq!(first, second);
+    }
+
+    #[tokio::test]
+    async fn test_render_undecodable_blob() {
+        // Given
+        let service = create_service();
+        let avatar = service.upload(1, CONTENT_TYPE_PNG, &create_png(8, 8), Rating::G).await.unwrap();
+        let storage_key = service.avatar_repository.find_by_id(avatar.id).await.unwrap().storage_key;
+        service.blob_store.put(&storage_key, PNG).await.unwrap();
+
+        // When
+        let image = service.render_primary(1, 16, OutputFormat::Png).await;
+
+        // Then
+        assert_eq!(image, Ok(None));
+    }
+
+    #[tokio::test]
+    async fn test_validate() {
+        assert_eq!(validate_upload(CONTENT_TYPE_PNG, &create_png(8, 8)), Ok(()));
+        assert!(matches!(validate_upload(CONTENT_TYPE_PNG, PNG), Err(AvatarError::InvalidImage(_))));
+        assert_eq!(validate_upload(CONTENT_TYPE_PNG, b""), Err(AvatarError::Empty));
+        assert_eq!(validate_upload("image/svg+xml", b"<svg/>"), Err(AvatarError::UnsupportedContentType("image/svg+xml".to_owned())));
+        assert_eq!(validate_upload(CONTENT_TYPE_GIF, PNG), Err(AvatarError::ContentTypeMismatch(CONTENT_TYPE_GIF.to_owned())));
+    }
+
+    #[tokio::test]
+    async fn test_upload_too_large() {
+        // Given
+        let service = create_service();
+        let mut data = PNG.to_vec();
+        data.resize(MAX_AVATAR_SIZE + 1, 0);
+
+        // When
+        let result = service.upload(1, CONTENT_TYPE_PNG, &data, Rating::G).await;
+
+        // Then
+        assert_eq!(result.unwrap_err(), AvatarError::TooLarge(MAX_AVATAR_SIZE + 1));
+        assert!(service.avatar_repository.find_by_user(1).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,473 @@
+use crate::models::avatar::{CONTENT_TYPE_PNG, CONTENT_TYPE_SVG, MAX_RENDER_SIZE};
+use crate::services::avatar_service::AvatarError;
+use crate::services::image_pipeline::{self, OutputFormat};
+use crate::views::avatar_view::AvatarImage;
+use image::{DynamicImage, Rgba, RgbaImage};
+use md5::{Digest, Md5};
+
+/// 1x1 fully transparent PNG
+const BLANK_PNG: [u8; 68] = [
+    0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x48, 0x44, 0x52,
+    0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x08, 0x06, 0x00, 0x00, 0x00, 0x1f, 0x15, 0xc4,
+    0x89, 0x00, 0x00, 0x00, 0x0b, 0x49, 0x44, 0x41, 0x54, 0x78, 0xda, 0x63, 0x60, 0x00, 0x02, 0x00,
+    0x00, 0x05, 0x00, 0x01, 0xe9, 0xfa, 0xdc, 0xd8, 0x00, 0x00, 0x00, 0x00, 0x49, 0x45, 0x4e, 0x44,
+    0xae, 0x42, 0x60, 0x82,
+];
+
+/// 5x7 bitmap glyphs for `A-Z` followed by `0-9`, one row per byte with the leftmost pixel in bit 4
+const GLYPHS: [[u8; 7]; 36] = [
+    [0b01110, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001],
+    [0b11110, 0b10001, 0b10001, 0b11110, 0b10001, 0b10001, 0b11110],
+    [0b01110, 0b10001, 0b10000, 0b10000, 0b10000, 0b10001, 0b01110],
+    [0b11110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b11110],
+    [0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b11111],
+    [0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b10000],
+    [0b01110, 0b10001, 0b10000, 0b10111, 0b10001, 0b10001, 0b01111],
+    [0b10001, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001],
+    [0b01110, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110],
+    [0b00111, 0b00010, 0b00010, 0b00010, 0b00010, 0b10010, 0b01100],
+    [0b10001, 0b10010, 0b10100, 0b11000, 0b10100, 0b10010, 0b10001],
+    [0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b11111],
+    [0b10001, 0b11011, 0b10101, 0b10101, 0b10001, 0b10001, 0b10001],
+    [0b10001, 0b10001, 0b11001, 0b10101, 0b10011, 0b10001, 0b10001],
+    [0b01110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110],
+    [0b11110, 0b10001, 0b10001, 0b11110, 0b10000, 0b10000, 0b10000],
+    [0b01110, 0b10001, 0b10001, 0b10001, 0b10101, 0b10010, 0b01101],
+    [0b11110, 0b10001, 0b10001, 0b11110, 0b10100, 0b10010, 0b10001],
+    [0b01111, 0b10000, 0b10000, 0b01110, 0b00001, 0b00001, 0b11110],
+    [0b11111, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100],
+    [0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110],
+    [0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01010, 0b00100],
+    [0b10001, 0b10001, 0b10001, 0b10101, 0b10101, 0b10101, 0b01010],
+    [0b10001, 0b10001, 0b01010, 0b00100, 0b01010, 0b10001, 0b10001],
+    [0b10001, 0b10001, 0b01010, 0b00100, 0b00100, 0b00100, 0b00100],
+    [0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b11111],
+    [0b01110, 0b10001, 0b10011, 0b10101, 0b11001, 0b10001, 0b01110],
+    [0b00100, 0b01100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110],
+    [0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b01000, 0b11111],
+    [0b11111, 0b00010, 0b00100, 0b00010, 0b00001, 0b10001, 0b01110],
+    [0b00010, 0b00110, 0b01010, 0b10010, 0b11111, 0b00010, 0b00010],
+    [0b11111, 0b10000, 0b11110, 0b00001, 0b00001, 0b10001, 0b01110],
+    [0b00110, 0b01000, 0b10000, 0b11110, 0b10001, 0b10001, 0b01110],
+    [0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b01000, 0b01000],
+    [0b01110, 0b10001, 0b10001, 0b01110, 0b10001, 0b10001, 0b01110],
+    [0b01110, 0b10001, 0b10001, 0b01111, 0b00001, 0b00010, 0b01100],
+];
+
+/// What to serve when no avatar is found for a hash, the `d`/`default` parameter
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum DefaultImage {
+    /// Respond with 404 instead of an image
+    NotFound,
+    /// Grey silhouette of a person
+    #[default]
+    MysteryPerson,
+    /// GitHub-style symmetric pixel pattern derived from the hash
+    Identicon,
+    /// Initials of the user on a coloured background
+    Initials,
+    /// Symmetric pattern of squares and triangles derived from the hash
+    Retro,
+    /// Transparent image
+    Blank,
+    /// Redirect to an external http(s) image
+    Url(String),
+}
+
+impl DefaultImage {
+    /// Parses the `d` parameter value. Unknown keywords and non-http(s) URLs fall back to
+    /// `MysteryPerson`.
+    pub fn parse(value: &str) -> Self {
+        match value {
+            "404" => DefaultImage::NotFound,
+            "mp" | "mm" | "mysteryman" => DefaultImage::MysteryPerson,
+            "identicon" => DefaultImage::Identicon,
+            "initials" => DefaultImage::Initials,
+            "retro" | "geometric" => DefaultImage::Retro,
+            "blank" => DefaultImage::Blank,
+            url if url.starts_with("https://") || url.starts_with("http://") => DefaultImage::Url(url.to_owned()),
+            _ => DefaultImage::MysteryPerson,
+        }
+    }
+}
+
+pub fn blank() -> AvatarImage {
+    AvatarImage::new(CONTENT_TYPE_PNG, BLANK_PNG.to_vec())
+}
+
+pub fn mystery_person(size: u32) -> AvatarImage {
+    let svg = format!(
+        r##"<svg xmlns="http://www.w3.org/2000/svg" width="{size}" height="{size}" viewBox="0 0 80 80"><rect width="80" height="80" fill="#c8c8c8"/><circle cx="40" cy="30" r="15" fill="#ffffff"/><path d="M10 80c0-19 13-30 30-30s30 11 30 30z" fill="#ffffff"/></svg>"##,
+    );
+
+    AvatarImage::new(CONTENT_TYPE_SVG, svg.into_bytes())
+}
+
+type Rgb = [u8; 3];
+
+const WHITE: Rgb = [255, 255, 255];
+const LIGHT_GREY: Rgb = [240, 240, 240];
+
+/// What is drawn in one cell of a `Pattern`
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Cell {
+    Empty,
+    Full,
+    /// Right triangle filling the top left half of the cell
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl Cell {
+    fn mirrored(self) -> Self {
+        match self {
+            Cell::TopLeft => Cell::TopRight,
+            Cell::TopRight => Cell::TopLeft,
+            Cell::BottomLeft => Cell::BottomRight,
+            Cell::BottomRight => Cell::BottomLeft,
+            cell => cell,
+        }
+    }
+
+    /// Whether the point `(u, v)`, relative to the cell's top left corner, is painted
+    fn covers(self, u: f32, v: f32) -> bool {
+        match self {
+            Cell::Empty => false,
+            Cell::Full => true,
+            Cell::TopLeft => u + v < 1.0,
+            Cell::TopRight => v < u,
+            Cell::BottomLeft => u < v,
+            Cell::BottomRight => u + v > 1.0,
+        }
+    }
+
+    fn svg(self, x: usize, y: usize) -> Option<String> {
+        let (x0, y0, x1, y1) = (x, y, x + 1, y + 1);
+
+        let points = match self {
+            Cell::Empty => return None,
+            Cell::Full => return Some(format!(r#"<rect x="{x0}" y="{y0}" width="1" height="1"/>"#)),
+            Cell::TopLeft => format!("{x0},{y0} {x1},{y0} {x0},{y1}"),
+            Cell::TopRight => format!("{x0},{y0} {x1},{y0} {x1},{y1}"),
+            Cell::BottomLeft => format!("{x0},{y0} {x0},{y1} {x1},{y1}"),
+            Cell::BottomRight => format!("{x1},{y0} {x1},{y1} {x0},{y1}"),
+        };
+
+        Some(format!(r#"<polygon points="{points}"/>"#))
+    }
+}
+
+/// Square grid of cells painted in one colour over a background, surrounded by a margin given
+/// in cells. Renders to SVG and to raster images alike, without fonts or network access.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pattern {
+    grid: usize,
+    margin: f32,
+    background: Rgb,
+    foreground: Rgb,
+    cells: Vec<Cell>,
+}
+
+impl Pattern {
+    fn new(grid: usize, margin: f32, background: Rgb, foreground: Rgb) -> Self {
+        Self { grid, margin, background, foreground, cells: vec![Cell::Empty; grid * grid] }
+    }
+
+    fn set(&mut self, x: usize, y: usize, cell: Cell) {
+        self.cells[y * self.grid + x] = cell;
+    }
+
+    /// Renders the pattern as SVG when `svg` is set, otherwise as a raster image in `format`
+    pub fn render(&self, size: u32, svg: bool, format: OutputFormat) -> Result<AvatarImage, AvatarError> {
+        if svg {
+            Ok(self.to_svg(size))
+        } else {
+            self.to_raster(size, format)
+        }
+    }
+
+    pub fn to_svg(&self, size: u32) -> AvatarImage {
+        let total = self.grid as f32 + 2.0 * self.margin;
+
+        let shapes: String = self.cells.iter().enumerate()
+            .filter_map(|(i, cell)| cell.svg(i % self.grid, i / self.grid))
+            .collect();
+
+        let svg = format!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{size}" height="{size}" viewBox="0 0 {total} {total}"><rect width="{total}" height="{total}" fill="{}"/><g fill="{}" transform="translate({margin} {margin})">{shapes}</g></svg>"#,
+            hex_color(self.background),
+            hex_color(self.foreground),
+            margin = self.margin,
+        );
+
+        AvatarImage::new(CONTENT_TYPE_SVG, svg.into_bytes())
+    }
+
+    /// Rasterizes with 2x2 supersampling so that triangle edges are smoothed
+    pub fn to_raster(&self, size: u32, format: OutputFormat) -> Result<AvatarImage, AvatarError> {
+        const SAMPLES: [f32; 2] = [0.25, 0.75];
+
+        let size = size.clamp(1, MAX_RENDER_SIZE);
+        let scale = (self.grid as f32 + 2.0 * self.margin) / size as f32;
+
+        let image = RgbaImage::from_fn(size, size, |px, py| {
+            let mut covered = 0;
+
+            for oy in SAMPLES {
+                for ox in SAMPLES {
+                    let x = (px as f32 + ox) * scale - self.margin;
+                    let y = (py as f32 + oy) * scale - self.margin;
+
+                    if self.covers(x, y) {
+                        covered += 1;
+                    }
+                }
+            }
+
+            let blend = |i: usize| {
+                let fg = self.foreground[i] as u32 * covered;
+                let bg = self.background[i] as u32 * (4 - covered);
+
+                ((fg + bg) / 4) as u8
+            };
+
+            Rgba([blend(0), blend(1), blend(2), 255])
+        });
+
+        let data = image_pipeline::encode(&DynamicImage::ImageRgba8(image), format)?;
+
+        Ok(AvatarImage::new(format.content_type(), data))
+    }
+
+    fn covers(&self, x: f32, y: f32) -> bool {
+        if x < 0.0 || y < 0.0 || x >= self.grid as f32 || y >= self.grid as f32 {
+            return false;
+        }
+
+        let cell = self.cells[y as usize * self.grid + x as usize];
+
+        cell.covers(x.fract(), y.fract())
+    }
+}
+
+fn hex_color([r, g, b]: Rgb) -> String {
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
+
+/// Converts a hue in degrees with saturation and lightness in `0..=1` to RGB
+fn hsl(hue: f32, saturation: f32, lightness: f32) -> Rgb {
+    let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+    let h = hue / 60.0;
+    let x = chroma * (1.0 - (h % 2.0 - 1.0).abs());
+
+    let (r, g, b) = match h as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+
+    let m = lightness - chroma / 2.0;
+    let channel = |c: f32| ((c + m) * 255.0).round() as u8;
+
+    [channel(r), channel(g), channel(b)]
+}
+
+/// At least 16 bytes derived from the hash. Hashes that are not hex are hashed again so that
+/// any key produces a stable picture.
+fn seed(hash: &str) -> Vec<u8> {
+    hex::decode(hash)
+        .ok()
+        .filter(|bytes| bytes.len() >= 16)
+        .unwrap_or_else(|| Md5::digest(hash.as_bytes()).to_vec())
+}
+
+fn nibble(bytes: &[u8], i: usize) -> u8 {
+    (bytes[i / 2] >> if i.is_multiple_of(2) { 4 } else { 0 }) & 0x0f
+}
+
+fn hue(bytes: &[u8]) -> f32 {
+    (u16::from_be_bytes([bytes[14], bytes[15]]) % 360) as f32
+}
+
+/// 5x5 horizontally mirrored grid of squares, GitHub style
+pub fn identicon(hash: &str) -> Pattern {
+    let bytes = seed(hash);
+    let mut pattern = Pattern::new(5, 0.5, LIGHT_GREY, hsl(hue(&bytes), 0.55, 0.5));
+
+    for row in 0..5 {
+        for col in 0..3 {
+            if nibble(&bytes, row * 3 + col).is_multiple_of(2) {
+                pattern.set(col, row, Cell::Full);
+                pattern.set(4 - col, row, Cell::Full);
+            }
+        }
+    }
+
+    pattern
+}
+
+/// 6x6 horizontally mirrored grid of squares and corner triangles on a tinted background
+pub fn retro(hash: &str) -> Pattern {
+    const SHAPES: [Cell; 8] = [
+        Cell::TopLeft, Cell::TopRight, Cell::BottomLeft, Cell::BottomRight,
+        Cell::Full, Cell::Empty, Cell::Empty, Cell::Empty,
+    ];
+
+    let bytes = seed(hash);
+    let hue = hue(&bytes);
+    let mut pattern = Pattern::new(6, 0.5, hsl(hue, 0.45, 0.9), hsl(hue, 0.55, 0.45));
+
+    for row in 0..6 {
+        for col in 0..3 {
+            let cell = SHAPES[(nibble(&bytes, row * 3 + col) & 0x07) as usize];
+
+            pattern.set(col, row, cell);
+            pattern.set(5 - col, row, cell.mirrored());
+        }
+    }
+
+    pattern
+}
+
+/// Up to two upper-case initials taken from the local part of `name`, e.g. `JD` for
+/// `john.doe@example.com`. Characters outside ASCII letters and digits are skipped.
+pub fn initials_of(name: &str) -> String {
+    let local = name.split('@').next().unwrap_or_default();
+
+    local.split(|c: char| !c.is_ascii_alphanumeric())
+        .filter_map(|part| part.chars().next())
+        .take(2)
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+/// Initials of `name` in white on a background coloured after the hash, or `None` when the
+/// name has no usable characters
+pub fn initials(name: &str, hash: &str) -> Option<Pattern> {
+    let initials = initials_of(name);
+
+    if initials.is_empty() {
+        return None;
+    }
+
+    let bytes = seed(hash);
+    let mut pattern = Pattern::new(11, 3.0, hsl(hue(&bytes), 0.5, 0.45), WHITE);
+
+    let width = initials.len() * 6 - 1;
+    let left = (11 - width) / 2;
+
+    for (i, c) in initials.chars().enumerate() {
+        let glyph = match c {
+            'A'..='Z' => GLYPHS[c as usize - 'A' as usize],
+            _ => GLYPHS[26 + c as usize - '0' as usize],
+        };
+
+        for (row, bits) in glyph.iter().enumerate() {
+            for col in 0..5 {
+                if bits & (0b10000 >> col) != 0 {
+                    pattern.set(left + i * 6 + col, 2 + row, Cell::Full);
+                }
+            }
+        }
+    }
+
+    Some(pattern)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::avatar::detect_content_type;
+
+    const HASH: &str = "0bc83cb571cd1c50ba6f3e8a78ef1346";
+
+    #[tokio::test]
+    async fn test_parse() {
+        assert_eq!(DefaultImage::parse("404"), DefaultImage::NotFound);
+        assert_eq!(DefaultImage::parse("mp"), DefaultImage::MysteryPerson);
+        assert_eq!(DefaultImage::parse("identicon"), DefaultImage::Identicon);
+        assert_eq!(DefaultImage::parse("initials"), DefaultImage::Initials);
+        assert_eq!(DefaultImage::parse("retro"), DefaultImage::Retro);
+        assert_eq!(DefaultImage::parse("geometric"), DefaultImage::Retro);
+        assert_eq!(DefaultImage::parse("blank"), DefaultImage::Blank);
+        assert_eq!(DefaultImage::parse("https://example.com/a.png"), DefaultImage::Url("https://example.com/a.png".to_owned()));
+        assert_eq!(DefaultImage::parse("javascript:alert(1)"), DefaultImage::MysteryPerson);
+    }
+
+    #[tokio::test]
+    async fn test_patterns_are_stable() {
+        assert_eq!(identicon(HASH), identicon(HASH));
+        assert_ne!(identicon(HASH), identicon("00000000000000000000000000000000"));
+        assert_eq!(retro(HASH), retro(HASH));
+        assert_ne!(retro(HASH), retro("00000000000000000000000000000000"));
+        assert_eq!(identicon("not hex"), identicon("not hex"));
+    }
+
+    #[tokio::test]
+    async fn test_identicon_is_mirrored() {
+        // Given
+        let pattern = identicon(HASH);
+
+        // Then
+        for row in 0..5 {
+            for col in 0..5 {
+                assert_eq!(pattern.cells[row * 5 + col], pattern.cells[row * 5 + 4 - col]);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_initials_of() {
+        assert_eq!(initials_of("john.doe@example.com"), "JD");
+        assert_eq!(initials_of("alice@example.com"), "A");
+        assert_eq!(initials_of("bob_smith_jr"), "BS");
+        assert_eq!(initials_of("__@example.com"), "");
+        assert!(initials("...", HASH).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_render_svg_and_raster() {
+        // Given
+        let pattern = initials("john.doe@example.com", HASH).unwrap();
+
+        // When
+        let svg = pattern.render(64, true, OutputFormat::Png).unwrap();
+        let png = pattern.render(64, false, OutputFormat::Png).unwrap();
+
+        // Then
+        assert_eq!(svg.content_type, CONTENT_TYPE_SVG);
+        assert!(String::from_utf8(svg.data).unwrap().starts_with("<svg"));
+        assert_eq!(detect_content_type(&png.data), Some(CONTENT_TYPE_PNG));
+        assert_eq!(image::load_from_memory(&png.data).unwrap().width(), 64);
+    }
+
+    #[tokio::test]
+    async fn test_raster_matches_pattern() {
+        // Given
+        let pattern = identicon(HASH);
+
+        // When
+        let png = pattern.to_raster(60, OutputFormat::Png).unwrap();
+
+        // Then
+        let image = image::load_from_memory(&png.data).unwrap().to_rgba8();
+        for row in 0..5 {
+            for col in 0..5 {
+                // Centre of the cell, the margin is half a cell of 10 pixels
+                let pixel = image.get_pixel(col * 10 + 10, row * 10 + 10);
+                let expected = if pattern.cells[(row * 5 + col) as usize] == Cell::Full { pattern.foreground } else { pattern.background };
+                assert_eq!(pixel.0[..3], expected);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_blank_is_png() {
+        assert_eq!(blank().data[..8], *b"\x89PNG\r\n\x1a\n");
+    }
+}
@@ -0,0 +1,523 @@
+use crate::models::email_address::{is_valid_email, normalize_email, Email, EMAIL_VERIFICATION_LIFETIME_HOURS};
+use crate::models::outbox::{MailContent, OutboxMessage, CODE_PLACEHOLDER};
+use crate::models::user::User;
+use crate::repositories::email_repository::EmailRepository;
+use crate::repositories::id_provider::IdProvider;
+use crate::repositories::outbox_repository::OutboxBuilder;
+use crate::repositories::user_repository::UserRepository;
+use crate::repositories::DbError;
+use crate::services::otp_hasher::OtpHasher;
+use crate::services::session_rotator::{NoopSessionRotator, SessionRotator};
+use crate::views::email_view::{EmailVerificationView, EmailView};
+use crate::views::session_view::SessionView;
+use chrono::{DateTime, Duration, Utc};
+use std::fmt::{self, Display, Formatter};
+use std::sync::Arc;
+
+/// Length of the code mailed to confirm an address
+const VERIFICATION_CODE_LENGTH: usize = 32;
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum EmailAddressError {
+    InvalidAddress(String),
+    /// The user already added this address
+    AlreadyAdded(String),
+    /// Another user has verified this address
+    Taken(String),
+    NotFound,
+    /// The user the address is managed for does not exist
+    UserNotFound,
+    AlreadyVerified,
+    NotVerified,
+    InvalidCode,
+    Expired,
+    /// The avatar to assign is not in the user's library
+    AvatarNotFound,
+    InternalError(String),
+}
+
+impl Display for EmailAddressError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            EmailAddressError::InvalidAddress(address) => write!(f, "Invalid email address: {}", address),
+            EmailAddressError::AlreadyAdded(address) => write!(f, "Email address {} was already added", address),
+            EmailAddressError::Taken(address) => write!(f, "Email address {} belongs to another account", address),
+            EmailAddressError::NotFound => write!(f, "Email address not found"),
+            EmailAddressError::UserNotFound => write!(f, "User not found"),
+            EmailAddressError::AlreadyVerified => write!(f, "Email address is already verified"),
+            EmailAddressError::NotVerified => write!(f, "Email address is not verified"),
+            EmailAddressError::InvalidCode => write!(f, "Invalid verification code"),
+            EmailAddressError::Expired => write!(f, "Verification code expired, add the address again"),
+            EmailAddressError::AvatarNotFound => write!(f, "Avatar not found"),
+            EmailAddressError::InternalError(msg) => write!(f, "Internal error: {}", msg),
+        }
+    }
+}
+
+impl From<DbError> for EmailAddressError {
+    fn from(err: DbError) -> Self {
+        EmailAddressError::InternalError(err.to_string())
+    }
+}
+
+/// Manages the addresses of a user. Addresses are added unverified with a mailed code and
+/// only count for avatar lookup once the code has been confirmed.
+#[derive(Clone)]
+pub struct EmailService<ER, UR, IP>
+where
+    ER: EmailRepository + Sync + Send,
+    UR: UserRepository + Sync + Send,
+    IP: IdProvider + Sync + Send,
+{
+    email_repository: ER,
+    user_repository: UR,
+    id_provider: IP,
+    otp_hasher: OtpHasher,
+    session_rotator: Arc<dyn SessionRotator>,
+}
+
+impl<ER, UR, IP> fmt::Debug for EmailService<ER, UR, IP>
+where
+    ER: EmailRepository + Sync + Send,
+    UR: UserRepository + Sync + Send,
+    IP: IdProvider + Sync + Send,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EmailService").finish_non_exhaustive()
+    }
+}
+
+impl<ER, UR, IP> EmailService<ER, UR, IP>
+where
+    ER: EmailRepository + Sync + Send,
+    UR: UserRepository + Sync + Send,
+    IP: IdProvider + Sync + Send,
+{
+    pub fn new(email_repository: ER, user_repository: UR, id_provider: IP) -> Self {
+        Self {
+            email_repository,
+            user_repository,
+            id_provider,
+            otp_hasher: OtpHasher::random(),
+            session_rotator: Arc::new(NoopSessionRotator),
+        }
+    }
+
+    /// Replaces the per-process random key verification codes are hashed with
+    pub fn with_otp_hasher(mut self, otp_hasher: OtpHasher) -> Self {
+        self.otp_hasher = otp_hasher;
+        self
+    }
+
+    /// Rotates the sessions address changes are made in, see `rotate_session`
+    pub fn with_session_rotator(mut self, session_rotator: Arc<dyn SessionRotator>) -> Self {
+        self.session_rotator = session_rotator;
+        self
+    }
+
+    pub async fn list(&self, user_id: i64) -> Result<Vec<EmailView>, EmailAddressError> {
+        let user = self.find_user(user_id).await?;
+        let emails = self.email_repository.find_by_user(user_id).await?;
+
+        Ok(emails.into_iter()
+            .map(|email| {
+                let is_primary = user.primary_email_id == Some(email.id);
+                EmailView::new(email, is_primary)
+            })
+            .collect())
+    }
+
+    /// Adds an unverified address to the user and returns the code that confirms it
+    pub async fn add(&self, user_id: i64, address: &str) -> Result<EmailVerificationView, EmailAddressError> {
+        let (email, code, expires_at) = self.new_address(user_id, address).await?;
+        let value = email.value.clone();
+        let email = added(self.email_repository.save(email).await, value)?;
+
+        Ok(EmailVerificationView { email: EmailView::new(email, false), code, expires_at })
+    }
+
+    /// Adds an unverified address like `add` and writes the mail with the code that confirms it
+    /// to the outbox in the same write, so the address is never stored without its mail.
+    /// `compose` is handed `CODE_PLACEHOLDER` for the code, the outbox only stores it sealed.
+    pub async fn add_and_mail<F>(&self, user_id: i64, address: &str, compose: F) -> Result<EmailView, EmailAddressError>
+    where
+        F: FnOnce(&EmailVerificationView) -> MailContent + Send,
+    {
+        let (email, code, expires_at) = self.new_address(user_id, address).await?;
+        let value = email.value.clone();
+        let sealed_code = self.otp_hasher.seal(user_id, &code);
+
+        let messages: OutboxBuilder<Email> = Box::new(move |email: &Email| {
+            let verification = EmailVerificationView {
+                email: EmailView::new(email.clone(), false),
+                code: CODE_PLACEHOLDER.to_owned(),
+                expires_at,
+            };
+            let mail = compose(&verification).sealed(email.value.clone(), email.user_id, sealed_code);
+
+            vec![OutboxMessage::new(format!("email:{}:verification", email.id), mail).with_expiry(expires_at)]
+        });
+
+        let email = added(self.email_repository.save_with_outbox(email, messages).await, value)?;
+
+        Ok(EmailView::new(email, false))
+    }
+
+    /// Unsaved address for the user with its verification code and when the code expires
+    async fn new_address(&self, user_id: i64, address: &str) -> Result<(Email, String, DateTime<Utc>), EmailAddressError> {
+        if !is_valid_email(address) {
+            return Err(EmailAddressError::InvalidAddress(address.to_owned()));
+        }
+
+        let value = normalize_email(address);
+
+        self.find_user(user_id).await?;
+
+        if self.email_repository.find_by_user(user_id).await?.iter().any(|e| e.value == value) {
+            return Err(EmailAddressError::AlreadyAdded(value));
+        }
+
+        if self.email_repository.find_verified_by_value(&value).await.is_some() {
+            return Err(EmailAddressError::Taken(value));
+        }
+
+        let code = self.id_provider.get_id(VERIFICATION_CODE_LENGTH);
+        let expires_at = Utc::now() + Duration::hours(EMAIL_VERIFICATION_LIFETIME_HOURS);
+
+        let mut email = Email::new(user_id, value.clone());
+        email.verification_hash = Some(self.otp_hasher.hash(user_id, &verification_input(&value, &code)));
+        email.verification_expires_at = Some(expires_at);
+
+        Ok((email, code, expires_at))
+    }
+
+    /// Confirms the address with the mailed code. The first verified address becomes the
+    /// user's primary one.
+    pub async fn verify(&self, user_id: i64, email_id: i64, code: &str) -> Result<EmailView, EmailAddressError> {
+        let mut email = self.find_owned(user_id, email_id).await?;
+
+        if email.is_verified {
+            return Err(EmailAddressError::AlreadyVerified);
+        }
+
+        let Some(hash) = email.verification_hash.clone() else {
+            return Err(EmailAddressError::Expired);
+        };
+
+        if !self.otp_hasher.verify(user_id, &verification_input(&email.value, code), &hash) {
+            return Err(EmailAddressError::InvalidCode);
+        }
+
+        if email.is_verification_expired() {
+            return Err(EmailAddressError::Expired);
+        }
+
+        email.is_verified = true;
+        email.verification_hash = None;
+        email.verification_expires_at = None;
+        email.updated_at = Utc::now();
+
+        let email = match self.email_repository.save(email).await {
+            Ok(email) => email,
+            Err(DbError::UniqueViolation(value)) => return Err(EmailAddressError::Taken(value)),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut user = self.find_user(user_id).await?;
+
+        if user.primary_email_id.is_none() {
+            user.primary_email_id = Some(email.id);
+            user.last_update_date = Utc::now();
+            self.user_repository.save(user).await?;
+
+            return Ok(EmailView::new(email, true));
+        }
+
+        let is_primary = user.primary_email_id == Some(email.id);
+
+        Ok(EmailView::new(email, is_primary))
+    }
+
+    /// Removes the address, clearing the user's primary address if it was this one
+    pub async fn remove(&self, user_id: i64, email_id: i64) -> Result<(), EmailAddressError> {
+        let email = self.find_owned(user_id, email_id).await?;
+
+        let mut user = self.find_user(user_id).await?;
+
+        if user.primary_email_id == Some(email.id) {
+            user.primary_email_id = None;
+            user.last_update_date = Utc::now();
+            self.user_repository.save(user).await?;
+        }
+
+        self.email_repository.delete(email.id).await?;
+
+        Ok(())
+    }
+
+    /// Makes a verified address the user's primary one
+    pub async fn set_primary(&self, user_id: i64, email_id: i64) -> Result<EmailView, EmailAddressError> {
+        let email = self.find_owned(user_id, email_id).await?;
+
+        if !email.is_verified {
+            return Err(EmailAddressError::NotVerified);
+        }
+
+        let mut user = self.find_user(user_id).await?;
+
+        user.primary_email_id = Some(email.id);
+        user.last_update_date = Utc::now();
+        self.user_repository.save(user).await?;
+
+        Ok(EmailView::new(email, true))
+    }
+
+    /// Shows `avatar_id` for a verified address instead of the user's primary avatar, `None`
+    /// restores the fallback. The caller checks that the avatar belongs to the user.
+    pub async fn assign_avatar(&self, user_id: i64, email_id: i64, avatar_id: Option<i64>) -> Result<EmailView, EmailAddressError> {
+        let mut email = self.find_owned(user_id, email_id).await?;
+
+        if !email.is_verified {
+            return Err(EmailAddressError::NotVerified);
+        }
+
+        email.avatar_id = avatar_id;
+        email.updated_at = Utc::now();
+
+        let email = self.email_repository.save(email).await?;
+        let user = self.find_user(user_id).await?;
+        let is_primary = user.primary_email_id == Some(email.id);
+
+        Ok(EmailView::new(email, is_primary))
+    }
+
+    /// Adding, verifying, removing or choosing the primary address changes what the account is
+    /// reachable under, so the session such a change was made in gets a new value afterwards.
+    /// Returns `None` when there is no session or it could not be rotated, the old value then
+    /// stays valid and the change itself is kept.
+    pub async fn rotate_session(&self, session: Option<&str>) -> Option<SessionView> {
+        self.session_rotator.rotate(session?).await.ok()
+    }
+
+    async fn find_user(&self, user_id: i64) -> Result<User, EmailAddressError> {
+        self.user_repository.find_by_id(user_id).await
+            .map_err(|e| EmailAddressError::InternalError(e.to_string()))?
+            .ok_or(EmailAddressError::UserNotFound)
+    }
+
+    /// Returns the email when it belongs to `user_id`. Emails of other users are reported as
+    /// missing so that ids cannot be probed.
+    async fn find_owned(&self, user_id: i64, email_id: i64) -> Result<Email, EmailAddressError> {
+        self.email_repository.find_by_id(email_id).await
+            .filter(|email| email.user_id == user_id)
+            .ok_or(EmailAddressError::NotFound)
+    }
+}
+
+/// Outcome of saving a new address, a duplicate means the user added it concurrently
+fn added(result: Result<Email, DbError>, value: String) -> Result<Email, EmailAddressError> {
+    match result {
+        Ok(email) => Ok(email),
+        Err(DbError::UniqueViolation(_)) => Err(EmailAddressError::AlreadyAdded(value)),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Binds the verification code to the address it was sent to
+fn verification_input(value: &str, code: &str) -> String {
+    format!("{}:{}", value, code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::outbox::OutboxPayload;
+    use crate::repositories::email_repository::InMemoryEmailRepository;
+    use crate::repositories::id_provider::SimpleIdProvider;
+    use crate::repositories::otp_repository::InMemoryOtpRepository;
+    use crate::repositories::outbox_repository::InMemoryOutboxRepository;
+    use crate::repositories::session_repository::InMemorySessionRepository;
+    use crate::repositories::user_repository::InMemoryUserRepository;
+    use crate::services::user_service::UserService;
+
+    type TestEmailService = EmailService<InMemoryEmailRepository, InMemoryUserRepository, SimpleIdProvider>;
+
+    async fn create_service() -> (TestEmailService, i64) {
+        let user_repository = InMemoryUserRepository::new();
+        let user = user_repository.save(User::new("alice".to_owned())).await.unwrap();
+
+        let service = EmailService::new(InMemoryEmailRepository::new(), user_repository, SimpleIdProvider::new());
+
+        (service, user.id)
+    }
+
+    #[tokio::test]
+    async fn test_add_and_verify() {
+        // Given
+        let (service, user_id) = create_service().await;
+        let pending = service.add(user_id, "Alice@Example.com").await.unwrap();
+
+        // When
+        let verified = service.verify(user_id, pending.email.id, &pending.code).await.unwrap();
+
+        // Then
+        assert!(!pending.email.is_verified);
+        assert_eq!(verified.value, "alice@example.com");
+        assert!(verified.is_verified);
+        assert!(verified.is_primary);
+        assert_eq!(service.user_repository.find_by_id(user_id).await.unwrap().unwrap().primary_email_id, Some(verified.id));
+    }
+
+    #[tokio::test]
+    async fn test_add_and_mail() {
+        // Given
+        let (service, user_id) = create_service().await;
+        let outbox = InMemoryOutboxRepository::new();
+        let service = EmailService { email_repository: InMemoryEmailRepository::new().with_outbox(outbox.clone()), ..service };
+
+        // When
+        let added = service.add_and_mail(user_id, "alice@example.com", |verification| MailContent {
+            subject: "Confirm".to_owned(),
+            html_body: format!("{}/{}", verification.email.id, verification.code),
+            plain_body: verification.code.clone(),
+        }).await.unwrap();
+
+        // Then
+        let messages = outbox.messages().await;
+        assert_eq!(messages.len(), 1);
+        let OutboxPayload::SealedMail { to, sealed_code, html_body, .. } = &messages[0].payload else {
+            panic!("Expected a sealed mail");
+        };
+        assert_eq!(to, "alice@example.com");
+        assert_eq!(html_body, &format!("{}/{}", added.id, CODE_PLACEHOLDER));
+        let code = service.otp_hasher.open(user_id, sealed_code).unwrap();
+        assert!(service.verify(user_id, added.id, &code).await.unwrap().is_verified);
+    }
+
+    #[tokio::test]
+    async fn test_add_invalid_or_duplicate() {
+        // Given
+        let (service, user_id) = create_service().await;
+        service.add(user_id, "alice@example.com").await.unwrap();
+
+        // Then
+        assert!(matches!(service.add(user_id, "alice").await, Err(EmailAddressError::InvalidAddress(_))));
+        assert!(matches!(service.add(user_id, "ALICE@example.com").await, Err(EmailAddressError::AlreadyAdded(_))));
+    }
+
+    #[tokio::test]
+    async fn test_add_verified_by_other_user() {
+        // Given
+        let (service, alice) = create_service().await;
+        let bob = service.user_repository.save(User::new("bob".to_owned())).await.unwrap().id;
+        let pending = service.add(alice, "shared@example.com").await.unwrap();
+        let bobs = service.add(bob, "shared@example.com").await.unwrap();
+        service.verify(alice, pending.email.id, &pending.code).await.unwrap();
+
+        // When
+        let added = service.add(bob, "shared@example.com").await;
+        let verified = service.verify(bob, bobs.email.id, &bobs.code).await;
+
+        // Then
+        assert!(matches!(added, Err(EmailAddressError::AlreadyAdded(_))));
+        assert!(matches!(verified, Err(EmailAddressError::Taken(_))));
+    }
+
+    #[tokio::test]
+    async fn test_verify_wrong_code_or_user() {
+        // Given
+        let (service, user_id) = create_service().await;
+        let other = service.user_repository.save(User::new("bob".to_owned())).await.unwrap().id;
+        let pending = service.add(user_id, "alice@example.com").await.unwrap();
+
+        // Then
+        assert_eq!(service.verify(user_id, pending.email.id, "wrong").await, Err(EmailAddressError::InvalidCode));
+        assert_eq!(service.verify(other, pending.email.id, &pending.code).await, Err(EmailAddressError::NotFound));
+    }
+
+    #[tokio::test]
+    async fn test_verify_expired() {
+        // Given
+        let (service, user_id) = create_service().await;
+        let pending = service.add(user_id, "alice@example.com").await.unwrap();
+        let mut email = service.email_repository.find_by_id(pending.email.id).await.unwrap();
+        email.verification_expires_at = Some(Utc::now() - Duration::seconds(1));
+        service.email_repository.save(email).await.unwrap();
+
+        // When
+        let result = service.verify(user_id, pending.email.id, &pending.code).await;
+
+        // Then
+        assert_eq!(result, Err(EmailAddressError::Expired));
+    }
+
+    #[tokio::test]
+    async fn test_set_primary_and_remove() {
+        // Given
+        let (service, user_id) = create_service().await;
+        let first = service.add(user_id, "first@example.com").await.unwrap();
+        let second = service.add(user_id, "second@example.com").await.unwrap();
+        service.verify(user_id, first.email.id, &first.code).await.unwrap();
+
+        // When
+        let unverified = service.set_primary(user_id, second.email.id).await;
+        service.verify(user_id, second.email.id, &second.code).await.unwrap();
+        service.set_primary(user_id, second.email.id).await.unwrap();
+        service.remove(user_id, second.email.id).await.unwrap();
+
+        // Then
+        assert_eq!(unverified, Err(EmailAddressError::NotVerified));
+        let emails = service.list(user_id).await.unwrap();
+        assert_eq!(emails.len(), 1);
+        assert!(!emails[0].is_primary);
+        assert_eq!(service.user_repository.find_by_id(user_id).await.unwrap().unwrap().primary_email_id, None);
+    }
+
+    #[tokio::test]
+    async fn test_assign_avatar() {
+        // Given
+        let (service, user_id) = create_service().await;
+        let verified = service.add(user_id, "work@example.com").await.unwrap();
+        let pending = service.add(user_id, "home@example.com").await.unwrap();
+        service.verify(user_id, verified.email.id, &verified.code).await.unwrap();
+
+        // When
+        let assigned = service.assign_avatar(user_id, verified.email.id, Some(7)).await.unwrap();
+        let unverified = service.assign_avatar(user_id, pending.email.id, Some(7)).await;
+        let cleared = service.assign_avatar(user_id, verified.email.id, None).await.unwrap();
+
+        // Then
+        assert_eq!(assigned.avatar_id, Some(7));
+        assert_eq!(unverified, Err(EmailAddressError::NotVerified));
+        assert_eq!(cleared.avatar_id, None);
+        assert_eq!(service.email_repository.find_by_id(verified.email.id).await.unwrap().avatar_id, None);
+    }
+
+    #[tokio::test]
+    async fn test_rotate_session() {
+        // Given
+        let user_repository = InMemoryUserRepository::new();
+        user_repository.save(User::new("alice".to_owned())).await.unwrap();
+        let user_service = UserService::new(
+            user_repository.clone(),
+            InMemorySessionRepository::new(),
+            InMemoryOtpRepository::new(),
+            SimpleIdProvider::new(),
+        );
+        let old = user_service.generate_session("alice", None, None).await.unwrap();
+        let unrotated = EmailService::new(InMemoryEmailRepository::new(), user_repository.clone(), SimpleIdProvider::new());
+        let service = unrotated.clone().with_session_rotator(Arc::new(user_service));
+
+        // When
+        let without_rotator = unrotated.rotate_session(Some(&old.value)).await;
+        let without_session = service.rotate_session(None).await;
+        let rotated = service.rotate_session(Some(&old.value)).await;
+        let stale = service.rotate_session(Some(&old.value)).await;
+
+        // Then
+        assert!(without_rotator.is_none());
+        assert!(without_session.is_none());
+        assert_ne!(rotated.unwrap().value, old.value);
+        assert!(stale.is_none());
+    }
+}
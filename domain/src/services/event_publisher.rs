@@ -0,0 +1,74 @@
+use crate::models::event::{DomainEvent, EventEnvelope};
+use async_trait::async_trait;
+use std::fmt::Debug;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+/// Hands events raised by the services to whoever listens. Publishing never fails the
+/// operation that raised the event, publishers deal with their own errors.
+#[async_trait]
+pub trait EventPublisher: Debug + Send + Sync {
+    async fn publish(&self, event: EventEnvelope);
+}
+
+/// Drops every event, used until a real publisher is set
+#[derive(Debug, Clone, Default)]
+pub struct NoopEventPublisher;
+
+#[async_trait]
+impl EventPublisher for NoopEventPublisher {
+    async fn publish(&self, _event: EventEnvelope) {}
+}
+
+/// Keeps published events in memory. Clones share the same list.
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryEventPublisher {
+    events: Arc<RwLock<Vec<EventEnvelope>>>,
+}
+
+impl InMemoryEventPublisher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub async fn events(&self) -> Vec<DomainEvent> {
+        self.events.read().await.iter().map(|envelope| envelope.event.clone()).collect()
+    }
+}
+
+#[async_trait]
+impl EventPublisher for InMemoryEventPublisher {
+    async fn publish(&self, event: EventEnvelope) {
+        self.events.write().await.push(event);
+    }
+}
+
+/// Holds events back until `flush` hands them to the inner publisher, for events raised inside
+/// a transaction that must not be seen before it is committed. Clones share the same buffer.
+#[derive(Debug, Clone)]
+pub struct BufferedEventPublisher {
+    inner: Arc<dyn EventPublisher>,
+    buffer: Arc<RwLock<Vec<EventEnvelope>>>,
+}
+
+impl BufferedEventPublisher {
+    pub fn new(inner: Arc<dyn EventPublisher>) -> Self {
+        Self { inner, buffer: Arc::new(RwLock::new(Vec::new())) }
+    }
+
+    /// Publishes the buffered events in the order they were raised
+    pub async fn flush(&self) {
+        let events = std::mem::take(&mut *self.buffer.write().await);
+
+        for event in events {
+            self.inner.publish(event).await;
+        }
+    }
+}
+
+#[async_trait]
+impl EventPublisher for BufferedEventPublisher {
+    async fn publish(&self, event: EventEnvelope) {
+        self.buffer.write().await.push(event);
+    }
+}
@@ -0,0 +1,228 @@
+use crate::models::avatar::{CONTENT_TYPE_JPEG, CONTENT_TYPE_PNG, CONTENT_TYPE_SVG, CONTENT_TYPE_WEBP, MAX_RENDER_SIZE};
+use crate::services::avatar_service::AvatarError;
+use crate::views::avatar_view::AvatarImage;
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat, ImageReader, Limits};
+use std::io::Cursor;
+
+/// Largest width or height of an image we are willing to decode
+const MAX_DECODE_DIMENSION: u32 = 8192;
+/// Upper bound on memory the decoder may allocate, in bytes
+const MAX_DECODE_ALLOC: u64 = 256 * 1024 * 1024;
+const JPEG_QUALITY: u8 = 85;
+
+/// Encodings avatars can be served in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OutputFormat {
+    Png,
+    WebP,
+    Jpeg,
+}
+
+impl OutputFormat {
+    /// In order of preference when the client accepts several equally
+    const ALL: [OutputFormat; 3] = [OutputFormat::Png, OutputFormat::WebP, OutputFormat::Jpeg];
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            OutputFormat::WebP => CONTENT_TYPE_WEBP,
+            OutputFormat::Png => CONTENT_TYPE_PNG,
+            OutputFormat::Jpeg => CONTENT_TYPE_JPEG,
+        }
+    }
+
+    /// Picks the format to serve from an `Accept` header. Explicitly listed types win over
+    /// wildcards at equal quality, PNG is used when the header is missing or accepts nothing
+    /// we can produce.
+    pub fn negotiate(accept: Option<&str>) -> Self {
+        let ranges = parse_accept(accept);
+
+        if ranges.is_empty() {
+            return OutputFormat::Png;
+        }
+
+        let mut best = (OutputFormat::Png, (0.0, false));
+
+        for format in OutputFormat::ALL {
+            let candidate = quality(&ranges, format.content_type());
+
+            if candidate.0 > best.1 .0 || (candidate.0 == best.1 .0 && candidate.1 && !best.1 .1) {
+                best = (format, candidate);
+            }
+        }
+
+        best.0
+    }
+}
+
+/// Whether a vector image should be served rather than a raster one. Only clients that list
+/// `image/svg+xml` explicitly, at least as high as any raster format, get SVG.
+pub fn prefers_svg(accept: Option<&str>) -> bool {
+    let ranges = parse_accept(accept);
+    let (svg, explicit) = quality(&ranges, CONTENT_TYPE_SVG);
+
+    explicit && svg > 0.0 && OutputFormat::ALL.iter().all(|format| quality(&ranges, format.content_type()).0 <= svg)
+}
+
+fn parse_accept(accept: Option<&str>) -> Vec<(&str, f32)> {
+    accept.map(|accept| accept.split(',').filter_map(parse_media_range).collect()).unwrap_or_default()
+}
+
+/// Quality the client gives `content_type` and whether it was listed explicitly rather than
+/// matched by a wildcard
+fn quality(ranges: &[(&str, f32)], content_type: &str) -> (f32, bool) {
+    match ranges.iter().find(|(range, _)| range.eq_ignore_ascii_case(content_type)) {
+        Some((_, q)) => (*q, true),
+        None => ranges.iter()
+            .filter(|(range, _)| *range == "image/*" || *range == "*/*")
+            .map(|(_, q)| (*q, false))
+            .fold((0.0, false), |best, candidate| if candidate.0 > best.0 { candidate } else { best }),
+    }
+}
+
+/// Splits `type/subtype;q=0.5` into the media range and its quality
+fn parse_media_range(range: &str) -> Option<(&str, f32)> {
+    let mut parts = range.split(';');
+    let media_type = parts.next()?.trim();
+
+    if media_type.is_empty() {
+        return None;
+    }
+
+    let q = parts
+        .filter_map(|param| param.trim().strip_prefix("q="))
+        .find_map(|q| q.trim().parse::<f32>().ok())
+        .unwrap_or(1.0);
+
+    Some((media_type, q.clamp(0.0, 1.0)))
+}
+
+/// Decodes an uploaded image (PNG, JPEG, the first frame of a GIF or WebP), crops it to the
+/// centered square, resizes it to `size` pixels and encodes it as `format`. `size` is clamped
+/// to `1..=MAX_RENDER_SIZE`.
+pub fn render(data: &[u8], size: u32, format: OutputFormat) -> Result<AvatarImage, AvatarError> {
+    let size = size.clamp(1, MAX_RENDER_SIZE);
+    let image = decode(data)?;
+
+    let side = image.width().min(image.height());
+    let x = (image.width() - side) / 2;
+    let y = (image.height() - side) / 2;
+
+    let square = image.crop_imm(x, y, side, side);
+    let resized = if side == size { square } else { square.resize_exact(size, size, FilterType::Lanczos3) };
+
+    let data = encode(&resized, format)?;
+
+    Ok(AvatarImage::new(format.content_type(), data))
+}
+
+/// Decodes `data` within the dimension and allocation limits, failing with
+/// `AvatarError::InvalidImage` for anything that is not a complete image
+pub fn decode(data: &[u8]) -> Result<DynamicImage, AvatarError> {
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_DECODE_DIMENSION);
+    limits.max_image_height = Some(MAX_DECODE_DIMENSION);
+    limits.max_alloc = Some(MAX_DECODE_ALLOC);
+
+    let mut reader = ImageReader::new(Cursor::new(data))
+        .with_guessed_format()
+        .map_err(|e| AvatarError::InvalidImage(e.to_string()))?;
+
+    reader.limits(limits);
+
+    reader.decode().map_err(|e| AvatarError::InvalidImage(e.to_string()))
+}
+
+pub(crate) fn encode(image: &DynamicImage, format: OutputFormat) -> Result<Vec<u8>, AvatarError> {
+    let mut buffer = Cursor::new(Vec::new());
+
+    let result = match format {
+        OutputFormat::WebP => DynamicImage::ImageRgba8(image.to_rgba8()).write_to(&mut buffer, ImageFormat::WebP),
+        OutputFormat::Png => DynamicImage::ImageRgba8(image.to_rgba8()).write_to(&mut buffer, ImageFormat::Png),
+        OutputFormat::Jpeg => image.to_rgb8().write_with_encoder(JpegEncoder::new_with_quality(&mut buffer, JPEG_QUALITY)),
+    };
+
+    result.map_err(|e| AvatarError::InternalError(format!("Failed to encode image: {}", e)))?;
+
+    Ok(buffer.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::avatar::detect_content_type;
+    use image::{Rgba, RgbaImage};
+
+    fn create_png(width: u32, height: u32) -> Vec<u8> {
+        let image = RgbaImage::from_fn(width, height, |x, _| if x < width / 2 { Rgba([255, 0, 0, 255]) } else { Rgba([0, 0, 255, 255]) });
+        let mut buffer = Cursor::new(Vec::new());
+        image.write_to(&mut buffer, ImageFormat::Png).unwrap();
+
+        buffer.into_inner()
+    }
+
+    #[tokio::test]
+    async fn test_render_crops_and_resizes() {
+        // Given
+        let data = create_png(120, 60);
+
+        // When
+        let rendered = render(&data, 32, OutputFormat::Png).unwrap();
+
+        // Then
+        let image = image::load_from_memory(&rendered.data).unwrap();
+        assert_eq!(rendered.content_type, CONTENT_TYPE_PNG);
+        assert_eq!((image.width(), image.height()), (32, 32));
+    }
+
+    #[tokio::test]
+    async fn test_render_formats() {
+        // Given
+        let data = create_png(16, 16);
+
+        // Then
+        for format in OutputFormat::ALL {
+            let rendered = render(&data, 8, format).unwrap();
+            assert_eq!(detect_content_type(&rendered.data), Some(format.content_type()));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_render_clamps_size() {
+        // Given
+        let data = create_png(4, 4);
+
+        // When
+        let rendered = render(&data, 0, OutputFormat::Png).unwrap();
+
+        // Then
+        let image = image::load_from_memory(&rendered.data).unwrap();
+        assert_eq!(image.width(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_render_invalid_data() {
+        assert!(matches!(render(b"not an image", 8, OutputFormat::Png), Err(AvatarError::InvalidImage(_))));
+        assert!(matches!(render(b"\x89PNG\r\n\x1a\n\x00", 8, OutputFormat::Png), Err(AvatarError::InvalidImage(_))));
+    }
+
+    #[tokio::test]
+    async fn test_negotiate() {
+        assert_eq!(OutputFormat::negotiate(None), OutputFormat::Png);
+        assert_eq!(OutputFormat::negotiate(Some("image/avif,image/webp,*/*")), OutputFormat::WebP);
+        assert_eq!(OutputFormat::negotiate(Some("image/jpeg")), OutputFormat::Jpeg);
+        assert_eq!(OutputFormat::negotiate(Some("image/webp;q=0.5, image/png")), OutputFormat::Png);
+        assert_eq!(OutputFormat::negotiate(Some("*/*")), OutputFormat::Png);
+        assert_eq!(OutputFormat::negotiate(Some("image/webp,image/*;q=0.8")), OutputFormat::WebP);
+        assert_eq!(OutputFormat::negotiate(Some("text/html")), OutputFormat::Png);
+    }
+
+    #[tokio::test]
+    async fn test_prefers_svg() {
+        assert!(prefers_svg(Some("image/avif,image/webp,image/svg+xml,image/*,*/*;q=0.8")));
+        assert!(!prefers_svg(Some("image/png,image/svg+xml;q=0.5")));
+        assert!(!prefers_svg(Some("*/*")));
+        assert!(!prefers_svg(None));
+    }
+}
@@ -23,9 +23,16 @@ pub trait MailService {
     async fn send_otp(&self, email: &str, otp: OtpView) -> Result<(), EmailError>;
 }
 
+/// Accepts every mail without sending it anywhere, for tests
 #[derive(Debug, Clone)]
 pub struct InMemoryMailService {}
 
+impl Default for InMemoryMailService {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl InMemoryMailService {
     pub fn new() -> Self {
         Self {}
@@ -34,15 +41,11 @@ impl InMemoryMailService {
 
 #[async_trait]
 impl MailService for InMemoryMailService {
-    async fn send(&self, email: &str, subject: &str, html_body: &str, plain_body: &str) -> Result<(), EmailError> {
-        println!("email: {}, subject: {}, html_body: {}, plain_body: {}", email, subject, html_body, plain_body);
-
+    async fn send(&self, _email: &str, _subject: &str, _html_body: &str, _plain_body: &str) -> Result<(), EmailError> {
         Ok(())
     }
 
-    async fn send_otp(&self, email: &str, otp: OtpView) -> Result<(), EmailError> {
-        println!("email: {}, otp: {:?}", email, otp);
-
+    async fn send_otp(&self, _email: &str, _otp: OtpView) -> Result<(), EmailError> {
         Ok(())
     }
 }
\ No newline at end of file
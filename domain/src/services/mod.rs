@@ -1,2 +1,10 @@
+pub mod avatar_service;
+pub mod default_avatar;
+pub mod email_service;
+pub mod event_publisher;
+pub mod image_pipeline;
 pub mod mail_service;
+pub mod otp_hasher;
+pub mod render_cache;
+pub mod session_rotator;
 pub mod user_service;
\ No newline at end of file
@@ -0,0 +1,156 @@
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hmac::{Hmac, Mac};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Length of the nonce in front of a sealed code
+const NONCE_LENGTH: usize = 12;
+
+/// Derives the stored form of one-time codes. Only the keyed hash of a code is ever persisted,
+/// so read access to the OTP storage is not enough to log in as another user. Codes that have
+/// to wait for their mail in the outbox are sealed with a key derived from the same secret.
+#[derive(Clone)]
+pub struct OtpHasher {
+    secret: Vec<u8>,
+}
+
+impl OtpHasher {
+    pub fn new(secret: &[u8]) -> Self {
+        Self { secret: secret.to_vec() }
+    }
+
+    /// Creates a hasher with a random secret. Codes hashed by it can't be verified after a
+    /// restart, which only matters for codes issued right before it.
+    pub fn random() -> Self {
+        let mut secret = [0u8; 32];
+        OsRng.fill_bytes(&mut secret);
+
+        Self::new(&secret)
+    }
+
+    fn mac(&self, user_id: i64, code: &str) -> HmacSha256 {
+        let mut mac = <HmacSha256 as Mac>::new_from_slice(&self.secret).expect("HMAC accepts keys of any size");
+
+        mac.update(&user_id.to_be_bytes());
+        mac.update(code.as_bytes());
+
+        mac
+    }
+
+    /// Returns the hex encoded HMAC of `code` bound to `user_id`
+    pub fn hash(&self, user_id: i64, code: &str) -> String {
+        hex::encode(self.mac(user_id, code).finalize().into_bytes())
+    }
+
+    /// Key for `seal`, kept apart from the HMAC key by deriving it under its own label
+    fn cipher(&self) -> ChaCha20Poly1305 {
+        let mut mac = <HmacSha256 as Mac>::new_from_slice(&self.secret).expect("HMAC accepts keys of any size");
+        mac.update(b"otp-seal");
+
+        ChaCha20Poly1305::new(Key::from_slice(&mac.finalize().into_bytes()))
+    }
+
+    /// Encrypts `code` for `user_id`, returning the hex encoded nonce and ciphertext. Only a
+    /// hasher with the same secret can `open` it, and only for the same user.
+    pub fn seal(&self, user_id: i64, code: &str) -> String {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let payload = Payload { msg: code.as_bytes(), aad: &user_id.to_be_bytes() };
+        let ciphertext = self.cipher().encrypt(&nonce, payload).expect("Encrypting a code doesn't fail");
+
+        hex::encode([nonce.as_slice(), &ciphertext].concat())
+    }
+
+    /// Decrypts a code sealed by `seal`
+    pub fn open(&self, user_id: i64, sealed: &str) -> Result<String, String> {
+        let bytes = hex::decode(sealed).map_err(|_| "Sealed code is not hex".to_string())?;
+
+        if bytes.len() < NONCE_LENGTH {
+            return Err("Sealed code is too short".to_string());
+        }
+
+        let (nonce, ciphertext) = bytes.split_at(NONCE_LENGTH);
+        let payload = Payload { msg: ciphertext, aad: &user_id.to_be_bytes() };
+        let code = self.cipher().decrypt(Nonce::from_slice(nonce), payload)
+            .map_err(|_| "Sealed code could not be opened".to_string())?;
+
+        String::from_utf8(code).map_err(|_| "Sealed code is not UTF-8".to_string())
+    }
+
+    /// Checks `code` against a hash produced by `hash` in constant time
+    pub fn verify(&self, user_id: i64, code: &str, code_hash: &str) -> bool {
+        match hex::decode(code_hash) {
+            Ok(expected) => self.mac(user_id, code).verify_slice(&expected).is_ok(),
+            Err(_) => false,
+        }
+    }
+}
+
+impl std::fmt::Debug for OtpHasher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OtpHasher").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_hash_is_not_plaintext() {
+        // Given
+        let hasher = OtpHasher::new(b"secret");
+
+        // When
+        let hash = hasher.hash(1, "12345678");
+
+        // Then
+        assert_ne!(hash, "12345678");
+        assert_eq!(hash.len(), 64);
+    }
+
+    #[tokio::test]
+    async fn test_verify() {
+        // Given
+        let hasher = OtpHasher::new(b"secret");
+        let hash = hasher.hash(1, "12345678");
+
+        // Then
+        assert!(hasher.verify(1, "12345678", &hash));
+        assert!(!hasher.verify(1, "87654321", &hash));
+        assert!(!hasher.verify(2, "12345678", &hash));
+        assert!(!hasher.verify(1, "12345678", "not hex"));
+    }
+
+    #[tokio::test]
+    async fn test_hash_depends_on_secret() {
+        // Given
+        let first = OtpHasher::new(b"first");
+        let second = OtpHasher::new(b"second");
+
+        // Then
+        assert_ne!(first.hash(1, "12345678"), second.hash(1, "12345678"));
+        assert!(!second.verify(1, "12345678", &first.hash(1, "12345678")));
+    }
+
+    #[tokio::test]
+    async fn test_seal_and_open() {
+        // Given
+        let hasher = OtpHasher::new(b"secret");
+
+        // When
+        let sealed = hasher.seal(1, "12345678");
+
+        // Then
+        assert!(!sealed.contains("12345678"));
+        assert_ne!(sealed, hasher.seal(1, "12345678"));
+        assert_eq!(hasher.open(1, &sealed), Ok("12345678".to_string()));
+        assert!(hasher.open(2, &sealed).is_err());
+        assert!(OtpHasher::new(b"other").open(1, &sealed).is_err());
+        assert!(hasher.open(1, "abcd").is_err());
+        assert!(hasher.open(1, "not hex").is_err());
+    }
+}
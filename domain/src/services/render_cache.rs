@@ -0,0 +1,158 @@
+use crate::services::image_pipeline::OutputFormat;
+use crate::views::avatar_view::AvatarImage;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+/// Bytes of rendered variants kept when no budget is given
+pub const DEFAULT_RENDER_CACHE_BYTES: usize = 32 * 1024 * 1024;
+
+/// Identifies one rendering of a stored image. Storage keys are never reused, so a variant
+/// never goes stale.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RenderKey {
+    pub storage_key: String,
+    pub size: u32,
+    pub format: OutputFormat,
+}
+
+impl RenderKey {
+    pub fn new(storage_key: &str, size: u32, format: OutputFormat) -> Self {
+        Self { storage_key: storage_key.to_owned(), size, format }
+    }
+}
+
+struct CacheStore {
+    entries: HashMap<RenderKey, AvatarImage>,
+    /// Keys in insertion order, the oldest is evicted first
+    order: VecDeque<RenderKey>,
+    /// Total size of the cached image data
+    bytes: usize,
+}
+
+/// In-process cache of resized avatars, bounded by the total size of the cached images since
+/// their sizes differ by orders of magnitude. Clones share the same entries.
+#[derive(Clone)]
+pub struct RenderCache {
+    store: Arc<RwLock<CacheStore>>,
+    max_bytes: usize,
+}
+
+impl fmt::Debug for RenderCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RenderCache").field("max_bytes", &self.max_bytes).finish_non_exhaustive()
+    }
+}
+
+impl Default for RenderCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_RENDER_CACHE_BYTES)
+    }
+}
+
+impl RenderCache {
+    /// Cache keeping at most `max_bytes` of image data
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            store: Arc::new(RwLock::new(CacheStore { entries: HashMap::new(), order: VecDeque::new(), bytes: 0 })),
+            max_bytes,
+        }
+    }
+
+    pub async fn get(&self, key: &RenderKey) -> Option<AvatarImage> {
+        self.store.read().await.entries.get(key).cloned()
+    }
+
+    /// Caches `image`, evicting the oldest entries until the cache is within its budget again.
+    /// Images larger than the whole budget are not cached.
+    pub async fn put(&self, key: RenderKey, image: AvatarImage) {
+        let size = image.data.len();
+
+        if size > self.max_bytes {
+            return;
+        }
+
+        let mut store = self.store.write().await;
+
+        if store.entries.contains_key(&key) {
+            return;
+        }
+
+        while store.bytes + size > self.max_bytes {
+            let Some(oldest) = store.order.pop_front() else {
+                break;
+            };
+
+            if let Some(evicted) = store.entries.remove(&oldest) {
+                store.bytes -= evicted.data.len();
+            }
+        }
+
+        store.bytes += size;
+        store.order.push_back(key.clone());
+        store.entries.insert(key, image);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_image(byte: u8) -> AvatarImage {
+        AvatarImage::new("image/png", vec![byte])
+    }
+
+    fn create_sized_image(len: usize) -> AvatarImage {
+        AvatarImage::new("image/png", vec![0; len])
+    }
+
+    #[tokio::test]
+    async fn test_put_and_get() {
+        // Given
+        let cache = RenderCache::new(2);
+        let key = RenderKey::new("a", 80, OutputFormat::Png);
+
+        // When
+        cache.put(key.clone(), create_image(1)).await;
+
+        // Then
+        assert_eq!(cache.get(&key).await, Some(create_image(1)));
+        assert_eq!(cache.get(&RenderKey::new("a", 80, OutputFormat::WebP)).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_evicts_oldest() {
+        // Given
+        let cache = RenderCache::new(2);
+
+        // When
+        for (i, size) in [10, 20, 30].into_iter().enumerate() {
+            cache.put(RenderKey::new("a", size, OutputFormat::Png), create_image(i as u8)).await;
+        }
+
+        // Then
+        assert_eq!(cache.get(&RenderKey::new("a", 10, OutputFormat::Png)).await, None);
+        assert!(cache.get(&RenderKey::new("a", 20, OutputFormat::Png)).await.is_some());
+        assert!(cache.get(&RenderKey::new("a", 30, OutputFormat::Png)).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_stays_within_byte_budget() {
+        // Given
+        let cache = RenderCache::new(100);
+
+        // When
+        cache.put(RenderKey::new("a", 10, OutputFormat::Png), create_sized_image(40)).await;
+        cache.put(RenderKey::new("a", 20, OutputFormat::Png), create_sized_image(40)).await;
+        cache.put(RenderKey::new("a", 30, OutputFormat::Png), create_sized_image(60)).await;
+        cache.put(RenderKey::new("a", 40, OutputFormat::Png), create_sized_image(101)).await;
+
+        // Then
+        assert_eq!(cache.get(&RenderKey::new("a", 10, OutputFormat::Png)).await, None);
+        assert_eq!(cache.get(&RenderKey::new("a", 20, OutputFormat::Png)).await, Some(create_sized_image(40)));
+        assert_eq!(cache.get(&RenderKey::new("a", 30, OutputFormat::Png)).await, Some(create_sized_image(60)));
+        assert_eq!(cache.get(&RenderKey::new("a", 40, OutputFormat::Png)).await, None);
+        assert_eq!(cache.store.read().await.bytes, 100);
+    }
+}
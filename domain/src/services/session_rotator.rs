@@ -0,0 +1,40 @@
+use crate::repositories::id_provider::IdProvider;
+use crate::repositories::otp_repository::OtpRepository;
+use crate::repositories::session_repository::SessionRepository;
+use crate::repositories::user_repository::UserRepository;
+use crate::services::user_service::UserService;
+use crate::views::session_view::SessionView;
+use async_trait::async_trait;
+
+/// Gives a session a new value after a privilege-sensitive change, so that a value which leaked
+/// before stops working. Lets services that change what an account can do rotate the session
+/// without depending on all repositories of `UserService`.
+#[async_trait]
+pub trait SessionRotator: Send + Sync {
+    /// Returns the session under its new value, the old one is invalid afterwards
+    async fn rotate(&self, value: &str) -> Result<SessionView, String>;
+}
+
+/// Leaves sessions alone, used until a real rotator is set
+#[derive(Debug, Clone, Default)]
+pub struct NoopSessionRotator;
+
+#[async_trait]
+impl SessionRotator for NoopSessionRotator {
+    async fn rotate(&self, _value: &str) -> Result<SessionView, String> {
+        Err("Sessions are not rotated".to_string())
+    }
+}
+
+#[async_trait]
+impl<UR, SR, OR, IP> SessionRotator for UserService<UR, SR, OR, IP>
+where
+    UR: UserRepository + Sync + Send,
+    SR: SessionRepository + Sync + Send,
+    OR: OtpRepository + Sync + Send,
+    IP: IdProvider + Sync + Send,
+{
+    async fn rotate(&self, value: &str) -> Result<SessionView, String> {
+        self.rotate_session(value).await
+    }
+}
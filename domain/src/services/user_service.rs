@@ -1,14 +1,85 @@
+use crate::models::event::{DomainEvent, EventEnvelope};
 use crate::models::otp::Otp;
+use crate::models::outbox::{MailContent, OutboxMessage, OutboxPayload};
 use crate::models::session::Session;
 use crate::models::user::User;
 use crate::repositories::id_provider::IdProvider;
 use crate::repositories::otp_repository::OtpRepository;
 use crate::repositories::session_repository::SessionRepository;
+use crate::repositories::unit_of_work::Transaction;
 use crate::repositories::user_repository::UserRepository;
-use crate::repositories::OTP_LENGTH;
-use crate::views::otp_view::OtpView;
-use crate::views::session_view::SessionView;
+use crate::repositories::{DbError, OTP_LENGTH};
+use crate::services::event_publisher::{BufferedEventPublisher, EventPublisher, NoopEventPublisher};
+use crate::services::otp_hasher::OtpHasher;
+use crate::views::otp_view::{OtpSentView, OtpView};
+use crate::views::session_view::{SessionInfoView, SessionView};
 use crate::views::user_view::UserView;
+use chrono::{DateTime, Duration, Utc};
+use std::sync::Arc;
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum OtpValidationError {
+    #[error("User not found")]
+    UserNotFound,
+    #[error("No pending OTP, request a new one")]
+    NoPendingCode,
+    #[error("Invalid OTP")]
+    InvalidCode,
+    #[error("OTP expired, request a new one")]
+    Expired,
+    #[error("Too many attempts, try again after {0}")]
+    TooManyAttempts(DateTime<Utc>),
+    #[error("Internal error: {0}")]
+    InternalError(String),
+}
+
+/// Limits how many wrong OTPs may be submitted before the account is locked
+#[derive(Debug, Clone)]
+pub struct LoginPolicy {
+    /// Number of failed OTP submissions that triggers a lockout
+    pub max_attempts: i8,
+    /// How long the account stays locked once `max_attempts` is reached
+    pub lockout_duration: Duration,
+    /// Minimum time between two codes mailed to the same user
+    pub resend_interval: Duration,
+    /// How long a mailed code can be used to sign in
+    pub otp_lifetime: Duration,
+}
+
+impl Default for LoginPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            lockout_duration: Duration::minutes(15),
+            resend_interval: Duration::minutes(1),
+            otp_lifetime: Duration::minutes(5),
+        }
+    }
+}
+
+/// How long sessions last. A session ends `idle_timeout` after the last request made with it
+/// and `absolute_lifetime` after sign-in at the latest, however active it is.
+#[derive(Debug, Clone)]
+pub struct SessionPolicy {
+    pub absolute_lifetime: Duration,
+    pub idle_timeout: Duration,
+    /// Minimum time between two extensions of the same session, so that not every request
+    /// writes to the session store
+    pub extend_interval: Duration,
+}
+
+impl Default for SessionPolicy {
+    fn default() -> Self {
+        Self {
+            absolute_lifetime: Duration::seconds(300000),
+            idle_timeout: Duration::hours(24),
+            extend_interval: Duration::minutes(5),
+        }
+    }
+}
+
+/// Length of session values
+const SESSION_VALUE_LENGTH: usize = 32;
 
 #[derive(Debug, Clone)]
 pub struct UserService<UR, SR, OR, IP>
@@ -22,8 +93,17 @@ where
     session_repository: SR,
     otp_repository: OR,
     id_provider: IP,
+    otp_hasher: OtpHasher,
+    login_policy: LoginPolicy,
+    session_policy: SessionPolicy,
+    event_publisher: Arc<dyn EventPublisher>,
+    /// Mailed to the login on the first successful sign-in
+    welcome_mail: Option<MailContent>,
 }
 
+/// `UserService` working on the repositories of transaction `T`, see `UserService::in_transaction`
+pub type TransactionUserService<T, IP> =
+    UserService<<T as Transaction>::Users, <T as Transaction>::Sessions, <T as Transaction>::Otps, IP>;
 
 impl<UR, SR, OR, IP> UserService<UR, SR, OR, IP>
 where
@@ -33,53 +113,231 @@ where
     IP: IdProvider + Sync + Send,
 {
     pub fn new(user_repository: UR, session_repository: SR, otp_repository: OR, id_provider: IP) -> Self {
-        UserService { user_repository, session_repository, id_provider, otp_repository }
+        UserService {
+            user_repository,
+            session_repository,
+            id_provider,
+            otp_repository,
+            otp_hasher: OtpHasher::random(),
+            login_policy: LoginPolicy::default(),
+            session_policy: SessionPolicy::default(),
+            event_publisher: Arc::new(NoopEventPublisher),
+            welcome_mail: None,
+        }
+    }
+
+    /// Replaces the per-process random OTP key, so codes stay valid across restarts and
+    /// between several server instances
+    pub fn with_otp_hasher(mut self, otp_hasher: OtpHasher) -> Self {
+        self.otp_hasher = otp_hasher;
+        self
+    }
+
+    pub fn with_login_policy(mut self, login_policy: LoginPolicy) -> Self {
+        self.login_policy = login_policy;
+        self
+    }
+
+    pub fn with_session_policy(mut self, session_policy: SessionPolicy) -> Self {
+        self.session_policy = session_policy;
+        self
+    }
+
+    /// Mail greeting users on their first successful sign-in. It goes out through the outbox,
+    /// written together with the sign-in, and only once the user proved they own the login.
+    pub fn with_welcome_mail(mut self, welcome_mail: MailContent) -> Self {
+        self.welcome_mail = Some(welcome_mail);
+        self
+    }
+
+    /// Where to report the `DomainEvent`s raised by the operations below
+    pub fn with_event_publisher(mut self, event_publisher: Arc<dyn EventPublisher>) -> Self {
+        self.event_publisher = event_publisher;
+        self
+    }
+
+    /// Same service working on the repositories of `transaction`. The events it raises are
+    /// held back in the returned publisher, to be flushed once the transaction is committed.
+    pub fn in_transaction<T: Transaction>(&self, transaction: &T) -> (TransactionUserService<T, IP>, BufferedEventPublisher)
+    where
+        IP: Clone,
+    {
+        let events = BufferedEventPublisher::new(self.event_publisher.clone());
+
+        let user_service = UserService {
+            user_repository: transaction.users(),
+            session_repository: transaction.sessions(),
+            otp_repository: transaction.otps(),
+            id_provider: self.id_provider.clone(),
+            otp_hasher: self.otp_hasher.clone(),
+            login_policy: self.login_policy.clone(),
+            session_policy: self.session_policy.clone(),
+            event_publisher: Arc::new(events.clone()),
+            welcome_mail: self.welcome_mail.clone(),
+        };
+
+        (user_service, events)
+    }
+
+    async fn publish(&self, event: DomainEvent) {
+        self.event_publisher.publish(EventEnvelope::new(event)).await;
+    }
+
+    /// Returns the end of the lockout if the user is currently locked out
+    pub async fn locked_until(&self, login: &str) -> Result<Option<DateTime<Utc>>, String> {
+        let user = self.user_repository.find_by_login(login).await.map_err(|e| e.to_string())?;
+
+        Ok(user.filter(|user| user.is_locked()).and_then(|user| user.locked_until))
     }
 
     pub async fn find_by_login(&self, login: &str) -> Result<UserView, String> {
-        let user = self.user_repository.find_by_login(login).await;
+        self.user_repository.find_by_login(login).await
+            .map_err(|e| e.to_string())?
+            .map(UserView::new)
+            .ok_or_else(|| "User not found".to_string())
+    }
 
-        match user {
+    pub async fn find_by_id(&self, id: i64) -> Result<UserView, String> {
+        self.user_repository.find_by_id(id).await
+            .map_err(|e| e.to_string())?
+            .map(UserView::new)
+            .ok_or_else(|| "User not found".to_string())
+    }
+
+    /// Returns the user with `login`, creating them on their first sign-in
+    pub async fn find_or_create(&self, login: &str) -> Result<UserView, String> {
+        match self.user_repository.find_by_login(login).await.map_err(|e| e.to_string())? {
             Some(user) => Ok(UserView::new(user)),
-            None => Err("User not found".to_string()),
+            None => self.create(login.to_owned()).await,
         }
     }
 
-    pub async fn create(&mut self, login: String) -> Result<UserView, String> {
-        match self.user_repository.save(User::new(login)).await {
-            Ok(user) => Ok(UserView::new(user)),
-            Err(_) => Err("Error saving user".to_string()),
+    pub async fn create(&self, login: String) -> Result<UserView, String> {
+        let user = self.user_repository.save(User::new(login)).await
+            .map_err(|_| "Error saving user".to_string())?;
+
+        self.publish(DomainEvent::UserCreated { user_id: user.id, login: user.username.clone() }).await;
+
+        Ok(UserView::new(user))
+    }
+
+    /// Checks `otp` against the codes issued to `login`, consuming it on success so that
+    /// every code can be used exactly once.
+    ///
+    /// Every failed submission counts towards the `LoginPolicy`; once the limit is reached the
+    /// pending codes are invalidated and the account is locked for the configured duration.
+    pub async fn validate_otp(&self, login: &str, otp: &str) -> Result<UserView, OtpValidationError> {
+        let user = self.user_repository.find_by_login(login).await
+            .map_err(|e| OtpValidationError::InternalError(e.to_string()))?
+            .ok_or(OtpValidationError::UserNotFound)?;
+
+        let user_id = user.id;
+
+        if let Some(until) = user.locked_until.filter(|_| user.is_locked()) {
+            let err = OtpValidationError::TooManyAttempts(until);
+            self.publish(DomainEvent::LoginFailed { user_id, reason: err.to_string() }).await;
+
+            return Err(err);
+        }
+
+        // Only the fields of the sign-in are written, so that failures counted concurrently
+        // are not overwritten with the counts loaded above
+        let result = match self.check_otp(user_id, otp).await {
+            Ok(()) => self.user_repository.record_login(user_id, Utc::now(), self.welcome_messages(&user)).await
+                .map_err(|e| OtpValidationError::InternalError(e.to_string()))
+                .and_then(|user| user.map(UserView::new).ok_or(OtpValidationError::UserNotFound)),
+            Err(OtpValidationError::InternalError(err)) => Err(OtpValidationError::InternalError(err)),
+            Err(err) => Err(self.register_failed_attempt(user_id, err).await),
+        };
+
+        match &result {
+            Ok(_) => self.publish(DomainEvent::LoginSucceeded { user_id }).await,
+            Err(OtpValidationError::InternalError(_)) => {}
+            Err(err) => self.publish(DomainEvent::LoginFailed { user_id, reason: err.to_string() }).await,
         }
+
+        result
     }
 
-    pub async fn validate_otp(&self, login: &str, otp: &str) -> Result<UserView, String> {
-        let user = self.user_repository.find_by_login(login).await;
+    /// Outbox messages greeting `user`, written only if this is their first sign-in
+    fn welcome_messages(&self, user: &User) -> Vec<OutboxMessage> {
+        self.welcome_mail.iter()
+            .map(|mail| OutboxMessage::new(format!("user:{}:welcome", user.id), mail.clone().to(user.username.clone())))
+            .collect()
+    }
 
-        // TODO count login attempts
+    async fn check_otp(&self, user_id: i64, code: &str) -> Result<(), OtpValidationError> {
+        let pending = self.otp_repository.find_by_user(user_id).await
+            .map_err(|e| OtpValidationError::InternalError(e.to_string()))?;
 
-        match user {
-            Some(user) => {
-                let otp = self.otp_repository.find_by_id(otp).await;
+        if pending.is_empty() {
+            return Err(OtpValidationError::NoPendingCode);
+        }
+
+        let matching = pending.iter().find(|otp| otp.matches(code, &self.otp_hasher));
+
+        let Some(matching) = matching else {
+            return if pending.iter().any(|otp| !otp.is_expired()) {
+                Err(OtpValidationError::InvalidCode)
+            } else {
+                Err(OtpValidationError::Expired)
+            };
+        };
 
-                match otp {
-                    Some(_) => Ok(UserView::new(user)),
-                    None => Err("Invalid OTP".to_string()),
+        let consumed = self.otp_repository.consume(user_id, &matching.code_hash).await
+            .map_err(|e| OtpValidationError::InternalError(e.to_string()))?;
+
+        match consumed {
+            Some(otp) if otp.is_expired() => Err(OtpValidationError::Expired),
+            Some(_) => Ok(()),
+            // Another request consumed the code first
+            None => Err(OtpValidationError::NoPendingCode),
+        }
+    }
+
+    /// Counts a failed OTP submission and locks the user once the policy limit is reached.
+    /// The count is kept by the repository in one atomic update and the lockout is decided from
+    /// the count it returns. Returns the error that should be reported to the caller.
+    async fn register_failed_attempt(&self, user_id: i64, err: OtpValidationError) -> OtpValidationError {
+        let now = Utc::now();
+        let lock_until = now + self.login_policy.lockout_duration;
+
+        let user = match self.user_repository.record_failed_login(user_id, now, self.login_policy.max_attempts, lock_until).await {
+            Ok(Some(user)) => user,
+            Ok(None) => return OtpValidationError::UserNotFound,
+            Err(e) => return OtpValidationError::InternalError(e.to_string()),
+        };
+
+        let Some(until) = user.locked_until.filter(|_| user.is_locked()) else {
+            return err;
+        };
+
+        // Codes mailed before the lockout must not sign in once it has passed
+        if let Ok(pending) = self.otp_repository.find_by_user(user_id).await {
+            for otp in pending {
+                if let Err(e) = self.otp_repository.delete(user_id, &otp.code_hash).await {
+                    return OtpValidationError::InternalError(e.to_string());
                 }
             }
-            None => Err("User not found".to_string()),
         }
+
+        OtpValidationError::TooManyAttempts(until)
     }
 
-    pub async fn generate_session(&mut self, login: &str) -> Result<SessionView, String> {
-        let user = self.user_repository.find_by_login(login).await;
+    /// Signs the user in, remembering the `user_agent` and `ip_address` of the client
+    pub async fn generate_session(&self, login: &str, user_agent: Option<String>, ip_address: Option<String>) -> Result<SessionView, String> {
+        let user = self.user_repository.find_by_login(login).await.map_err(|e| e.to_string())?;
 
         match user {
             Some(user) => {
-                let session_id = self.id_provider.get_id(32);
+                let session_id = self.id_provider.get_id(SESSION_VALUE_LENGTH);
+                let lifetime = self.session_policy.absolute_lifetime.num_seconds().max(0) as usize;
 
-                let session = Session::new(session_id.clone(), user.id.to_string(), 300000);
+                let session = Session::new(session_id.clone(), user.id.to_string(), lifetime)
+                    .with_idle_timeout(self.session_policy.idle_timeout)
+                    .with_client(user_agent, ip_address);
 
-                self.session_repository.save(&session).await;
+                self.session_repository.save(&session).await.map_err(|e| e.to_string())?;
 
                 Ok(SessionView::new(session, UserView::new(user)))
             }
@@ -87,14 +345,721 @@ where
         }
     }
 
-    pub async fn save_otp(&mut self, user_id: i64) -> Result<OtpView, String> {
-        let otp_id = self.id_provider.get_numeric_id(OTP_LENGTH);
+    /// Returns the session `value` with its user, or `None` when the session is unknown or
+    /// expired. Using the session extends it by the idle timeout, at most once per
+    /// `SessionPolicy::extend_interval`.
+    pub async fn authenticate(&self, value: &str) -> Option<SessionView> {
+        let mut session = self.session_repository.load(value).await?;
+        let now = Utc::now();
+
+        if session.expired_at < now {
+            return None;
+        }
+
+        let user_id = session.user_id.parse::<i64>().ok()?;
+        // Failing to load the user fails the authentication, the session is not trusted blindly
+        let user = self.user_repository.find_by_id(user_id).await.ok()??;
+
+        if session.is_extension_due(now, self.session_policy.extend_interval) {
+            let mut extended = session.clone();
+            extended.extend(now, self.session_policy.idle_timeout);
+
+            // The session is still valid until its current expiry, a failed extension only
+            // means it ends sooner. A missing row means it was removed meanwhile.
+            match self.session_repository.extend(value, extended.last_seen_at, extended.expired_at).await {
+                Ok(true) => session = extended,
+                Ok(false) => return None,
+                Err(_) => {}
+            }
+        }
+
+        Some(SessionView::new(session, UserView::new(user)))
+    }
+
+    /// Gives the session a new value, so that a value which leaked before a privilege-sensitive
+    /// action stops working. The old value is invalid afterwards.
+    pub async fn rotate_session(&self, value: &str) -> Result<SessionView, String> {
+        let mut session = self.session_repository.load(value).await
+            .ok_or_else(|| "Session not found".to_string())?;
 
-        let otp = Otp::new(otp_id.clone(), user_id, 300).map_err(|e| e.to_string())?;
+        let user_id = session.user_id.parse::<i64>().map_err(|e| e.to_string())?;
+        let user = self.user_repository.find_by_id(user_id).await
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| "User not found".to_string())?;
 
-        self.otp_repository.save(otp.clone()).await.map_err(|_| "Error saving OTP".to_string())?;
+        let new_value = self.id_provider.get_id(SESSION_VALUE_LENGTH);
+
+        if !self.session_repository.rotate(value, &new_value).await.map_err(|e| e.to_string())? {
+            return Err("Session not found".to_string());
+        }
+
+        session.value = new_value;
+
+        Ok(SessionView::new(session, UserView::new(user)))
+    }
+
+    /// Ends the session `value`. Sessions that are already gone count as ended.
+    pub async fn logout(&self, value: &str) -> Result<(), String> {
+        let Some(session) = self.session_repository.load(value).await else {
+            return Ok(());
+        };
+
+        match self.session_repository.destroy(value).await {
+            Ok(true) => {
+                if let Ok(user_id) = session.user_id.parse::<i64>() {
+                    self.publish(DomainEvent::SessionRevoked { user_id, count: 1 }).await;
+                }
+
+                Ok(())
+            }
+            Ok(false) | Err(DbError::NotFound(_)) => Ok(()),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+
+    /// Ends every session of the user, returns how many were ended
+    pub async fn revoke_sessions(&self, user_id: i64) -> Result<u64, String> {
+        let count = self.session_repository.destroy_by_user(user_id).await.map_err(|e| e.to_string())?;
+
+        if count > 0 {
+            self.publish(DomainEvent::SessionRevoked { user_id, count }).await;
+        }
 
-        Ok(OtpView::new(otp))
+        Ok(count)
+    }
+
+    /// Lists the unexpired sessions of the user, marking the one with the value `current`
+    pub async fn list_sessions(&self, user_id: i64, current: Option<&str>) -> Result<Vec<SessionInfoView>, String> {
+        let sessions = self.session_repository.find_by_user(user_id).await.map_err(|e| e.to_string())?;
+
+        Ok(sessions.into_iter()
+            .map(|session| {
+                let is_current = current == Some(session.value.as_str());
+                SessionInfoView::new(session, is_current)
+            })
+            .collect())
+    }
+
+    fn otp_lifetime_seconds(&self) -> usize {
+        self.login_policy.otp_lifetime.num_seconds().max(0) as usize
+    }
+
+    pub async fn save_otp(&self, user_id: i64) -> Result<OtpView, String> {
+        let code = self.id_provider.get_numeric_id(OTP_LENGTH);
+
+        let otp = Otp::new(&code, user_id, self.otp_lifetime_seconds(), &self.otp_hasher)?;
+
+        let otp = self.otp_repository.save(otp).await.map_err(|_| "Error saving OTP".to_string())?;
+
+        self.publish(DomainEvent::OtpIssued { user_id }).await;
+
+        Ok(OtpView::new(code, otp))
+    }
+
+    /// Issues a new OTP and stores it together with the outbox messages that mail it to `email`
+    /// and announce it, so that a code is never valid without its mail being on the way. The
+    /// mail is dropped if not delivered before the code expires.
+    ///
+    /// Within the `resend_interval` of the last code no new one is issued, the pending code is
+    /// returned instead.
+    pub async fn issue_otp(&self, user_id: i64, email: &str) -> Result<OtpSentView, String> {
+        let resend_interval = self.login_policy.resend_interval;
+
+        let pending = self.otp_repository.find_by_user(user_id).await
+            .map_err(|_| "Error loading OTPs".to_string())?
+            .into_iter()
+            .filter(|otp| !otp.is_expired())
+            .max_by_key(|otp| otp.created_at);
+
+        if let Some(otp) = pending.filter(|otp| otp.created_at + resend_interval > Utc::now()) {
+            return Ok(OtpSentView { expires_at: otp.expires_at, resend_after: otp.created_at + resend_interval });
+        }
+
+        let code = self.id_provider.get_numeric_id(OTP_LENGTH);
+
+        let otp = Otp::new(&code, user_id, self.otp_lifetime_seconds(), &self.otp_hasher)?;
+
+        let key = format!("otp:{}:{}", user_id, otp.code_hash);
+        let mail = OutboxMessage::new(format!("{}:mail", key), OutboxPayload::OtpMail {
+            to: email.to_owned(),
+            user_id,
+            sealed_code: self.otp_hasher.seal(user_id, &code),
+            expires_at: otp.expires_at,
+        }).with_expiry(otp.expires_at);
+        let event = OutboxMessage::new(
+            format!("{}:event", key),
+            OutboxPayload::Event(EventEnvelope::new(DomainEvent::OtpIssued { user_id })),
+        );
+
+        let otp = self.otp_repository.save_with_outbox(otp, vec![mail, event]).await.map_err(|_| "Error saving OTP".to_string())?;
+
+        Ok(OtpSentView { expires_at: otp.expires_at, resend_after: otp.created_at + resend_interval })
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repositories::otp_repository::InMemoryOtpRepository;
+    use crate::repositories::outbox_repository::InMemoryOutboxRepository;
+    use crate::repositories::session_repository::InMemorySessionRepository;
+    use crate::repositories::unit_of_work::{InMemoryUnitOfWork, UnitOfWork};
+    use crate::repositories::user_repository::InMemoryUserRepository;
+    use crate::services::event_publisher::InMemoryEventPublisher;
+    use chrono::{DateTime, Duration, Utc};
+
+    #[derive(Clone)]
+    struct SequenceIdProvider {}
+
+    impl IdProvider for SequenceIdProvider {
+        fn get_id(&self, length: usize) -> String {
+            self.get_numeric_id(length)
+        }
+
+        fn get_numeric_id(&self, length: usize) -> String {
+            self.get_from_alphabet(vec![], length)
+        }
+
+        fn get_from_alphabet(&self, _: Vec<&str>, length: usize) -> String {
+            (0..length).map(|i| (i % 10).to_string()).collect()
+        }
+    }
+
+    type TestUserService = UserService<InMemoryUserRepository, InMemorySessionRepository, InMemoryOtpRepository, SequenceIdProvider>;
+
+    fn create_service(otp_repository: InMemoryOtpRepository) -> TestUserService {
+        UserService::new(
+            InMemoryUserRepository::new(),
+            InMemorySessionRepository::new(),
+            otp_repository,
+            SequenceIdProvider {},
+        )
+    }
+
+    #[tokio::test]
+    async fn test_publishes_events() {
+        // Given
+        let events = InMemoryEventPublisher::new();
+        let service = create_service(InMemoryOtpRepository::new()).with_event_publisher(Arc::new(events.clone()));
+
+        // When
+        let user = service.create("alice".to_string()).await.unwrap();
+        let otp = service.save_otp(user.id).await.unwrap();
+        service.validate_otp("alice", "wrong").await.unwrap_err();
+        service.validate_otp("alice", &otp.code).await.unwrap();
+        let session = service.generate_session("alice", None, None).await.unwrap();
+        service.logout(&session.value).await.unwrap();
+        service.revoke_sessions(user.id).await.unwrap();
+
+        // Then
+        assert_eq!(events.events().await, vec![
+            DomainEvent::UserCreated { user_id: user.id, login: "alice".to_string() },
+            DomainEvent::OtpIssued { user_id: user.id },
+            DomainEvent::LoginFailed { user_id: user.id, reason: OtpValidationError::InvalidCode.to_string() },
+            DomainEvent::LoginSucceeded { user_id: user.id },
+            DomainEvent::SessionRevoked { user_id: user.id, count: 1 },
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_issue_otp_queues_mail_and_event() {
+        // Given
+        let outbox = InMemoryOutboxRepository::new();
+        let events = InMemoryEventPublisher::new();
+        let service = create_service(InMemoryOtpRepository::new().with_outbox(outbox.clone()))
+            .with_event_publisher(Arc::new(events.clone()));
+        let user = service.create("alice".to_string()).await.unwrap();
+
+        // When
+        service.issue_otp(user.id, "alice@example.com").await.unwrap();
+
+        // Then
+        let messages = outbox.messages().await;
+        assert_eq!(messages.len(), 2);
+        let OutboxPayload::OtpMail { to, sealed_code, expires_at, .. } = &messages[0].payload else {
+            panic!("Expected an OTP mail, got {:?}", messages[0].payload);
+        };
+        assert_eq!(to, "alice@example.com");
+        assert_eq!(messages[0].expires_at, Some(*expires_at));
+        assert!(matches!(&messages[1].payload, OutboxPayload::Event(envelope) if envelope.event == DomainEvent::OtpIssued { user_id: user.id }));
+        assert!(!events.events().await.contains(&DomainEvent::OtpIssued { user_id: user.id }));
+        let code = service.otp_hasher.open(user.id, sealed_code).unwrap();
+        assert_ne!(sealed_code, &code);
+        assert_eq!(service.validate_otp("alice", &code).await.unwrap().id, user.id);
+    }
+
+    #[tokio::test]
+    async fn test_validate_otp_queues_welcome_mail_on_first_login() {
+        // Given
+        let outbox = InMemoryOutboxRepository::new();
+        let service = UserService::new(
+            InMemoryUserRepository::new().with_outbox(outbox.clone()),
+            InMemorySessionRepository::new(),
+            InMemoryOtpRepository::new(),
+            SequenceIdProvider {},
+        ).with_welcome_mail(MailContent {
+            subject: "Welcome".to_string(),
+            html_body: "<p>Welcome</p>".to_string(),
+            plain_body: "Welcome".to_string(),
+        });
+        let user = service.create("alice@example.com".to_string()).await.unwrap();
+        let before_login = outbox.messages().await.len();
+
+        // When
+        for _ in 0..2 {
+            let otp = service.save_otp(user.id).await.unwrap();
+            service.validate_otp("alice@example.com", &otp.code).await.unwrap();
+        }
+
+        // Then
+        let messages = outbox.messages().await;
+        assert_eq!(before_login, 0);
+        assert_eq!(messages.len(), 1);
+        assert!(matches!(&messages[0].payload, OutboxPayload::Mail { to, subject, .. } if to == "alice@example.com" && subject == "Welcome"));
+    }
+
+    #[tokio::test]
+    async fn test_in_transaction() {
+        // Given
+        let users = InMemoryUserRepository::new();
+        let otps = InMemoryOtpRepository::new();
+        let unit_of_work = InMemoryUnitOfWork::new(users.clone(), InMemorySessionRepository::new(), otps.clone());
+        let events = InMemoryEventPublisher::new();
+        let service = UserService::new(users, InMemorySessionRepository::new(), otps, SequenceIdProvider {})
+            .with_event_publisher(Arc::new(events.clone()));
+
+        // When
+        let transaction = unit_of_work.begin().await.unwrap();
+        let (transactional, buffered) = service.in_transaction(&transaction);
+        let user = transactional.find_or_create("alice").await.unwrap();
+        transactional.issue_otp(user.id, "alice").await.unwrap();
+        let before_commit = (service.find_by_login("alice").await.is_ok(), events.events().await.len());
+        transaction.commit().await.unwrap();
+        buffered.flush().await;
+
+        // Then
+        assert_eq!(before_commit, (false, 0));
+        assert_eq!(service.find_by_login("alice").await.unwrap().id, user.id);
+        assert_eq!(events.events().await, vec![DomainEvent::UserCreated { user_id: user.id, login: "alice".to_string() }]);
+        assert_eq!(service.validate_otp("alice", "01234567").await.unwrap().id, user.id);
+    }
+
+    #[tokio::test]
+    async fn test_issue_otp_within_resend_interval() {
+        // Given
+        let outbox = InMemoryOutboxRepository::new();
+        let service = create_service(InMemoryOtpRepository::new().with_outbox(outbox.clone()));
+        let user = service.create("alice".to_string()).await.unwrap();
+
+        // When
+        let first = service.issue_otp(user.id, "alice@example.com").await.unwrap();
+        let second = service.issue_otp(user.id, "alice@example.com").await.unwrap();
+
+        // Then
+        // The in-memory store keeps whole seconds
+        assert_eq!(first.expires_at.timestamp(), second.expires_at.timestamp());
+        assert_eq!(first.resend_after.timestamp(), second.resend_after.timestamp());
+        assert_eq!(first.resend_after, first.expires_at - LoginPolicy::default().otp_lifetime + LoginPolicy::default().resend_interval);
+        assert_eq!(outbox.messages().await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_issue_otp_with_policy_lifetime() {
+        // Given
+        let policy = LoginPolicy { otp_lifetime: Duration::minutes(10), ..LoginPolicy::default() };
+        let service = create_service(InMemoryOtpRepository::new()).with_login_policy(policy);
+        let user = service.create("alice".to_string()).await.unwrap();
+
+        // When
+        let sent = service.issue_otp(user.id, "alice@example.com").await.unwrap();
+
+        // Then
+        assert_eq!(sent.expires_at - sent.resend_after, Duration::minutes(9));
+    }
+
+    #[tokio::test]
+    async fn test_validate_otp_consumes_code() {
+        // Given
+        let service = create_service(InMemoryOtpRepository::new());
+        let user = service.create("alice".to_string()).await.unwrap();
+        let otp = service.save_otp(user.id).await.unwrap();
+
+        // When
+        let first = service.validate_otp("alice", &otp.code).await;
+        let second = service.validate_otp("alice", &otp.code).await;
+
+        // Then
+        assert_eq!(first.unwrap().id, user.id);
+        assert_eq!(second.unwrap_err(), OtpValidationError::NoPendingCode);
+    }
+
+    #[tokio::test]
+    async fn test_validate_otp_of_other_user() {
+        // Given
+        let service = create_service(InMemoryOtpRepository::new());
+        let alice = service.create("alice".to_string()).await.unwrap();
+        let bob = service.create("bob".to_string()).await.unwrap();
+        let alice_otp = service.save_otp(alice.id).await.unwrap();
+
+        // When
+        let without_pending = service.validate_otp("bob", &alice_otp.code).await;
+        let bob_otp = Otp::new("99999999", bob.id, 300, &service.otp_hasher).unwrap();
+        service.otp_repository.save(bob_otp).await.unwrap();
+        let with_pending = service.validate_otp("bob", &alice_otp.code).await;
+
+        // Then
+        assert_eq!(without_pending.unwrap_err(), OtpValidationError::NoPendingCode);
+        assert_eq!(with_pending.unwrap_err(), OtpValidationError::InvalidCode);
+        assert_eq!(service.otp_repository.find_by_user(alice.id).await.unwrap().len(), 1);
+        assert_eq!(service.otp_repository.find_by_user(bob.id).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_validate_otp_expired() {
+        // Given
+        let service = create_service(InMemoryOtpRepository::new());
+        let user = service.create("alice".to_string()).await.unwrap();
+        let mut otp = Otp::new("12345678", user.id, 300, &service.otp_hasher).unwrap();
+        otp.created_at = Utc::now() - Duration::seconds(600);
+        otp.expires_at = Utc::now() - Duration::seconds(300);
+        service.otp_repository.save(otp).await.unwrap();
+
+        // When
+        let result = service.validate_otp("alice", "12345678").await;
+
+        // Then
+        assert_eq!(result.unwrap_err(), OtpValidationError::Expired);
+    }
+
+    #[tokio::test]
+    async fn test_validate_otp_unknown_user() {
+        // Given
+        let service = create_service(InMemoryOtpRepository::new());
+
+        // When
+        let result = service.validate_otp("nobody", "12345678").await;
+
+        // Then
+        assert_eq!(result.unwrap_err(), OtpValidationError::UserNotFound);
+    }
+
+    #[tokio::test]
+    async fn test_validate_otp_locks_after_max_attempts() {
+        // Given
+        let policy = LoginPolicy { max_attempts: 3, lockout_duration: Duration::minutes(5), ..LoginPolicy::default() };
+        let service = create_service(InMemoryOtpRepository::new()).with_login_policy(policy);
+        let user = service.create("alice".to_string()).await.unwrap();
+        let otp = service.save_otp(user.id).await.unwrap();
+
+        // When
+        let first = service.validate_otp("alice", "99999999").await;
+        let second = service.validate_otp("alice", "99999999").await;
+        let third = service.validate_otp("alice", "99999999").await;
+        let correct = service.validate_otp("alice", &otp.code).await;
+
+        // Then
+        assert_eq!(first.unwrap_err(), OtpValidationError::InvalidCode);
+        assert_eq!(second.unwrap_err(), OtpValidationError::InvalidCode);
+        assert!(matches!(third, Err(OtpValidationError::TooManyAttempts(_))));
+        assert!(matches!(correct, Err(OtpValidationError::TooManyAttempts(_))));
+        assert!(service.locked_until("alice").await.unwrap().is_some());
+        assert!(service.otp_repository.find_by_user(user.id).await.unwrap().is_empty());
+    }
+
+    /// User store that is slow to look users up, so that concurrent sign-ins all load the user
+    /// before any of them has written it back
+    #[derive(Clone)]
+    struct SlowUserRepository(InMemoryUserRepository);
+
+    #[async_trait::async_trait]
+    impl UserRepository for SlowUserRepository {
+        async fn find_by_id(&self, id: i64) -> Result<Option<User>, DbError> {
+            self.0.find_by_id(id).await
+        }
+
+        async fn find_by_login(&self, login: &str) -> Result<Option<User>, DbError> {
+            let user = self.0.find_by_login(login).await;
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            user
+        }
+
+        async fn save(&self, user: User) -> Result<User, DbError> {
+            self.0.save(user).await
+        }
+
+        async fn record_failed_login(&self, id: i64, at: DateTime<Utc>, max_attempts: i8, lock_until: DateTime<Utc>) -> Result<Option<User>, DbError> {
+            self.0.record_failed_login(id, at, max_attempts, lock_until).await
+        }
+
+        async fn record_login(&self, id: i64, at: DateTime<Utc>, first_login: Vec<OutboxMessage>) -> Result<Option<User>, DbError> {
+            self.0.record_login(id, at, first_login).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_validate_otp_locks_after_concurrent_attempts() {
+        // Given
+        let policy = LoginPolicy { max_attempts: 4, ..LoginPolicy::default() };
+        let service = UserService::new(
+            SlowUserRepository(InMemoryUserRepository::new()),
+            InMemorySessionRepository::new(),
+            InMemoryOtpRepository::new(),
+            SequenceIdProvider {},
+        ).with_login_policy(policy);
+        let user = service.create("alice".to_string()).await.unwrap();
+        service.save_otp(user.id).await.unwrap();
+
+        // When
+        let results = tokio::join!(
+            service.validate_otp("alice", "99999999"),
+            service.validate_otp("alice", "99999999"),
+            service.validate_otp("alice", "99999999"),
+            service.validate_otp("alice", "99999999"),
+        );
+
+        // Then
+        let results = [results.0, results.1, results.2, results.3];
+        assert_eq!(results.iter().filter(|result| matches!(result, Err(OtpValidationError::TooManyAttempts(_)))).count(), 1);
+        assert!(service.locked_until("alice").await.unwrap().is_some());
+        assert!(service.otp_repository.find_by_user(user.id).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_validate_otp_after_lockout_expired() {
+        // Given
+        let service = create_service(InMemoryOtpRepository::new());
+        service.create("alice".to_string()).await.unwrap();
+        let mut user = service.user_repository.find_by_login("alice").await.unwrap().unwrap();
+        user.locked_until = Some(Utc::now() - Duration::seconds(1));
+        service.user_repository.save(user.clone()).await.unwrap();
+        let otp = service.save_otp(user.id).await.unwrap();
+
+        // When
+        let result = service.validate_otp("alice", &otp.code).await;
+
+        // Then
+        assert!(result.is_ok());
+        assert!(service.locked_until("alice").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_validate_otp_success_resets_attempts() {
+        // Given
+        let service = create_service(InMemoryOtpRepository::new());
+        let user = service.create("alice".to_string()).await.unwrap();
+        let otp = service.save_otp(user.id).await.unwrap();
+        let _ = service.validate_otp("alice", "99999999").await;
+
+        // When
+        let result = service.validate_otp("alice", &otp.code).await;
+
+        // Then
+        assert!(result.is_ok());
+        let stored = service.user_repository.find_by_login("alice").await.unwrap().unwrap();
+        assert_eq!(stored.login_attempts, 0);
+        assert!(stored.last_login_date.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_save_otp_stores_only_hash() {
+        // Given
+        let service = create_service(InMemoryOtpRepository::new());
+        let user = service.create("alice".to_string()).await.unwrap();
+
+        // When
+        let otp = service.save_otp(user.id).await.unwrap();
+
+        // Then
+        let stored = service.otp_repository.find_by_user(user.id).await.unwrap();
+        assert_eq!(stored.len(), 1);
+        assert_ne!(stored[0].code_hash, otp.code);
+        assert!(stored[0].matches(&otp.code, &service.otp_hasher));
+    }
+
+    #[tokio::test]
+    async fn test_authenticate() {
+        // Given
+        let service = create_service(InMemoryOtpRepository::new());
+        let user = service.create("alice".to_owned()).await.unwrap();
+        let session = service.generate_session("alice", None, None).await.unwrap();
+
+        // When
+        let authenticated = service.authenticate(&session.value).await;
+        let unknown = service.authenticate("unknown").await;
+
+        // Then
+        assert_eq!(authenticated.map(|s| s.user.id), Some(user.id));
+        assert!(unknown.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_list_and_revoke_sessions() {
+        // Given
+        let service = create_service(InMemoryOtpRepository::new());
+        let user = service.create("alice".to_owned()).await.unwrap();
+        let first = service.generate_session("alice", Some("Firefox".to_owned()), Some("127.0.0.1".to_owned())).await.unwrap();
+
+        // When
+        let listed = service.list_sessions(user.id, Some(&first.value)).await.unwrap();
+        let revoked = service.revoke_sessions(user.id).await.unwrap();
+
+        // Then
+        assert_eq!(listed.len(), 1);
+        assert!(listed[0].is_current);
+        assert_eq!(listed[0].user_agent.as_deref(), Some("Firefox"));
+        assert_eq!(listed[0].ip_address.as_deref(), Some("127.0.0.1"));
+        assert_eq!(revoked, 1);
+        assert!(service.authenticate(&first.value).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_logout() {
+        // Given
+        let service = create_service(InMemoryOtpRepository::new());
+        service.create("alice".to_owned()).await.unwrap();
+        let session = service.generate_session("alice", None, None).await.unwrap();
+
+        // When
+        let first = service.logout(&session.value).await;
+        let second = service.logout(&session.value).await;
+
+        // Then
+        assert_eq!(first, Ok(()));
+        assert_eq!(second, Ok(()));
+        assert!(service.authenticate(&session.value).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_generate_session_uses_policy() {
+        // Given
+        let policy = SessionPolicy {
+            absolute_lifetime: Duration::hours(2),
+            idle_timeout: Duration::minutes(30),
+            extend_interval: Duration::minutes(1),
+        };
+        let session_repository = InMemorySessionRepository::new();
+        let service = UserService::new(
+            InMemoryUserRepository::new(),
+            session_repository.clone(),
+            InMemoryOtpRepository::new(),
+            SequenceIdProvider {},
+        ).with_session_policy(policy);
+        service.create("alice".to_owned()).await.unwrap();
+
+        // When
+        let view = service.generate_session("alice", None, None).await.unwrap();
+
+        // Then
+        let session = session_repository.clone().load(&view.value).await.unwrap();
+        assert_eq!(session.expired_at, session.created_at + Duration::minutes(30));
+        assert_eq!(session.absolute_expired_at, session.created_at + Duration::hours(2));
+        assert!((29 * 60..=30 * 60).contains(&view.max_age));
+    }
+
+    #[tokio::test]
+    async fn test_authenticate_extends_session() {
+        // Given
+        let policy = SessionPolicy { extend_interval: Duration::zero(), ..SessionPolicy::default() };
+        let session_repository = InMemorySessionRepository::new();
+        let service = UserService::new(
+            InMemoryUserRepository::new(),
+            session_repository.clone(),
+            InMemoryOtpRepository::new(),
+            SequenceIdProvider {},
+        ).with_session_policy(policy);
+        service.create("alice".to_owned()).await.unwrap();
+        let view = service.generate_session("alice", None, None).await.unwrap();
+        let before = session_repository.load(&view.value).await.unwrap();
+
+        // When
+        service.authenticate(&view.value).await.unwrap();
+
+        // Then
+        let after = session_repository.load(&view.value).await.unwrap();
+        assert!(after.last_seen_at > before.last_seen_at);
+        assert!(after.expired_at > before.expired_at);
+    }
+
+    /// Session store whose rows disappear between loading and extending them
+    #[derive(Clone)]
+    struct VanishingSessionRepository(InMemorySessionRepository);
+
+    #[async_trait::async_trait]
+    impl SessionRepository for VanishingSessionRepository {
+        async fn load(&self, id: &str) -> Option<Session> {
+            self.0.load(id).await
+        }
+
+        async fn save(&self, session: &Session) -> Result<String, DbError> {
+            self.0.save(session).await
+        }
+
+        async fn destroy(&self, id: &str) -> Result<bool, DbError> {
+            self.0.destroy(id).await
+        }
+
+        async fn extend(&self, _: &str, _: DateTime<Utc>, _: DateTime<Utc>) -> Result<bool, DbError> {
+            Ok(false)
+        }
+
+        async fn rotate(&self, id: &str, new_id: &str) -> Result<bool, DbError> {
+            self.0.rotate(id, new_id).await
+        }
+
+        async fn find_by_user(&self, user_id: i64) -> Result<Vec<Session>, DbError> {
+            self.0.find_by_user(user_id).await
+        }
+
+        async fn destroy_by_user(&self, user_id: i64) -> Result<u64, DbError> {
+            self.0.destroy_by_user(user_id).await
+        }
+
+        async fn cleanup(&self) -> Result<(), String> {
+            self.0.cleanup().await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_authenticate_rejects_session_removed_during_extension() {
+        // Given
+        let policy = SessionPolicy { extend_interval: Duration::zero(), ..SessionPolicy::default() };
+        let service = UserService::new(
+            InMemoryUserRepository::new(),
+            VanishingSessionRepository(InMemorySessionRepository::new()),
+            InMemoryOtpRepository::new(),
+            SequenceIdProvider {},
+        ).with_session_policy(policy);
+        service.create("alice".to_owned()).await.unwrap();
+        let view = service.generate_session("alice", None, None).await.unwrap();
+
+        // When
+        let authenticated = service.authenticate(&view.value).await;
+
+        // Then
+        assert!(authenticated.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_rotate_session() {
+        // Given
+        let user_repository = InMemoryUserRepository::new();
+        user_repository.save(User::new("alice".to_owned())).await.unwrap();
+        let service = UserService::new(
+            user_repository,
+            InMemorySessionRepository::new(),
+            InMemoryOtpRepository::new(),
+            crate::repositories::id_provider::SimpleIdProvider::new(),
+        );
+        let old = service.generate_session("alice", None, None).await.unwrap();
+
+        // When
+        let new = service.rotate_session(&old.value).await.unwrap();
+
+        // Then
+        assert_ne!(new.value, old.value);
+        assert!(service.authenticate(&old.value).await.is_none());
+        assert!(service.authenticate(&new.value).await.is_some());
+        assert!(service.rotate_session(&old.value).await.is_err());
+    }
+}
@@ -0,0 +1,40 @@
+use crate::models::avatar::{Avatar, Rating};
+use chrono::{DateTime, Utc};
+
+#[derive(Debug, Clone)]
+pub struct AvatarView {
+    pub id: i64,
+    pub user_id: i64,
+    pub content_type: String,
+    pub size_bytes: i64,
+    pub is_primary: bool,
+    pub rating: Rating,
+    pub created_at: DateTime<Utc>,
+}
+
+impl AvatarView {
+    pub fn new(avatar: Avatar) -> Self {
+        Self {
+            id: avatar.id,
+            user_id: avatar.user_id,
+            content_type: avatar.content_type,
+            size_bytes: avatar.size_bytes,
+            is_primary: avatar.is_primary,
+            rating: avatar.rating,
+            created_at: avatar.created_at,
+        }
+    }
+}
+
+/// Encoded picture ready to be served
+#[derive(Debug, Clone, PartialEq)]
+pub struct AvatarImage {
+    pub content_type: String,
+    pub data: Vec<u8>,
+}
+
+impl AvatarImage {
+    pub fn new(content_type: impl Into<String>, data: Vec<u8>) -> Self {
+        Self { content_type: content_type.into(), data }
+    }
+}
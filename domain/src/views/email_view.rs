@@ -0,0 +1,50 @@
+use crate::models::email_address::Email;
+use crate::views::session_view::SessionView;
+use chrono::{DateTime, Utc};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmailView {
+    pub id: i64,
+    pub user_id: i64,
+    pub value: String,
+    /// Hash the public avatar URL of the address is built from
+    pub md5_hash: String,
+    pub is_verified: bool,
+    /// Whether this is the user's primary address
+    pub is_primary: bool,
+    /// Avatar assigned to the address, `None` when it shows the user's primary avatar
+    pub avatar_id: Option<i64>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl EmailView {
+    pub fn new(email: Email, is_primary: bool) -> Self {
+        Self {
+            id: email.id,
+            user_id: email.user_id,
+            value: email.value,
+            md5_hash: email.md5_hash,
+            is_verified: email.is_verified,
+            is_primary,
+            avatar_id: email.avatar_id,
+            created_at: email.created_at,
+        }
+    }
+}
+
+/// Outcome of a change to the addresses of a user, together with the session it was made in
+/// under its new value. `session` is `None` when no session was given or it was not rotated.
+#[derive(Debug)]
+pub struct EmailChangeView<T> {
+    pub change: T,
+    pub session: Option<SessionView>,
+}
+
+/// Freshly added address together with the plaintext verification code. The code is only meant
+/// to be mailed to the address and dropped right after, only its hash is stored.
+#[derive(Debug)]
+pub struct EmailVerificationView {
+    pub email: EmailView,
+    pub code: String,
+    pub expires_at: DateTime<Utc>,
+}
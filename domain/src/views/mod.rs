@@ -1,3 +1,5 @@
 pub mod user_view;
 pub mod otp_view;
-pub mod session_view;
\ No newline at end of file
+pub mod session_view;
+pub mod avatar_view;
+pub mod email_view;
\ No newline at end of file
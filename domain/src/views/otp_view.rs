@@ -1,17 +1,27 @@
 use crate::models::otp::Otp;
 use chrono::{DateTime, Utc};
 
-#[derive(Debug, Clone)]
+/// Freshly issued OTP. This is the only place the plaintext code exists; it is meant to be
+/// moved into `MailService::send_otp` and dropped right after.
+#[derive(Debug)]
 pub struct OtpView {
-    pub id: String,
+    pub code: String,
     pub user_id: i64,
     pub expires_at: DateTime<Utc>,
 }
 
+/// What the user may learn about a code mailed to them
+#[derive(Debug, Clone, PartialEq)]
+pub struct OtpSentView {
+    pub expires_at: DateTime<Utc>,
+    /// No new code is sent before then, asking again answers with the pending one
+    pub resend_after: DateTime<Utc>,
+}
+
 impl OtpView {
-    pub fn new(otp: Otp) -> Self {
+    pub fn new(code: String, otp: Otp) -> Self {
         Self {
-            id: otp.id,
+            code,
             user_id: otp.user_id,
             expires_at: otp.expires_at,
         }
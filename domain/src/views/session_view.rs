@@ -1,5 +1,6 @@
 use crate::models::session::Session;
 use crate::views::user_view::UserView;
+use chrono::{DateTime, Utc};
 
 #[derive(Debug)]
 pub struct SessionView {
@@ -7,11 +8,14 @@ pub struct SessionView {
     pub user: UserView,
     pub created_at: String,
     pub expired_at: String,
+    /// Seconds left until `expired_at` when the view was made, for the `Max-Age` of the cookie
+    pub max_age: i64,
 }
 
 impl SessionView {
     pub fn new(session: Session, user: UserView) -> Self {
         Self {
+            max_age: (session.expired_at - Utc::now()).num_seconds().max(0),
             value: session.value,
             user,
             created_at: session.created_at.to_string(),
@@ -19,3 +23,29 @@ impl SessionView {
         }
     }
 }
+
+/// Signed in device as listed to its user. Leaves out the session value, which would let
+/// anyone reading the list take over the session.
+#[derive(Debug, Clone)]
+pub struct SessionInfoView {
+    pub id: i64,
+    pub created_at: DateTime<Utc>,
+    pub expired_at: DateTime<Utc>,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    /// Whether this is the session the list was requested with
+    pub is_current: bool,
+}
+
+impl SessionInfoView {
+    pub fn new(session: Session, is_current: bool) -> Self {
+        Self {
+            id: session.id,
+            created_at: session.created_at,
+            expired_at: session.expired_at,
+            user_agent: session.user_agent,
+            ip_address: session.ip_address,
+            is_current,
+        }
+    }
+}
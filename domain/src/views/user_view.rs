@@ -1,12 +1,13 @@
 use crate::models::user::User;
 use chrono::{DateTime, Utc};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct UserView {
     pub id: i64,
     pub username: String,
     pub register_complete: bool,
     pub register_date: DateTime<Utc>,
+    pub primary_email_id: Option<i64>,
 }
 
 impl UserView {
@@ -16,6 +17,7 @@ impl UserView {
             username: user.username,
             register_complete: user.register_complete,
             register_date: user.register_date,
+            primary_email_id: user.primary_email_id,
         }
     }
 }
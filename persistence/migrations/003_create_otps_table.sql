@@ -0,0 +1,10 @@
+CREATE TABLE otps (
+    user_id BIGINT NOT NULL,
+    id VARCHAR(8) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (user_id, id),
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX expires_at_otps_expires_at ON otps(expires_at);
\ No newline at end of file
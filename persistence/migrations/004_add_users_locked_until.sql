@@ -0,0 +1 @@
+ALTER TABLE users ADD COLUMN locked_until TIMESTAMPTZ;
\ No newline at end of file
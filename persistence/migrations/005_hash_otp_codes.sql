@@ -0,0 +1,5 @@
+-- Plaintext codes can't be migrated to keyed hashes, pending codes are dropped instead
+DELETE FROM otps;
+
+ALTER TABLE otps RENAME COLUMN id TO code_hash;
+ALTER TABLE otps ALTER COLUMN code_hash TYPE VARCHAR(64);
\ No newline at end of file
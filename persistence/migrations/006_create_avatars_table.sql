@@ -0,0 +1,13 @@
+CREATE TABLE avatars (
+    id BIGSERIAL PRIMARY KEY UNIQUE,
+    user_id BIGINT NOT NULL,
+    content_type VARCHAR(255) NOT NULL,
+    size_bytes BIGINT NOT NULL,
+    storage_key VARCHAR(255) NOT NULL UNIQUE,
+    is_primary BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMPTZ NOT NULL,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX user_id_avatars_user_id ON avatars(user_id);
+CREATE UNIQUE INDEX user_id_avatars_primary ON avatars(user_id) WHERE is_primary;
\ No newline at end of file
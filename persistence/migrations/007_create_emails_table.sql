@@ -0,0 +1,24 @@
+CREATE TABLE emails (
+    id BIGSERIAL PRIMARY KEY UNIQUE,
+    user_id BIGINT NOT NULL,
+    value VARCHAR(254) NOT NULL,
+    md5_hash VARCHAR(32) NOT NULL,
+    sha256_hash VARCHAR(64) NOT NULL,
+    is_verified BOOLEAN NOT NULL DEFAULT FALSE,
+    verification_hash VARCHAR(64),
+    verification_expires_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX user_id_emails_user_id ON emails(user_id);
+CREATE UNIQUE INDEX user_id_value_emails_user_id_value ON emails(user_id, value);
+-- A verified address belongs to exactly one user
+CREATE UNIQUE INDEX value_emails_verified ON emails(value) WHERE is_verified;
+CREATE INDEX md5_hash_emails_verified ON emails(md5_hash) WHERE is_verified;
+CREATE INDEX sha256_hash_emails_verified ON emails(sha256_hash) WHERE is_verified;
+
+ALTER TABLE users
+    ADD CONSTRAINT users_primary_email_id_fkey
+    FOREIGN KEY (primary_email_id) REFERENCES emails(id) ON DELETE SET NULL;
@@ -0,0 +1,3 @@
+-- Deleting an avatar makes the address fall back to the user's primary avatar
+ALTER TABLE emails
+    ADD COLUMN avatar_id BIGINT REFERENCES avatars(id) ON DELETE SET NULL;
@@ -0,0 +1,3 @@
+-- 0 = G, 1 = PG, 2 = R, 3 = X, see `domain::models::avatar::Rating`
+ALTER TABLE avatars
+    ADD COLUMN rating SMALLINT NOT NULL DEFAULT 0 CHECK (rating BETWEEN 0 AND 3);
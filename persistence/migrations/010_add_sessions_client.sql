@@ -0,0 +1,5 @@
+ALTER TABLE sessions
+    ADD COLUMN user_agent VARCHAR(512),
+    ADD COLUMN ip_address VARCHAR(45);
+
+CREATE INDEX user_id_sessions_user_id ON sessions(user_id);
@@ -0,0 +1,9 @@
+ALTER TABLE sessions
+    ADD COLUMN last_seen_at TIMESTAMPTZ,
+    ADD COLUMN absolute_expired_at TIMESTAMPTZ;
+
+UPDATE sessions SET last_seen_at = created_at, absolute_expired_at = expired_at;
+
+ALTER TABLE sessions
+    ALTER COLUMN last_seen_at SET NOT NULL,
+    ALTER COLUMN absolute_expired_at SET NOT NULL;
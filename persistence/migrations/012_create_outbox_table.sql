@@ -0,0 +1,14 @@
+CREATE TABLE outbox (
+    id BIGSERIAL PRIMARY KEY UNIQUE,
+    key VARCHAR NOT NULL UNIQUE,
+    payload JSONB NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMPTZ NOT NULL,
+    expires_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL,
+    last_error TEXT,
+    -- Set once the dispatcher gave up, the message is kept for inspection
+    failed_at TIMESTAMPTZ
+);
+
+CREATE INDEX next_attempt_at_outbox_pending ON outbox(next_attempt_at) WHERE failed_at IS NULL;
@@ -0,0 +1,11 @@
+-- Avatar lookups of users without verified addresses go by the hashes of their login. They are
+-- set by the application on every save, so that the lookup doesn't hash every row.
+ALTER TABLE users ADD COLUMN login_md5 VARCHAR(32);
+ALTER TABLE users ADD COLUMN login_sha256 VARCHAR(64);
+
+UPDATE users
+SET login_md5 = md5(lower(trim(username))),
+    login_sha256 = encode(sha256(convert_to(lower(trim(username)), 'UTF8')), 'hex');
+
+CREATE INDEX login_md5_users ON users(login_md5);
+CREATE INDEX login_sha256_users ON users(login_sha256);
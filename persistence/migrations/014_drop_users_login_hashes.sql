@@ -0,0 +1,6 @@
+-- Avatars are only looked up through verified addresses, a login proves no ownership of the
+-- address it looks like
+DROP INDEX login_md5_users;
+DROP INDEX login_sha256_users;
+ALTER TABLE users DROP COLUMN login_md5;
+ALTER TABLE users DROP COLUMN login_sha256;
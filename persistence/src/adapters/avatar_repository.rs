@@ -0,0 +1,139 @@
+use crate::adapters::map_sqlx_error;
+use async_trait::async_trait;
+use domain::models::avatar::Avatar;
+use domain::repositories::avatar_repository::AvatarRepository;
+use domain::repositories::DbError;
+use sqlx::PgPool;
+
+/// `AvatarRepository` backed by the `avatars` table
+#[derive(Debug, Clone)]
+pub struct PgAvatarRepository {
+    pool: PgPool,
+}
+
+impl PgAvatarRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl AvatarRepository for PgAvatarRepository {
+    async fn save(&self, avatar: Avatar) -> Result<Avatar, DbError> {
+        if avatar.id < 0 {
+            return sqlx::query_as::<_, Avatar>(
+                r#"
+                INSERT INTO avatars (user_id, content_type, size_bytes, storage_key, is_primary, rating, created_at)
+                VALUES ($1, $2, $3, $4, $5, $6, $7)
+                RETURNING *
+                "#,
+            )
+                .bind(avatar.user_id)
+                .bind(&avatar.content_type)
+                .bind(avatar.size_bytes)
+                .bind(&avatar.storage_key)
+                .bind(avatar.is_primary)
+                .bind(avatar.rating)
+                .bind(avatar.created_at)
+                .fetch_one(&self.pool)
+                .await
+                .map_err(map_sqlx_error);
+        }
+
+        sqlx::query_as::<_, Avatar>(
+            r#"
+            UPDATE avatars
+            SET content_type = $2,
+                size_bytes = $3,
+                storage_key = $4,
+                is_primary = $5,
+                rating = $6
+            WHERE id = $1
+            RETURNING *
+            "#,
+        )
+            .bind(avatar.id)
+            .bind(&avatar.content_type)
+            .bind(avatar.size_bytes)
+            .bind(&avatar.storage_key)
+            .bind(avatar.is_primary)
+            .bind(avatar.rating)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(map_sqlx_error)?
+            .ok_or_else(|| DbError::NotFound(format!("Avatar with id {}", avatar.id)))
+    }
+
+    async fn find_by_id(&self, id: i64) -> Option<Avatar> {
+        let result = sqlx::query_as::<_, Avatar>("SELECT * FROM avatars WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await;
+
+        match result {
+            Ok(avatar) => avatar,
+            Err(err) => {
+                eprintln!("Error loading avatar {}: {}", id, err);
+                None
+            }
+        }
+    }
+
+    async fn find_by_user(&self, user_id: i64) -> Result<Vec<Avatar>, DbError> {
+        sqlx::query_as::<_, Avatar>("SELECT * FROM avatars WHERE user_id = $1 ORDER BY created_at DESC")
+            .bind(user_id)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(map_sqlx_error)
+    }
+
+    async fn find_primary_by_user(&self, user_id: i64) -> Option<Avatar> {
+        let result = sqlx::query_as::<_, Avatar>("SELECT * FROM avatars WHERE user_id = $1 AND is_primary")
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await;
+
+        match result {
+            Ok(avatar) => avatar,
+            Err(err) => {
+                eprintln!("Error loading primary avatar of user {}: {}", user_id, err);
+                None
+            }
+        }
+    }
+
+    async fn set_primary(&self, user_id: i64, avatar_id: i64) -> Result<(), DbError> {
+        let mut transaction = self.pool.begin().await.map_err(map_sqlx_error)?;
+
+        // Clear the old flag first, the partial unique index allows one primary avatar per user
+        sqlx::query("UPDATE avatars SET is_primary = FALSE WHERE user_id = $1 AND is_primary AND id <> $2")
+            .bind(user_id)
+            .bind(avatar_id)
+            .execute(&mut *transaction)
+            .await
+            .map_err(map_sqlx_error)?;
+
+        let result = sqlx::query("UPDATE avatars SET is_primary = TRUE WHERE user_id = $1 AND id = $2")
+            .bind(user_id)
+            .bind(avatar_id)
+            .execute(&mut *transaction)
+            .await
+            .map_err(map_sqlx_error)?;
+
+        if result.rows_affected() == 0 {
+            return Err(DbError::NotFound(format!("Avatar with id {}", avatar_id)));
+        }
+
+        transaction.commit().await.map_err(map_sqlx_error)
+    }
+
+    async fn delete(&self, id: i64) -> Result<(), DbError> {
+        sqlx::query("DELETE FROM avatars WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await
+            .map_err(map_sqlx_error)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,148 @@
+use crate::adapters::map_sqlx_error;
+use crate::adapters::outbox_repository::insert_message;
+use async_trait::async_trait;
+use domain::models::email_address::{normalize_email, Email};
+use domain::repositories::email_repository::EmailRepository;
+use domain::repositories::outbox_repository::OutboxBuilder;
+use domain::repositories::DbError;
+use sqlx::{PgConnection, PgPool};
+
+/// `EmailRepository` backed by the `emails` table
+#[derive(Debug, Clone)]
+pub struct PgEmailRepository {
+    pool: PgPool,
+}
+
+impl PgEmailRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    async fn find_one(&self, query: &str, value: &str) -> Option<Email> {
+        let result = sqlx::query_as::<_, Email>(query)
+            .bind(value)
+            .fetch_optional(&self.pool)
+            .await;
+
+        match result {
+            Ok(email) => email,
+            Err(err) => {
+                eprintln!("Error loading email {}: {}", value, err);
+                None
+            }
+        }
+    }
+}
+
+/// Inserts or updates `email` on `connection`, see `EmailRepository::save`
+async fn save_email(connection: &mut PgConnection, email: &Email) -> Result<Email, DbError> {
+    if email.id < 0 {
+        return sqlx::query_as::<_, Email>(
+            r#"
+            INSERT INTO emails (user_id, value, md5_hash, sha256_hash, is_verified, avatar_id, verification_hash, verification_expires_at, created_at, updated_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+            RETURNING *
+            "#,
+        )
+            .bind(email.user_id)
+            .bind(&email.value)
+            .bind(&email.md5_hash)
+            .bind(&email.sha256_hash)
+            .bind(email.is_verified)
+            .bind(email.avatar_id)
+            .bind(&email.verification_hash)
+            .bind(email.verification_expires_at)
+            .bind(email.created_at)
+            .bind(email.updated_at)
+            .fetch_one(&mut *connection)
+            .await
+            .map_err(map_sqlx_error);
+    }
+
+    sqlx::query_as::<_, Email>(
+        r#"
+        UPDATE emails
+        SET is_verified = $2,
+            avatar_id = $3,
+            verification_hash = $4,
+            verification_expires_at = $5,
+            updated_at = $6
+        WHERE id = $1
+        RETURNING *
+        "#,
+    )
+        .bind(email.id)
+        .bind(email.is_verified)
+        .bind(email.avatar_id)
+        .bind(&email.verification_hash)
+        .bind(email.verification_expires_at)
+        .bind(email.updated_at)
+        .fetch_optional(connection)
+        .await
+        .map_err(map_sqlx_error)?
+        .ok_or_else(|| DbError::NotFound(format!("Email with id {}", email.id)))
+}
+
+#[async_trait]
+impl EmailRepository for PgEmailRepository {
+    async fn save(&self, email: Email) -> Result<Email, DbError> {
+        let mut connection = self.pool.acquire().await.map_err(map_sqlx_error)?;
+
+        save_email(&mut connection, &email).await
+    }
+
+    async fn save_with_outbox<'a>(&'a self, email: Email, messages: OutboxBuilder<'a, Email>) -> Result<Email, DbError> {
+        let mut transaction = self.pool.begin().await.map_err(map_sqlx_error)?;
+
+        let email = save_email(&mut transaction, &email).await?;
+
+        for message in &messages(&email) {
+            insert_message(&mut transaction, message).await?;
+        }
+
+        transaction.commit().await.map_err(map_sqlx_error)?;
+
+        Ok(email)
+    }
+
+    async fn find_by_id(&self, id: i64) -> Option<Email> {
+        let result = sqlx::query_as::<_, Email>("SELECT * FROM emails WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await;
+
+        match result {
+            Ok(email) => email,
+            Err(err) => {
+                eprintln!("Error loading email {}: {}", id, err);
+                None
+            }
+        }
+    }
+
+    async fn find_by_user(&self, user_id: i64) -> Result<Vec<Email>, DbError> {
+        sqlx::query_as::<_, Email>("SELECT * FROM emails WHERE user_id = $1 ORDER BY created_at")
+            .bind(user_id)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(map_sqlx_error)
+    }
+
+    async fn find_verified_by_value(&self, value: &str) -> Option<Email> {
+        self.find_one("SELECT * FROM emails WHERE value = $1 AND is_verified", &normalize_email(value)).await
+    }
+
+    async fn find_verified_by_hash(&self, hash: &str) -> Option<Email> {
+        self.find_one("SELECT * FROM emails WHERE (md5_hash = $1 OR sha256_hash = $1) AND is_verified", hash).await
+    }
+
+    async fn delete(&self, id: i64) -> Result<(), DbError> {
+        sqlx::query("DELETE FROM emails WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await
+            .map_err(map_sqlx_error)?;
+
+        Ok(())
+    }
+}
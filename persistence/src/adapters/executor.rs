@@ -0,0 +1,58 @@
+use crate::adapters::map_sqlx_error;
+use domain::repositories::DbError;
+use sqlx::pool::PoolConnection;
+use sqlx::{PgConnection, PgPool, Postgres, Transaction};
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+use tokio::sync::{MappedMutexGuard, Mutex, MutexGuard};
+
+/// Transaction shared by the repositories of one unit of work. Taken out on commit or rollback.
+pub(crate) type SharedTransaction = Arc<Mutex<Option<Transaction<'static, Postgres>>>>;
+
+/// Where a repository runs its queries: on connections from the pool, each query on its own,
+/// or inside the transaction of a unit of work
+#[derive(Debug, Clone)]
+pub(crate) enum PgExecutor {
+    Pool(PgPool),
+    Transaction(SharedTransaction),
+}
+
+/// Connection to run queries on. Inside a unit of work it keeps the transaction locked, so it
+/// must be dropped before the repository calls another method acquiring a connection.
+pub(crate) enum PgConnectionGuard<'a> {
+    Pooled(Box<PoolConnection<Postgres>>),
+    Transaction(MappedMutexGuard<'a, Transaction<'static, Postgres>>),
+}
+
+impl PgExecutor {
+    pub(crate) async fn acquire(&self) -> Result<PgConnectionGuard<'_>, DbError> {
+        match self {
+            PgExecutor::Pool(pool) => pool.acquire().await
+                .map(|connection| PgConnectionGuard::Pooled(Box::new(connection)))
+                .map_err(map_sqlx_error),
+            PgExecutor::Transaction(transaction) => MutexGuard::try_map(transaction.lock().await, Option::as_mut)
+                .map(PgConnectionGuard::Transaction)
+                .map_err(|_| DbError::InternalError("Transaction already committed or rolled back".to_owned())),
+        }
+    }
+}
+
+impl Deref for PgConnectionGuard<'_> {
+    type Target = PgConnection;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            PgConnectionGuard::Pooled(connection) => connection,
+            PgConnectionGuard::Transaction(transaction) => transaction,
+        }
+    }
+}
+
+impl DerefMut for PgConnectionGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        match self {
+            PgConnectionGuard::Pooled(connection) => connection,
+            PgConnectionGuard::Transaction(transaction) => transaction,
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use async_trait::async_trait;
+use domain::repositories::blob_store::BlobStore;
+use domain::repositories::DbError;
+use std::io::ErrorKind;
+use std::path::{Component, Path, PathBuf};
+use tokio::fs;
+
+/// `BlobStore` keeping every blob as a file below `root`
+#[derive(Debug, Clone)]
+pub struct LocalBlobStore {
+    root: PathBuf,
+}
+
+impl LocalBlobStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// Maps a key to a path, refusing keys that would escape `root`
+    fn path(&self, key: &str) -> Result<PathBuf, DbError> {
+        let relative = Path::new(key);
+
+        if key.is_empty() || !relative.components().all(|c| matches!(c, Component::Normal(_))) {
+            return Err(DbError::InternalError(format!("Invalid blob key: {}", key)));
+        }
+
+        Ok(self.root.join(relative))
+    }
+}
+
+#[async_trait]
+impl BlobStore for LocalBlobStore {
+    async fn put(&self, key: &str, data: &[u8]) -> Result<(), DbError> {
+        let path = self.path(key)?;
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await.map_err(|e| DbError::InternalError(e.to_string()))?;
+        }
+
+        // Write to a temporary file first so readers never see a partially written blob
+        let tmp_path = path.with_extension("tmp");
+
+        fs::write(&tmp_path, data).await.map_err(|e| DbError::InternalError(e.to_string()))?;
+        fs::rename(&tmp_path, &path).await.map_err(|e| DbError::InternalError(e.to_string()))
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, DbError> {
+        match fs::read(self.path(key)?).await {
+            Ok(data) => Ok(Some(data)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(DbError::InternalError(err.to_string())),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), DbError> {
+        match fs::remove_file(self.path(key)?).await {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(DbError::InternalError(err.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use domain::repositories::id_provider::{IdProvider, SimpleIdProvider};
+
+    fn create_store() -> LocalBlobStore {
+        let root = std::env::temp_dir().join(format!("avatars-blobs-{}", SimpleIdProvider::new().get_id(16)));
+
+        LocalBlobStore::new(root)
+    }
+
+    #[tokio::test]
+    async fn test_put_and_get() {
+        // Given
+        let store = create_store();
+
+        // When
+        store.put("avatars/1/key", b"data").await.unwrap();
+
+        // Then
+        assert_eq!(store.get("avatars/1/key").await.unwrap(), Some(b"data".to_vec()));
+        assert_eq!(store.get("avatars/1/missing").await.unwrap(), None);
+
+        fs::remove_dir_all(&store.root).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_delete() {
+        // Given
+        let store = create_store();
+        store.put("key", b"data").await.unwrap();
+
+        // When
+        store.delete("key").await.unwrap();
+
+        // Then
+        assert_eq!(store.get("key").await.unwrap(), None);
+        assert!(store.delete("key").await.is_ok());
+
+        fs::remove_dir_all(&store.root).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_rejects_keys_outside_root() {
+        // Given
+        let store = create_store();
+
+        // Then
+        assert!(store.put("../escape", b"data").await.is_err());
+        assert!(store.put("/etc/passwd", b"data").await.is_err());
+        assert!(store.get("").await.is_err());
+    }
+}
@@ -1 +1,23 @@
-mod smtp;
\ No newline at end of file
+pub mod avatar_repository;
+pub mod email_repository;
+pub(crate) mod executor;
+pub mod local_blob_store;
+pub mod otp_repository;
+pub mod outbox_repository;
+pub mod session_repository;
+pub mod smtp;
+pub mod unit_of_work;
+pub mod user_repository;
+
+use domain::repositories::DbError;
+
+/// Translates a `sqlx` error into the storage-agnostic `DbError` used by the domain
+pub(crate) fn map_sqlx_error(err: sqlx::Error) -> DbError {
+    match err {
+        sqlx::Error::RowNotFound => DbError::NotFound(err.to_string()),
+        sqlx::Error::Database(db_err) if db_err.is_unique_violation() => {
+            DbError::UniqueViolation(db_err.message().to_owned())
+        }
+        _ => DbError::InternalError(err.to_string()),
+    }
+}
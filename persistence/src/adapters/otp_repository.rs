@@ -0,0 +1,166 @@
+use crate::adapters::executor::{PgExecutor, SharedTransaction};
+use crate::adapters::map_sqlx_error;
+use crate::adapters::outbox_repository::insert_message;
+use async_trait::async_trait;
+use domain::models::otp::Otp;
+use domain::models::outbox::OutboxMessage;
+use domain::repositories::otp_repository::OtpRepository;
+use domain::repositories::DbError;
+use sqlx::{Connection, PgConnection, PgPool};
+
+/// `OtpRepository` backed by the `otps` table
+#[derive(Debug, Clone)]
+pub struct PgOtpRepository {
+    executor: PgExecutor,
+}
+
+impl PgOtpRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { executor: PgExecutor::Pool(pool) }
+    }
+
+    /// Repository running its queries in the transaction of a unit of work
+    pub(crate) fn in_transaction(transaction: SharedTransaction) -> Self {
+        Self { executor: PgExecutor::Transaction(transaction) }
+    }
+}
+
+/// Removes the expired codes of `user_id` on `connection`, codes of other users are left to
+/// `cleanup`
+async fn purge_expired(connection: &mut PgConnection, user_id: i64) -> Result<(), DbError> {
+    sqlx::query("DELETE FROM otps WHERE user_id = $1 AND expires_at <= now()")
+        .bind(user_id)
+        .execute(connection)
+        .await
+        .map_err(map_sqlx_error)?;
+
+    Ok(())
+}
+
+#[async_trait]
+impl OtpRepository for PgOtpRepository {
+    async fn save<'a>(&'a self, otp: Otp) -> Result<Otp, DbError> {
+        let mut connection = self.executor.acquire().await?;
+
+        purge_expired(&mut connection, otp.user_id).await?;
+
+        sqlx::query_as::<_, Otp>(
+            r#"
+            INSERT INTO otps (user_id, code_hash, created_at, expires_at)
+            VALUES ($1, $2, $3, $4)
+            RETURNING code_hash, user_id, created_at, expires_at
+            "#,
+        )
+            .bind(otp.user_id)
+            .bind(&otp.code_hash)
+            .bind(otp.created_at)
+            .bind(otp.expires_at)
+            .fetch_one(&mut *connection)
+            .await
+            .map_err(map_sqlx_error)
+    }
+
+    async fn save_with_outbox<'a>(&'a self, otp: Otp, messages: Vec<OutboxMessage>) -> Result<Otp, DbError> {
+        // Inside a unit of work this is a savepoint, committed only with the unit of work
+        let mut connection = self.executor.acquire().await?;
+        let mut transaction = connection.begin().await.map_err(map_sqlx_error)?;
+
+        purge_expired(&mut transaction, otp.user_id).await?;
+
+        let otp = sqlx::query_as::<_, Otp>(
+            r#"
+            INSERT INTO otps (user_id, code_hash, created_at, expires_at)
+            VALUES ($1, $2, $3, $4)
+            RETURNING code_hash, user_id, created_at, expires_at
+            "#,
+        )
+            .bind(otp.user_id)
+            .bind(&otp.code_hash)
+            .bind(otp.created_at)
+            .bind(otp.expires_at)
+            .fetch_one(&mut *transaction)
+            .await
+            .map_err(map_sqlx_error)?;
+
+        for message in &messages {
+            insert_message(&mut transaction, message).await?;
+        }
+
+        transaction.commit().await.map_err(map_sqlx_error)?;
+
+        Ok(otp)
+    }
+
+    async fn find_by_id<'a>(&'a self, user_id: i64, code_hash: &'a str) -> Option<Otp> {
+        let mut connection = match self.executor.acquire().await {
+            Ok(connection) => connection,
+            Err(err) => {
+                eprintln!("Error loading OTP: {}", err);
+                return None;
+            }
+        };
+
+        let result = sqlx::query_as::<_, Otp>(
+            "SELECT code_hash, user_id, created_at, expires_at FROM otps WHERE user_id = $1 AND code_hash = $2",
+        )
+            .bind(user_id)
+            .bind(code_hash)
+            .fetch_optional(&mut *connection)
+            .await;
+
+        match result {
+            Ok(otp) => otp,
+            Err(err) => {
+                eprintln!("Error loading OTP: {}", err);
+                None
+            }
+        }
+    }
+
+    async fn find_by_user<'a>(&'a self, user_id: i64) -> Result<Vec<Otp>, DbError> {
+        let mut connection = self.executor.acquire().await?;
+
+        sqlx::query_as::<_, Otp>("SELECT code_hash, user_id, created_at, expires_at FROM otps WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_all(&mut *connection)
+            .await
+            .map_err(map_sqlx_error)
+    }
+
+    async fn delete<'a>(&'a self, user_id: i64, code_hash: &'a str) -> Result<(), DbError> {
+        let mut connection = self.executor.acquire().await?;
+
+        sqlx::query("DELETE FROM otps WHERE user_id = $1 AND code_hash = $2")
+            .bind(user_id)
+            .bind(code_hash)
+            .execute(&mut *connection)
+            .await
+            .map_err(map_sqlx_error)?;
+
+        Ok(())
+    }
+
+    async fn consume<'a>(&'a self, user_id: i64, code_hash: &'a str) -> Result<Option<Otp>, DbError> {
+        let mut connection = self.executor.acquire().await?;
+
+        sqlx::query_as::<_, Otp>(
+            "DELETE FROM otps WHERE user_id = $1 AND code_hash = $2 RETURNING code_hash, user_id, created_at, expires_at",
+        )
+            .bind(user_id)
+            .bind(code_hash)
+            .fetch_optional(&mut *connection)
+            .await
+            .map_err(map_sqlx_error)
+    }
+
+    async fn cleanup<'a>(&'a self) -> Result<(), DbError> {
+        let mut connection = self.executor.acquire().await?;
+
+        sqlx::query("DELETE FROM otps WHERE expires_at <= now()")
+            .execute(&mut *connection)
+            .await
+            .map_err(map_sqlx_error)?;
+
+        Ok(())
+    }
+}
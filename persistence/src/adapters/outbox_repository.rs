@@ -0,0 +1,156 @@
+use crate::adapters::map_sqlx_error;
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use domain::models::outbox::{OutboxMessage, OutboxPayload};
+use domain::repositories::outbox_repository::OutboxRepository;
+use domain::repositories::DbError;
+use sqlx::{FromRow, PgConnection, PgPool};
+
+/// `OutboxRepository` backed by the `outbox` table. Payloads are stored as JSON.
+#[derive(Debug, Clone)]
+pub struct PgOutboxRepository {
+    pool: PgPool,
+}
+
+impl PgOutboxRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[derive(FromRow)]
+struct OutboxRow {
+    id: i64,
+    key: String,
+    payload: String,
+    attempts: i32,
+    next_attempt_at: DateTime<Utc>,
+    expires_at: Option<DateTime<Utc>>,
+    created_at: DateTime<Utc>,
+}
+
+impl TryFrom<OutboxRow> for OutboxMessage {
+    type Error = DbError;
+
+    fn try_from(row: OutboxRow) -> Result<Self, Self::Error> {
+        let payload = serde_json::from_str::<OutboxPayload>(&row.payload)
+            .map_err(|e| DbError::InternalError(format!("Invalid payload of outbox message {}: {}", row.id, e)))?;
+
+        Ok(OutboxMessage {
+            id: row.id,
+            key: row.key,
+            payload,
+            attempts: row.attempts,
+            next_attempt_at: row.next_attempt_at,
+            expires_at: row.expires_at,
+            created_at: row.created_at,
+        })
+    }
+}
+
+/// Inserts `message` on `connection`, so that it can be part of the transaction making the
+/// change the message belongs to. A message with the same key already stored wins.
+pub(crate) async fn insert_message(connection: &mut PgConnection, message: &OutboxMessage) -> Result<(), DbError> {
+    let payload = serde_json::to_string(&message.payload).map_err(|e| DbError::InternalError(e.to_string()))?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO outbox (key, payload, attempts, next_attempt_at, expires_at, created_at)
+        VALUES ($1, $2::jsonb, $3, $4, $5, $6)
+        ON CONFLICT (key) DO NOTHING
+        "#,
+    )
+        .bind(&message.key)
+        .bind(payload)
+        .bind(message.attempts)
+        .bind(message.next_attempt_at)
+        .bind(message.expires_at)
+        .bind(message.created_at)
+        .execute(connection)
+        .await
+        .map_err(map_sqlx_error)?;
+
+    Ok(())
+}
+
+#[async_trait]
+impl OutboxRepository for PgOutboxRepository {
+    async fn enqueue(&self, message: OutboxMessage) -> Result<(), DbError> {
+        let mut connection = self.pool.acquire().await.map_err(map_sqlx_error)?;
+
+        insert_message(&mut connection, &message).await
+    }
+
+    async fn claim_due(&self, now: DateTime<Utc>, limit: usize, lease: Duration) -> Result<Vec<OutboxMessage>, DbError> {
+        // SKIP LOCKED lets concurrent dispatchers claim disjoint batches instead of waiting
+        let rows = sqlx::query_as::<_, OutboxRow>(
+            r#"
+            UPDATE outbox SET attempts = attempts + 1, next_attempt_at = $3
+            WHERE id IN (
+                SELECT id FROM outbox
+                WHERE failed_at IS NULL AND next_attempt_at <= $1 AND (expires_at IS NULL OR expires_at > $1)
+                ORDER BY id
+                LIMIT $2
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING id, key, payload::text AS payload, attempts, next_attempt_at, expires_at, created_at
+            "#,
+        )
+            .bind(now)
+            .bind(limit as i64)
+            .bind(now + lease)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(map_sqlx_error)?;
+
+        let mut messages = rows.into_iter()
+            .map(OutboxMessage::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        messages.sort_by_key(|message| message.id);
+
+        Ok(messages)
+    }
+
+    async fn mark_delivered(&self, id: i64) -> Result<(), DbError> {
+        sqlx::query("DELETE FROM outbox WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await
+            .map_err(map_sqlx_error)?;
+
+        Ok(())
+    }
+
+    async fn mark_failed(&self, id: i64, error: &str, retry_at: Option<DateTime<Utc>>) -> Result<(), DbError> {
+        let result = match retry_at {
+            Some(retry_at) => sqlx::query("UPDATE outbox SET last_error = $2, next_attempt_at = $3 WHERE id = $1")
+                .bind(id)
+                .bind(error)
+                .bind(retry_at)
+                .execute(&self.pool)
+                .await,
+            None => sqlx::query("UPDATE outbox SET last_error = $2, failed_at = now() WHERE id = $1")
+                .bind(id)
+                .bind(error)
+                .execute(&self.pool)
+                .await,
+        };
+
+        if result.map_err(map_sqlx_error)?.rows_affected() == 0 {
+            return Err(DbError::NotFound(format!("Outbox message {}", id)));
+        }
+
+        Ok(())
+    }
+
+    async fn cleanup(&self, now: DateTime<Utc>) -> Result<u64, DbError> {
+        let result = sqlx::query("DELETE FROM outbox WHERE expires_at <= $1")
+            .bind(now)
+            .execute(&self.pool)
+            .await
+            .map_err(map_sqlx_error)?;
+
+        Ok(result.rows_affected())
+    }
+}
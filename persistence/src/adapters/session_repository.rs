@@ -0,0 +1,194 @@
+use crate::adapters::executor::{PgExecutor, SharedTransaction};
+use crate::adapters::map_sqlx_error;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use domain::models::session::Session;
+use domain::repositories::session_repository::SessionRepository;
+use domain::repositories::DbError;
+use sqlx::{FromRow, PgPool};
+
+/// `SessionRepository` backed by the `sessions` table
+#[derive(Debug, Clone)]
+pub struct PgSessionRepository {
+    executor: PgExecutor,
+}
+
+impl PgSessionRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { executor: PgExecutor::Pool(pool) }
+    }
+
+    /// Repository running its queries in the transaction of a unit of work
+    pub(crate) fn in_transaction(transaction: SharedTransaction) -> Self {
+        Self { executor: PgExecutor::Transaction(transaction) }
+    }
+}
+
+#[derive(FromRow)]
+struct SessionRow {
+    id: i64,
+    user_id: i64,
+    value: String,
+    created_at: DateTime<Utc>,
+    expired_at: DateTime<Utc>,
+    last_seen_at: DateTime<Utc>,
+    absolute_expired_at: DateTime<Utc>,
+    user_agent: Option<String>,
+    ip_address: Option<String>,
+}
+
+impl From<SessionRow> for Session {
+    fn from(row: SessionRow) -> Self {
+        Session {
+            id: row.id,
+            user_id: row.user_id.to_string(),
+            value: row.value,
+            created_at: row.created_at,
+            expired_at: row.expired_at,
+            last_seen_at: row.last_seen_at,
+            absolute_expired_at: row.absolute_expired_at,
+            user_agent: row.user_agent,
+            ip_address: row.ip_address,
+        }
+    }
+}
+
+#[async_trait]
+impl SessionRepository for PgSessionRepository {
+    async fn load(&self, id: &str) -> Option<Session> {
+        let mut connection = match self.executor.acquire().await {
+            Ok(connection) => connection,
+            Err(err) => {
+                eprintln!("Error loading session: {}", err);
+                return None;
+            }
+        };
+
+        let result = sqlx::query_as::<_, SessionRow>(
+            "SELECT id, user_id, value, created_at, expired_at, last_seen_at, absolute_expired_at, user_agent, ip_address FROM sessions WHERE value = $1 AND expired_at > now()",
+        )
+            .bind(id)
+            .fetch_optional(&mut *connection)
+            .await;
+
+        match result {
+            Ok(row) => row.map(Session::from),
+            Err(err) => {
+                eprintln!("Error loading session: {}", err);
+                None
+            }
+        }
+    }
+
+    async fn save(&self, session: &Session) -> Result<String, DbError> {
+        let mut connection = self.executor.acquire().await?;
+
+        let user_id = session.user_id.parse::<i64>()
+            .map_err(|_| DbError::InternalError(format!("Invalid user id: {}", session.user_id)))?;
+
+        sqlx::query_scalar::<_, String>(
+            r#"
+            INSERT INTO sessions (user_id, value, created_at, expired_at, last_seen_at, absolute_expired_at, user_agent, ip_address)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            RETURNING value
+            "#,
+        )
+            .bind(user_id)
+            .bind(&session.value)
+            .bind(session.created_at)
+            .bind(session.expired_at)
+            .bind(session.last_seen_at)
+            .bind(session.absolute_expired_at)
+            .bind(&session.user_agent)
+            .bind(&session.ip_address)
+            .fetch_one(&mut *connection)
+            .await
+            .map_err(map_sqlx_error)
+    }
+
+    async fn destroy(&self, id: &str) -> Result<bool, DbError> {
+        let mut connection = self.executor.acquire().await?;
+
+        let result = sqlx::query("DELETE FROM sessions WHERE value = $1")
+            .bind(id)
+            .execute(&mut *connection)
+            .await
+            .map_err(map_sqlx_error)?;
+
+        if result.rows_affected() == 0 {
+            return Err(DbError::NotFound(format!("Session {}", id)));
+        }
+
+        Ok(true)
+    }
+
+    async fn extend(&self, id: &str, last_seen_at: DateTime<Utc>, expired_at: DateTime<Utc>) -> Result<bool, DbError> {
+        let mut connection = self.executor.acquire().await?;
+
+        let result = sqlx::query(
+            "UPDATE sessions SET last_seen_at = $2, expired_at = $3 WHERE value = $1 AND expired_at > now()",
+        )
+            .bind(id)
+            .bind(last_seen_at)
+            .bind(expired_at)
+            .execute(&mut *connection)
+            .await
+            .map_err(map_sqlx_error)?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn rotate(&self, id: &str, new_id: &str) -> Result<bool, DbError> {
+        let mut connection = self.executor.acquire().await?;
+
+        let result = sqlx::query("UPDATE sessions SET value = $2 WHERE value = $1 AND expired_at > now()")
+            .bind(id)
+            .bind(new_id)
+            .execute(&mut *connection)
+            .await
+            .map_err(map_sqlx_error)?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn find_by_user(&self, user_id: i64) -> Result<Vec<Session>, DbError> {
+        let mut connection = self.executor.acquire().await?;
+
+        let rows = sqlx::query_as::<_, SessionRow>(
+            r#"
+            SELECT id, user_id, value, created_at, expired_at, last_seen_at, absolute_expired_at, user_agent, ip_address FROM sessions
+            WHERE user_id = $1 AND expired_at > now()
+            ORDER BY created_at DESC
+            "#,
+        )
+            .bind(user_id)
+            .fetch_all(&mut *connection)
+            .await
+            .map_err(map_sqlx_error)?;
+
+        Ok(rows.into_iter().map(Session::from).collect())
+    }
+
+    async fn destroy_by_user(&self, user_id: i64) -> Result<u64, DbError> {
+        let mut connection = self.executor.acquire().await?;
+
+        let result = sqlx::query("DELETE FROM sessions WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut *connection)
+            .await
+            .map_err(map_sqlx_error)?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn cleanup(&self) -> Result<(), String> {
+        let mut connection = self.executor.acquire().await.map_err(|e| e.to_string())?;
+
+        sqlx::query("DELETE FROM sessions WHERE expired_at <= now()")
+            .execute(&mut *connection)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(())
+    }
+}
@@ -5,6 +5,7 @@ use lettre::message::{header, Mailbox, MultiPart, SinglePart};
 use lettre::transport::smtp::authentication::Credentials;
 use lettre::{Message, SmtpTransport, Transport};
 
+#[derive(Clone)]
 pub struct SmtpService {
     pub host: String,
     pub port: u16,
@@ -17,7 +18,7 @@ impl SmtpService {
     pub fn new(host: String, port: u16, username: String, password: String, email_from: String) -> Result<Self, String> {
         let from = email_from.parse::<Mailbox>();
 
-        if let Err(_) = from {
+        if from.is_err() {
             return Err("Invalid email address".to_owned());
         }
 
@@ -73,8 +74,8 @@ impl MailService for SmtpService {
         self.send(
             email,
             "Your OTP",
-            &format!("Your OTP is: {}", otp.id),
-            &format!("Your OTP is: {}", otp.id),
+            &format!("Your OTP is: {}", otp.code),
+            &format!("Your OTP is: {}", otp.code),
         ).await
     }
 }
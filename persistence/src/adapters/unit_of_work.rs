@@ -0,0 +1,75 @@
+use crate::adapters::executor::SharedTransaction;
+use crate::adapters::map_sqlx_error;
+use crate::adapters::otp_repository::PgOtpRepository;
+use crate::adapters::session_repository::PgSessionRepository;
+use crate::adapters::user_repository::PgUserRepository;
+use async_trait::async_trait;
+use domain::repositories::unit_of_work::{Transaction, UnitOfWork};
+use domain::repositories::DbError;
+use sqlx::PgPool;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// `UnitOfWork` over a database transaction
+#[derive(Debug, Clone)]
+pub struct PgUnitOfWork {
+    pool: PgPool,
+}
+
+impl PgUnitOfWork {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+/// Repositories handed out share the transaction and run their queries one at a time. Once it
+/// is committed or rolled back they fail every query.
+#[derive(Debug)]
+pub struct PgTransaction {
+    transaction: SharedTransaction,
+}
+
+impl PgTransaction {
+    async fn take(&self) -> Result<sqlx::Transaction<'static, sqlx::Postgres>, DbError> {
+        self.transaction.lock().await.take()
+            .ok_or_else(|| DbError::InternalError("Transaction already committed or rolled back".to_owned()))
+    }
+}
+
+#[async_trait]
+impl UnitOfWork for PgUnitOfWork {
+    type Transaction = PgTransaction;
+
+    async fn begin(&self) -> Result<Self::Transaction, DbError> {
+        let transaction = self.pool.begin().await.map_err(map_sqlx_error)?;
+
+        Ok(PgTransaction { transaction: Arc::new(Mutex::new(Some(transaction))) })
+    }
+}
+
+#[async_trait]
+impl Transaction for PgTransaction {
+    type Users = PgUserRepository;
+    type Sessions = PgSessionRepository;
+    type Otps = PgOtpRepository;
+
+    fn users(&self) -> Self::Users {
+        PgUserRepository::in_transaction(self.transaction.clone())
+    }
+
+    fn sessions(&self) -> Self::Sessions {
+        PgSessionRepository::in_transaction(self.transaction.clone())
+    }
+
+    fn otps(&self) -> Self::Otps {
+        PgOtpRepository::in_transaction(self.transaction.clone())
+    }
+
+    async fn commit(self) -> Result<(), DbError> {
+        self.take().await?.commit().await.map_err(map_sqlx_error)
+    }
+
+    async fn rollback(self) -> Result<(), DbError> {
+        self.take().await?.rollback().await.map_err(map_sqlx_error)
+    }
+}
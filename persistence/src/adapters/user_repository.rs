@@ -0,0 +1,189 @@
+use crate::adapters::executor::{PgExecutor, SharedTransaction};
+use crate::adapters::map_sqlx_error;
+use crate::adapters::outbox_repository::insert_message;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use domain::models::outbox::OutboxMessage;
+use domain::models::user::User;
+use domain::repositories::user_repository::UserRepository;
+use domain::repositories::DbError;
+use sqlx::{Connection, PgPool};
+
+/// `UserRepository` backed by the `users` table
+#[derive(Debug, Clone)]
+pub struct PgUserRepository {
+    executor: PgExecutor,
+}
+
+impl PgUserRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { executor: PgExecutor::Pool(pool) }
+    }
+
+    /// Repository running its queries in the transaction of a unit of work
+    pub(crate) fn in_transaction(transaction: SharedTransaction) -> Self {
+        Self { executor: PgExecutor::Transaction(transaction) }
+    }
+
+    async fn insert(&self, user: &User) -> Result<User, DbError> {
+        let mut connection = self.executor.acquire().await?;
+
+        sqlx::query_as::<_, User>(
+            r#"
+            INSERT INTO users (username, login_attempts, register_complete, primary_email_id, register_date, last_update_date, last_login_date, locked_until)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            RETURNING *
+            "#,
+        )
+            .bind(&user.username)
+            .bind(user.login_attempts as i16)
+            .bind(user.register_complete)
+            .bind(user.primary_email_id)
+            .bind(user.register_date)
+            .bind(user.last_update_date)
+            .bind(user.last_login_date)
+            .bind(user.locked_until)
+            .fetch_one(&mut *connection)
+            .await
+            .map_err(map_sqlx_error)
+    }
+
+    async fn update(&self, user: &User) -> Result<User, DbError> {
+        let mut connection = self.executor.acquire().await?;
+
+        sqlx::query_as::<_, User>(
+            r#"
+            UPDATE users
+            SET username = $2,
+                login_attempts = $3,
+                register_complete = $4,
+                primary_email_id = $5,
+                last_update_date = $6,
+                last_login_date = $7,
+                locked_until = $8
+            WHERE id = $1
+            RETURNING *
+            "#,
+        )
+            .bind(user.id)
+            .bind(&user.username)
+            .bind(user.login_attempts as i16)
+            .bind(user.register_complete)
+            .bind(user.primary_email_id)
+            .bind(user.last_update_date)
+            .bind(user.last_login_date)
+            .bind(user.locked_until)
+            .fetch_optional(&mut *connection)
+            .await
+            .map_err(map_sqlx_error)?
+            .ok_or_else(|| DbError::NotFound(format!("User with id {}", user.id)))
+    }
+}
+
+#[async_trait]
+impl UserRepository for PgUserRepository {
+    async fn find_by_id(&self, id: i64) -> Result<Option<User>, DbError> {
+        let mut connection = self.executor.acquire().await?;
+
+        sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&mut *connection)
+            .await
+            .map_err(map_sqlx_error)
+    }
+
+    async fn find_by_login(&self, login: &str) -> Result<Option<User>, DbError> {
+        let mut connection = self.executor.acquire().await?;
+
+        sqlx::query_as::<_, User>("SELECT * FROM users WHERE username = $1")
+            .bind(login)
+            .fetch_optional(&mut *connection)
+            .await
+            .map_err(map_sqlx_error)
+    }
+
+    async fn save(&self, user: User) -> Result<User, DbError> {
+        if user.id < 0 {
+            self.insert(&user).await
+        } else {
+            self.update(&user).await
+        }
+    }
+
+    async fn record_failed_login(&self, id: i64, at: DateTime<Utc>, max_attempts: i8, lock_until: DateTime<Utc>) -> Result<Option<User>, DbError> {
+        let mut connection = self.executor.acquire().await?;
+
+        // The right-hand sides all see the row as it was before the update, and concurrent
+        // updates of the row wait for each other, so no failure is lost
+        sqlx::query_as::<_, User>(
+            r#"
+            UPDATE users
+            SET login_attempts = CASE
+                    WHEN locked_until > $2 THEN login_attempts
+                    WHEN login_attempts + 1 >= $3 THEN 0
+                    ELSE login_attempts + 1
+                END,
+                locked_until = CASE
+                    WHEN locked_until > $2 THEN locked_until
+                    WHEN login_attempts + 1 >= $3 THEN $4
+                    ELSE locked_until
+                END,
+                last_update_date = CASE WHEN locked_until > $2 THEN last_update_date ELSE $2 END
+            WHERE id = $1
+            RETURNING *
+            "#,
+        )
+            .bind(id)
+            .bind(at)
+            .bind(max_attempts as i16)
+            .bind(lock_until)
+            .fetch_optional(&mut *connection)
+            .await
+            .map_err(map_sqlx_error)
+    }
+
+    async fn record_login(&self, id: i64, at: DateTime<Utc>, first_login: Vec<OutboxMessage>) -> Result<Option<User>, DbError> {
+        // Inside a unit of work this is a savepoint, committed only with the unit of work
+        let mut connection = self.executor.acquire().await?;
+        let mut transaction = connection.begin().await.map_err(map_sqlx_error)?;
+
+        // Locking the row makes concurrent first sign-ins wait, so only one of them sees no
+        // earlier sign-in
+        let previous = sqlx::query_scalar::<_, Option<DateTime<Utc>>>("SELECT last_login_date FROM users WHERE id = $1 FOR UPDATE")
+            .bind(id)
+            .fetch_optional(&mut *transaction)
+            .await
+            .map_err(map_sqlx_error)?;
+
+        let Some(previous) = previous else {
+            return Ok(None);
+        };
+
+        let user = sqlx::query_as::<_, User>(
+            r#"
+            UPDATE users
+            SET login_attempts = 0,
+                locked_until = NULL,
+                last_login_date = $2,
+                last_update_date = $2
+            WHERE id = $1
+            RETURNING *
+            "#,
+        )
+            .bind(id)
+            .bind(at)
+            .fetch_one(&mut *transaction)
+            .await
+            .map_err(map_sqlx_error)?;
+
+        if previous.is_none() {
+            for message in &first_login {
+                insert_message(&mut transaction, message).await?;
+            }
+        }
+
+        transaction.commit().await.map_err(map_sqlx_error)?;
+
+        Ok(Some(user))
+    }
+}
@@ -0,0 +1,432 @@
+use chrono::{DateTime, Duration, Utc};
+use domain::models::outbox::{OutboxMessage, OutboxPayload, CODE_PLACEHOLDER};
+use domain::repositories::outbox_repository::OutboxRepository;
+use domain::repositories::DbError;
+use domain::services::event_publisher::EventPublisher;
+use domain::services::mail_service::MailService;
+use domain::services::otp_hasher::OtpHasher;
+use domain::views::otp_view::OtpView;
+use std::sync::Arc;
+use tokio::task::JoinHandle;
+
+/// How often and how far apart failed deliveries are retried. The delay doubles with every
+/// attempt, starting at `base_delay` and capped at `max_delay`.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Attempts after which a message is given up on
+    pub max_attempts: i32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 8,
+            base_delay: Duration::seconds(5),
+            max_delay: Duration::hours(1),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before the next attempt once `attempts` attempts have failed
+    pub fn delay(&self, attempts: i32) -> Duration {
+        let exponent = attempts.saturating_sub(1).clamp(0, 30) as u32;
+
+        self.base_delay.checked_mul(2i32.pow(exponent))
+            .map_or(self.max_delay, |delay| delay.min(self.max_delay))
+    }
+}
+
+/// Delivers the messages in the outbox: mails through the `MailService` and events through
+/// the `EventPublisher`. Delivered messages are deleted, failed ones retried with backoff.
+///
+/// Delivery is at least once. Messages are claimed one at a time and leased for their own
+/// delivery only, so several dispatchers never send the same message at the same time, however
+/// slow the messages before it were. A message is sent again if the process dies between sending
+/// and deleting it.
+pub struct OutboxDispatcher<OR, MS>
+where
+    OR: OutboxRepository,
+    MS: MailService + Send + Sync,
+{
+    outbox: OR,
+    mail_service: MS,
+    event_publisher: Arc<dyn EventPublisher>,
+    /// Opens the codes sealed into OTP mails
+    otp_hasher: OtpHasher,
+    retry_policy: RetryPolicy,
+    /// Messages delivered in one round, between two cleanups
+    batch_size: usize,
+    /// How long a claimed message is reserved for its delivery before others may claim it again
+    lease: Duration,
+    /// Wait between two polls when the outbox is drained
+    poll_interval: std::time::Duration,
+}
+
+impl<OR, MS> OutboxDispatcher<OR, MS>
+where
+    OR: OutboxRepository,
+    MS: MailService + Send + Sync,
+{
+    pub fn new(outbox: OR, mail_service: MS, event_publisher: Arc<dyn EventPublisher>) -> Self {
+        Self {
+            outbox,
+            mail_service,
+            event_publisher,
+            otp_hasher: OtpHasher::random(),
+            retry_policy: RetryPolicy::default(),
+            batch_size: 20,
+            lease: Duration::minutes(1),
+            poll_interval: std::time::Duration::from_secs(1),
+        }
+    }
+
+    /// Must be the hasher the codes were issued with, mails with sealed codes can't be opened
+    /// otherwise
+    pub fn with_otp_hasher(mut self, otp_hasher: OtpHasher) -> Self {
+        self.otp_hasher = otp_hasher;
+        self
+    }
+
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    pub fn with_poll_interval(mut self, poll_interval: std::time::Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Delivers up to one batch of due messages, returning how many were claimed
+    pub async fn dispatch_due(&self) -> Result<usize, DbError> {
+        let started = Utc::now();
+        let mut claimed = 0;
+
+        while claimed < self.batch_size {
+            let now = Utc::now();
+
+            // Only messages due when the pass started, so one that just failed is not retried in
+            // the same pass, each leased for `lease` from its own claim
+            let lease = self.lease + (now - started);
+
+            let Some(message) = self.outbox.claim_due(started, 1, lease).await?.pop() else {
+                break;
+            };
+
+            claimed += 1;
+
+            let result = match self.deliver(&message.payload).await {
+                Ok(()) => self.outbox.mark_delivered(message.id).await,
+                Err(err) => {
+                    eprintln!("Error delivering outbox message {} (attempt {}): {}", message.key, message.attempts, err);
+
+                    self.outbox.mark_failed(message.id, &err, self.retry_at(&message, now)).await
+                }
+            };
+
+            if let Err(err) = result {
+                eprintln!("Error updating outbox message {}: {}", message.key, err);
+            }
+        }
+
+        Ok(claimed)
+    }
+
+    /// Next attempt after `message` failed, `None` once it is out of attempts
+    fn retry_at(&self, message: &OutboxMessage, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        if message.attempts >= self.retry_policy.max_attempts {
+            return None;
+        }
+
+        Some(now + self.retry_policy.delay(message.attempts))
+    }
+
+    async fn deliver(&self, payload: &OutboxPayload) -> Result<(), String> {
+        match payload {
+            OutboxPayload::Mail { to, subject, html_body, plain_body } => self.mail_service
+                .send(to, subject, html_body, plain_body).await
+                .map_err(|e| e.to_string()),
+            OutboxPayload::OtpMail { to, user_id, sealed_code, expires_at } => {
+                let code = self.otp_hasher.open(*user_id, sealed_code)?;
+                let otp = OtpView { code, user_id: *user_id, expires_at: *expires_at };
+
+                self.mail_service.send_otp(to, otp).await.map_err(|e| e.to_string())
+            }
+            OutboxPayload::SealedMail { to, user_id, sealed_code, subject, html_body, plain_body } => {
+                let code = self.otp_hasher.open(*user_id, sealed_code)?;
+
+                self.mail_service
+                    .send(to, subject, &html_body.replace(CODE_PLACEHOLDER, &code), &plain_body.replace(CODE_PLACEHOLDER, &code))
+                    .await
+                    .map_err(|e| e.to_string())
+            }
+            OutboxPayload::Event(envelope) => {
+                self.event_publisher.publish(envelope.clone()).await;
+
+                Ok(())
+            }
+        }
+    }
+}
+
+impl<OR, MS> OutboxDispatcher<OR, MS>
+where
+    OR: OutboxRepository + 'static,
+    MS: MailService + Send + Sync + 'static,
+{
+    /// Keeps delivering messages in the background for as long as the runtime is running,
+    /// dropping expired ones along the way
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                if let Err(err) = self.outbox.cleanup(Utc::now()).await {
+                    eprintln!("Error cleaning up outbox: {}", err);
+                }
+
+                // A full batch means more may be waiting, so the next one is claimed right away
+                match self.dispatch_due().await {
+                    Ok(claimed) if claimed == self.batch_size => continue,
+                    Ok(_) => {}
+                    Err(err) => eprintln!("Error claiming outbox messages: {}", err),
+                }
+
+                tokio::time::sleep(self.poll_interval).await;
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use domain::models::event::{DomainEvent, EventEnvelope};
+    use domain::repositories::outbox_repository::InMemoryOutboxRepository;
+    use domain::services::event_publisher::InMemoryEventPublisher;
+    use domain::services::mail_service::EmailError;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::sync::RwLock;
+
+    /// Fails the first `failures` mails, then records the recipients and plain bodies of the
+    /// ones sent and the codes of the OTP mails among them
+    #[derive(Clone, Default)]
+    struct FlakyMailService {
+        failures: Arc<AtomicUsize>,
+        sent: Arc<RwLock<Vec<String>>>,
+        bodies: Arc<RwLock<Vec<String>>>,
+        codes: Arc<RwLock<Vec<String>>>,
+    }
+
+    impl FlakyMailService {
+        fn failing(failures: usize) -> Self {
+            Self { failures: Arc::new(AtomicUsize::new(failures)), ..Self::default() }
+        }
+
+        fn fail(&self) -> bool {
+            self.failures.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1)).is_ok()
+        }
+    }
+
+    #[async_trait]
+    impl MailService for FlakyMailService {
+        async fn send(&self, email: &str, _subject: &str, _html_body: &str, plain_body: &str) -> Result<(), EmailError> {
+            if self.fail() {
+                return Err(EmailError::InternalError("Connection refused".to_string()));
+            }
+
+            self.sent.write().await.push(email.to_string());
+            self.bodies.write().await.push(plain_body.to_string());
+
+            Ok(())
+        }
+
+        async fn send_otp(&self, email: &str, otp: OtpView) -> Result<(), EmailError> {
+            self.send(email, "Your OTP", &otp.code, &otp.code).await?;
+            self.codes.write().await.push(otp.code);
+
+            Ok(())
+        }
+    }
+
+    /// Records how many messages in the outbox are claimed whenever a mail is sent
+    #[derive(Clone)]
+    struct ClaimCountingMailService {
+        outbox: InMemoryOutboxRepository,
+        claimed: Arc<RwLock<Vec<usize>>>,
+    }
+
+    #[async_trait]
+    impl MailService for ClaimCountingMailService {
+        async fn send(&self, _email: &str, _subject: &str, _html_body: &str, _plain_body: &str) -> Result<(), EmailError> {
+            let claimed = self.outbox.messages().await.iter().filter(|message| message.attempts > 0).count();
+            self.claimed.write().await.push(claimed);
+
+            Ok(())
+        }
+
+        async fn send_otp(&self, email: &str, otp: OtpView) -> Result<(), EmailError> {
+            self.send(email, "Your OTP", &otp.code, &otp.code).await
+        }
+    }
+
+    fn otp_mail(key: &str, otp_hasher: &OtpHasher) -> OutboxMessage {
+        OutboxMessage::new(key.to_string(), OutboxPayload::OtpMail {
+            to: "alice@example.com".to_string(),
+            user_id: 1,
+            sealed_code: otp_hasher.seal(1, "12345678"),
+            expires_at: Utc::now() + Duration::minutes(5),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_due_delivers_mails_and_events() {
+        // Given
+        let outbox = InMemoryOutboxRepository::new();
+        let mail_service = FlakyMailService::default();
+        let events = InMemoryEventPublisher::new();
+        let otp_hasher = OtpHasher::new(b"secret");
+        outbox.enqueue(otp_mail("a", &otp_hasher)).await.unwrap();
+        outbox.enqueue(OutboxMessage::new(
+            "b".to_string(),
+            OutboxPayload::Event(EventEnvelope::new(DomainEvent::OtpIssued { user_id: 1 })),
+        )).await.unwrap();
+        let dispatcher = OutboxDispatcher::new(outbox.clone(), mail_service.clone(), Arc::new(events.clone()))
+            .with_otp_hasher(otp_hasher);
+
+        // When
+        let claimed = dispatcher.dispatch_due().await.unwrap();
+
+        // Then
+        assert_eq!(claimed, 2);
+        assert_eq!(*mail_service.sent.read().await, vec!["alice@example.com"]);
+        assert_eq!(*mail_service.codes.read().await, vec!["12345678"]);
+        assert_eq!(events.events().await, vec![DomainEvent::OtpIssued { user_id: 1 }]);
+        assert!(outbox.messages().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_due_opens_sealed_mail() {
+        // Given
+        let outbox = InMemoryOutboxRepository::new();
+        let mail_service = FlakyMailService::default();
+        let otp_hasher = OtpHasher::new(b"secret");
+        outbox.enqueue(OutboxMessage::new("a".to_string(), OutboxPayload::SealedMail {
+            to: "alice@example.com".to_string(),
+            user_id: 1,
+            sealed_code: otp_hasher.seal(1, "abcdef"),
+            subject: "Confirm".to_string(),
+            html_body: format!("<b>{}</b>", CODE_PLACEHOLDER),
+            plain_body: format!("/verify?code={}", CODE_PLACEHOLDER),
+        })).await.unwrap();
+        let dispatcher = OutboxDispatcher::new(outbox.clone(), mail_service.clone(), Arc::new(InMemoryEventPublisher::new()))
+            .with_otp_hasher(otp_hasher);
+
+        // When
+        dispatcher.dispatch_due().await.unwrap();
+
+        // Then
+        assert_eq!(*mail_service.sent.read().await, vec!["alice@example.com"]);
+        assert_eq!(*mail_service.bodies.read().await, vec!["/verify?code=abcdef"]);
+        assert!(outbox.messages().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_due_leases_one_message_at_a_time() {
+        // Given
+        let outbox = InMemoryOutboxRepository::new();
+        let otp_hasher = OtpHasher::new(b"secret");
+        outbox.enqueue(otp_mail("a", &otp_hasher)).await.unwrap();
+        outbox.enqueue(otp_mail("b", &otp_hasher)).await.unwrap();
+        let mail_service = ClaimCountingMailService { outbox: outbox.clone(), claimed: Arc::new(RwLock::new(Vec::new())) };
+        let dispatcher = OutboxDispatcher::new(outbox.clone(), mail_service.clone(), Arc::new(InMemoryEventPublisher::new()))
+            .with_otp_hasher(otp_hasher);
+
+        // When
+        let claimed = dispatcher.dispatch_due().await.unwrap();
+
+        // Then
+        assert_eq!(claimed, 2);
+        assert_eq!(*mail_service.claimed.read().await, vec![1, 1]);
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_due_retries_with_backoff() {
+        // Given
+        let outbox = InMemoryOutboxRepository::new();
+        let mail_service = FlakyMailService::failing(1);
+        let otp_hasher = OtpHasher::new(b"secret");
+        outbox.enqueue(otp_mail("a", &otp_hasher)).await.unwrap();
+        let dispatcher = OutboxDispatcher::new(outbox.clone(), mail_service.clone(), Arc::new(InMemoryEventPublisher::new()))
+            .with_otp_hasher(otp_hasher);
+        let before = Utc::now();
+
+        // When
+        dispatcher.dispatch_due().await.unwrap();
+
+        // Then
+        let messages = outbox.messages().await;
+        assert_eq!(messages[0].attempts, 1);
+        assert!(messages[0].next_attempt_at >= before + RetryPolicy::default().base_delay);
+        assert_eq!(outbox.last_error(messages[0].id).await, Some("Internal error: Connection refused".to_string()));
+        assert!(mail_service.sent.read().await.is_empty());
+        assert_eq!(dispatcher.dispatch_due().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_due_gives_up_after_max_attempts() {
+        // Given
+        let outbox = InMemoryOutboxRepository::new();
+        let mail_service = FlakyMailService::failing(2);
+        let otp_hasher = OtpHasher::new(b"secret");
+        outbox.enqueue(otp_mail("a", &otp_hasher)).await.unwrap();
+        let policy = RetryPolicy { max_attempts: 2, base_delay: Duration::zero(), ..RetryPolicy::default() };
+        let dispatcher = OutboxDispatcher::new(outbox.clone(), mail_service.clone(), Arc::new(InMemoryEventPublisher::new()))
+            .with_otp_hasher(otp_hasher)
+            .with_retry_policy(policy);
+
+        // When
+        let first = dispatcher.dispatch_due().await.unwrap();
+        let second = dispatcher.dispatch_due().await.unwrap();
+        let third = dispatcher.dispatch_due().await.unwrap();
+
+        // Then
+        assert_eq!((first, second, third), (1, 1, 0));
+        assert_eq!(outbox.messages().await[0].attempts, 2);
+        assert!(mail_service.sent.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_due_keeps_mail_sealed_with_other_secret() {
+        // Given
+        let outbox = InMemoryOutboxRepository::new();
+        let mail_service = FlakyMailService::default();
+        outbox.enqueue(otp_mail("a", &OtpHasher::new(b"secret"))).await.unwrap();
+        let dispatcher = OutboxDispatcher::new(outbox.clone(), mail_service.clone(), Arc::new(InMemoryEventPublisher::new()))
+            .with_otp_hasher(OtpHasher::new(b"other"));
+
+        // When
+        dispatcher.dispatch_due().await.unwrap();
+
+        // Then
+        let messages = outbox.messages().await;
+        assert_eq!(messages[0].attempts, 1);
+        assert_eq!(outbox.last_error(messages[0].id).await, Some("Sealed code could not be opened".to_string()));
+        assert!(mail_service.sent.read().await.is_empty());
+    }
+
+    #[test]
+    fn test_retry_policy_delay() {
+        // Given
+        let policy = RetryPolicy { max_attempts: 8, base_delay: Duration::seconds(5), max_delay: Duration::seconds(60) };
+
+        // Then
+        assert_eq!(policy.delay(1), Duration::seconds(5));
+        assert_eq!(policy.delay(2), Duration::seconds(10));
+        assert_eq!(policy.delay(4), Duration::seconds(40));
+        assert_eq!(policy.delay(5), Duration::seconds(60));
+        assert_eq!(policy.delay(100), Duration::seconds(60));
+    }
+}
@@ -1,110 +1,129 @@
-use application::command::user::login_user::LoginUserCommand;
-use application::shared::error::AppStatus;
+use application::config::AppConfig;
 use application::AppContainer;
 use domain::repositories::id_provider::SimpleIdProvider;
-use domain::repositories::otp_repository::InMemoryOtpRepository;
-use domain::repositories::session_repository::InMemorySessionRepository;
-use domain::repositories::user_repository::InMemoryUserRepository;
-use domain::services::mail_service::InMemoryMailService;
-use sqlx::PgPool;
+use domain::repositories::otp_repository::OtpRepository;
+use domain::repositories::session_repository::SessionRepository;
+use domain::services::user_service::{LoginPolicy, SessionPolicy};
+use persistence::adapters::avatar_repository::PgAvatarRepository;
+use persistence::adapters::email_repository::PgEmailRepository;
+use persistence::adapters::local_blob_store::LocalBlobStore;
+use persistence::adapters::otp_repository::PgOtpRepository;
+use persistence::adapters::outbox_repository::PgOutboxRepository;
+use persistence::adapters::session_repository::PgSessionRepository;
+use persistence::adapters::smtp::SmtpService;
+use persistence::adapters::unit_of_work::PgUnitOfWork;
+use persistence::adapters::user_repository::PgUserRepository;
+use persistence::outbox_dispatcher::OutboxDispatcher;
 use std::sync::Arc;
-use tokio::io;
-use tokio::io::{stdin, AsyncBufReadExt, BufReader};
-use web::Server;
-
-async fn test_db(pool: &PgPool) -> Result<(), sqlx::Error> {
-    for i in 10..100 {
-        let name = format!("Alice{i}");
-        sqlx::query(r#"
-            INSERT INTO persons (name, age)
-            VALUES ($1, $2)
-        "#)
-            .bind(&name)
-            .bind(30)
-            .execute(pool)
-            .await?;
-
-        println!("Inserted person with name: {}", name);
-    }
-
-    let people = sqlx::query_as::<_, (String, i64)>(r#"
-        SELECT name, age
-        FROM persons
-    "#)
-        .fetch_all(pool)
-        .await?;
-
-    for (name, age) in people {
-        println!("Name: {}, Age: {}", name, age);
-    }
+use std::time::Duration;
+use web::{CookiePolicy, Server};
 
-    Ok(())
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Periodically removes expired sessions and OTPs for as long as the server is running
+fn spawn_expiry_cleanup(
+    session_repository: impl SessionRepository + 'static,
+    otp_repository: impl OtpRepository + Send + 'static,
+) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(CLEANUP_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            if let Err(err) = session_repository.cleanup().await {
+                eprintln!("Error cleaning up sessions: {}", err);
+            }
+
+            if let Err(err) = otp_repository.cleanup().await {
+                eprintln!("Error cleaning up OTPs: {}", err);
+            }
+        }
+    });
 }
 
-async fn read_input() -> io::Result<String> {
-    let mut input = String::new();
-    let stdin = stdin();
-    let mut reader = BufReader::new(stdin);
+/// Reads a number of seconds from the environment, keeping `default` when unset or invalid
+fn env_seconds(name: &str, default: chrono::Duration) -> chrono::Duration {
+    std::env::var(name).ok()
+        .and_then(|value| value.parse::<i64>().ok())
+        .map(chrono::Duration::seconds)
+        .unwrap_or(default)
+}
 
-    reader.read_line(&mut input).await?;
-    Ok(input.trim().to_string())
+/// Mail server configured by `SMTP_HOST`, `SMTP_PORT`, `SMTP_USERNAME`, `SMTP_PASSWORD` and
+/// `EMAIL_FROM`. The port defaults to 587.
+fn smtp_from_env() -> Result<SmtpService, String> {
+    let var = |name: &str| std::env::var(name).map_err(|_| format!("{} must be set", name));
+    let port = match std::env::var("SMTP_PORT") {
+        Ok(port) => port.parse::<u16>().map_err(|_| "SMTP_PORT must be a port number".to_owned())?,
+        Err(_) => 587,
+    };
+
+    SmtpService::new(var("SMTP_HOST")?, port, var("SMTP_USERNAME")?, var("SMTP_PASSWORD")?, var("EMAIL_FROM")?)
+        .map_err(|err| format!("EMAIL_FROM: {}", err))
 }
 
 #[tokio::main]
 async fn main() -> Result<(), ()> {
-    // let pool = setup_database().await.expect("Failed to initialize database pool");
-    //
-    // if let Err(err) = test_db(&pool).await {
-    //     eprintln!("Error running test_db: {}", err);
-    // }
-
-    // let pool = persistence::init_db().await.expect("Failed to initialize database");
-
-    let command = LoginUserCommand::new("test".to_owned(), None);
+    let pool = persistence::init_db().await.expect("Failed to initialize database");
 
-    let container = Arc::new(AppContainer::new(
-        InMemoryUserRepository::new(),
-        InMemorySessionRepository::new(),
-        InMemoryOtpRepository::new(),
-        SimpleIdProvider::new(),
-        InMemoryMailService::new(),
-    ));
+    spawn_expiry_cleanup(PgSessionRepository::new(pool.clone()), PgOtpRepository::new(pool.clone()));
 
-    let server = Server::new(3000, container.clone());
+    let session_policy = SessionPolicy::default();
+    let session_policy = SessionPolicy {
+        absolute_lifetime: env_seconds("SESSION_ABSOLUTE_LIFETIME", session_policy.absolute_lifetime),
+        idle_timeout: env_seconds("SESSION_IDLE_TIMEOUT", session_policy.idle_timeout),
+        ..session_policy
+    };
 
-    server.run().await;
+    let login_policy = LoginPolicy::default();
+    let login_policy = LoginPolicy {
+        otp_lifetime: env_seconds("OTP_LIFETIME", login_policy.otp_lifetime),
+        ..login_policy
+    };
 
-    match container.send_command(command).await {
-        Ok(u) => println!("User: {:?}", u),
+    let mail_service = match smtp_from_env() {
+        Ok(mail_service) => mail_service,
         Err(err) => {
-            match err {
-                AppStatus::Ok(msg) => {
-                    println!("Success: {}", msg);
-                }
-                _ => {
-                    eprintln!("Error: {}", err);
-                }
-            }
+            eprintln!("Invalid mail configuration: {}", err);
+            return Err(());
         }
-    }
+    };
+
+    let config = AppConfig {
+        session_policy,
+        login_policy,
+        otp_secret: std::env::var("OTP_SECRET").ok(),
+        public_url: std::env::var("PUBLIC_URL").ok(),
+    };
+
+    let container = Arc::new(AppContainer::with_config(
+        PgUserRepository::new(pool.clone()),
+        PgSessionRepository::new(pool.clone()),
+        PgOtpRepository::new(pool.clone()),
+        SimpleIdProvider::new(),
+        PgAvatarRepository::new(pool.clone()),
+        LocalBlobStore::new(std::env::var("AVATAR_STORAGE_DIR").unwrap_or_else(|_| "data/avatars".to_owned())),
+        PgEmailRepository::new(pool.clone()),
+        PgUnitOfWork::new(pool.clone()),
+        config,
+    ));
 
-    let input = read_input().await.unwrap();
+    // Delivers the mails and events written to the outbox along with the changes that caused them
+    OutboxDispatcher::new(PgOutboxRepository::new(pool.clone()), mail_service, container.event_bus())
+        .with_otp_hasher(container.otp_hasher())
+        .spawn();
 
-    println!("You entered: {}", input.clone());
+    let cookie_policy = CookiePolicy::default();
+    let cookie_policy = CookiePolicy {
+        session_name: std::env::var("SESSION_COOKIE_NAME").unwrap_or(cookie_policy.session_name),
+        domain: std::env::var("COOKIE_DOMAIN").ok(),
+        secure: std::env::var("COOKIE_SECURE").map(|v| v != "false").unwrap_or(cookie_policy.secure),
+    };
 
-    match container.send_command(LoginUserCommand::new("test".to_owned(), Some(input))).await {
-        Ok(u) => println!("User: {:?}", u),
-        Err(err) => {
-            match err {
-                AppStatus::Ok(msg) => {
-                    println!("Success: {}", msg);
-                }
-                _ => {
-                    eprintln!("Error: {}", err);
-                }
-            }
-        }
-    }
+    let server = Server::new(3000, container).with_cookie_policy(cookie_policy);
+
+    server.run().await;
 
     Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,65 @@
+use application::query::avatar::get_avatar::{AvatarResponse, GetAvatarQuery};
+use application::AppContainer;
+use crate::error::AppError;
+use axum::extract::{Path, Query, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use serde::Deserialize;
+use std::sync::Arc;
+
+/// Query parameters of the Gravatar-compatible endpoint. Short and long names are both
+/// accepted.
+#[derive(Deserialize)]
+pub struct AvatarQuery {
+    #[serde(alias = "size")]
+    s: Option<String>,
+    #[serde(alias = "default")]
+    d: Option<String>,
+    #[serde(alias = "rating")]
+    r: Option<String>,
+}
+
+/// Content type asked for by an extension on the hash, which takes precedence over `Accept`
+fn extension_content_type(extension: &str) -> Option<&'static str> {
+    match extension.to_ascii_lowercase().as_str() {
+        "png" => Some("image/png"),
+        "jpg" | "jpeg" => Some("image/jpeg"),
+        "webp" => Some("image/webp"),
+        "svg" => Some("image/svg+xml"),
+        _ => None,
+    }
+}
+
+/// `GET /avatar/{hash}` where `hash` is the MD5 or SHA-256 of the trimmed, lower-cased email
+/// address, optionally followed by an image extension such as `.png`
+pub(crate) async fn avatar_get(
+    State(container): State<Arc<AppContainer>>,
+    Path(hash): Path<String>,
+    Query(query): Query<AvatarQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let (hash, extension) = hash.split_once('.').unwrap_or((&hash, ""));
+    let size = query.s.and_then(|s| s.parse::<u32>().ok());
+
+    let accept = match extension_content_type(extension) {
+        Some(content_type) => Some(content_type.to_owned()),
+        None => headers.get(header::ACCEPT).and_then(|v| v.to_str().ok()).map(str::to_owned),
+    };
+
+    let lookup = GetAvatarQuery::new(hash.to_owned(), size, query.d)
+        .with_accept(accept)
+        .with_rating(query.r);
+
+    match container.query(lookup).await {
+        Ok(AvatarResponse::Image(image)) => (
+            [
+                (header::CONTENT_TYPE, image.content_type),
+                (header::CACHE_CONTROL, "public, max-age=300".to_owned()),
+                (header::VARY, "Accept".to_owned()),
+            ],
+            image.data,
+        ).into_response(),
+        Ok(AvatarResponse::Redirect(url)) => (StatusCode::FOUND, [(header::LOCATION, url)]).into_response(),
+        Err(err) => AppError(err).into_response(),
+    }
+}
@@ -0,0 +1,241 @@
+use application::command::avatar::upload_avatar::UploadAvatarCommand;
+use application::shared::error::AppStatus;
+use application::AppContainer;
+use axum::extract::{Multipart, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Redirect, Response};
+use crate::cookie_layer::CurrentUser;
+use crate::csrf_layer::{self, CSRF_FIELD, MAX_FORM_SIZE};
+use crate::error::AppError;
+use domain::models::avatar::MAX_AVATAR_SIZE;
+use std::sync::Arc;
+
+/// Largest upload accepted, an avatar with room for the other fields
+pub(crate) const MAX_UPLOAD_SIZE: usize = MAX_AVATAR_SIZE + MAX_FORM_SIZE;
+
+/// `POST /avatars` adds an image to the avatar library of the signed in user. Takes
+/// `multipart/form-data` with the image in the `file` part and optionally its `rating`, then
+/// goes to the email page where avatars are assigned.
+///
+/// Mounted outside `csrf_layer::protect`, so the CSRF token is checked here once the session
+/// is known. It is taken from the `X-CSRF-Token` header or a `csrf_token` part, which has to
+/// come before the file.
+pub(crate) async fn avatars_post(
+    State(container): State<Arc<AppContainer>>,
+    CurrentUser(user): CurrentUser,
+    headers: HeaderMap,
+    mut multipart: Multipart,
+) -> Response {
+    if csrf_layer::exceeds_announced(&headers, MAX_UPLOAD_SIZE) {
+        return StatusCode::PAYLOAD_TOO_LARGE.into_response();
+    }
+
+    let mut verified = csrf_layer::header_matches(&headers);
+    let mut file = None;
+    let mut rating = None;
+
+    loop {
+        let field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(err) => return err.into_response(),
+        };
+
+        match field.name() {
+            Some(CSRF_FIELD) => match field.text().await {
+                Ok(token) => verified |= csrf_layer::token_matches(&headers, &token),
+                Err(err) => return err.into_response(),
+            },
+            Some("file") => {
+                if !verified {
+                    return csrf_layer::forbidden();
+                }
+
+                let content_type = field.content_type().unwrap_or_default().to_owned();
+
+                match field.bytes().await {
+                    Ok(data) => file = Some((content_type, data.to_vec())),
+                    Err(err) => return err.into_response(),
+                }
+            }
+            Some("rating") => match field.text().await {
+                Ok(value) => rating = Some(value),
+                Err(err) => return err.into_response(),
+            },
+            _ => {}
+        }
+    }
+
+    if !verified {
+        return csrf_layer::forbidden();
+    }
+
+    let Some((content_type, data)) = file else {
+        return AppError(AppStatus::BadRequest("No image uploaded".to_owned())).into_response();
+    };
+
+    let mut command = UploadAvatarCommand::new(user.id, content_type, data);
+
+    if let Some(rating) = rating.filter(|rating| !rating.is_empty()) {
+        command = command.with_rating(rating);
+    }
+
+    match container.send_command(command).await {
+        Ok(_) => Redirect::to("/emails").into_response(),
+        Err(err) => AppError(err).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MAX_UPLOAD_SIZE;
+    use crate::test_support::{cookies, TestApp, CSRF_TOKEN};
+    use axum::body::Body;
+    use axum::http::{header, Request, StatusCode};
+    use domain::models::avatar::Rating;
+    use domain::repositories::avatar_repository::AvatarRepository;
+    use domain::services::default_avatar;
+    use tower::ServiceExt;
+
+    const BOUNDARY: &str = "test-boundary";
+
+    /// Multipart upload of `data` as `image/png` along with the given text parts
+    fn upload_request(session: Option<&str>, fields: &[(&str, &str)], data: &[u8]) -> Request<Body> {
+        let mut body = Vec::new();
+
+        for (name, value) in fields {
+            body.extend_from_slice(format!("--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n", BOUNDARY, name, value).as_bytes());
+        }
+
+        body.extend_from_slice(format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"avatar.png\"\r\nContent-Type: image/png\r\n\r\n",
+            BOUNDARY,
+        ).as_bytes());
+        body.extend_from_slice(data);
+        body.extend_from_slice(format!("\r\n--{}--\r\n", BOUNDARY).as_bytes());
+
+        Request::post("/avatars")
+            .header(header::COOKIE, cookies(session))
+            .header(header::CONTENT_TYPE, format!("multipart/form-data; boundary={}", BOUNDARY))
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_upload() {
+        // Given
+        let app = TestApp::new();
+        let session = app.sign_in("alice").await;
+        let request = upload_request(Some(&session), &[("csrf_token", CSRF_TOKEN), ("rating", "pg")], &default_avatar::blank().data);
+
+        // When
+        let response = app.router.clone().oneshot(request).await.unwrap();
+
+        // Then
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(response.headers().get(header::LOCATION).unwrap(), "/emails");
+        let avatars = app.avatars.find_by_user(app.user_id(&session).await).await.unwrap();
+        assert_eq!(avatars.len(), 1);
+        assert_eq!(avatars[0].rating, Rating::PG);
+    }
+
+    #[tokio::test]
+    async fn test_upload_with_csrf_header() {
+        // Given
+        let app = TestApp::new();
+        let session = app.sign_in("alice").await;
+        let mut request = upload_request(Some(&session), &[], &default_avatar::blank().data);
+        request.headers_mut().insert("X-CSRF-Token", CSRF_TOKEN.parse().unwrap());
+
+        // When
+        let response = app.router.clone().oneshot(request).await.unwrap();
+
+        // Then
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(app.avatars.find_by_user(app.user_id(&session).await).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_upload_without_csrf_token_is_forbidden() {
+        // Given
+        let app = TestApp::new();
+        let session = app.sign_in("alice").await;
+        let request = upload_request(Some(&session), &[("csrf_token", "wrong")], &default_avatar::blank().data);
+
+        // When
+        let response = app.router.clone().oneshot(request).await.unwrap();
+
+        // Then
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(app.avatars.find_by_user(app.user_id(&session).await).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_upload_requires_session() {
+        // Given
+        let app = TestApp::new();
+        let request = upload_request(None, &[("csrf_token", CSRF_TOKEN)], &default_avatar::blank().data);
+
+        // When
+        let response = app.router.clone().oneshot(request).await.unwrap();
+
+        // Then
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_upload_rejects_announced_size_before_reading() {
+        // Given
+        let app = TestApp::new();
+        let session = app.sign_in("alice").await;
+        let mut request = upload_request(Some(&session), &[("csrf_token", CSRF_TOKEN)], &default_avatar::blank().data);
+        request.headers_mut().insert(header::CONTENT_LENGTH, (MAX_UPLOAD_SIZE + 1).into());
+
+        // When
+        let response = app.router.clone().oneshot(request).await.unwrap();
+
+        // Then
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn test_upload_undecodable_image() {
+        // Given
+        let app = TestApp::new();
+        let session = app.sign_in("alice").await;
+        let mut data = default_avatar::blank().data;
+        data.truncate(16);
+        let request = upload_request(Some(&session), &[("csrf_token", CSRF_TOKEN)], &data);
+
+        // When
+        let response = app.router.clone().oneshot(request).await.unwrap();
+
+        // Then
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(app.avatars.find_by_user(app.user_id(&session).await).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_upload_with_csrf_token_after_file_is_forbidden() {
+        // Given
+        let app = TestApp::new();
+        let session = app.sign_in("alice").await;
+        let body = format!(
+            "--{0}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"avatar.png\"\r\nContent-Type: image/png\r\n\r\nimage\r\n\
+             --{0}\r\nContent-Disposition: form-data; name=\"csrf_token\"\r\n\r\n{1}\r\n--{0}--\r\n",
+            BOUNDARY, CSRF_TOKEN,
+        );
+        let request = Request::post("/avatars")
+            .header(header::COOKIE, cookies(Some(&session)))
+            .header(header::CONTENT_TYPE, format!("multipart/form-data; boundary={}", BOUNDARY))
+            .body(Body::from(body))
+            .unwrap();
+
+        // When
+        let response = app.router.clone().oneshot(request).await.unwrap();
+
+        // Then
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(app.avatars.find_by_user(app.user_id(&session).await).await.unwrap().is_empty());
+    }
+}
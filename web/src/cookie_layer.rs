@@ -0,0 +1,295 @@
+use application::command::user::authenticate_session::AuthenticateSessionCommand;
+use application::AppContainer;
+use axum::async_trait;
+use axum::extract::{FromRequestParts, Request, State};
+use axum::http::request::Parts;
+use axum::http::{header, HeaderMap, HeaderValue, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Redirect, Response};
+use domain::views::user_view::UserView;
+use std::sync::Arc;
+
+/// Attributes of the cookies the server sets. `Path=/`, `HttpOnly` and `SameSite=Lax` are
+/// always added.
+#[derive(Debug, Clone)]
+pub struct CookiePolicy {
+    /// Name of the cookie holding the session value
+    pub session_name: String,
+    /// `Domain` attribute, the cookies are host-only when not set
+    pub domain: Option<String>,
+    /// Whether to send the cookies over HTTPS only. Browsers treat `localhost` as secure, so
+    /// this only has to be turned off when testing over plain HTTP on another host.
+    pub secure: bool,
+}
+
+impl Default for CookiePolicy {
+    fn default() -> Self {
+        Self {
+            session_name: "sessionId".to_owned(),
+            domain: None,
+            secure: true,
+        }
+    }
+}
+
+impl CookiePolicy {
+    /// `Set-Cookie` value for cookie `name`, kept by the browser for `max_age` seconds or until
+    /// it is closed when `None`
+    pub(crate) fn cookie(&self, name: &str, value: &str, max_age: Option<i64>) -> String {
+        let mut cookie = format!("{}={}; Path=/; HttpOnly; SameSite=Lax", name, value);
+
+        if let Some(max_age) = max_age {
+            cookie.push_str(&format!("; Max-Age={}", max_age));
+        }
+
+        if let Some(domain) = &self.domain {
+            cookie.push_str(&format!("; Domain={}", domain));
+        }
+
+        if self.secure {
+            cookie.push_str("; Secure");
+        }
+
+        cookie
+    }
+
+    /// `Set-Cookie` value handing the browser a session that expires in `max_age` seconds
+    pub(crate) fn session_cookie(&self, value: &str, max_age: i64) -> String {
+        self.cookie(&self.session_name, value, Some(max_age))
+    }
+
+    /// `Set-Cookie` value that makes the browser drop the session cookie
+    pub(crate) fn expired_session_cookie(&self) -> String {
+        self.cookie(&self.session_name, "", Some(0))
+    }
+
+    /// Returns the value of the session cookie, if the request carries one
+    pub(crate) fn read_session<'a>(&self, headers: &'a HeaderMap) -> Option<&'a str> {
+        read_cookie(headers, &self.session_name)
+    }
+
+    /// Whether `response` already sets the session cookie
+    fn sets_session(&self, response: &Response) -> bool {
+        let prefix = format!("{}=", self.session_name);
+
+        response.headers().get_all(header::SET_COOKIE).iter()
+            .filter_map(|cookie| cookie.to_str().ok())
+            .any(|cookie| cookie.starts_with(&prefix))
+    }
+}
+
+/// Returns the value of cookie `name`, if the request carries it
+pub(crate) fn read_cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get_all(header::COOKIE).iter()
+        .filter_map(|cookie| cookie.to_str().ok())
+        .flat_map(|cookie| cookie.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(cookie_name, _)| *cookie_name == name)
+        .map(|(_, value)| value)
+        .filter(|value| !value.is_empty())
+}
+
+/// User signed in through the session cookie. Put into the request by `require_session`, so
+/// only routes behind that layer can extract it.
+#[derive(Debug, Clone)]
+pub struct CurrentUser(pub UserView);
+
+/// Value of the session cookie the request was authenticated with, put next to `CurrentUser`
+#[derive(Debug, Clone)]
+pub struct CurrentSession(pub String);
+
+/// Middleware for protected routes. Loads the session named by the cookie and hands the
+/// signed in user to the handler, rejecting requests without a valid, unexpired session.
+///
+/// The cookie is sent again with every response, so that its `Max-Age` follows the session
+/// as it gets extended. Responses setting the cookie themselves are left alone.
+pub(crate) async fn require_session(
+    State(container): State<Arc<AppContainer>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let policy = cookie_policy(&request);
+
+    let session = match policy.read_session(request.headers()) {
+        Some(value) => container.send_command(AuthenticateSessionCommand::new(value.to_owned())).await.ok(),
+        None => None,
+    };
+
+    let Some(session) = session else {
+        return unauthenticated(&request);
+    };
+
+    request.extensions_mut().insert(CurrentUser(session.user));
+    request.extensions_mut().insert(CurrentSession(session.value.clone()));
+
+    let mut response = next.run(request).await;
+
+    if !policy.sets_session(&response) {
+        if let Ok(cookie) = HeaderValue::from_str(&policy.session_cookie(&session.value, session.max_age)) {
+            response.headers_mut().append(header::SET_COOKIE, cookie);
+        }
+    }
+
+    response
+}
+
+/// Policy put into the request by the router, the default one if it is missing
+pub(crate) fn cookie_policy(request: &Request) -> Arc<CookiePolicy> {
+    request.extensions().get::<Arc<CookiePolicy>>().cloned().unwrap_or_default()
+}
+
+/// Browsers navigating to a page are sent to the login form. HTMX requests get a 401 with an
+/// `HX-Redirect` so the whole page is replaced, anything else a plain 401.
+fn unauthenticated(request: &Request) -> Response {
+    if request.headers().contains_key("HX-Request") {
+        return (StatusCode::UNAUTHORIZED, [("HX-Redirect", "/login")]).into_response();
+    }
+
+    if request.method() == Method::GET {
+        return Redirect::to("/login").into_response();
+    }
+
+    StatusCode::UNAUTHORIZED.into_response()
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for CurrentUser
+where
+    S: Send + Sync,
+{
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts.extensions.get::<CurrentUser>().cloned().ok_or(StatusCode::UNAUTHORIZED)
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for CurrentSession
+where
+    S: Send + Sync,
+{
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts.extensions.get::<CurrentSession>().cloned().ok_or(StatusCode::UNAUTHORIZED)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{body_string, cookies, TestApp, CSRF_TOKEN};
+    use axum::body::Body;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_session_cookie_attributes() {
+        // Given
+        let policy = CookiePolicy { domain: Some("example.com".to_owned()), ..CookiePolicy::default() };
+        let app = TestApp::with_cookie_policy(policy);
+        let session = app.sign_in("alice").await;
+        let request = Request::get("/profile")
+            .header(header::COOKIE, cookies(Some(&session)))
+            .body(Body::empty())
+            .unwrap();
+
+        // When
+        let response = app.router.oneshot(request).await.unwrap();
+
+        // Then
+        let cookie = response.headers().get_all(header::SET_COOKIE).iter()
+            .filter_map(|cookie| cookie.to_str().ok())
+            .find(|cookie| cookie.starts_with("sessionId="))
+            .unwrap();
+        assert!(cookie.starts_with(&format!("sessionId={};", session)));
+        assert!(cookie.contains("; HttpOnly"));
+        assert!(cookie.contains("; Secure"));
+        assert!(cookie.contains("; SameSite=Lax"));
+        assert!(cookie.contains("; Max-Age="));
+        assert!(cookie.contains("; Domain=example.com"));
+    }
+
+    #[tokio::test]
+    async fn test_get_without_session_redirects_to_login() {
+        // Given
+        let app = TestApp::new();
+        let request = Request::get("/profile").body(Body::empty()).unwrap();
+
+        // When
+        let response = app.router.oneshot(request).await.unwrap();
+
+        // Then
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(response.headers().get(header::LOCATION).unwrap(), "/login");
+    }
+
+    #[tokio::test]
+    async fn test_htmx_request_without_session_gets_redirect_header() {
+        // Given
+        let app = TestApp::new();
+        let request = Request::get("/profile")
+            .header("HX-Request", "true")
+            .body(Body::empty())
+            .unwrap();
+
+        // When
+        let response = app.router.oneshot(request).await.unwrap();
+
+        // Then
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers().get("HX-Redirect").unwrap(), "/login");
+    }
+
+    #[tokio::test]
+    async fn test_post_without_session_is_unauthorized() {
+        // Given
+        let app = TestApp::new();
+        let request = Request::post("/devices/revoke")
+            .header(header::COOKIE, cookies(None))
+            .header("X-CSRF-Token", CSRF_TOKEN)
+            .body(Body::empty())
+            .unwrap();
+
+        // When
+        let response = app.router.oneshot(request).await.unwrap();
+
+        // Then
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(response.headers().get(header::LOCATION).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_unknown_session_redirects_to_login() {
+        // Given
+        let app = TestApp::new();
+        let request = Request::get("/profile")
+            .header(header::COOKIE, cookies(Some("unknown")))
+            .body(Body::empty())
+            .unwrap();
+
+        // When
+        let response = app.router.oneshot(request).await.unwrap();
+
+        // Then
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    }
+
+    #[tokio::test]
+    async fn test_valid_session_passes_current_user() {
+        // Given
+        let app = TestApp::new();
+        let session = app.sign_in("alice").await;
+        let request = Request::get("/profile")
+            .header(header::COOKIE, cookies(Some(&session)))
+            .body(Body::empty())
+            .unwrap();
+
+        // When
+        let response = app.router.oneshot(request).await.unwrap();
+
+        // Then
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(body_string(response).await.contains("<h1>alice</h1>"));
+    }
+}
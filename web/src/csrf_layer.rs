@@ -0,0 +1,275 @@
+use crate::cookie_layer::{cookie_policy, read_cookie};
+use axum::async_trait;
+use axum::body::{self, Body};
+use axum::extract::{FromRequestParts, Request};
+use axum::http::request::Parts;
+use axum::http::{header, HeaderMap, HeaderValue, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use domain::repositories::id_provider::{IdProvider, SimpleIdProvider};
+
+/// Name of the cookie holding the CSRF token
+const CSRF_COOKIE: &str = "csrfToken";
+
+/// Form field the token is submitted in by forms
+pub(crate) const CSRF_FIELD: &str = "csrf_token";
+
+/// Header the token may be submitted in instead, for scripted requests without a form body
+const CSRF_HEADER: &str = "X-CSRF-Token";
+
+const CSRF_TOKEN_LENGTH: usize = 32;
+
+/// Largest form body read while looking for the token
+pub(crate) const MAX_FORM_SIZE: usize = 64 * 1024;
+
+/// Token to put into the `csrf_token` field of every form. Put into the request by `protect`.
+#[derive(Debug, Clone)]
+pub struct CsrfToken(pub String);
+
+/// Double-submit CSRF protection for all routes. Hands out a random token in a cookie and
+/// rejects state-changing requests unless they submit the same token in the `csrf_token`
+/// form field or the `X-CSRF-Token` header. Other sites can make the browser send the cookie,
+/// but can't read it to fill in the field.
+///
+/// Multipart bodies are never read here, so that anonymous requests can't make the server
+/// buffer uploads. They need the header, unless their route is mounted outside this layer and
+/// checks the field itself once the session is known, like `avatars::avatars_post`.
+pub(crate) async fn protect(request: Request, next: Next) -> Response {
+    let policy = cookie_policy(&request);
+    let existing = read_cookie(request.headers(), CSRF_COOKIE).map(str::to_owned);
+
+    let mut request = if is_safe(request.method()) {
+        request
+    } else {
+        let Some(expected) = existing.as_deref() else {
+            return forbidden();
+        };
+
+        let (submitted, request) = match submitted_token(request).await {
+            Ok(submitted) => submitted,
+            Err(response) => return response,
+        };
+
+        if !submitted.is_some_and(|token| tokens_match(&token, expected)) {
+            return forbidden();
+        }
+
+        request
+    };
+
+    let token = existing.clone().unwrap_or_else(|| SimpleIdProvider::new().get_id(CSRF_TOKEN_LENGTH));
+
+    request.extensions_mut().insert(CsrfToken(token.clone()));
+
+    let mut response = next.run(request).await;
+
+    if existing.is_none() {
+        if let Ok(cookie) = HeaderValue::from_str(&policy.cookie(CSRF_COOKIE, &token, None)) {
+            response.headers_mut().append(header::SET_COOKIE, cookie);
+        }
+    }
+
+    response
+}
+
+fn is_safe(method: &Method) -> bool {
+    matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE)
+}
+
+/// Reads the submitted token from the header or the urlencoded form body. Reading the body
+/// consumes it, so the request is rebuilt around the bytes read for the handler to parse again.
+async fn submitted_token(request: Request) -> Result<(Option<String>, Request), Response> {
+    if let Some(token) = request.headers().get(CSRF_HEADER).and_then(|v| v.to_str().ok()) {
+        return Ok((Some(token.to_owned()), request));
+    }
+
+    let content_type = request.headers().get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_owned();
+
+    if !content_type.starts_with("application/x-www-form-urlencoded") {
+        return Ok((None, request));
+    }
+
+    if exceeds_announced(request.headers(), MAX_FORM_SIZE) {
+        return Err(StatusCode::PAYLOAD_TOO_LARGE.into_response());
+    }
+
+    let (parts, body) = request.into_parts();
+    let bytes = body::to_bytes(body, MAX_FORM_SIZE).await
+        .map_err(|_| StatusCode::PAYLOAD_TOO_LARGE.into_response())?;
+
+    let token = serde_urlencoded::from_bytes::<Vec<(String, String)>>(&bytes).ok()
+        .and_then(|fields| fields.into_iter().find(|(name, _)| name == CSRF_FIELD))
+        .map(|(_, value)| value);
+
+    Ok((token, Request::from_parts(parts, Body::from(bytes))))
+}
+
+/// Whether the request announces a body larger than `limit`, so that it can be rejected before
+/// anything is read. Bodies without a length are cut off while reading.
+pub(crate) fn exceeds_announced(headers: &HeaderMap, limit: usize) -> bool {
+    headers.get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<usize>().ok())
+        .is_some_and(|length| length > limit)
+}
+
+/// Whether the `X-CSRF-Token` header matches the CSRF cookie, for routes checking the token
+/// themselves
+pub(crate) fn header_matches(headers: &HeaderMap) -> bool {
+    headers.get(CSRF_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|token| token_matches(headers, token))
+}
+
+/// Whether a token read from the body matches the CSRF cookie, for routes checking the token
+/// themselves
+pub(crate) fn token_matches(headers: &HeaderMap, submitted: &str) -> bool {
+    read_cookie(headers, CSRF_COOKIE).is_some_and(|expected| tokens_match(submitted, expected))
+}
+
+/// Compares in constant time, so the token can't be guessed from response timings
+fn tokens_match(submitted: &str, expected: &str) -> bool {
+    submitted.len() == expected.len()
+        && submitted.bytes().zip(expected.bytes()).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+pub(crate) fn forbidden() -> Response {
+    (StatusCode::FORBIDDEN, "Invalid or missing CSRF token").into_response()
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for CsrfToken
+where
+    S: Send + Sync,
+{
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts.extensions.get::<CsrfToken>().cloned().ok_or(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::{cookies, TestApp, CSRF_TOKEN};
+    use axum::body::Body;
+    use axum::http::{header, Request, StatusCode};
+    use tower::ServiceExt;
+
+    fn logout_request(cookie: &str) -> axum::http::request::Builder {
+        Request::post("/logout").header(header::COOKIE, cookie)
+    }
+
+    #[tokio::test]
+    async fn test_post_without_token_is_forbidden() {
+        // Given
+        let app = TestApp::new();
+        let request = Request::post("/logout").body(Body::empty()).unwrap();
+
+        // When
+        let response = app.router.oneshot(request).await.unwrap();
+
+        // Then
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_post_with_mismatched_token_is_forbidden() {
+        // Given
+        let app = TestApp::new();
+        let request = logout_request(&cookies(None))
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from("csrf_token=other-token"))
+            .unwrap();
+
+        // When
+        let response = app.router.oneshot(request).await.unwrap();
+
+        // Then
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_post_with_matching_header_passes() {
+        // Given
+        let app = TestApp::new();
+        let request = logout_request(&cookies(None))
+            .header("X-CSRF-Token", CSRF_TOKEN)
+            .body(Body::empty())
+            .unwrap();
+
+        // When
+        let response = app.router.oneshot(request).await.unwrap();
+
+        // Then
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    }
+
+    #[tokio::test]
+    async fn test_post_with_matching_form_field_passes() {
+        // Given
+        let app = TestApp::new();
+        let request = logout_request(&cookies(None))
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(format!("csrf_token={}", CSRF_TOKEN)))
+            .unwrap();
+
+        // When
+        let response = app.router.oneshot(request).await.unwrap();
+
+        // Then
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    }
+
+    #[tokio::test]
+    async fn test_post_with_oversized_form_is_rejected() {
+        // Given
+        let app = TestApp::new();
+        let padding = "a".repeat(super::MAX_FORM_SIZE);
+        let request = logout_request(&cookies(None))
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(format!("csrf_token={}&padding={}", CSRF_TOKEN, padding)))
+            .unwrap();
+
+        // When
+        let response = app.router.oneshot(request).await.unwrap();
+
+        // Then
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn test_get_hands_out_token_cookie() {
+        // Given
+        let app = TestApp::new();
+        let request = Request::get("/login").body(Body::empty()).unwrap();
+
+        // When
+        let response = app.router.oneshot(request).await.unwrap();
+
+        // Then
+        let cookie = response.headers().get(header::SET_COOKIE).unwrap().to_str().unwrap();
+        assert!(cookie.starts_with("csrfToken="));
+        assert!(cookie.contains("HttpOnly"));
+    }
+
+    #[tokio::test]
+    async fn test_multipart_without_header_is_forbidden() {
+        // Given
+        let app = TestApp::new();
+        let body = format!("--b\r\nContent-Disposition: form-data; name=\"csrf_token\"\r\n\r\n{}\r\n--b--\r\n", CSRF_TOKEN);
+        let request = logout_request(&cookies(None))
+            .header(header::CONTENT_TYPE, "multipart/form-data; boundary=b")
+            .body(Body::from(body))
+            .unwrap();
+
+        // When
+        let response = app.router.oneshot(request).await.unwrap();
+
+        // Then
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+}
@@ -0,0 +1,71 @@
+use application::query::user::list_sessions::ListSessionsQuery;
+use application::command::user::revoke_all_sessions::RevokeAllSessionsCommand;
+use application::AppContainer;
+use askama::Template;
+use axum::extract::State;
+use axum::http::{header, StatusCode};
+use axum::response::{Html, IntoResponse, Redirect, Response};
+use axum::Extension;
+use crate::cookie_layer::{CookiePolicy, CurrentSession, CurrentUser};
+use crate::csrf_layer::CsrfToken;
+use std::sync::Arc;
+
+/// Signed in device as listed on the page
+pub struct DeviceRow {
+    pub user_agent: String,
+    pub ip_address: String,
+    pub created_at: String,
+    pub expired_at: String,
+    pub is_current: bool,
+}
+
+#[derive(Template)]
+#[template(path = "devices.html")]
+pub struct DevicesTemplate {
+    pub devices: Vec<DeviceRow>,
+    pub csrf_token: String,
+}
+
+/// `GET /devices` lists the sessions of the signed in user
+pub(crate) async fn devices_get(
+    State(container): State<Arc<AppContainer>>,
+    CurrentUser(user): CurrentUser,
+    CurrentSession(current): CurrentSession,
+    CsrfToken(csrf_token): CsrfToken,
+) -> Response {
+    let sessions = match container.query(ListSessionsQuery::new(user.id, Some(current))).await {
+        Ok(sessions) => sessions,
+        Err(err) => {
+            eprintln!("Error listing sessions: {}", err);
+
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let devices = sessions.into_iter()
+        .map(|session| DeviceRow {
+            user_agent: session.user_agent.unwrap_or_else(|| "Unknown browser".to_owned()),
+            ip_address: session.ip_address.unwrap_or_else(|| "unknown address".to_owned()),
+            created_at: session.created_at.format("%Y-%m-%d %H:%M").to_string(),
+            expired_at: session.expired_at.format("%Y-%m-%d %H:%M").to_string(),
+            is_current: session.is_current,
+        })
+        .collect();
+
+    Html(DevicesTemplate { devices, csrf_token }.render().unwrap()).into_response()
+}
+
+/// `POST /devices/revoke` signs the user out everywhere, this browser included
+pub(crate) async fn revoke_post(
+    State(container): State<Arc<AppContainer>>,
+    Extension(cookies): Extension<Arc<CookiePolicy>>,
+    CurrentUser(user): CurrentUser,
+) -> Response {
+    if let Err(err) = container.send_command(RevokeAllSessionsCommand::new(user.id)).await {
+        eprintln!("Error revoking sessions: {}", err);
+
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    ([(header::SET_COOKIE, cookies.expired_session_cookie())], Redirect::to("/login")).into_response()
+}
@@ -0,0 +1,309 @@
+use application::command::email::add_email::AddEmailCommand;
+use application::command::email::assign_avatar_to_email::AssignAvatarToEmailCommand;
+use application::command::email::remove_email::RemoveEmailCommand;
+use application::command::email::set_primary_email::SetPrimaryEmailCommand;
+use application::command::email::verify_email::VerifyEmailCommand;
+use application::query::avatar::list_avatars::ListAvatarsQuery;
+use application::query::email::list_emails::ListEmailsQuery;
+use application::shared::error::AppStatus;
+use application::AppContainer;
+use askama::Template;
+use axum::async_trait;
+use axum::extract::{FromRequestParts, Path, Query, State};
+use axum::http::request::Parts;
+use axum::http::{header, StatusCode};
+use axum::response::{Html, IntoResponse, Redirect, Response};
+use axum::{Extension, Form};
+use crate::cookie_layer::{CookiePolicy, CurrentSession, CurrentUser};
+use crate::csrf_layer::CsrfToken;
+use crate::error::{status_code, AppError};
+use domain::views::email_view::EmailChangeView;
+use serde::Deserialize;
+use std::sync::Arc;
+
+/// Option of the avatar picker of one address
+pub struct AvatarOption {
+    pub id: i64,
+    pub label: String,
+    pub selected: bool,
+}
+
+/// Address as listed on the page, with the avatars it can be assigned
+pub struct EmailRow {
+    pub id: i64,
+    pub value: String,
+    pub md5_hash: String,
+    pub is_verified: bool,
+    pub is_primary: bool,
+    pub avatars: Vec<AvatarOption>,
+}
+
+#[derive(Template)]
+#[template(path = "emails.html")]
+pub struct EmailsTemplate {
+    pub emails: Vec<EmailRow>,
+    pub error: Option<String>,
+    pub csrf_token: String,
+}
+
+/// Confirmation step of the link in the verification mail. Following a link must not change
+/// anything, the code is only submitted by the form with its CSRF token.
+#[derive(Template)]
+#[template(path = "email_verify.html")]
+pub struct VerifyEmailTemplate {
+    pub email_id: i64,
+    pub code: String,
+    pub csrf_token: String,
+}
+
+#[derive(Deserialize)]
+pub struct AddEmailData {
+    email: String,
+}
+
+#[derive(Deserialize)]
+pub struct VerifyEmailData {
+    code: String,
+}
+
+#[derive(Deserialize)]
+pub struct AssignAvatarData {
+    /// Empty to fall back to the primary avatar
+    avatar_id: String,
+}
+
+/// What the handlers need besides the command to run: who is signed in, with which session,
+/// and the CSRF token to render the page with
+pub(crate) struct EmailsContext {
+    user_id: i64,
+    session: CurrentSession,
+    csrf_token: String,
+    cookies: Arc<CookiePolicy>,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for EmailsContext
+where
+    S: Send + Sync,
+{
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let CurrentUser(user) = CurrentUser::from_request_parts(parts, state).await?;
+        let session = CurrentSession::from_request_parts(parts, state).await?;
+        let CsrfToken(csrf_token) = CsrfToken::from_request_parts(parts, state).await?;
+        let Extension(cookies) = Extension::<Arc<CookiePolicy>>::from_request_parts(parts, state).await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+        Ok(Self { user_id: user.id, session, csrf_token, cookies })
+    }
+}
+
+async fn render_page(container: &AppContainer, context: &EmailsContext, error: Option<AppStatus>) -> Response {
+    let user_id = context.user_id;
+    let emails = container.query(ListEmailsQuery::new(user_id)).await;
+    let avatars = container.query(ListAvatarsQuery::new(user_id)).await;
+
+    let (emails, avatars) = match (emails, avatars) {
+        (Ok(emails), Ok(avatars)) => (emails, avatars),
+        (Err(err), _) | (_, Err(err)) => {
+            eprintln!("Error loading emails: {}", err);
+
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let rows = emails.into_iter()
+        .map(|email| EmailRow {
+            avatars: avatars.iter()
+                .map(|avatar| AvatarOption {
+                    id: avatar.id,
+                    label: format!(
+                        "#{} ({}) uploaded {}",
+                        avatar.id,
+                        avatar.rating.as_str().to_uppercase(),
+                        avatar.created_at.format("%Y-%m-%d %H:%M"),
+                    ),
+                    selected: email.avatar_id == Some(avatar.id),
+                })
+                .collect(),
+            id: email.id,
+            value: email.value,
+            md5_hash: email.md5_hash,
+            is_verified: email.is_verified,
+            is_primary: email.is_primary,
+        })
+        .collect();
+
+    let status = error.as_ref().map_or(StatusCode::OK, status_code);
+    let template = EmailsTemplate { emails: rows, error: error.map(|err| err.to_string()), csrf_token: context.csrf_token.clone() };
+
+    (status, Html(template.render().unwrap())).into_response()
+}
+
+/// Goes back to the page on success, shows it with the error otherwise
+async fn respond<T>(container: &AppContainer, context: &EmailsContext, result: Result<T, AppStatus>) -> Response {
+    match result {
+        Ok(_) => Redirect::to("/emails").into_response(),
+        Err(err @ (AppStatus::InternalError(_) | AppStatus::NotFound(_))) => AppError(err).into_response(),
+        Err(err) => render_page(container, context, Some(err)).await,
+    }
+}
+
+/// Like `respond`, but also sets the cookie to the new session value when the command rotated
+/// the session
+async fn respond_rotated<T>(
+    container: &AppContainer,
+    context: &EmailsContext,
+    result: Result<EmailChangeView<T>, AppStatus>,
+) -> Response {
+    match result {
+        Ok(EmailChangeView { session: Some(session), .. }) => (
+            [(header::SET_COOKIE, context.cookies.session_cookie(&session.value, session.max_age))],
+            Redirect::to("/emails"),
+        ).into_response(),
+        result => respond(container, context, result).await,
+    }
+}
+
+/// `GET /emails` lists the addresses of the signed in user
+pub(crate) async fn emails_get(State(container): State<Arc<AppContainer>>, context: EmailsContext) -> Response {
+    render_page(&container, &context, None).await
+}
+
+/// `POST /emails` adds an address and mails it a verification code
+pub(crate) async fn emails_post(
+    State(container): State<Arc<AppContainer>>,
+    context: EmailsContext,
+    Form(data): Form<AddEmailData>,
+) -> Response {
+    let result = container.send_command(AddEmailCommand::new(context.user_id, data.email).with_session(context.session.0.clone())).await;
+
+    respond_rotated(&container, &context, result).await
+}
+
+/// `GET /emails/{id}/verify?code=...` is the link in the verification mail. Shows a form
+/// posting the code, see `VerifyEmailTemplate`.
+pub(crate) async fn verify_get(
+    context: EmailsContext,
+    Path(email_id): Path<i64>,
+    Query(data): Query<VerifyEmailData>,
+) -> Html<String> {
+    let template = VerifyEmailTemplate { email_id, code: data.code, csrf_token: context.csrf_token };
+
+    Html(template.render().unwrap())
+}
+
+/// `POST /emails/{id}/verify` with the code from the mail, confirmed from the link or typed
+/// in by hand
+pub(crate) async fn verify_post(
+    State(container): State<Arc<AppContainer>>,
+    context: EmailsContext,
+    Path(email_id): Path<i64>,
+    Form(data): Form<VerifyEmailData>,
+) -> Response {
+    let result = container.send_command(VerifyEmailCommand::new(context.user_id, email_id, data.code.trim().to_owned())
+        .with_session(context.session.0.clone())).await;
+
+    respond_rotated(&container, &context, result).await
+}
+
+/// `POST /emails/{id}/primary`
+pub(crate) async fn primary_post(
+    State(container): State<Arc<AppContainer>>,
+    context: EmailsContext,
+    Path(email_id): Path<i64>,
+) -> Response {
+    let result = container.send_command(SetPrimaryEmailCommand::new(context.user_id, email_id).with_session(context.session.0.clone())).await;
+
+    respond_rotated(&container, &context, result).await
+}
+
+/// `POST /emails/{id}/delete`
+pub(crate) async fn delete_post(
+    State(container): State<Arc<AppContainer>>,
+    context: EmailsContext,
+    Path(email_id): Path<i64>,
+) -> Response {
+    let result = container.send_command(RemoveEmailCommand::new(context.user_id, email_id).with_session(context.session.0.clone())).await;
+
+    respond_rotated(&container, &context, result).await
+}
+
+/// `POST /emails/{id}/avatar` assigns an avatar from the library to the address
+pub(crate) async fn avatar_post(
+    State(container): State<Arc<AppContainer>>,
+    context: EmailsContext,
+    Path(email_id): Path<i64>,
+    Form(data): Form<AssignAvatarData>,
+) -> Response {
+    let avatar_id = match data.avatar_id.trim() {
+        "" => None,
+        id => match id.parse::<i64>() {
+            Ok(id) => Some(id),
+            Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+        },
+    };
+
+    let result = container.send_command(AssignAvatarToEmailCommand::new(context.user_id, email_id, avatar_id)).await;
+
+    respond(&container, &context, result).await
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::{body_string, cookies, TestApp, CSRF_TOKEN};
+    use axum::body::Body;
+    use axum::http::{header, Request, StatusCode};
+    use domain::models::email_address::Email;
+    use domain::repositories::email_repository::EmailRepository;
+    use domain::repositories::session_repository::SessionRepository;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_verify_link_only_renders_confirmation() {
+        // Given
+        let app = TestApp::new();
+        let session = app.sign_in("alice").await;
+        let email = app.emails.save(Email::new(app.user_id(&session).await, "alice@example.com".to_owned())).await.unwrap();
+        let request = Request::get(format!("/emails/{}/verify?code=abc", email.id))
+            .header(header::COOKIE, cookies(Some(&session)))
+            .body(Body::empty())
+            .unwrap();
+
+        // When
+        let response = app.router.clone().oneshot(request).await.unwrap();
+
+        // Then
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = body_string(response).await;
+        assert!(body.contains(&format!(r#"<form method="post" action="/emails/{}/verify">"#, email.id)));
+        assert!(body.contains(r#"name="code" value="abc""#));
+        assert!(!app.emails.find_by_id(email.id).await.unwrap().is_verified);
+    }
+
+    #[tokio::test]
+    async fn test_address_change_rotates_session() {
+        // Given
+        let app = TestApp::new();
+        let session = app.sign_in("alice").await;
+        let email = app.emails.save(Email::new(app.user_id(&session).await, "alice@example.com".to_owned())).await.unwrap();
+        let request = Request::post(format!("/emails/{}/delete", email.id))
+            .header(header::COOKIE, cookies(Some(&session)))
+            .header("X-CSRF-Token", CSRF_TOKEN)
+            .body(Body::empty())
+            .unwrap();
+
+        // When
+        let response = app.router.clone().oneshot(request).await.unwrap();
+
+        // Then
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        let cookie = response.headers().get(header::SET_COOKIE).unwrap().to_str().unwrap();
+        let value = cookie.strip_prefix("sessionId=").unwrap().split(';').next().unwrap();
+        assert_ne!(value, session);
+        assert!(app.sessions.load(&session).await.is_none());
+        assert!(app.sessions.load(value).await.is_some());
+        assert!(app.emails.find_by_id(email.id).await.is_none());
+    }
+}
@@ -0,0 +1,41 @@
+use application::shared::error::AppStatus;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+
+/// Header carrying `AppStatus::code`, so scripts can tell failures apart without reading the body
+const ERROR_CODE_HEADER: &str = "X-Error-Code";
+
+/// HTTP status answering a request that failed with `status`
+pub(crate) fn status_code(status: &AppStatus) -> StatusCode {
+    match status {
+        AppStatus::NotFound(_) => StatusCode::NOT_FOUND,
+        AppStatus::BadRequest(_) => StatusCode::BAD_REQUEST,
+        AppStatus::AuthError(_) => StatusCode::UNAUTHORIZED,
+        AppStatus::TooManyAttempts(_) => StatusCode::TOO_MANY_REQUESTS,
+        AppStatus::InternalError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+/// Answers a failed request with the status and code of `status` and the given body. Internal
+/// errors are logged here, their details are not meant for the client.
+pub(crate) fn error_response(status: &AppStatus, body: impl IntoResponse) -> Response {
+    if let AppStatus::InternalError(err) = status {
+        log::error!("Internal error: {}", err);
+    }
+
+    (status_code(status), [(ERROR_CODE_HEADER, status.code())], body).into_response()
+}
+
+/// Failure of a command or query as a plain text response, see `error_response`
+pub(crate) struct AppError(pub AppStatus);
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let message = match &self.0 {
+            AppStatus::InternalError(_) => "Internal error".to_owned(),
+            status => status.message().to_owned(),
+        };
+
+        error_response(&self.0, message)
+    }
+}
@@ -1,17 +1,28 @@
+mod avatar;
+mod avatars;
+mod cookie_layer;
+mod csrf_layer;
+mod devices;
+mod emails;
+mod error;
 mod login;
 mod profile;
-mod cookie_layer;
+#[cfg(test)]
+mod test_support;
 use application::AppContainer;
 use askama::Template;
+use axum::extract::DefaultBodyLimit;
+use axum::middleware;
 use axum::response::Html;
 use axum::routing::{get, post};
-use axum::Router;
+use axum::{Extension, Router};
 use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::net;
-use tower::Layer;
 use tower_http::services::ServeDir;
 
+pub use cookie_layer::CookiePolicy;
+
 #[derive(Template)]
 #[template(path = "index.html")]
 pub struct IndexTemplate<'a> {
@@ -28,7 +39,7 @@ pub async fn hello() -> Html<String> {
     Html("<p>Hello from the server!</p>".to_string())
 }
 
-fn get_router(container: Arc<AppContainer>) -> Router {
+fn get_router(container: Arc<AppContainer>, cookie_policy: CookiePolicy) -> Router {
     let static_files_router = Router::new()
         .fallback_service(ServeDir::new("./web/static"));
 
@@ -36,11 +47,36 @@ fn get_router(container: Arc<AppContainer>) -> Router {
         .route("/", get(index))
         .route("/hello", get(hello))
         .route("/login/email", post(login::handle_email))
-        .route("/login", get(login::login_get).post(login::handle_login));
+        .route("/login", get(login::login_get).post(login::handle_login))
+        .route("/logout", post(login::handle_logout))
+        .route("/avatar/:hash", get(avatar::avatar_get));
+
+    // Only reachable with a valid session, see `cookie_layer::require_session`
+    let protected_routes = Router::new()
+        .route("/profile", get(profile::profile_get))
+        .route("/devices", get(devices::devices_get))
+        .route("/devices/revoke", post(devices::revoke_post))
+        .route("/emails", get(emails::emails_get).post(emails::emails_post))
+        .route("/emails/:id/verify", get(emails::verify_get).post(emails::verify_post))
+        .route("/emails/:id/primary", post(emails::primary_post))
+        .route("/emails/:id/delete", post(emails::delete_post))
+        .route("/emails/:id/avatar", post(emails::avatar_post))
+        .route_layer(middleware::from_fn_with_state(container.clone(), cookie_layer::require_session));
+
+    // Uploads check their CSRF token themselves once the session is known, so that no
+    // anonymous upload is read, see `avatars::avatars_post`
+    let upload_routes = Router::new()
+        .route("/avatars", post(avatars::avatars_post).layer(DefaultBodyLimit::max(avatars::MAX_UPLOAD_SIZE)))
+        .route_layer(middleware::from_fn_with_state(container.clone(), cookie_layer::require_session));
 
     Router::new()
         .nest_service("/static", static_files_router)
         .merge(app_routes)
+        .merge(protected_routes)
+        // Every other POST has to carry the CSRF token, see `csrf_layer::protect`
+        .layer(middleware::from_fn(csrf_layer::protect))
+        .merge(upload_routes)
+        .layer(Extension(Arc::new(cookie_policy)))
         .with_state(container)
 }
 
@@ -48,21 +84,28 @@ pub struct Server
 {
     port: u16,
     container: Arc<AppContainer>,
+    cookie_policy: CookiePolicy,
 }
 
 impl Server
 {
     pub fn new(port: u16, container: Arc<AppContainer>) -> Self {
-        Server { port, container }
+        Server { port, container, cookie_policy: CookiePolicy::default() }
+    }
+
+    pub fn with_cookie_policy(mut self, cookie_policy: CookiePolicy) -> Self {
+        self.cookie_policy = cookie_policy;
+        self
     }
 
     pub async fn run(self) {
-        let router = get_router(self.container);
+        let router = get_router(self.container, self.cookie_policy);
 
         let addr = SocketAddr::from(([127, 0, 0, 1], self.port));
 
         let listener = net::TcpListener::bind(&addr).await.unwrap();
 
-        axum::serve(listener, router).await.unwrap();
+        // Connection info gives login the client address to remember with the session
+        axum::serve(listener, router.into_make_service_with_connect_info::<SocketAddr>()).await.unwrap();
     }
 }
\ No newline at end of file
@@ -1,20 +1,34 @@
-use application::command::user::login_user::LoginUserCommand;
+use application::command::user::login_user::{LoginResponse, LoginUserCommand};
+use application::command::user::logout::LogoutCommand;
 use application::shared::error::AppStatus;
 use application::AppContainer;
 use askama::Template;
-use axum::extract::State;
-use axum::response::{Html, IntoResponse};
-use axum::Form;
+use axum::extract::{ConnectInfo, State};
+use axum::response::{Html, IntoResponse, Redirect, Response};
+use axum::{Extension, Form};
 use serde::Deserialize;
+use std::net::SocketAddr;
 use std::sync::Arc;
-use axum::http::HeaderMap;
+use axum::http::{header, HeaderMap};
+use crate::cookie_layer::CookiePolicy;
+use crate::csrf_layer::CsrfToken;
+use crate::error::{error_response, AppError};
 
 #[derive(Template)]
 #[template(path = "login.html")]
-pub struct LoginTemplate<> {}
+pub struct LoginTemplate<> {
+    pub csrf_token: String,
+}
 
-pub async fn login_get() -> Html<String> {
-    let template = LoginTemplate {};
+#[derive(Template)]
+#[template(path = "otp_form.html")]
+pub struct OtpFormTemplate {
+    pub csrf_token: String,
+    pub email: String,
+}
+
+pub async fn login_get(CsrfToken(csrf_token): CsrfToken) -> Html<String> {
+    let template = LoginTemplate { csrf_token };
 
     Html(template.render().unwrap())
 }
@@ -32,54 +46,81 @@ pub struct LoginData {
 
 pub(crate) async fn handle_email(
     State(container): State<Arc<AppContainer>>,
+    CsrfToken(csrf_token): CsrfToken,
     Form(data): Form<EmailData>,
-) -> Html<String> {
-    println!("Received email: {}", data.email.clone());
-
+) -> Response {
     match container.send_command(LoginUserCommand::new(data.email.clone(), None)).await {
-        Ok(_) => {}
-        Err(err) => {
-            match err {
-                AppStatus::Ok(_) => {
-                    println!("Email sent successfully.")
-                }
-                _ => { return Html("Error sending email.".to_owned()) }
-            }
+        Ok(LoginResponse::OtpSent { .. }) => {}
+        Ok(LoginResponse::LoggedIn(_)) => {
+            return AppError(AppStatus::InternalError("Signed in without an OTP".to_owned())).into_response();
         }
+        Err(err @ AppStatus::TooManyAttempts(_)) => return error_response(&err, "Too many attempts, try again later."),
+        Err(err) => return error_response(&err, "Error sending email."),
     };
 
-    let response = format!(
-        r#"
-        <h2>Enter OTP</h2>
-        <form id="otp-form" hx-post="/login" hx-swap="innerHTML">
-            <input type="hidden" name="email" value="{email}">
-            <label for="otp">OTP:</label>
-            <input type="text" id="otp" name="otp" required>
-            <button type="submit">Submit</button>
-        </form>
-        "#,
-        email = data.email
-    );
-
-    // Return Html with the response String
-    Html(response)
+    let template = OtpFormTemplate { csrf_token, email: data.email };
+
+    Html(template.render().unwrap()).into_response()
 }
 
 pub(crate) async fn handle_login(
     State(container): State<Arc<AppContainer>>,
+    Extension(cookies): Extension<Arc<CookiePolicy>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    request_headers: HeaderMap,
     Form(data): Form<LoginData>,
-) -> impl IntoResponse {
+) -> Response {
 
     let mut headers = HeaderMap::new();
 
-    match container.send_command(LoginUserCommand::new(data.email.clone(), data.otp.clone())).await {
-        Ok(s) => {
-            headers.insert("Set-Cookie", format!("sessionId={}; Path=/; HttpOnly", s.value).parse().unwrap());
+    let user_agent = request_headers.get(header::USER_AGENT).and_then(|v| v.to_str().ok()).map(str::to_owned);
+    let command = LoginUserCommand::new(data.email.clone(), data.otp.clone())
+        .with_client(user_agent, Some(addr.ip().to_string()));
+
+    match container.send_command(command).await {
+        Ok(LoginResponse::LoggedIn(s)) => {
+            headers.insert("Set-Cookie", cookies.session_cookie(&s.value, s.max_age).parse().unwrap());
 
-            (headers, "Login successful.".to_owned())
+            (headers, "Login successful.".to_owned()).into_response()
         }
-        Err(_) => {
-            (headers, "Login failed.".to_owned())
+        Ok(LoginResponse::OtpSent { .. }) => (headers, "OTP sent to email.".to_owned()).into_response(),
+        Err(err @ AppStatus::TooManyAttempts(_)) => error_response(&err, "Too many attempts, try again later."),
+        Err(err) => error_response(&err, "Login failed."),
+    }
+}
+
+/// `POST /logout` ends the current session and drops its cookie
+pub(crate) async fn handle_logout(
+    State(container): State<Arc<AppContainer>>,
+    Extension(cookies): Extension<Arc<CookiePolicy>>,
+    request_headers: HeaderMap,
+) -> impl IntoResponse {
+    if let Some(value) = cookies.read_session(&request_headers) {
+        if let Err(err) = container.send_command(LogoutCommand::new(value.to_owned())).await {
+            eprintln!("Error logging out: {}", err);
         }
     }
+
+    ([(header::SET_COOKIE, cookies.expired_session_cookie())], Redirect::to("/login"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_otp_form_escapes_email() {
+        // Given
+        let template = OtpFormTemplate {
+            csrf_token: "token".to_owned(),
+            email: "\"><script>alert(1)</script>@example.com".to_owned(),
+        };
+
+        // When
+        let html = template.render().unwrap();
+
+        // Then
+        assert!(!html.contains("<script>"));
+        assert!(html.contains("&quot;&gt;&lt;script&gt;"));
+    }
 }
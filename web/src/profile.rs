@@ -0,0 +1,41 @@
+use application::query::user::get_profile::GetProfileQuery;
+use application::AppContainer;
+use crate::cookie_layer::CurrentUser;
+use crate::csrf_layer::CsrfToken;
+use askama::Template;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::{Html, IntoResponse, Response};
+use std::sync::Arc;
+
+#[derive(Template)]
+#[template(path = "profile.html")]
+pub struct ProfileTemplate {
+    pub username: String,
+    pub register_date: String,
+    pub csrf_token: String,
+}
+
+/// `GET /profile` shows the signed in user
+pub(crate) async fn profile_get(
+    State(container): State<Arc<AppContainer>>,
+    CurrentUser(user): CurrentUser,
+    CsrfToken(csrf_token): CsrfToken,
+) -> Response {
+    let user = match container.query(GetProfileQuery::new(user.id)).await {
+        Ok(user) => user,
+        Err(err) => {
+            eprintln!("Error loading profile: {}", err);
+
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let template = ProfileTemplate {
+        username: user.username,
+        register_date: user.register_date.format("%Y-%m-%d").to_string(),
+        csrf_token,
+    };
+
+    Html(template.render().unwrap()).into_response()
+}
@@ -0,0 +1,83 @@
+use crate::cookie_layer::CookiePolicy;
+use crate::get_router;
+use application::AppContainer;
+use axum::body;
+use axum::response::Response;
+use axum::Router;
+use domain::models::session::Session;
+use domain::models::user::User;
+use domain::repositories::avatar_repository::InMemoryAvatarRepository;
+use domain::repositories::blob_store::InMemoryBlobStore;
+use domain::repositories::email_repository::InMemoryEmailRepository;
+use domain::repositories::id_provider::SimpleIdProvider;
+use domain::repositories::otp_repository::InMemoryOtpRepository;
+use domain::repositories::session_repository::{InMemorySessionRepository, SessionRepository};
+use domain::repositories::unit_of_work::InMemoryUnitOfWork;
+use domain::repositories::user_repository::{InMemoryUserRepository, UserRepository};
+use std::sync::Arc;
+
+/// CSRF token the test requests carry in both the cookie and the form or header
+pub(crate) const CSRF_TOKEN: &str = "test-csrf-token";
+
+/// Router over in-memory repositories, with handles on the stores to set up users and sessions
+pub(crate) struct TestApp {
+    pub router: Router,
+    pub users: InMemoryUserRepository,
+    pub sessions: InMemorySessionRepository,
+    pub emails: InMemoryEmailRepository,
+    pub avatars: InMemoryAvatarRepository,
+}
+
+impl TestApp {
+    pub fn new() -> Self {
+        Self::with_cookie_policy(CookiePolicy::default())
+    }
+
+    pub fn with_cookie_policy(cookie_policy: CookiePolicy) -> Self {
+        let users = InMemoryUserRepository::new();
+        let sessions = InMemorySessionRepository::new();
+        let otps = InMemoryOtpRepository::new();
+        let emails = InMemoryEmailRepository::new();
+        let avatars = InMemoryAvatarRepository::new();
+
+        let container = AppContainer::new(
+            users.clone(),
+            sessions.clone(),
+            otps.clone(),
+            SimpleIdProvider::new(),
+            avatars.clone(),
+            InMemoryBlobStore::new(),
+            emails.clone(),
+            InMemoryUnitOfWork::new(users.clone(), sessions.clone(), otps),
+        );
+
+        Self { router: get_router(Arc::new(container), cookie_policy), users, sessions, emails, avatars }
+    }
+
+    /// Creates user `username` with a session valid for an hour, returning the session value
+    pub async fn sign_in(&self, username: &str) -> String {
+        let user = self.users.save(User::new(username.to_owned())).await.unwrap();
+        let session = Session::new(format!("session-{}", user.id), user.id.to_string(), 3600);
+
+        self.sessions.save(&session).await.unwrap()
+    }
+
+    /// Id of the user signed in with session `value`
+    pub async fn user_id(&self, session: &str) -> i64 {
+        self.sessions.load(session).await.unwrap().user_id.parse().unwrap()
+    }
+}
+
+/// `Cookie` header carrying the CSRF token and, when given, the session
+pub(crate) fn cookies(session: Option<&str>) -> String {
+    match session {
+        Some(session) => format!("csrfToken={}; sessionId={}", CSRF_TOKEN, session),
+        None => format!("csrfToken={}", CSRF_TOKEN),
+    }
+}
+
+pub(crate) async fn body_string(response: Response) -> String {
+    let bytes = body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+    String::from_utf8(bytes.to_vec()).unwrap()
+}